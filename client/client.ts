@@ -1,57 +1,207 @@
-import * as borsh from "borsh";
-import * as web3 from "@solana/web3.js";
 import {
   Connection,
   PublicKey,
   clusterApiUrl,
   Keypair,
+  SystemProgram,
+  SYSVAR_RENT_PUBKEY,
   Transaction,
-  sendAndConfirmTransaction,
   TransactionInstruction,
+  sendAndConfirmTransaction,
 } from "@solana/web3.js";
-import * as borsh from "borsh";
-// Manually initialize variables that are automatically defined in Playground
-const PROGRAM_ID = new web3.PublicKey("DRGtxC9Z1pmxgA6a4G9kQxivATjGGQ3CQWKNAfUhwUPU");
-const connection = new web3.Connection("https://api.devnet.solana.com", "confirmed");
-const wallet = { keypair: web3.Keypair.generate() };
+import * as fs from "fs";
+import * as os from "os";
+
+//  the program ID and connection
+const PROGRAM_ID = new PublicKey(
+  "DRGtxC9Z1pmxgA6a4G9kQxivATjGGQ3CQWKNAfUhwUPU"
+);
+const connection = new Connection(clusterApiUrl("devnet"), "confirmed");
+
+const TOKEN_PROGRAM_ID = new PublicKey(
+  "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA"
+);
+const BPF_LOADER_UPGRADEABLE_ID = new PublicKey(
+  "BPFLoaderUpgradeab1e11111111111111111111111"
+);
+// size of an SPL token account
+const TOKEN_ACCOUNT_LEN = 165;
+const GAME_TOKENS_PER_CHUNK = 32;
+const TOKEN_HISTORY_PER_CHUNK = 8;
+
+//  the program's CustomError codes, in declaration order, with the messages
+//  its Display impl gives them
+enum CustomError {
+  NotAdmin,
+  MintNotPermitted,
+  NotTokenOwner,
+  InvalidInstruction,
+  StateCorrupted,
+  PermissionCorrupted,
+  InvalidTreasury,
+  UriSchemeNotAllowed,
+  BurnNotQueued,
+  TokenBurned,
+  PriceBelowFloor,
+  MintingDisabled,
+  TransferApprovalRequired,
+  NotSnapshotHolder,
+  SnapshotRewardClaimed,
+  GrantExists,
+  InvalidTreasurySplit,
+  KycRequired,
+  RequiredTokenMissing,
+  NotAnNft,
+  GameNotActive,
+  InvalidRoyalty,
+  GrantCooldownActive,
+  MetadataRentTooHigh,
+  InvalidReceiver,
+  TokenProgramNotAllowed,
+  TokenExpired,
+  TokenNotExpired,
+  TransfersFrozen,
+  Soulbound,
+  InvalidReferrer,
+  UriTooLong,
+  InvalidDiscount,
+  SupplyExhausted,
+  InvalidPriceFeed,
+  StalePriceFeed,
+  TimelockRequired,
+  TimelockActive,
+  NoPendingChange,
+  TokensOutstanding,
+  AlreadyInitialized,
+  ContractPaused,
+}
+
+const CUSTOM_ERROR_MESSAGES = [
+  "caller is not the contract admin",
+  "minter has no permission for this game",
+  "signer does not own the token",
+  "instruction data or accounts are invalid",
+  "account state could not be decoded",
+  "mint permission could not be decoded",
+  "account is not the contract treasury",
+  "token URI scheme is not allowed",
+  "token is not queued for burning",
+  "token has been burned",
+  "price is below the game's floor",
+  "minting is disabled",
+  "transfer needs the admin's approval",
+  "owner held no tokens at the snapshot",
+  "snapshot reward already claimed",
+  "a mint permission already exists",
+  "treasury split shares are invalid",
+  "receiver has no valid KYC attestation",
+  "receiver does not hold the game's required token",
+  "mint is not a 0-decimal, supply-1 token",
+  "game is not active",
+  "royalty is above 100%",
+  "user's grant cooldown has not passed",
+  "token metadata rent is above the contract's limit",
+  "receiver is one of the contract's own accounts",
+  "token program is not allowed",
+  "token has expired",
+  "token has not expired yet",
+  "transfers are frozen",
+  "token is soulbound",
+  "referrer is the minter or the receiver",
+  "token URI is too long",
+  "discount is above 100%",
+  "supply is exhausted",
+  "price feed is missing or invalid",
+  "price feed quote is too old",
+  "change has to go through the timelock",
+  "pending change's timelock has not passed",
+  "no change is pending",
+  "contract still has live tokens",
+  "contract is already initialized",
+  "contract is paused",
+];
+
+// Turns the code of a "custom program error: 0x.." back into the program's
+// error name and message, or undefined for codes it doesn't define
+function describeCustomError(code: number): string | undefined {
+  const message = CUSTOM_ERROR_MESSAGES[code];
+  return message && `${CustomError[code]}: ${message}`;
+}
 
+function customErrorCode(error: unknown): number | undefined {
+  const match = /custom program error: 0x([0-9a-f]+)/i.exec(String(error));
+  return match ? parseInt(match[1], 16) : undefined;
+}
 
-//  the structure of the contract state
+//  the leading fields of the contract state; the rest are not read here
 class ContractState {
   contractOwner: PublicKey;
   lastTokenId: number;
 
-  constructor(fields: { contractOwner: Uint8Array; lastTokenId: number }) {
-    this.contractOwner = new PublicKey(fields.contractOwner);
-    this.lastTokenId = fields.lastTokenId;
+  constructor(data: Buffer) {
+    this.contractOwner = new PublicKey(data.subarray(0, 32));
+    this.lastTokenId = readU64(data, 32);
   }
 }
 
-// Borsh schema for contract state
-const ContractStateSchema = new Map([
-  [
-    ContractState,
-    {
-      kind: "struct",
-      fields: [
-        ["contractOwner", [32]],
-        ["lastTokenId", "u64"],
-      ],
-    },
-  ],
-]);
+// Encodes a string the way borsh does: a u32 little-endian byte length
+// followed by the UTF-8 bytes
+function encodeString(value: string): Buffer {
+  const bytes = Buffer.from(value, "utf8");
+  const length = Buffer.alloc(4);
+  length.writeUInt32LE(bytes.length);
+  return Buffer.concat([length, bytes]);
+}
 
-//  the program ID and connection
-const PROGRAM_ID = new PublicKey(
-  "DRGtxC9Z1pmxgA6a4G9kQxivATjGGQ3CQWKNAfUhwUPU"
-);
-const connection = new Connection(clusterApiUrl("devnet"), "confirmed");
+// u64s stay below 2^53 here, so plain numbers hold them exactly
+function encodeU64(value: number): Buffer {
+  const bytes = Buffer.alloc(8);
+  bytes.writeUInt32LE(value % 2 ** 32);
+  bytes.writeUInt32LE(Math.floor(value / 2 ** 32), 4);
+  return bytes;
+}
 
-async function main() {
-  const payer = Keypair.generate();
+function readU64(data: Buffer, offset: number): number {
+  return data.readUInt32LE(offset) + data.readUInt32LE(offset + 4) * 2 ** 32;
+}
+
+function encodeU32(value: number): Buffer {
+  const bytes = Buffer.alloc(4);
+  bytes.writeUInt32LE(value);
+  return bytes;
+}
+
+function pda(seeds: (Buffer | Uint8Array)[]): PublicKey {
+  return PublicKey.findProgramAddressSync(seeds, PROGRAM_ID)[0];
+}
+
+const contractPda = () => pda([Buffer.from("contract")]);
+const gamePda = (gameId: string) =>
+  pda([Buffer.from("game"), Buffer.from(gameId)]);
+const permissionPda = (user: PublicKey, gameId: string) =>
+  pda([Buffer.from("permission"), user.toBuffer(), Buffer.from(gameId)]);
+const mintPda = (tokenId: number) =>
+  pda([Buffer.from("mint"), encodeU64(tokenId)]);
+
+function writable(pubkey: PublicKey, isSigner = false) {
+  return { pubkey, isSigner, isWritable: true };
+}
+
+function readonly(pubkey: PublicKey, isSigner = false) {
+  return { pubkey, isSigner, isWritable: false };
+}
+
+// The signer must be the program's upgrade authority to initialize the
+// contract; it defaults to the Solana CLI's keypair
+function loadPayer(): Keypair {
+  const path =
+    process.env.KEYPAIR_PATH ?? `${os.homedir()}/.config/solana/id.json`;
+  const secretKey = JSON.parse(fs.readFileSync(path, "utf8"));
+  return Keypair.fromSecretKey(Uint8Array.from(secretKey));
+}
 
-  // Airdrop SOL to the payer
-  // await requestAndConfirmAirdrop(payer.publicKey);
+async function main() {
+  const payer = loadPayer();
 
   // Initialize Contract
   await initializeContract(payer);
@@ -63,97 +213,224 @@ async function main() {
   await grantMintPermission(payer, gameId, tokenUri);
   console.log("Mint permission granted!");
 
+  // Activate the game, which the first grant created inactive
+  await activateGame(payer, gameId);
+  console.log("Game activated!");
+
   // Mint a new token
-  await mintToken(payer, gameId);
+  const { tokenId, tokenAccount } = await mintToken(payer, gameId);
   console.log("Token minted!");
 
+  // Burn it again
+  await burnToken(payer, gameId, tokenId, tokenAccount);
+  console.log("Token burned!");
+
   // Fetch and print contract state
   await printContractState();
-
-  // Close connection
-  // connection.close();
-}
-
-async function requestAndConfirmAirdrop(publicKey: PublicKey) {
-  try {
-    const airdropSignature = await connection.requestAirdrop(
-      publicKey,
-      1e9 // 1 SOL
-    );
-    await connection.confirmTransaction(airdropSignature);
-  } catch (error) {
-    throw new Error(`Error requesting airdrop: ${error}`);
-  }
 }
 
-async function sendTransaction(
-  instruction: TransactionInstruction,
-  signers: Keypair[]
-) {
+async function sendTransaction(transaction: Transaction, signers: Keypair[]) {
   try {
-    const transaction = new Transaction().add(instruction);
-    // await sendAndConfirmTransaction(connection, transaction, signers);
+    await sendAndConfirmTransaction(connection, transaction, signers);
   } catch (error) {
-    throw new Error(`Error sending transaction: ${error}`);
+    const code = customErrorCode(error);
+    const description =
+      code === undefined ? undefined : describeCustomError(code);
+    throw new Error(`Error sending transaction: ${description ?? error}`);
   }
 }
 
+// Accounts as listed in the program's `initialize_contract`
 async function initializeContract(payer: Keypair) {
-  const initializeContractData = Buffer.from(
-    Uint8Array.of(0, ...payer.publicKey.toBytes())
-  );
+  const programData = PublicKey.findProgramAddressSync(
+    [PROGRAM_ID.toBuffer()],
+    BPF_LOADER_UPGRADEABLE_ID
+  )[0];
   const initializeContractIx = new TransactionInstruction({
-    keys: [{ pubkey: payer.publicKey, isSigner: true, isWritable: true }],
+    keys: [
+      writable(contractPda()),
+      readonly(SYSVAR_RENT_PUBKEY),
+      writable(payer.publicKey, true),
+      readonly(SystemProgram.programId),
+      readonly(programData),
+    ],
     programId: PROGRAM_ID,
-    data: initializeContractData,
+    // owner, then max_supply (0 for unlimited)
+    data: Buffer.concat([
+      Buffer.of(0),
+      payer.publicKey.toBuffer(),
+      encodeU64(0),
+    ]),
   });
-  await sendTransaction(initializeContractIx, [payer]);
+  await sendTransaction(new Transaction().add(initializeContractIx), [payer]);
 }
 
+// Accounts as listed in the program's `grant_mint`, with no grant cooldown set
 async function grantMintPermission(
   payer: Keypair,
   gameId: string,
   tokenUri: string
 ) {
-  const grantMintData = Buffer.concat([
-    Buffer.from(Uint8Array.of(1)),
-    payer.publicKey.toBuffer(),
-    Buffer.from(gameId),
-    Buffer.from(tokenUri),
-  ]);
   const grantMintIx = new TransactionInstruction({
-    keys: [{ pubkey: payer.publicKey, isSigner: true, isWritable: true }],
+    keys: [
+      writable(contractPda()),
+      writable(payer.publicKey, true),
+      writable(permissionPda(payer.publicKey, gameId)),
+      writable(gamePda(gameId)),
+      readonly(SystemProgram.programId),
+    ],
     programId: PROGRAM_ID,
-    data: grantMintData,
+    // user, game_id, token_uri, name (empty for the default), overwrite
+    data: Buffer.concat([
+      Buffer.of(1),
+      payer.publicKey.toBuffer(),
+      encodeString(gameId),
+      encodeString(tokenUri),
+      encodeString(""),
+      Buffer.of(0),
+    ]),
   });
-  await sendTransaction(grantMintIx, [payer]);
+  await sendTransaction(new Transaction().add(grantMintIx), [payer]);
 }
 
+// Accounts as listed in the program's `activate_game`
+async function activateGame(payer: Keypair, gameId: string) {
+  const activateGameIx = new TransactionInstruction({
+    keys: [
+      readonly(contractPda()),
+      readonly(payer.publicKey, true),
+      writable(gamePda(gameId)),
+    ],
+    programId: PROGRAM_ID,
+    data: Buffer.concat([Buffer.of(27), encodeString(gameId)]),
+  });
+  await sendTransaction(new Transaction().add(activateGameIx), [payer]);
+}
+
+// Mints the contract's next token to the payer, for a game without fees,
+// required tokens, a validator, receipts or referral rewards. Accounts as
+// listed in the program's `mint`
 async function mintToken(payer: Keypair, gameId: string) {
-  const mintData = Buffer.concat([
-    Buffer.from(Uint8Array.of(2)),
-    payer.publicKey.toBuffer(),
-    Buffer.from(gameId),
-  ]);
+  const contractInfo = await connection.getAccountInfo(contractPda());
+  const game = await connection.getAccountInfo(gamePda(gameId));
+  if (!contractInfo || !game) {
+    throw new Error("Contract or game account not found");
+  }
+  const tokenId = new ContractState(contractInfo.data).lastTokenId + 1;
+  // GameRecord starts with its game_id string, then token_count
+  const tokenCount = readU64(game.data, 4 + Buffer.byteLength(gameId));
+  const chunkIndex = Math.floor(tokenCount / GAME_TOKENS_PER_CHUNK);
+
+  const mint = mintPda(tokenId);
+  const receiver = payer.publicKey;
+  // the receiver token account only has to be allocated for the token
+  // program; the mint initializes it
+  const receiverToken = Keypair.generate();
+  const createReceiverTokenIx = SystemProgram.createAccount({
+    fromPubkey: payer.publicKey,
+    newAccountPubkey: receiverToken.publicKey,
+    lamports:
+      await connection.getMinimumBalanceForRentExemption(TOKEN_ACCOUNT_LEN),
+    space: TOKEN_ACCOUNT_LEN,
+    programId: TOKEN_PROGRAM_ID,
+  });
   const mintIx = new TransactionInstruction({
-    keys: [{ pubkey: payer.publicKey, isSigner: true, isWritable: true }],
+    keys: [
+      writable(contractPda()),
+      writable(mint),
+      writable(receiverToken.publicKey),
+      writable(permissionPda(payer.publicKey, gameId)),
+      writable(payer.publicKey, true),
+      readonly(pda([Buffer.from("mint_authority"), mint.toBuffer()])),
+      writable(gamePda(gameId)),
+      writable(
+        pda([
+          Buffer.from("game_tokens"),
+          Buffer.from(gameId),
+          encodeU32(chunkIndex),
+        ])
+      ),
+      writable(pda([Buffer.from("token"), encodeU64(tokenId)])),
+      writable(pda([Buffer.from("metadata"), mint.toBuffer()])),
+      writable(pda([Buffer.from("uri"), encodeU64(tokenId)])),
+      readonly(pda([Buffer.from("template"), Buffer.from(gameId)])),
+      readonly(TOKEN_PROGRAM_ID),
+      readonly(SystemProgram.programId),
+      readonly(pda([Buffer.from("kyc"), receiver.toBuffer()])),
+      writable(
+        pda([Buffer.from("holding"), Buffer.from(gameId), receiver.toBuffer()])
+      ),
+      writable(pda([Buffer.from("history"), encodeU64(tokenId), encodeU32(0)])),
+    ],
+    programId: PROGRAM_ID,
+    // receiver, game_id
+    data: Buffer.concat([
+      Buffer.of(2),
+      receiver.toBuffer(),
+      encodeString(gameId),
+    ]),
+  });
+  await sendTransaction(
+    new Transaction().add(createReceiverTokenIx, mintIx),
+    [payer, receiverToken]
+  );
+  return { tokenId, tokenAccount: receiverToken.publicKey };
+}
+
+// Burns the payer's token, for a game without a burn callback. `historyLen` is
+// the number of entries in the token's history, 1 right after its mint; the
+// Burn entry goes into the chunk after them. Accounts as listed in the
+// program's `burn`
+async function burnToken(
+  payer: Keypair,
+  gameId: string,
+  tokenId: number,
+  tokenAccount: PublicKey,
+  historyLen = 1
+) {
+  const owner = payer.publicKey;
+  const mint = mintPda(tokenId);
+  const historyChunk = Math.floor(historyLen / TOKEN_HISTORY_PER_CHUNK);
+  const burnIx = new TransactionInstruction({
+    keys: [
+      writable(owner, true),
+      writable(mint),
+      writable(tokenAccount),
+      writable(pda([Buffer.from("token"), encodeU64(tokenId)])),
+      writable(contractPda()),
+      writable(gamePda(gameId)),
+      writable(
+        pda([Buffer.from("holding"), Buffer.from(gameId), owner.toBuffer()])
+      ),
+      writable(pda([Buffer.from("metadata"), mint.toBuffer()])),
+      writable(pda([Buffer.from("uri"), encodeU64(tokenId)])),
+      readonly(TOKEN_PROGRAM_ID),
+      writable(
+        pda([
+          Buffer.from("history"),
+          encodeU64(tokenId),
+          encodeU32(historyChunk),
+        ])
+      ),
+      readonly(SystemProgram.programId),
+    ],
     programId: PROGRAM_ID,
-    data: mintData,
+    // token_id
+    data: Buffer.concat([Buffer.of(4), encodeU64(tokenId)]),
   });
-  await sendTransaction(mintIx, [payer]);
+  await sendTransaction(new Transaction().add(burnIx), [payer]);
 }
 
 async function printContractState() {
-  const accountInfo = await connection.getAccountInfo(PROGRAM_ID);
+  const accountInfo = await connection.getAccountInfo(contractPda());
   if (!accountInfo) {
     throw new Error("Contract account not found");
   }
-  // const contractState = borsh.deserialize(
-  //   ContractStateSchema,
-  //   ContractState,
-  //   accountInfo.data,
-  // );
-  console.log("Contract state:", accountInfo);
+  const contractState = new ContractState(accountInfo.data);
+  console.log("Contract state:", {
+    contractOwner: contractState.contractOwner.toBase58(),
+    lastTokenId: contractState.lastTokenId,
+  });
 }
 
 main().catch((err) => {
@@ -1,17 +1,25 @@
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
+    bpf_loader_upgradeable::{self, UpgradeableLoaderState},
     entrypoint,
     entrypoint::ProgramResult,
-    program::invoke,
+    instruction::{AccountMeta, Instruction},
+    msg,
+    program::{invoke, invoke_signed, set_return_data},
     program_error::ProgramError,
+    program_option::COption,
     program_pack::{IsInitialized, Pack, Sealed},
     pubkey::Pubkey,
-    sysvar::{rent::Rent, Sysvar},
+    system_instruction,
+    sysvar::{clock::Clock, rent::Rent, Sysvar},
 };
 
 use spl_token::{
-    instruction::{burn as spl_burn, initialize_account, initialize_mint, mint_to},
-    state::{Account, Mint},
+    instruction::{
+        approve, burn as spl_burn, freeze_account, initialize_account3, initialize_mint2, mint_to,
+        set_authority, AuthorityType,
+    },
+    state::{Account as TokenAccount, Mint},
 };
 
 use borsh::{BorshDeserialize, BorshSerialize};
@@ -23,6 +31,44 @@ pub enum CustomError {
     MintNotPermitted,
     NotTokenOwner,
     InvalidInstruction,
+    StateCorrupted,
+    PermissionCorrupted,
+    InvalidTreasury,
+    UriSchemeNotAllowed,
+    BurnNotQueued,
+    TokenBurned,
+    PriceBelowFloor,
+    MintingDisabled,
+    TransferApprovalRequired,
+    NotSnapshotHolder,
+    SnapshotRewardClaimed,
+    GrantExists,
+    InvalidTreasurySplit,
+    KycRequired,
+    RequiredTokenMissing,
+    NotAnNft,
+    GameNotActive,
+    InvalidRoyalty,
+    GrantCooldownActive,
+    MetadataRentTooHigh,
+    InvalidReceiver,
+    TokenProgramNotAllowed,
+    TokenExpired,
+    TokenNotExpired,
+    TransfersFrozen,
+    Soulbound,
+    InvalidReferrer,
+    UriTooLong,
+    InvalidDiscount,
+    SupplyExhausted,
+    InvalidPriceFeed,
+    StalePriceFeed,
+    TimelockRequired,
+    TimelockActive,
+    NoPendingChange,
+    TokensOutstanding,
+    AlreadyInitialized,
+    ContractPaused,
 }
 
 impl From<CustomError> for ProgramError {
@@ -31,38 +77,823 @@ impl From<CustomError> for ProgramError {
     }
 }
 
-//  the contract's state
+impl CustomError {
+    // Decodes the code of a `ProgramError::Custom` the program returned
+    pub fn from_u32(code: u32) -> Option<Self> {
+        Some(match code {
+            0 => Self::NotAdmin,
+            1 => Self::MintNotPermitted,
+            2 => Self::NotTokenOwner,
+            3 => Self::InvalidInstruction,
+            4 => Self::StateCorrupted,
+            5 => Self::PermissionCorrupted,
+            6 => Self::InvalidTreasury,
+            7 => Self::UriSchemeNotAllowed,
+            8 => Self::BurnNotQueued,
+            9 => Self::TokenBurned,
+            10 => Self::PriceBelowFloor,
+            11 => Self::MintingDisabled,
+            12 => Self::TransferApprovalRequired,
+            13 => Self::NotSnapshotHolder,
+            14 => Self::SnapshotRewardClaimed,
+            15 => Self::GrantExists,
+            16 => Self::InvalidTreasurySplit,
+            17 => Self::KycRequired,
+            18 => Self::RequiredTokenMissing,
+            19 => Self::NotAnNft,
+            20 => Self::GameNotActive,
+            21 => Self::InvalidRoyalty,
+            22 => Self::GrantCooldownActive,
+            23 => Self::MetadataRentTooHigh,
+            24 => Self::InvalidReceiver,
+            25 => Self::TokenProgramNotAllowed,
+            26 => Self::TokenExpired,
+            27 => Self::TokenNotExpired,
+            28 => Self::TransfersFrozen,
+            29 => Self::Soulbound,
+            30 => Self::InvalidReferrer,
+            31 => Self::UriTooLong,
+            32 => Self::InvalidDiscount,
+            33 => Self::SupplyExhausted,
+            34 => Self::InvalidPriceFeed,
+            35 => Self::StalePriceFeed,
+            36 => Self::TimelockRequired,
+            37 => Self::TimelockActive,
+            38 => Self::NoPendingChange,
+            39 => Self::TokensOutstanding,
+            40 => Self::AlreadyInitialized,
+            41 => Self::ContractPaused,
+            _ => return None,
+        })
+    }
+}
+
+impl std::fmt::Display for CustomError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::NotAdmin => "caller is not the contract admin",
+            Self::MintNotPermitted => "minter has no permission for this game",
+            Self::NotTokenOwner => "signer does not own the token",
+            Self::InvalidInstruction => "instruction data or accounts are invalid",
+            Self::StateCorrupted => "account state could not be decoded",
+            Self::PermissionCorrupted => "mint permission could not be decoded",
+            Self::InvalidTreasury => "account is not the contract treasury",
+            Self::UriSchemeNotAllowed => "token URI scheme is not allowed",
+            Self::BurnNotQueued => "token is not queued for burning",
+            Self::TokenBurned => "token has been burned",
+            Self::PriceBelowFloor => "price is below the game's floor",
+            Self::MintingDisabled => "minting is disabled",
+            Self::TransferApprovalRequired => "transfer needs the admin's approval",
+            Self::NotSnapshotHolder => "owner held no tokens at the snapshot",
+            Self::SnapshotRewardClaimed => "snapshot reward already claimed",
+            Self::GrantExists => "a mint permission already exists",
+            Self::InvalidTreasurySplit => "treasury split shares are invalid",
+            Self::KycRequired => "receiver has no valid KYC attestation",
+            Self::RequiredTokenMissing => "receiver does not hold the game's required token",
+            Self::NotAnNft => "mint is not a 0-decimal, supply-1 token",
+            Self::GameNotActive => "game is not active",
+            Self::InvalidRoyalty => "royalty is above 100%",
+            Self::GrantCooldownActive => "user's grant cooldown has not passed",
+            Self::MetadataRentTooHigh => "token metadata rent is above the contract's limit",
+            Self::InvalidReceiver => "receiver is one of the contract's own accounts",
+            Self::TokenProgramNotAllowed => "token program is not allowed",
+            Self::TokenExpired => "token has expired",
+            Self::TokenNotExpired => "token has not expired yet",
+            Self::TransfersFrozen => "transfers are frozen",
+            Self::Soulbound => "token is soulbound",
+            Self::InvalidReferrer => "referrer is the minter or the receiver",
+            Self::UriTooLong => "token URI is too long",
+            Self::InvalidDiscount => "discount is above 100%",
+            Self::SupplyExhausted => "supply is exhausted",
+            Self::InvalidPriceFeed => "price feed is missing or invalid",
+            Self::StalePriceFeed => "price feed quote is too old",
+            Self::TimelockRequired => "change has to go through the timelock",
+            Self::TimelockActive => "pending change's timelock has not passed",
+            Self::NoPendingChange => "no change is pending",
+            Self::TokensOutstanding => "contract still has live tokens",
+            Self::AlreadyInitialized => "contract is already initialized",
+            Self::ContractPaused => "contract is paused",
+        })
+    }
+}
+
+//  the contract's state, kept in the contract PDA
 #[derive(BorshSerialize, BorshDeserialize, Debug)]
 pub struct ContractState {
     pub contract_owner: Pubkey,
     pub last_token_id: u64,
+    // receives mint fees; set to the owner at initialization
+    pub treasury: Pubkey,
+    // bitmask of URI_SCHEME_* flags token URIs must use; 0 allows any scheme
+    pub allowed_uri_schemes: u8,
+    // tokens waiting in the burn queue
+    pub queued_burns: u64,
+    // whether new mints are accepted; transfers and burns ignore it
+    pub minting_enabled: bool,
+    // transfers of tokens valued above this need the contract owner's co-signature; 0 disables
+    pub transfer_approval_threshold: u64,
+    // mint fee recipients, summing to MAX_BPS; empty sends everything to `treasury`
+    pub treasury_split: Vec<TreasuryShare>,
+    // when on, mint and transfer receivers need a valid attestation from `kyc_authority`
+    pub kyc_required: bool,
+    pub kyc_authority: Pubkey,
+    // royalty recorded on tokens of games that don't configure their own
+    pub default_royalty_bps: u16,
+    pub default_royalty_recipient: Pubkey,
+    // slots that must pass between two grants to the same user; 0 disables
+    pub grant_cooldown_slots: u64,
+    // most lamports a mint may lock up as rent for the token's metadata and
+    // URI accounts, see `metadata_rent`. Those accounts have a fixed size, so
+    // this bounds rent parameter changes rather than any one token; 0 disables
+    pub max_metadata_rent: u64,
+    // token programs the contract CPIs into; initialized to classic SPL Token
+    pub allowed_token_programs: Vec<Pubkey>,
+    // mint fees charged so far, adding up the prices paid whatever
+    // it is priced in
+    pub total_fees_collected: u64,
+    // transfers are rejected before this slot, e.g. while a snapshot is
+    // taken; minting and burning carry on. 0 means no freeze
+    pub freeze_transfers_until_slot: u64,
+    // slots a proposed change must wait before ExecuteChange applies it; while
+    // non-zero, the changes `ProposedChange` covers can only go through it
+    pub timelock_slots: u64,
+    // tokens minted and not yet burned, receipts included
+    pub active_tokens: u64,
+    // mint permissions ever granted; overwriting a grant doesn't count
+    pub total_grants: u64,
+    // most token ids the contract will ever assign, receipts included; 0 is
+    // unlimited. Set at initialization only. Adding it grew the state, so
+    // accounts sized for the older layout fail to load and have to be
+    // re-created at ContractState::LEN and initialized again
+    pub max_supply: u64,
+    // set by InitializeContract, which refuses to run again once it is
+    pub is_initialized: bool,
+    // emergency stop set by SetPaused: mints and transfers are rejected,
+    // burns and the owner's admin instructions carry on
+    pub paused: bool,
+}
+
+impl ContractState {
+    pub const LEN: usize = 32
+        + 8
+        + 32
+        + 1
+        + 8
+        + 1
+        + 8
+        + 4
+        + TreasuryShare::LEN * MAX_TREASURY_RECIPIENTS
+        + 1
+        + 32
+        + 2
+        + 32
+        + 8
+        + 8
+        + 4
+        + 32 * MAX_TOKEN_PROGRAMS
+        + 8
+        + 8
+        + 8
+        + 8
+        + 8
+        + 8
+        + 1
+        + 1;
+}
+
+//  the contract statistics GetDashboard returns in one call
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct Dashboard {
+    pub last_token_id: u64,
+    pub active_tokens: u64,
+    pub total_grants: u64,
+    pub total_fees_collected: u64,
+    // set by SetPaused; a FreezeTransfersUntil window is reported separately
+    pub paused: bool,
+    pub minting_enabled: bool,
+    // the slot transfers are frozen until, 0 when no freeze was set
+    pub freeze_transfers_until_slot: u64,
+}
+
+//  one recipient's share of mint fees, in basis points
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct TreasuryShare {
+    pub recipient: Pubkey,
+    pub bps: u16,
+}
+
+impl TreasuryShare {
+    pub const LEN: usize = 32 + 2;
 }
 
 impl Sealed for ContractState {}
 
 impl IsInitialized for ContractState {
     fn is_initialized(&self) -> bool {
-        true
+        self.is_initialized
     }
 }
 
-//  the mint permission structure
-#[derive(BorshSerialize, BorshDeserialize, Debug)]
+// PDA seeds
+pub const PERMISSION_SEED: &[u8] = b"permission";
+pub const GAME_SEED: &[u8] = b"game";
+pub const GAME_TOKENS_SEED: &[u8] = b"game_tokens";
+pub const MINT_AUTHORITY_SEED: &[u8] = b"mint_authority";
+pub const MINT_SEED: &[u8] = b"mint";
+pub const TOKEN_SEED: &[u8] = b"token";
+pub const METADATA_SEED: &[u8] = b"metadata";
+pub const CONTRACT_SEED: &[u8] = b"contract";
+pub const URI_SEED: &[u8] = b"uri";
+pub const SNAPSHOT_SEED: &[u8] = b"snapshot";
+pub const TEMPLATE_SEED: &[u8] = b"template";
+pub const KYC_SEED: &[u8] = b"kyc";
+pub const USER_GRANT_SEED: &[u8] = b"user_grant";
+pub const HOLDING_SEED: &[u8] = b"holding";
+pub const REFERRAL_SEED: &[u8] = b"referral";
+pub const HISTORY_SEED: &[u8] = b"history";
+pub const PENDING_CHANGE_SEED: &[u8] = b"pending_change";
+
+// `game_id` is used as a PDA seed, so it can't exceed the 32-byte seed limit
+pub const MAX_GAME_ID_LEN: usize = 32;
+pub const MAX_TOKEN_URI_LEN: usize = 200;
+pub const MAX_TOKEN_NAME_LEN: usize = 32;
+pub const MAX_TOKEN_SYMBOL_LEN: usize = 10;
+// Most decimal digits a token id renders to, and the longest ` #<token_id>`
+// suffix a templated name gets
+const TOKEN_ID_DIGITS: usize = 20;
+// replaced by the token id in a game's template URI
+pub const TOKEN_ID_PLACEHOLDER: &str = "{id}";
+const TOKEN_ID_SUFFIX_LEN: usize = 2 + TOKEN_ID_DIGITS;
+
+// Token URI schemes, combined into `ContractState::allowed_uri_schemes`
+pub const URI_SCHEME_IPFS: u8 = 1 << 0;
+pub const URI_SCHEME_ARWEAVE: u8 = 1 << 1;
+pub const URI_SCHEME_HTTPS: u8 = 1 << 2;
+const URI_SCHEMES: [(u8, &str); 3] = [
+    (URI_SCHEME_IPFS, "ipfs://"),
+    (URI_SCHEME_ARWEAVE, "ar://"),
+    (URI_SCHEME_HTTPS, "https://"),
+];
+
+// IsBurned return byte for an id that was never minted (0 and 1 are the bool)
+pub const TOKEN_NOT_MINTED: u8 = 2;
+
+// Indexers can follow tokens through program logs: every mint, transfer and
+// burn logs one line of `:`-separated fields after this prefix,
+//   COC_EVENT:MINT:<token_id>:<receiver>:<game_id>
+//   COC_EVENT:TRANSFER:<token_id>:<from>:<to>
+//   COC_EVENT:BURN:<token_id>
+// with pubkeys in base58. game_id comes last as it may itself contain `:`.
+pub const EVENT_LOG_PREFIX: &str = "COC_EVENT";
+
+// Basis points in a whole, and the most recipients a treasury split can have
+pub const MAX_BPS: u16 = 10_000;
+pub const MAX_TREASURY_RECIPIENTS: usize = 4;
+
+// Most entries `ContractState::allowed_token_programs` holds: classic SPL
+// Token and Token-2022
+pub const MAX_TOKEN_PROGRAMS: usize = 2;
+pub const TOKEN_2022_PROGRAM_ID: Pubkey =
+    solana_program::pubkey!("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb");
+
+// Tokens listed per ExportState page: as many as fit in return data next to
+// the largest contract state
+pub const EXPORT_TOKENS_PER_PAGE: u64 =
+    ((solana_program::program::MAX_RETURN_DATA - ContractState::LEN - 32 - 4 - 1)
+        / ExportedToken::LEN) as u64;
+
+// Most ids one PeekBatchIds call returns, keeping its Borsh Vec<u64> within
+// return data limits
+pub const MAX_PEEK_BATCH_IDS: u64 = ((solana_program::program::MAX_RETURN_DATA - 4) / 8) as u64;
+
+// Most grants a single BatchGrant writes
+pub const MAX_BATCH_GRANTS: usize = 10;
+
+// Most tokens one VerifyBundleOwnership checks, one bit each of its u64 result
+pub const MAX_BUNDLE_TOKENS: usize = 64;
+
+// Most records one GetTokenRecords returns, keeping its Borsh
+// Vec<Option<TokenRecord>> within return data limits
+pub const MAX_TOKEN_RECORDS_PAGE: usize =
+    (solana_program::program::MAX_RETURN_DATA - 4) / (1 + TokenRecord::LEN);
+
+// Most URIs a single BatchUpdateUris rewrites
+pub const MAX_BATCH_URI_UPDATES: usize = 10;
+
+// Most holdings a single snapshot can hold
+pub const MAX_SNAPSHOT_HOLDERS: usize = 64;
+
+// Most queued burns a single ProcessBurnQueue executes
+pub const MAX_BURN_BATCH: usize = 16;
+
+// Most tokens a single BatchMint mints
+pub const MAX_BATCH_MINT: u16 = 64;
+
+// Oldest price feed quote, in slots, a USD-priced mint accepts
+pub const MAX_PRICE_FEED_AGE_SLOTS: u64 = 50;
+
+// Lamports in one SOL and cents in one USD, as a power of ten
+const LAMPORTS_PER_CENT_EXPO: i64 = 9 - 2;
+
+// Token ids per game token-list chunk, and the most ids a single page returns
+pub const GAME_TOKENS_PER_CHUNK: u64 = 32;
+pub const MAX_GAME_TOKENS_PAGE: u64 = 64;
+
+// History entries per token history chunk, and the most a single page returns
+pub const TOKEN_HISTORY_PER_CHUNK: u64 = 8;
+pub const MAX_TOKEN_HISTORY_PAGE: u64 =
+    ((solana_program::program::MAX_RETURN_DATA - 4) / HistoryEntry::LEN) as u64;
+
+//  the mint permission structure, stored in a PDA derived from
+//  [PERMISSION_SEED, user, game_id]
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
 pub struct MintPermission {
     pub user: Pubkey,
     pub game_id: String,
+    // empty to use the game's metadata template URI
     pub token_uri: String,
+    // overrides the game's templated name; empty to use the template
+    pub name: String,
+    // tokens minted under this permission; kept across overwriting grants
+    pub minted: u64,
+}
+
+impl MintPermission {
+    pub const LEN: usize =
+        32 + 4 + MAX_GAME_ID_LEN + 4 + MAX_TOKEN_URI_LEN + 4 + MAX_TOKEN_NAME_LEN + 8;
+}
+
+//  per-game metadata defaults, stored in a PDA derived from [TEMPLATE_SEED, game_id].
+//  Minted tokens are named `<name_template> #<token_id>` and, unless their
+//  permission carries a URI, get `<uri_prefix><token_id>` as their URI, or
+//  `uri_prefix` with each `{id}` replaced by the token id when it has one.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Default, Clone, PartialEq)]
+pub struct GameMetadataTemplate {
+    pub name_template: String,
+    pub symbol: String,
+    pub uri_prefix: String,
+}
+
+impl GameMetadataTemplate {
+    pub const LEN: usize =
+        4 + MAX_TOKEN_NAME_LEN + 4 + MAX_TOKEN_SYMBOL_LEN + 4 + MAX_TOKEN_URI_LEN;
+}
+
+//  admin-settable per-game configuration
+#[derive(BorshSerialize, BorshDeserialize, Debug, Default, Clone, PartialEq)]
+pub struct GameConfig {
+    // SPL mint the mint price is paid in; the default pubkey means lamports
+    pub price_mint: Pubkey,
+    // mint price per token, in `price_mint` base units (or lamports); 0 is free
+    pub price_amount: u64,
+    // lowest lamport price TransferWithPayment accepts; 0 disables the floor
+    pub min_sale_price: u64,
+    // mint receivers must hold at least one token of this mint; the default
+    // pubkey disables the requirement
+    pub required_token_mint: Pubkey,
+    // secondary-sale royalty recorded in token metadata; the default recipient
+    // falls back to the contract's default royalty
+    pub royalty_bps: u16,
+    pub royalty_recipient: Pubkey,
+    // program invoked with a `MintValidation` before each mint, which fails
+    // the mint by failing; the default pubkey disables the hook
+    pub validator_program: Pubkey,
+    // every Nth token of the game is minted to the contract's treasury rather
+    // than the receiver; 0 disables
+    pub reserve_every_n: u64,
+    // slots a token stays usable after its mint, after which BurnExpired can
+    // burn it; 0 mints tokens that never expire
+    pub token_lifetime_slots: u64,
+    // tokens minted while set are soulbound: they can be burned but never
+    // transferred
+    pub soulbound: bool,
+    // lamports the treasury pays a mint's referrer, if one is passed; 0
+    // disables referrals
+    pub referral_reward_lamports: u64,
+    // most lamports a single referrer can earn from the game; 0 is uncapped
+    pub referral_reward_cap: u64,
+    // every mint also mints the minter a soulbound receipt token, with the
+    // next token id; receipts stay out of the game's token list and holders
+    pub mint_receipt: bool,
+    // root of the off-chain whitelist tree for the game's drop, set with
+    // SetMerkleRoot; all zeroes when unset
+    pub merkle_root: [u8; 32],
+    // transfers reset the token's `cooldown_ends_slot` to the transfer slot,
+    // so a new owner can use its ability straight away
+    pub reset_cooldown_on_transfer: bool,
+    // discount off `price_amount` for minters already holding one of the
+    // game's tokens, in basis points; 0 disables
+    pub holder_discount_bps: u16,
+    // most tokens the game can ever mint; 0 is uncapped
+    pub max_supply: u64,
+    // part of `max_supply` held back for later phases, released with
+    // ReleaseReserved; mints stop at `max_supply - reserved_supply`
+    pub reserved_supply: u64,
+    // mint price in US cents, replacing `price_amount` with the lamports it
+    // buys at the `price_feed` rate; lamport pricing only, 0 disables
+    pub price_usd_cents: u64,
+    // the `PriceFeed` account quoting SOL in USD for `price_usd_cents`
+    pub price_feed: Pubkey,
+    // program invoked with a `BurnNotification` after each of the game's
+    // tokens burns, so the game can drop it from inventory; failing fails the
+    // burn. The default pubkey disables the callback
+    pub burn_callback_program: Pubkey,
+    // burns give their token back to `max_supply`, for re-mintable editions;
+    // off, the game's supply only ever shrinks
+    pub refill_on_burn: bool,
+}
+
+impl GameConfig {
+    pub const LEN: usize = 32
+        + 8
+        + 8
+        + 32
+        + 2
+        + 32
+        + 32
+        + 8
+        + 8
+        + 1
+        + 8
+        + 8
+        + 1
+        + 32
+        + 1
+        + 2
+        + 8
+        + 8
+        + 8
+        + 32
+        + 32
+        + 1;
+}
+
+//  the SOL/USD quote a game's `price_feed` account holds, kept up to date by
+//  the oracle: one SOL is worth `price * 10^expo` USD as of `publish_slot`
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct PriceFeed {
+    pub price: i64,
+    pub expo: i32,
+    pub publish_slot: u64,
+}
+
+//  the instruction data a game's validator program receives for a mint, with
+//  the game PDA and the minter (neither signing) as accounts
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct MintValidation {
+    pub game_id: String,
+    pub token_id: u64,
+    pub minter: Pubkey,
+    pub receiver: Pubkey,
+}
+
+//  the instruction data a game's burn callback program receives for a burn,
+//  with the game PDA (not signing) as its account
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct BurnNotification {
+    pub game_id: String,
+    pub token_id: u64,
+    pub former_owner: Pubkey,
+}
+
+//  per-game record, stored in a PDA derived from [GAME_SEED, game_id]
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+pub struct GameRecord {
+    pub game_id: String,
+    // number of token ids appended to the game's token list
+    pub token_count: u64,
+    pub config: GameConfig,
+    // set once by ActivateGame; mints are rejected until then
+    pub active: bool,
+    // wallets currently holding at least one of the game's tokens
+    pub holder_count: u64,
+    // tokens ever minted for the game, counted against `max_supply`
+    pub minted: u64,
+}
+
+impl GameRecord {
+    pub const LEN: usize = 4 + MAX_GAME_ID_LEN + 8 + GameConfig::LEN + 1 + 8 + 8;
+}
+
+//  how many of a game's tokens `holder` has, stored in a PDA derived from
+//  [HOLDING_SEED, game_id, holder]; backs the game's `holder_count`
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct GameHolding {
+    pub game_id: String,
+    pub holder: Pubkey,
+    pub count: u64,
+}
+
+impl GameHolding {
+    pub const LEN: usize = 4 + MAX_GAME_ID_LEN + 32 + 8;
+}
+
+//  referral rewards paid to `referrer` for a game's mints, stored in a PDA
+//  derived from [REFERRAL_SEED, game_id, referrer]; backs the reward cap
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct ReferralRecord {
+    pub game_id: String,
+    pub referrer: Pubkey,
+    pub rewards_paid: u64,
+}
+
+impl ReferralRecord {
+    pub const LEN: usize = 4 + MAX_GAME_ID_LEN + 32 + 8;
+}
+
+//  one chunk of a game's minted token ids, stored in a PDA derived from
+//  [GAME_TOKENS_SEED, game_id, chunk_index as u32 LE]
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+pub struct GameTokenChunk {
+    pub token_ids: Vec<u64>,
+}
+
+impl GameTokenChunk {
+    pub const LEN: usize = 4 + 8 * GAME_TOKENS_PER_CHUNK as usize;
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq)]
+pub enum HistoryEvent {
+    Mint,
+    Transfer,
+    Burn,
+}
+
+//  one event in a token's history; `from` is the default pubkey for mints and
+//  `to` for burns
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    pub event: HistoryEvent,
+    pub slot: u64,
+    pub from: Pubkey,
+    pub to: Pubkey,
+}
+
+impl HistoryEntry {
+    pub const LEN: usize = 1 + 8 + 32 + 32;
+}
+
+//  one chunk of a token's append-only history, stored in a PDA derived from
+//  [HISTORY_SEED, token_id as u64 LE, chunk_index as u32 LE]
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+pub struct TokenHistoryChunk {
+    pub entries: Vec<HistoryEntry>,
+}
+
+impl TokenHistoryChunk {
+    pub const LEN: usize = 4 + HistoryEntry::LEN * TOKEN_HISTORY_PER_CHUNK as usize;
+}
+
+//  one of the contract's PDAs, by the seeds it is derived from, e.g. for
+//  CloseContract to check each account it is asked to close
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub enum ProgramPda {
+    Permission { user: Pubkey, game_id: String },
+    Game { game_id: String },
+    GameTokens { game_id: String, chunk_index: u32 },
+    Template { game_id: String },
+    Holding { game_id: String, holder: Pubkey },
+    Referral { game_id: String, referrer: Pubkey },
+    TokenRecord { token_id: u64 },
+    TokenMetadata { token_id: u64 },
+    TokenUri { token_id: u64 },
+    TokenHistory { token_id: u64, chunk_index: u32 },
+    Kyc { wallet: Pubkey },
+    UserGrant { user: Pubkey },
+    Snapshot { snapshot_slot: u64 },
+    PendingChange,
+}
+
+impl ProgramPda {
+    pub fn address(&self, program_id: &Pubkey) -> Pubkey {
+        let find = |seeds: &[&[u8]]| Pubkey::find_program_address(seeds, program_id).0;
+        match self {
+            Self::Permission { user, game_id } => {
+                find(&[PERMISSION_SEED, user.as_ref(), game_id.as_bytes()])
+            }
+            Self::Game { game_id } => find(&[GAME_SEED, game_id.as_bytes()]),
+            Self::GameTokens {
+                game_id,
+                chunk_index,
+            } => game_tokens_chunk_address(program_id, game_id, *chunk_index).0,
+            Self::Template { game_id } => find(&[TEMPLATE_SEED, game_id.as_bytes()]),
+            Self::Holding { game_id, holder } => {
+                find(&[HOLDING_SEED, game_id.as_bytes(), holder.as_ref()])
+            }
+            Self::Referral { game_id, referrer } => {
+                find(&[REFERRAL_SEED, game_id.as_bytes(), referrer.as_ref()])
+            }
+            Self::TokenRecord { token_id } => token_record_address(program_id, *token_id).0,
+            Self::TokenMetadata { token_id } => {
+                let mint = token_mint_address(program_id, *token_id).0;
+                find(&[METADATA_SEED, mint.as_ref()])
+            }
+            Self::TokenUri { token_id } => token_uri_address(program_id, *token_id).0,
+            Self::TokenHistory {
+                token_id,
+                chunk_index,
+            } => token_history_chunk_address(program_id, *token_id, *chunk_index).0,
+            Self::Kyc { wallet } => find(&[KYC_SEED, wallet.as_ref()]),
+            Self::UserGrant { user } => find(&[USER_GRANT_SEED, user.as_ref()]),
+            Self::Snapshot { snapshot_slot } => {
+                find(&[SNAPSHOT_SEED, &snapshot_slot.to_le_bytes()])
+            }
+            Self::PendingChange => pending_change_address(program_id).0,
+        }
+    }
+}
+
+//  a privileged change that can be put behind the contract's timelock: who
+//  owns the contract, where mint fees go and who may attest KYC. Mint
+//  permissions stay instant so a leaked minter key can be revoked at once,
+//  and fees are paid straight to the treasury, so there is nothing to withdraw
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub enum ProposedChange {
+    TransferOwnership { new_owner: Pubkey },
+    SetTimelock { slots: u64 },
+    SetTreasurySplit { recipients: Vec<TreasuryShare> },
+    SetKycGate { required: bool, authority: Pubkey },
+}
+
+//  the contract's one pending change, stored in a PDA derived from
+//  [PENDING_CHANGE_SEED, contract PDA] until ExecuteChange applies it
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct PendingChange {
+    pub change: ProposedChange,
+    pub execute_after_slot: u64,
+}
+
+impl PendingChange {
+    // Sized for the largest change, a full treasury split
+    pub const LEN: usize = 1 + 4 + TreasuryShare::LEN * MAX_TREASURY_RECIPIENTS + 8;
+}
+
+//  per-token record, stored in a PDA derived from [TOKEN_SEED, token_id as u64 LE],
+//  see `token_record_address`
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct TokenRecord {
+    // layout version, bumped whenever the fields after it change so readers
+    // can tell layouts apart; TOKEN_RECORD_VERSION for new records
+    pub version: u8,
+    pub token_id: u64,
+    pub owner: Pubkey,
+    pub game_id: String,
+    pub mint: Pubkey,
+    // set by EnqueueBurn, cleared once ProcessBurnQueue burns the token
+    pub burn_queued: bool,
+    pub burned: bool,
+    // the token's URI PDA, holding the URI the granting permission or the
+    // game's template gave it; see `token_uri_address`
+    pub uri_account: Pubkey,
+    // the mint price paid, then the last TransferWithPayment sale price
+    pub value: u64,
+    // first slot the token is expired at; 0 means it never expires
+    pub expires_at_slot: u64,
+    // copied from the game's config at mint; soulbound tokens can't be
+    // transferred
+    pub soulbound: bool,
+    // a mint receipt rather than a game asset; receipts have no metadata or
+    // URI PDA, so `uri_account` is the default pubkey
+    pub is_receipt: bool,
+    // slot the token's in-game ability cooldown ends at, kept for the game
+    pub cooldown_ends_slot: u64,
+    // entries in the token's history; the next lands in chunk
+    // `history_len / TOKEN_HISTORY_PER_CHUNK`
+    pub history_len: u64,
+}
+
+impl TokenRecord {
+    pub const LEN: usize =
+        1 + 8 + 32 + 4 + MAX_GAME_ID_LEN + 32 + 1 + 1 + 32 + 8 + 8 + 1 + 1 + 8 + 8;
+}
+
+pub const TOKEN_RECORD_VERSION: u8 = 1;
+
+//  one token's holder as of a snapshot
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct SnapshotHolding {
+    pub token_id: u64,
+    pub owner: Pubkey,
+    pub claimed: bool,
+}
+
+impl SnapshotHolding {
+    pub const LEN: usize = 8 + 32 + 1;
+}
+
+//  the holder set frozen at a slot, stored in a PDA derived from
+//  [SNAPSHOT_SEED, snapshot_slot as u64 LE]
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+pub struct Snapshot {
+    pub snapshot_slot: u64,
+    pub holdings: Vec<SnapshotHolding>,
+}
+
+impl Snapshot {
+    pub const LEN: usize = 8 + 4 + SnapshotHolding::LEN * MAX_SNAPSHOT_HOLDERS;
+}
+
+//  a KYC attestation for `subject`, stored in a PDA derived from
+//  [KYC_SEED, subject] and written by the attesting authority
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct KycAttestation {
+    pub subject: Pubkey,
+    pub authority: Pubkey,
+    pub valid: bool,
+}
+
+impl KycAttestation {
+    pub const LEN: usize = 32 + 32 + 1;
+}
+
+//  the slot of the latest grant to `user`, stored in a PDA derived from
+//  [USER_GRANT_SEED, user] while a grant cooldown is configured
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct UserGrantRecord {
+    pub user: Pubkey,
+    pub last_grant_slot: u64,
+}
+
+impl UserGrantRecord {
+    pub const LEN: usize = 32 + 8;
+}
+
+//  one page of ExportState output: the contract state plus the addresses of
+//  the PDAs backing a run of token ids
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+pub struct StateExport {
+    pub state: ContractState,
+    pub contract_pda: Pubkey,
+    pub tokens: Vec<ExportedToken>,
+    // whether later pages list more tokens
+    pub has_more: bool,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct ExportedToken {
+    pub token_id: u64,
+    pub record: Pubkey,
+    pub mint: Pubkey,
+    pub metadata: Pubkey,
+}
+
+impl ExportedToken {
+    pub const LEN: usize = 8 + 32 + 32 + 32;
+}
+
+//  per-token metadata, stored in a PDA derived from [METADATA_SEED, mint].
+//  The update authority is the contract PDA derived from [CONTRACT_SEED].
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct TokenMetadata {
+    pub mint: Pubkey,
+    pub update_authority: Pubkey,
+    // the token's URI PDA, holding the current URI
+    pub uri_account: Pubkey,
+    pub name: String,
+    pub symbol: String,
+    // royalty in basis points of sale price, paid to `royalty_recipient`
+    pub royalty_bps: u16,
+    pub royalty_recipient: Pubkey,
+}
+
+impl TokenMetadata {
+    pub const LEN: usize = 32
+        + 32
+        + 32
+        + 4
+        + MAX_TOKEN_NAME_LEN
+        + TOKEN_ID_SUFFIX_LEN
+        + 4
+        + MAX_TOKEN_SYMBOL_LEN
+        + 2
+        + 32;
+}
+
+//  a token's URI, stored apart from its permission and metadata in a PDA
+//  derived from [URI_SEED, token_id as u64 LE]
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct TokenUri {
+    pub token_id: u64,
+    pub uri: String,
+}
+
+impl TokenUri {
+    pub const LEN: usize = 8 + 4 + MAX_TOKEN_URI_LEN;
 }
 
 //  the instruction types
+#[derive(Debug, PartialEq)]
 pub enum ContractInstruction {
     InitializeContract {
         owner: Pubkey,
+        max_supply: u64,
     },
     GrantMint {
         user: Pubkey,
         game_id: String,
         token_uri: String,
+        name: String,
+        overwrite: bool,
     },
     Mint {
         receiver: Pubkey,
@@ -76,264 +907,13609 @@ pub enum ContractInstruction {
     Burn {
         token_id: u64,
     },
+    GetGameTokens {
+        game_id: String,
+        start: u64,
+        limit: u64,
+    },
+    SetGameConfig {
+        game_id: String,
+        config: Box<GameConfig>,
+    },
+    VerifyAuthentic {
+        token_id: u64,
+        mint: Pubkey,
+    },
+    SetAllowedUriSchemes {
+        schemes: u8,
+    },
+    UpdateTokenUri {
+        token_id: u64,
+        token_uri: String,
+    },
+    EnqueueBurn {
+        token_id: u64,
+    },
+    ProcessBurnQueue,
+    TransferWithPayment {
+        token_id: u64,
+        price: u64,
+    },
+    BatchGrant {
+        grants: Vec<MintPermission>,
+    },
+    SetMintingEnabled {
+        enabled: bool,
+    },
+    IsBurned {
+        token_id: u64,
+    },
+    SetTransferApprovalThreshold {
+        threshold: u64,
+    },
+    RecordSnapshot {
+        snapshot_slot: u64,
+    },
+    ClaimSnapshotReward {
+        token_id: u64,
+    },
+    SetGameMetadataTemplate {
+        game_id: String,
+        template: GameMetadataTemplate,
+    },
+    GetContractPda,
+    SetTreasurySplit {
+        recipients: Vec<TreasuryShare>,
+    },
+    DisableMinting {
+        token_id: u64,
+    },
+    SetKycGate {
+        required: bool,
+        authority: Pubkey,
+    },
+    AttestKyc {
+        subject: Pubkey,
+        valid: bool,
+    },
+    CompactGameTokens {
+        game_id: String,
+    },
+    ExportState {
+        page: u32,
+    },
+    ActivateGame {
+        game_id: String,
+    },
+    SetDefaultRoyalty {
+        bps: u16,
+        recipient: Pubkey,
+    },
+    SetGrantCooldown {
+        slots: u64,
+    },
+    GetHolderCount {
+        game_id: String,
+    },
+    SetMaxMetadataRent {
+        lamports: u64,
+    },
+    ReassignPermission {
+        game_id: String,
+        old_user: Pubkey,
+        new_user: Pubkey,
+    },
+    PeekBatchIds {
+        count: u64,
+    },
+    BatchUpdateUris {
+        updates: Vec<(u64, String)>,
+    },
+    SetAllowedTokenPrograms {
+        programs: Vec<Pubkey>,
+    },
+    GetTotalFees,
+    VerifyBundleOwnership {
+        token_ids: Vec<u64>,
+        owner: Pubkey,
+    },
+    BurnExpired {
+        token_id: u64,
+    },
+    FreezeTransfersUntil {
+        slot: u64,
+    },
+    GetGameConfig {
+        game_id: String,
+    },
+    RenameGame {
+        old_game_id: String,
+        new_game_id: String,
+    },
+    CanMint {
+        user: Pubkey,
+        game_id: String,
+        receiver: Pubkey,
+    },
+    SetMerkleRoot {
+        game_id: String,
+        root: [u8; 32],
+    },
+    GetMerkleRoot {
+        game_id: String,
+    },
+    GetTokenHistory {
+        token_id: u64,
+        start: u64,
+        limit: u64,
+    },
+    Bootstrap {
+        owner: Pubkey,
+        game_id: String,
+        token_uri: String,
+    },
+    ReleaseReserved {
+        game_id: String,
+        amount: u64,
+    },
+    RevokeMint {
+        user: Pubkey,
+        game_id: String,
+    },
+    TransferOwnership {
+        new_owner: Pubkey,
+    },
+    GetTokenRecords {
+        token_ids: Vec<u64>,
+    },
+    ProposeChange {
+        change: ProposedChange,
+    },
+    ExecuteChange,
+    GetDashboard,
+    BatchMint {
+        receiver: Pubkey,
+        game_id: String,
+        count: u16,
+    },
+    CloseContract {
+        force: bool,
+        pdas: Vec<ProgramPda>,
+    },
+    SetPaused {
+        paused: bool,
+    },
 }
 
 impl ContractInstruction {
+    // The inverse of `unpack`: every field is encoded Borsh-style, which is the
+    // layout `unpack` reads, strings included. GrantMint always carries its
+    // optional name and overwrite flag.
+    pub fn pack(&self) -> Vec<u8> {
+        let (variant, fields) = match self {
+            Self::InitializeContract { owner, max_supply } => (0, (owner, max_supply).try_to_vec()),
+            Self::GrantMint {
+                user,
+                game_id,
+                token_uri,
+                name,
+                overwrite,
+            } => (1, (user, game_id, token_uri, name, overwrite).try_to_vec()),
+            Self::Mint { receiver, game_id } => (2, (receiver, game_id).try_to_vec()),
+            Self::Transfer {
+                token_id,
+                owner,
+                receiver,
+            } => (3, (token_id, owner, receiver).try_to_vec()),
+            Self::Burn { token_id } => (4, token_id.try_to_vec()),
+            Self::GetGameTokens {
+                game_id,
+                start,
+                limit,
+            } => (5, (game_id, start, limit).try_to_vec()),
+            Self::SetGameConfig { game_id, config } => (6, (game_id, config).try_to_vec()),
+            Self::VerifyAuthentic { token_id, mint } => (7, (token_id, mint).try_to_vec()),
+            Self::SetAllowedUriSchemes { schemes } => (8, schemes.try_to_vec()),
+            Self::UpdateTokenUri {
+                token_id,
+                token_uri,
+            } => (9, (token_id, token_uri).try_to_vec()),
+            Self::EnqueueBurn { token_id } => (10, token_id.try_to_vec()),
+            Self::ProcessBurnQueue => (11, Ok(Vec::new())),
+            Self::TransferWithPayment { token_id, price } => (12, (token_id, price).try_to_vec()),
+            Self::BatchGrant { grants } => (13, grants.try_to_vec()),
+            Self::SetMintingEnabled { enabled } => (14, enabled.try_to_vec()),
+            Self::IsBurned { token_id } => (15, token_id.try_to_vec()),
+            Self::SetTransferApprovalThreshold { threshold } => (16, threshold.try_to_vec()),
+            Self::RecordSnapshot { snapshot_slot } => (17, snapshot_slot.try_to_vec()),
+            Self::ClaimSnapshotReward { token_id } => (18, token_id.try_to_vec()),
+            Self::SetGameMetadataTemplate { game_id, template } => {
+                (19, (game_id, template).try_to_vec())
+            }
+            Self::GetContractPda => (20, Ok(Vec::new())),
+            Self::SetTreasurySplit { recipients } => (21, recipients.try_to_vec()),
+            Self::DisableMinting { token_id } => (22, token_id.try_to_vec()),
+            Self::SetKycGate {
+                required,
+                authority,
+            } => (23, (required, authority).try_to_vec()),
+            Self::AttestKyc { subject, valid } => (24, (subject, valid).try_to_vec()),
+            Self::CompactGameTokens { game_id } => (25, game_id.try_to_vec()),
+            Self::ExportState { page } => (26, page.try_to_vec()),
+            Self::ActivateGame { game_id } => (27, game_id.try_to_vec()),
+            Self::SetDefaultRoyalty { bps, recipient } => (28, (bps, recipient).try_to_vec()),
+            Self::SetGrantCooldown { slots } => (29, slots.try_to_vec()),
+            Self::GetHolderCount { game_id } => (30, game_id.try_to_vec()),
+            Self::SetMaxMetadataRent { lamports } => (31, lamports.try_to_vec()),
+            Self::ReassignPermission {
+                game_id,
+                old_user,
+                new_user,
+            } => (32, (game_id, old_user, new_user).try_to_vec()),
+            Self::PeekBatchIds { count } => (33, count.try_to_vec()),
+            Self::BatchUpdateUris { updates } => (34, updates.try_to_vec()),
+            Self::SetAllowedTokenPrograms { programs } => (35, programs.try_to_vec()),
+            Self::GetTotalFees => (36, Ok(Vec::new())),
+            Self::VerifyBundleOwnership { token_ids, owner } => {
+                (37, (token_ids, owner).try_to_vec())
+            }
+            Self::BurnExpired { token_id } => (38, token_id.try_to_vec()),
+            Self::FreezeTransfersUntil { slot } => (39, slot.try_to_vec()),
+            Self::GetGameConfig { game_id } => (40, game_id.try_to_vec()),
+            Self::RenameGame {
+                old_game_id,
+                new_game_id,
+            } => (41, (old_game_id, new_game_id).try_to_vec()),
+            Self::CanMint {
+                user,
+                game_id,
+                receiver,
+            } => (42, (user, game_id, receiver).try_to_vec()),
+            Self::SetMerkleRoot { game_id, root } => (43, (game_id, root).try_to_vec()),
+            Self::GetMerkleRoot { game_id } => (44, game_id.try_to_vec()),
+            Self::GetTokenHistory {
+                token_id,
+                start,
+                limit,
+            } => (45, (token_id, start, limit).try_to_vec()),
+            Self::Bootstrap {
+                owner,
+                game_id,
+                token_uri,
+            } => (46, (owner, game_id, token_uri).try_to_vec()),
+            Self::ReleaseReserved { game_id, amount } => (47, (game_id, amount).try_to_vec()),
+            Self::RevokeMint { user, game_id } => (48, (user, game_id).try_to_vec()),
+            Self::TransferOwnership { new_owner } => (49, new_owner.try_to_vec()),
+            Self::GetTokenRecords { token_ids } => (50, token_ids.try_to_vec()),
+            Self::ProposeChange { change } => (51, change.try_to_vec()),
+            Self::ExecuteChange => (52, Ok(Vec::new())),
+            Self::GetDashboard => (53, Ok(Vec::new())),
+            Self::BatchMint {
+                receiver,
+                game_id,
+                count,
+            } => (54, (receiver, game_id, count).try_to_vec()),
+            Self::CloseContract { force, pdas } => (55, (force, pdas).try_to_vec()),
+            Self::SetPaused { paused } => (56, paused.try_to_vec()),
+        };
+        let mut data = vec![variant];
+        data.extend(fields.expect("serializing into a Vec can't fail"));
+        data
+    }
+
     pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
         let (&variant, rest) = input.split_first().ok_or(CustomError::InvalidInstruction)?;
-        Ok(match variant {
+        let (instruction, rest) = match variant {
             0 => {
-                let (owner, _) = Self::unpack_pubkey(rest)?;
-                Self::InitializeContract { owner }
+                let (owner, rest) = Self::unpack_pubkey(rest)?;
+                // the supply cap is optional and defaults to unlimited
+                let (max_supply, rest) = if rest.is_empty() {
+                    (0, rest)
+                } else {
+                    Self::unpack_u64(rest)?
+                };
+                (Self::InitializeContract { owner, max_supply }, rest)
             }
             1 => {
                 let (user, rest) = Self::unpack_pubkey(rest)?;
                 let (game_id, rest) = Self::unpack_string(rest)?;
-                let (token_uri, _) = Self::unpack_string(rest)?;
-                Self::GrantMint {
-                    user,
-                    game_id,
-                    token_uri,
-                }
+                let (token_uri, rest) = Self::unpack_string(rest)?;
+                // the name override and overwrite flag are optional, in that order
+                let (name, rest) = if rest.is_empty() {
+                    (String::new(), rest)
+                } else {
+                    Self::unpack_string(rest)?
+                };
+                let (overwrite, rest) = if rest.is_empty() {
+                    (false, rest)
+                } else {
+                    Self::unpack_bool(rest)?
+                };
+                (
+                    Self::GrantMint {
+                        user,
+                        game_id,
+                        token_uri,
+                        name,
+                        overwrite,
+                    },
+                    rest,
+                )
             }
             2 => {
                 let (receiver, rest) = Self::unpack_pubkey(rest)?;
-                let (game_id, _) = Self::unpack_string(rest)?;
-                Self::Mint { receiver, game_id }
+                let (game_id, rest) = Self::unpack_string(rest)?;
+                (Self::Mint { receiver, game_id }, rest)
             }
             3 => {
                 let (token_id, rest) = Self::unpack_u64(rest)?;
                 let (owner, rest) = Self::unpack_pubkey(rest)?;
-                let (receiver, _) = Self::unpack_pubkey(rest)?;
-                Self::Transfer {
-                    token_id,
-                    owner,
-                    receiver,
-                }
+                let (receiver, rest) = Self::unpack_pubkey(rest)?;
+                (
+                    Self::Transfer {
+                        token_id,
+                        owner,
+                        receiver,
+                    },
+                    rest,
+                )
             }
             4 => {
-                let (token_id, _) = Self::unpack_u64(rest)?;
-                Self::Burn { token_id }
+                let (token_id, rest) = Self::unpack_u64(rest)?;
+                (Self::Burn { token_id }, rest)
             }
-            _ => return Err(CustomError::InvalidInstruction.into()),
-        })
-    }
-
-    fn unpack_pubkey(input: &[u8]) -> Result<(Pubkey, &[u8]), ProgramError> {
-        if input.len() < 32 {
-            return Err(CustomError::InvalidInstruction.into());
-        }
-        let (key, rest) = input.split_at(32);
-        let pubkey = Pubkey::new_from_array(
-            key.try_into()
-                .map_err(|_| CustomError::InvalidInstruction)?,
-        );
-        Ok((pubkey, rest))
-    }
-
-    fn unpack_u64(input: &[u8]) -> Result<(u64, &[u8]), ProgramError> {
-        if input.len() < 8 {
-            return Err(CustomError::InvalidInstruction.into());
-        }
-        let (bytes, rest) = input.split_at(8);
-        let value = u64::from_le_bytes(bytes.try_into().unwrap());
+            5 => {
+                let (game_id, rest) = Self::unpack_string(rest)?;
+                let (start, rest) = Self::unpack_u64(rest)?;
+                let (limit, rest) = Self::unpack_u64(rest)?;
+                (
+                    Self::GetGameTokens {
+                        game_id,
+                        start,
+                        limit,
+                    },
+                    rest,
+                )
+            }
+            6 => {
+                let (game_id, mut rest) = Self::unpack_string(rest)?;
+                let config = GameConfig::deserialize(&mut rest)
+                    .map_err(|_| CustomError::InvalidInstruction)?;
+                (
+                    Self::SetGameConfig {
+                        game_id,
+                        config: Box::new(config),
+                    },
+                    rest,
+                )
+            }
+            7 => {
+                let (token_id, rest) = Self::unpack_u64(rest)?;
+                let (mint, rest) = Self::unpack_pubkey(rest)?;
+                (Self::VerifyAuthentic { token_id, mint }, rest)
+            }
+            8 => {
+                let (&schemes, rest) = rest.split_first().ok_or(CustomError::InvalidInstruction)?;
+                (Self::SetAllowedUriSchemes { schemes }, rest)
+            }
+            9 => {
+                let (token_id, rest) = Self::unpack_u64(rest)?;
+                let (token_uri, rest) = Self::unpack_string(rest)?;
+                (
+                    Self::UpdateTokenUri {
+                        token_id,
+                        token_uri,
+                    },
+                    rest,
+                )
+            }
+            10 => {
+                let (token_id, rest) = Self::unpack_u64(rest)?;
+                (Self::EnqueueBurn { token_id }, rest)
+            }
+            11 => (Self::ProcessBurnQueue, rest),
+            12 => {
+                let (token_id, rest) = Self::unpack_u64(rest)?;
+                let (price, rest) = Self::unpack_u64(rest)?;
+                (Self::TransferWithPayment { token_id, price }, rest)
+            }
+            13 => {
+                let mut rest = rest;
+                let grants = Vec::<MintPermission>::deserialize(&mut rest)
+                    .map_err(|_| CustomError::InvalidInstruction)?;
+                (Self::BatchGrant { grants }, rest)
+            }
+            14 => {
+                let (enabled, rest) = Self::unpack_bool(rest)?;
+                (Self::SetMintingEnabled { enabled }, rest)
+            }
+            15 => {
+                let (token_id, rest) = Self::unpack_u64(rest)?;
+                (Self::IsBurned { token_id }, rest)
+            }
+            16 => {
+                let (threshold, rest) = Self::unpack_u64(rest)?;
+                (Self::SetTransferApprovalThreshold { threshold }, rest)
+            }
+            17 => {
+                let (snapshot_slot, rest) = Self::unpack_u64(rest)?;
+                (Self::RecordSnapshot { snapshot_slot }, rest)
+            }
+            18 => {
+                let (token_id, rest) = Self::unpack_u64(rest)?;
+                (Self::ClaimSnapshotReward { token_id }, rest)
+            }
+            19 => {
+                let (game_id, mut rest) = Self::unpack_string(rest)?;
+                let template = GameMetadataTemplate::deserialize(&mut rest)
+                    .map_err(|_| CustomError::InvalidInstruction)?;
+                (Self::SetGameMetadataTemplate { game_id, template }, rest)
+            }
+            20 => (Self::GetContractPda, rest),
+            21 => {
+                let mut rest = rest;
+                let recipients = Vec::<TreasuryShare>::deserialize(&mut rest)
+                    .map_err(|_| CustomError::InvalidInstruction)?;
+                (Self::SetTreasurySplit { recipients }, rest)
+            }
+            22 => {
+                let (token_id, rest) = Self::unpack_u64(rest)?;
+                (Self::DisableMinting { token_id }, rest)
+            }
+            23 => {
+                let (required, rest) = Self::unpack_bool(rest)?;
+                let (authority, rest) = Self::unpack_pubkey(rest)?;
+                (
+                    Self::SetKycGate {
+                        required,
+                        authority,
+                    },
+                    rest,
+                )
+            }
+            24 => {
+                let (subject, rest) = Self::unpack_pubkey(rest)?;
+                let (valid, rest) = Self::unpack_bool(rest)?;
+                (Self::AttestKyc { subject, valid }, rest)
+            }
+            25 => {
+                let (game_id, rest) = Self::unpack_string(rest)?;
+                (Self::CompactGameTokens { game_id }, rest)
+            }
+            26 => {
+                let (page, rest) = Self::unpack_u32(rest)?;
+                (Self::ExportState { page }, rest)
+            }
+            27 => {
+                let (game_id, rest) = Self::unpack_string(rest)?;
+                (Self::ActivateGame { game_id }, rest)
+            }
+            28 => {
+                let (bps, rest) = Self::unpack_u16(rest)?;
+                let (recipient, rest) = Self::unpack_pubkey(rest)?;
+                (Self::SetDefaultRoyalty { bps, recipient }, rest)
+            }
+            29 => {
+                let (slots, rest) = Self::unpack_u64(rest)?;
+                (Self::SetGrantCooldown { slots }, rest)
+            }
+            30 => {
+                let (game_id, rest) = Self::unpack_string(rest)?;
+                (Self::GetHolderCount { game_id }, rest)
+            }
+            31 => {
+                let (lamports, rest) = Self::unpack_u64(rest)?;
+                (Self::SetMaxMetadataRent { lamports }, rest)
+            }
+            32 => {
+                let (game_id, rest) = Self::unpack_string(rest)?;
+                let (old_user, rest) = Self::unpack_pubkey(rest)?;
+                let (new_user, rest) = Self::unpack_pubkey(rest)?;
+                (
+                    Self::ReassignPermission {
+                        game_id,
+                        old_user,
+                        new_user,
+                    },
+                    rest,
+                )
+            }
+            33 => {
+                let (count, rest) = Self::unpack_u64(rest)?;
+                (Self::PeekBatchIds { count }, rest)
+            }
+            34 => {
+                let mut rest = rest;
+                let updates = Vec::<(u64, String)>::deserialize(&mut rest)
+                    .map_err(|_| CustomError::InvalidInstruction)?;
+                (Self::BatchUpdateUris { updates }, rest)
+            }
+            35 => {
+                let mut rest = rest;
+                let programs = Vec::<Pubkey>::deserialize(&mut rest)
+                    .map_err(|_| CustomError::InvalidInstruction)?;
+                (Self::SetAllowedTokenPrograms { programs }, rest)
+            }
+            36 => (Self::GetTotalFees, rest),
+            37 => {
+                let mut rest = rest;
+                let token_ids = Vec::<u64>::deserialize(&mut rest)
+                    .map_err(|_| CustomError::InvalidInstruction)?;
+                let (owner, rest) = Self::unpack_pubkey(rest)?;
+                (Self::VerifyBundleOwnership { token_ids, owner }, rest)
+            }
+            38 => {
+                let (token_id, rest) = Self::unpack_u64(rest)?;
+                (Self::BurnExpired { token_id }, rest)
+            }
+            39 => {
+                let (slot, rest) = Self::unpack_u64(rest)?;
+                (Self::FreezeTransfersUntil { slot }, rest)
+            }
+            40 => {
+                let (game_id, rest) = Self::unpack_string(rest)?;
+                (Self::GetGameConfig { game_id }, rest)
+            }
+            41 => {
+                let (old_game_id, rest) = Self::unpack_string(rest)?;
+                let (new_game_id, rest) = Self::unpack_string(rest)?;
+                (
+                    Self::RenameGame {
+                        old_game_id,
+                        new_game_id,
+                    },
+                    rest,
+                )
+            }
+            42 => {
+                let (user, rest) = Self::unpack_pubkey(rest)?;
+                let (game_id, rest) = Self::unpack_string(rest)?;
+                let (receiver, rest) = Self::unpack_pubkey(rest)?;
+                (
+                    Self::CanMint {
+                        user,
+                        game_id,
+                        receiver,
+                    },
+                    rest,
+                )
+            }
+            43 => {
+                let (game_id, rest) = Self::unpack_string(rest)?;
+                let (root, rest) = Self::unpack_bytes32(rest)?;
+                (Self::SetMerkleRoot { game_id, root }, rest)
+            }
+            44 => {
+                let (game_id, rest) = Self::unpack_string(rest)?;
+                (Self::GetMerkleRoot { game_id }, rest)
+            }
+            45 => {
+                let (token_id, rest) = Self::unpack_u64(rest)?;
+                let (start, rest) = Self::unpack_u64(rest)?;
+                let (limit, rest) = Self::unpack_u64(rest)?;
+                (
+                    Self::GetTokenHistory {
+                        token_id,
+                        start,
+                        limit,
+                    },
+                    rest,
+                )
+            }
+            46 => {
+                let (owner, rest) = Self::unpack_pubkey(rest)?;
+                let (game_id, rest) = Self::unpack_string(rest)?;
+                let (token_uri, rest) = Self::unpack_string(rest)?;
+                (
+                    Self::Bootstrap {
+                        owner,
+                        game_id,
+                        token_uri,
+                    },
+                    rest,
+                )
+            }
+            47 => {
+                let (game_id, rest) = Self::unpack_string(rest)?;
+                let (amount, rest) = Self::unpack_u64(rest)?;
+                (Self::ReleaseReserved { game_id, amount }, rest)
+            }
+            48 => {
+                let (user, rest) = Self::unpack_pubkey(rest)?;
+                let (game_id, rest) = Self::unpack_string(rest)?;
+                (Self::RevokeMint { user, game_id }, rest)
+            }
+            49 => {
+                let (new_owner, rest) = Self::unpack_pubkey(rest)?;
+                (Self::TransferOwnership { new_owner }, rest)
+            }
+            50 => {
+                let mut rest = rest;
+                let token_ids = Vec::<u64>::deserialize(&mut rest)
+                    .map_err(|_| CustomError::InvalidInstruction)?;
+                (Self::GetTokenRecords { token_ids }, rest)
+            }
+            51 => {
+                let mut rest = rest;
+                let change = ProposedChange::deserialize(&mut rest)
+                    .map_err(|_| CustomError::InvalidInstruction)?;
+                (Self::ProposeChange { change }, rest)
+            }
+            52 => (Self::ExecuteChange, rest),
+            53 => (Self::GetDashboard, rest),
+            54 => {
+                let (receiver, rest) = Self::unpack_pubkey(rest)?;
+                let (game_id, rest) = Self::unpack_string(rest)?;
+                let (count, rest) = Self::unpack_u16(rest)?;
+                (
+                    Self::BatchMint {
+                        receiver,
+                        game_id,
+                        count,
+                    },
+                    rest,
+                )
+            }
+            55 => {
+                let (force, mut rest) = Self::unpack_bool(rest)?;
+                // the PDAs to close are optional and default to none
+                let pdas = if rest.is_empty() {
+                    vec![]
+                } else {
+                    Vec::<ProgramPda>::deserialize(&mut rest)
+                        .map_err(|_| CustomError::InvalidInstruction)?
+                };
+                (Self::CloseContract { force, pdas }, rest)
+            }
+            56 => {
+                let (paused, rest) = Self::unpack_bool(rest)?;
+                (Self::SetPaused { paused }, rest)
+            }
+            _ => return Err(CustomError::InvalidInstruction.into()),
+        };
+        // Bytes left over mean the client encoded a different layout
+        if !rest.is_empty() {
+            return Err(CustomError::InvalidInstruction.into());
+        }
+
+        Ok(instruction)
+    }
+
+    // Flags are one byte, 0 or 1; anything else is rejected
+    fn unpack_bool(input: &[u8]) -> Result<(bool, &[u8]), ProgramError> {
+        let (&flag, rest) = input.split_first().ok_or(CustomError::InvalidInstruction)?;
+        let flag = match flag {
+            0 => false,
+            1 => true,
+            _ => return Err(CustomError::InvalidInstruction.into()),
+        };
+        Ok((flag, rest))
+    }
+
+    fn unpack_pubkey(input: &[u8]) -> Result<(Pubkey, &[u8]), ProgramError> {
+        if input.len() < 32 {
+            return Err(CustomError::InvalidInstruction.into());
+        }
+        let (key, rest) = input.split_at(32);
+        let pubkey = Pubkey::new_from_array(
+            key.try_into()
+                .map_err(|_| CustomError::InvalidInstruction)?,
+        );
+        Ok((pubkey, rest))
+    }
+
+    fn unpack_bytes32(input: &[u8]) -> Result<([u8; 32], &[u8]), ProgramError> {
+        if input.len() < 32 {
+            return Err(CustomError::InvalidInstruction.into());
+        }
+        let (bytes, rest) = input.split_at(32);
+        let bytes = bytes
+            .try_into()
+            .map_err(|_| CustomError::InvalidInstruction)?;
+        Ok((bytes, rest))
+    }
+
+    fn unpack_u64(input: &[u8]) -> Result<(u64, &[u8]), ProgramError> {
+        if input.len() < 8 {
+            return Err(CustomError::InvalidInstruction.into());
+        }
+        let (bytes, rest) = input.split_at(8);
+        let value = u64::from_le_bytes(
+            bytes
+                .try_into()
+                .map_err(|_| CustomError::InvalidInstruction)?,
+        );
+        Ok((value, rest))
+    }
+
+    fn unpack_u16(input: &[u8]) -> Result<(u16, &[u8]), ProgramError> {
+        if input.len() < 2 {
+            return Err(CustomError::InvalidInstruction.into());
+        }
+        let (bytes, rest) = input.split_at(2);
+        let value = u16::from_le_bytes(
+            bytes
+                .try_into()
+                .map_err(|_| CustomError::InvalidInstruction)?,
+        );
+        Ok((value, rest))
+    }
+
+    fn unpack_u32(input: &[u8]) -> Result<(u32, &[u8]), ProgramError> {
+        if input.len() < 4 {
+            return Err(CustomError::InvalidInstruction.into());
+        }
+        let (bytes, rest) = input.split_at(4);
+        let value = u32::from_le_bytes(
+            bytes
+                .try_into()
+                .map_err(|_| CustomError::InvalidInstruction)?,
+        );
         Ok((value, rest))
     }
 
-    fn unpack_string(input: &[u8]) -> Result<(String, &[u8]), ProgramError> {
-        let length = input.len();
-        let string = String::from_utf8(input[..length].to_vec())
-            .map_err(|_| CustomError::InvalidInstruction)?;
-        Ok((string, &input[length..]))
+    // Strings are encoded Borsh-style: a u32 LE byte length followed by the UTF-8 bytes
+    fn unpack_string(input: &[u8]) -> Result<(String, &[u8]), ProgramError> {
+        if input.len() < 4 {
+            return Err(CustomError::InvalidInstruction.into());
+        }
+        let (length, rest) = input.split_at(4);
+        let length = u32::from_le_bytes(
+            length
+                .try_into()
+                .map_err(|_| CustomError::InvalidInstruction)?,
+        ) as usize;
+        if rest.len() < length {
+            return Err(CustomError::InvalidInstruction.into());
+        }
+        let (bytes, rest) = rest.split_at(length);
+        let string =
+            String::from_utf8(bytes.to_vec()).map_err(|_| CustomError::InvalidInstruction)?;
+        Ok((string, rest))
+    }
+}
+
+// Client-side builders, one per instruction. Each derives the PDAs its
+// account list needs from `program_id`, in the order the handler's
+// `Accounts:` block lists them, and leaves out the accounts that are only
+// passed when optional game or contract settings are on.
+
+pub fn build_initialize_contract(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    owner: &Pubkey,
+    max_supply: u64,
+) -> Instruction {
+    let instruction = ContractInstruction::InitializeContract {
+        owner: *owner,
+        max_supply,
+    };
+    Instruction::new_with_bytes(
+        *program_id,
+        &instruction.pack(),
+        vec![
+            AccountMeta::new(*contract, false),
+            AccountMeta::new_readonly(solana_program::sysvar::rent::id(), false),
+            AccountMeta::new(*owner, true),
+            AccountMeta::new_readonly(solana_program::system_program::id(), false),
+            AccountMeta::new_readonly(program_data_address(program_id), false),
+        ],
+    )
+}
+
+// Without a grant cooldown set; append the user grant PDA for `user` when one is.
+#[allow(clippy::too_many_arguments)]
+pub fn build_grant_mint(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    admin: &Pubkey,
+    user: &Pubkey,
+    game_id: &str,
+    token_uri: &str,
+    name: &str,
+    overwrite: bool,
+) -> Instruction {
+    let (permission, _) = Pubkey::find_program_address(
+        &[PERMISSION_SEED, user.as_ref(), game_id.as_bytes()],
+        program_id,
+    );
+    let (game, _) = Pubkey::find_program_address(&[GAME_SEED, game_id.as_bytes()], program_id);
+    let instruction = ContractInstruction::GrantMint {
+        user: *user,
+        game_id: game_id.to_string(),
+        token_uri: token_uri.to_string(),
+        name: name.to_string(),
+        overwrite,
+    };
+    Instruction::new_with_bytes(
+        *program_id,
+        &instruction.pack(),
+        vec![
+            AccountMeta::new(*contract, false),
+            AccountMeta::new(*admin, true),
+            AccountMeta::new(permission, false),
+            AccountMeta::new(game, false),
+            AccountMeta::new_readonly(solana_program::system_program::id(), false),
+        ],
+    )
+}
+
+pub fn build_revoke_mint(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    admin: &Pubkey,
+    user: &Pubkey,
+    game_id: &str,
+) -> Instruction {
+    let (permission, _) = Pubkey::find_program_address(
+        &[PERMISSION_SEED, user.as_ref(), game_id.as_bytes()],
+        program_id,
+    );
+    let instruction = ContractInstruction::RevokeMint {
+        user: *user,
+        game_id: game_id.to_string(),
+    };
+    Instruction::new_with_bytes(
+        *program_id,
+        &instruction.pack(),
+        vec![
+            AccountMeta::new_readonly(*contract, false),
+            AccountMeta::new(*admin, true),
+            AccountMeta::new(permission, false),
+        ],
+    )
+}
+
+// `token_id` must be the contract's `last_token_id + 1` and `chunk_index` the
+// game token-list chunk it lands in; `receiver_token` is allocated for the
+// token program but not yet initialized.
+#[allow(clippy::too_many_arguments)]
+pub fn build_mint(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    minter: &Pubkey,
+    receiver: &Pubkey,
+    receiver_token: &Pubkey,
+    game_id: &str,
+    token_id: u64,
+    chunk_index: u32,
+) -> Instruction {
+    let (mint, _) = token_mint_address(program_id, token_id);
+    let (permission, _) = Pubkey::find_program_address(
+        &[PERMISSION_SEED, minter.as_ref(), game_id.as_bytes()],
+        program_id,
+    );
+    let (authority, _) =
+        Pubkey::find_program_address(&[MINT_AUTHORITY_SEED, mint.as_ref()], program_id);
+    let (game, _) = Pubkey::find_program_address(&[GAME_SEED, game_id.as_bytes()], program_id);
+    let (chunk, _) = game_tokens_chunk_address(program_id, game_id, chunk_index);
+    let (record, _) = token_record_address(program_id, token_id);
+    let (metadata, _) = Pubkey::find_program_address(&[METADATA_SEED, mint.as_ref()], program_id);
+    let (uri, _) = token_uri_address(program_id, token_id);
+    let (template, _) =
+        Pubkey::find_program_address(&[TEMPLATE_SEED, game_id.as_bytes()], program_id);
+    let (kyc, _) = Pubkey::find_program_address(&[KYC_SEED, receiver.as_ref()], program_id);
+    let (holding, _) = Pubkey::find_program_address(
+        &[HOLDING_SEED, game_id.as_bytes(), receiver.as_ref()],
+        program_id,
+    );
+    let (history, _) = token_history_chunk_address(program_id, token_id, 0);
+    let instruction = ContractInstruction::Mint {
+        receiver: *receiver,
+        game_id: game_id.to_string(),
+    };
+    Instruction::new_with_bytes(
+        *program_id,
+        &instruction.pack(),
+        vec![
+            AccountMeta::new(*contract, false),
+            AccountMeta::new(mint, false),
+            AccountMeta::new(*receiver_token, false),
+            AccountMeta::new(permission, false),
+            AccountMeta::new(*minter, true),
+            AccountMeta::new_readonly(authority, false),
+            AccountMeta::new(game, false),
+            AccountMeta::new(chunk, false),
+            AccountMeta::new(record, false),
+            AccountMeta::new(metadata, false),
+            AccountMeta::new(uri, false),
+            AccountMeta::new_readonly(template, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(solana_program::system_program::id(), false),
+            AccountMeta::new_readonly(kyc, false),
+            AccountMeta::new(holding, false),
+            AccountMeta::new(history, false),
+        ],
+    )
+}
+
+// `history_chunk_index` is the token history chunk the transfer is logged in.
+#[allow(clippy::too_many_arguments)]
+pub fn build_transfer(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    token_id: u64,
+    game_id: &str,
+    owner: &Pubkey,
+    owner_token: &Pubkey,
+    receiver: &Pubkey,
+    receiver_token: &Pubkey,
+    history_chunk_index: u32,
+) -> Instruction {
+    let (record, _) = token_record_address(program_id, token_id);
+    let (kyc, _) = Pubkey::find_program_address(&[KYC_SEED, receiver.as_ref()], program_id);
+    let (game, _) = Pubkey::find_program_address(&[GAME_SEED, game_id.as_bytes()], program_id);
+    let (owner_holding, _) = Pubkey::find_program_address(
+        &[HOLDING_SEED, game_id.as_bytes(), owner.as_ref()],
+        program_id,
+    );
+    let (receiver_holding, _) = Pubkey::find_program_address(
+        &[HOLDING_SEED, game_id.as_bytes(), receiver.as_ref()],
+        program_id,
+    );
+    let (history, _) = token_history_chunk_address(program_id, token_id, history_chunk_index);
+    let instruction = ContractInstruction::Transfer {
+        token_id,
+        owner: *owner,
+        receiver: *receiver,
+    };
+    Instruction::new_with_bytes(
+        *program_id,
+        &instruction.pack(),
+        vec![
+            AccountMeta::new_readonly(*contract, false),
+            AccountMeta::new(record, false),
+            AccountMeta::new(*owner_token, false),
+            AccountMeta::new(*receiver_token, false),
+            AccountMeta::new(*owner, true),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(kyc, false),
+            AccountMeta::new(game, false),
+            AccountMeta::new(owner_holding, false),
+            AccountMeta::new(receiver_holding, false),
+            AccountMeta::new_readonly(solana_program::system_program::id(), false),
+            AccountMeta::new(history, false),
+        ],
+    )
+}
+
+pub fn build_burn(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    token_id: u64,
+    game_id: &str,
+    owner: &Pubkey,
+    owner_token: &Pubkey,
+    history_chunk_index: u32,
+) -> Instruction {
+    let (mint, _) = token_mint_address(program_id, token_id);
+    let (record, _) = token_record_address(program_id, token_id);
+    let (game, _) = Pubkey::find_program_address(&[GAME_SEED, game_id.as_bytes()], program_id);
+    let (holding, _) = Pubkey::find_program_address(
+        &[HOLDING_SEED, game_id.as_bytes(), owner.as_ref()],
+        program_id,
+    );
+    let (metadata, _) = Pubkey::find_program_address(&[METADATA_SEED, mint.as_ref()], program_id);
+    let (uri, _) = token_uri_address(program_id, token_id);
+    let (history, _) = token_history_chunk_address(program_id, token_id, history_chunk_index);
+    Instruction::new_with_bytes(
+        *program_id,
+        &ContractInstruction::Burn { token_id }.pack(),
+        vec![
+            AccountMeta::new(*owner, true),
+            AccountMeta::new(mint, false),
+            AccountMeta::new(*owner_token, false),
+            AccountMeta::new(record, false),
+            AccountMeta::new(*contract, false),
+            AccountMeta::new(game, false),
+            AccountMeta::new(holding, false),
+            AccountMeta::new(metadata, false),
+            AccountMeta::new(uri, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new(history, false),
+            AccountMeta::new_readonly(solana_program::system_program::id(), false),
+        ],
+    )
+}
+
+// Mints one token per `(receiver_token, chunk_index)` in `tokens`, with ids
+// counting up from `first_token_id`; each entry is laid out as for
+// `build_mint`.
+pub fn build_batch_mint(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    minter: &Pubkey,
+    receiver: &Pubkey,
+    game_id: &str,
+    first_token_id: u64,
+    tokens: &[(Pubkey, u32)],
+) -> Instruction {
+    let accounts = tokens
+        .iter()
+        .zip(first_token_id..)
+        .flat_map(|(&(receiver_token, chunk_index), token_id)| {
+            build_mint(
+                program_id,
+                contract,
+                minter,
+                receiver,
+                &receiver_token,
+                game_id,
+                token_id,
+                chunk_index,
+            )
+            .accounts
+        })
+        .collect();
+    let instruction = ContractInstruction::BatchMint {
+        receiver: *receiver,
+        game_id: game_id.to_string(),
+        count: tokens.len() as u16,
+    };
+    Instruction::new_with_bytes(*program_id, &instruction.pack(), accounts)
+}
+
+pub fn build_transfer_ownership(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    admin: &Pubkey,
+    new_owner: &Pubkey,
+) -> Instruction {
+    let instruction = ContractInstruction::TransferOwnership {
+        new_owner: *new_owner,
+    };
+    Instruction::new_with_bytes(
+        *program_id,
+        &instruction.pack(),
+        vec![
+            AccountMeta::new(*contract, false),
+            AccountMeta::new_readonly(*admin, true),
+        ],
+    )
+}
+
+pub fn build_get_dashboard(program_id: &Pubkey, contract: &Pubkey) -> Instruction {
+    Instruction::new_with_bytes(
+        *program_id,
+        &ContractInstruction::GetDashboard.pack(),
+        vec![AccountMeta::new_readonly(*contract, false)],
+    )
+}
+pub fn build_get_game_tokens(
+    program_id: &Pubkey,
+    game_id: &str,
+    start: u64,
+    limit: u64,
+) -> Instruction {
+    let (game, _) = Pubkey::find_program_address(&[GAME_SEED, game_id.as_bytes()], program_id);
+    let end = start.saturating_add(limit.min(MAX_GAME_TOKENS_PAGE));
+    let mut accounts = vec![AccountMeta::new_readonly(game, false)];
+    if end > start {
+        accounts.extend(
+            (start / GAME_TOKENS_PER_CHUNK..=(end - 1) / GAME_TOKENS_PER_CHUNK).map(
+                |chunk_index| {
+                    let (chunk, _) =
+                        game_tokens_chunk_address(program_id, game_id, chunk_index as u32);
+                    AccountMeta::new_readonly(chunk, false)
+                },
+            ),
+        );
+    }
+    let instruction = ContractInstruction::GetGameTokens {
+        game_id: game_id.to_string(),
+        start,
+        limit,
+    };
+    Instruction::new_with_bytes(*program_id, &instruction.pack(), accounts)
+}
+
+pub fn build_set_game_config(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    admin: &Pubkey,
+    game_id: &str,
+    config: &GameConfig,
+) -> Instruction {
+    let (game, _) = Pubkey::find_program_address(&[GAME_SEED, game_id.as_bytes()], program_id);
+    let instruction = ContractInstruction::SetGameConfig {
+        game_id: game_id.to_string(),
+        config: Box::new(config.clone()),
+    };
+    Instruction::new_with_bytes(
+        *program_id,
+        &instruction.pack(),
+        vec![
+            AccountMeta::new_readonly(*contract, false),
+            AccountMeta::new(*admin, true),
+            AccountMeta::new(game, false),
+            AccountMeta::new_readonly(solana_program::system_program::id(), false),
+        ],
+    )
+}
+
+pub fn build_verify_authentic(program_id: &Pubkey, token_id: u64, mint: &Pubkey) -> Instruction {
+    let (record, _) = token_record_address(program_id, token_id);
+    let (metadata, _) = Pubkey::find_program_address(&[METADATA_SEED, mint.as_ref()], program_id);
+    Instruction::new_with_bytes(
+        *program_id,
+        &ContractInstruction::VerifyAuthentic {
+            token_id,
+            mint: *mint,
+        }
+        .pack(),
+        vec![
+            AccountMeta::new_readonly(record, false),
+            AccountMeta::new_readonly(metadata, false),
+        ],
+    )
+}
+
+// The contract-wide settings below all take the contract state account and
+// the admin, in that order.
+fn build_contract_setting(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    admin: &Pubkey,
+    instruction: ContractInstruction,
+) -> Instruction {
+    Instruction::new_with_bytes(
+        *program_id,
+        &instruction.pack(),
+        vec![
+            AccountMeta::new(*contract, false),
+            AccountMeta::new_readonly(*admin, true),
+        ],
+    )
+}
+
+pub fn build_set_allowed_uri_schemes(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    admin: &Pubkey,
+    schemes: u8,
+) -> Instruction {
+    build_contract_setting(
+        program_id,
+        contract,
+        admin,
+        ContractInstruction::SetAllowedUriSchemes { schemes },
+    )
+}
+
+pub fn build_update_token_uri(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    admin: &Pubkey,
+    token_id: u64,
+    token_uri: &str,
+) -> Instruction {
+    let (record, _) = token_record_address(program_id, token_id);
+    let (uri, _) = token_uri_address(program_id, token_id);
+    let instruction = ContractInstruction::UpdateTokenUri {
+        token_id,
+        token_uri: token_uri.to_string(),
+    };
+    Instruction::new_with_bytes(
+        *program_id,
+        &instruction.pack(),
+        vec![
+            AccountMeta::new_readonly(*contract, false),
+            AccountMeta::new_readonly(*admin, true),
+            AccountMeta::new_readonly(record, false),
+            AccountMeta::new(uri, false),
+        ],
+    )
+}
+
+pub fn build_enqueue_burn(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    token_id: u64,
+    owner: &Pubkey,
+    owner_token: &Pubkey,
+) -> Instruction {
+    let (record, _) = token_record_address(program_id, token_id);
+    Instruction::new_with_bytes(
+        *program_id,
+        &ContractInstruction::EnqueueBurn { token_id }.pack(),
+        vec![
+            AccountMeta::new(*contract, false),
+            AccountMeta::new(record, false),
+            AccountMeta::new(*owner_token, false),
+            AccountMeta::new_readonly(*owner, true),
+            AccountMeta::new_readonly(contract_pda_address(program_id).0, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+    )
+}
+
+// Each of `burns` is `(token_id, game_id, owner, owner_token,
+// history_chunk_index)`, for games without a burn callback.
+pub fn build_process_burn_queue(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    payer: &Pubkey,
+    burns: &[(u64, &str, Pubkey, Pubkey, u32)],
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(*contract, false),
+        AccountMeta::new_readonly(contract_pda_address(program_id).0, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new(*payer, true),
+        AccountMeta::new_readonly(solana_program::system_program::id(), false),
+    ];
+    for &(token_id, game_id, owner, owner_token, history_chunk_index) in burns {
+        let (game, _) = Pubkey::find_program_address(&[GAME_SEED, game_id.as_bytes()], program_id);
+        let (holding, _) = Pubkey::find_program_address(
+            &[HOLDING_SEED, game_id.as_bytes(), owner.as_ref()],
+            program_id,
+        );
+        let (history, _) = token_history_chunk_address(program_id, token_id, history_chunk_index);
+        accounts.extend([
+            AccountMeta::new(token_record_address(program_id, token_id).0, false),
+            AccountMeta::new(token_mint_address(program_id, token_id).0, false),
+            AccountMeta::new(owner_token, false),
+            AccountMeta::new(game, false),
+            AccountMeta::new(holding, false),
+            AccountMeta::new(history, false),
+        ]);
+    }
+    Instruction::new_with_bytes(
+        *program_id,
+        &ContractInstruction::ProcessBurnQueue.pack(),
+        accounts,
+    )
+}
+
+// For tokens whose value is within the transfer approval threshold; append
+// the admin as a signer when it is above.
+#[allow(clippy::too_many_arguments)]
+pub fn build_transfer_with_payment(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    token_id: u64,
+    price: u64,
+    game_id: &str,
+    seller: &Pubkey,
+    seller_token: &Pubkey,
+    buyer: &Pubkey,
+    buyer_token: &Pubkey,
+    history_chunk_index: u32,
+) -> Instruction {
+    let (record, _) = token_record_address(program_id, token_id);
+    let (game, _) = Pubkey::find_program_address(&[GAME_SEED, game_id.as_bytes()], program_id);
+    let (kyc, _) = Pubkey::find_program_address(&[KYC_SEED, buyer.as_ref()], program_id);
+    let (seller_holding, _) = Pubkey::find_program_address(
+        &[HOLDING_SEED, game_id.as_bytes(), seller.as_ref()],
+        program_id,
+    );
+    let (buyer_holding, _) = Pubkey::find_program_address(
+        &[HOLDING_SEED, game_id.as_bytes(), buyer.as_ref()],
+        program_id,
+    );
+    let (history, _) = token_history_chunk_address(program_id, token_id, history_chunk_index);
+    Instruction::new_with_bytes(
+        *program_id,
+        &ContractInstruction::TransferWithPayment { token_id, price }.pack(),
+        vec![
+            AccountMeta::new(record, false),
+            AccountMeta::new(game, false),
+            AccountMeta::new(*seller_token, false),
+            AccountMeta::new(*buyer_token, false),
+            AccountMeta::new(*seller, true),
+            AccountMeta::new(*buyer, true),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(solana_program::system_program::id(), false),
+            AccountMeta::new_readonly(*contract, false),
+            AccountMeta::new_readonly(kyc, false),
+            AccountMeta::new(seller_holding, false),
+            AccountMeta::new(buyer_holding, false),
+            AccountMeta::new(history, false),
+        ],
+    )
+}
+
+// Without a grant cooldown set; follow each grant's pair with the user grant
+// PDA for its user when one is.
+pub fn build_batch_grant(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    admin: &Pubkey,
+    grants: &[MintPermission],
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(*contract, false),
+        AccountMeta::new(*admin, true),
+        AccountMeta::new_readonly(solana_program::system_program::id(), false),
+    ];
+    for grant in grants {
+        let (permission, _) = Pubkey::find_program_address(
+            &[
+                PERMISSION_SEED,
+                grant.user.as_ref(),
+                grant.game_id.as_bytes(),
+            ],
+            program_id,
+        );
+        let (game, _) =
+            Pubkey::find_program_address(&[GAME_SEED, grant.game_id.as_bytes()], program_id);
+        accounts.push(AccountMeta::new(permission, false));
+        accounts.push(AccountMeta::new(game, false));
+    }
+    let instruction = ContractInstruction::BatchGrant {
+        grants: grants.to_vec(),
+    };
+    Instruction::new_with_bytes(*program_id, &instruction.pack(), accounts)
+}
+
+pub fn build_set_minting_enabled(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    admin: &Pubkey,
+    enabled: bool,
+) -> Instruction {
+    build_contract_setting(
+        program_id,
+        contract,
+        admin,
+        ContractInstruction::SetMintingEnabled { enabled },
+    )
+}
+
+pub fn build_is_burned(program_id: &Pubkey, contract: &Pubkey, token_id: u64) -> Instruction {
+    let (record, _) = token_record_address(program_id, token_id);
+    Instruction::new_with_bytes(
+        *program_id,
+        &ContractInstruction::IsBurned { token_id }.pack(),
+        vec![
+            AccountMeta::new_readonly(record, false),
+            AccountMeta::new_readonly(*contract, false),
+        ],
+    )
+}
+
+pub fn build_set_transfer_approval_threshold(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    admin: &Pubkey,
+    threshold: u64,
+) -> Instruction {
+    build_contract_setting(
+        program_id,
+        contract,
+        admin,
+        ContractInstruction::SetTransferApprovalThreshold { threshold },
+    )
+}
+
+pub fn build_record_snapshot(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    admin: &Pubkey,
+    snapshot_slot: u64,
+    token_ids: &[u64],
+) -> Instruction {
+    let (snapshot, _) =
+        Pubkey::find_program_address(&[SNAPSHOT_SEED, &snapshot_slot.to_le_bytes()], program_id);
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*contract, false),
+        AccountMeta::new(*admin, true),
+        AccountMeta::new(snapshot, false),
+        AccountMeta::new_readonly(solana_program::system_program::id(), false),
+    ];
+    accounts.extend(token_ids.iter().map(|&token_id| {
+        AccountMeta::new_readonly(token_record_address(program_id, token_id).0, false)
+    }));
+    Instruction::new_with_bytes(
+        *program_id,
+        &ContractInstruction::RecordSnapshot { snapshot_slot }.pack(),
+        accounts,
+    )
+}
+
+pub fn build_claim_snapshot_reward(
+    program_id: &Pubkey,
+    snapshot_slot: u64,
+    token_id: u64,
+    claimant: &Pubkey,
+) -> Instruction {
+    let (snapshot, _) =
+        Pubkey::find_program_address(&[SNAPSHOT_SEED, &snapshot_slot.to_le_bytes()], program_id);
+    Instruction::new_with_bytes(
+        *program_id,
+        &ContractInstruction::ClaimSnapshotReward { token_id }.pack(),
+        vec![
+            AccountMeta::new(snapshot, false),
+            AccountMeta::new_readonly(*claimant, true),
+        ],
+    )
+}
+
+pub fn build_set_game_metadata_template(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    admin: &Pubkey,
+    game_id: &str,
+    template: &GameMetadataTemplate,
+) -> Instruction {
+    let (template_address, _) =
+        Pubkey::find_program_address(&[TEMPLATE_SEED, game_id.as_bytes()], program_id);
+    let instruction = ContractInstruction::SetGameMetadataTemplate {
+        game_id: game_id.to_string(),
+        template: template.clone(),
+    };
+    Instruction::new_with_bytes(
+        *program_id,
+        &instruction.pack(),
+        vec![
+            AccountMeta::new_readonly(*contract, false),
+            AccountMeta::new(*admin, true),
+            AccountMeta::new(template_address, false),
+            AccountMeta::new_readonly(solana_program::system_program::id(), false),
+        ],
+    )
+}
+
+pub fn build_get_contract_pda(program_id: &Pubkey) -> Instruction {
+    Instruction::new_with_bytes(
+        *program_id,
+        &ContractInstruction::GetContractPda.pack(),
+        vec![],
+    )
+}
+
+pub fn build_set_treasury_split(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    admin: &Pubkey,
+    recipients: &[TreasuryShare],
+) -> Instruction {
+    build_contract_setting(
+        program_id,
+        contract,
+        admin,
+        ContractInstruction::SetTreasurySplit {
+            recipients: recipients.to_vec(),
+        },
+    )
+}
+
+pub fn build_disable_minting(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    admin: &Pubkey,
+    token_id: u64,
+) -> Instruction {
+    let (mint, _) = token_mint_address(program_id, token_id);
+    let (authority, _) =
+        Pubkey::find_program_address(&[MINT_AUTHORITY_SEED, mint.as_ref()], program_id);
+    Instruction::new_with_bytes(
+        *program_id,
+        &ContractInstruction::DisableMinting { token_id }.pack(),
+        vec![
+            AccountMeta::new_readonly(*contract, false),
+            AccountMeta::new_readonly(*admin, true),
+            AccountMeta::new(mint, false),
+            AccountMeta::new_readonly(authority, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+    )
+}
+
+pub fn build_set_kyc_gate(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    admin: &Pubkey,
+    required: bool,
+    authority: &Pubkey,
+) -> Instruction {
+    build_contract_setting(
+        program_id,
+        contract,
+        admin,
+        ContractInstruction::SetKycGate {
+            required,
+            authority: *authority,
+        },
+    )
+}
+
+pub fn build_attest_kyc(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    authority: &Pubkey,
+    subject: &Pubkey,
+    valid: bool,
+) -> Instruction {
+    let (kyc, _) = Pubkey::find_program_address(&[KYC_SEED, subject.as_ref()], program_id);
+    let instruction = ContractInstruction::AttestKyc {
+        subject: *subject,
+        valid,
+    };
+    Instruction::new_with_bytes(
+        *program_id,
+        &instruction.pack(),
+        vec![
+            AccountMeta::new_readonly(*contract, false),
+            AccountMeta::new(*authority, true),
+            AccountMeta::new(kyc, false),
+            AccountMeta::new_readonly(solana_program::system_program::id(), false),
+        ],
+    )
+}
+
+// `chunk_count` is the number of chunks in the game's token list,
+// `token_count.div_ceil(GAME_TOKENS_PER_CHUNK)`.
+pub fn build_compact_game_tokens(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    admin: &Pubkey,
+    game_id: &str,
+    chunk_count: u32,
+    burned_token_ids: &[u64],
+) -> Instruction {
+    let (game, _) = Pubkey::find_program_address(&[GAME_SEED, game_id.as_bytes()], program_id);
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*contract, false),
+        AccountMeta::new(*admin, true),
+        AccountMeta::new(game, false),
+    ];
+    accounts.extend((0..chunk_count).map(|chunk_index| {
+        AccountMeta::new(
+            game_tokens_chunk_address(program_id, game_id, chunk_index).0,
+            false,
+        )
+    }));
+    accounts.extend(burned_token_ids.iter().map(|&token_id| {
+        AccountMeta::new_readonly(token_record_address(program_id, token_id).0, false)
+    }));
+    let instruction = ContractInstruction::CompactGameTokens {
+        game_id: game_id.to_string(),
+    };
+    Instruction::new_with_bytes(*program_id, &instruction.pack(), accounts)
+}
+
+pub fn build_export_state(program_id: &Pubkey, contract: &Pubkey, page: u32) -> Instruction {
+    Instruction::new_with_bytes(
+        *program_id,
+        &ContractInstruction::ExportState { page }.pack(),
+        vec![AccountMeta::new_readonly(*contract, false)],
+    )
+}
+
+// The per-game admin instructions below all take the contract state account,
+// the admin and the game PDA, in that order.
+fn build_game_setting(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    admin: &Pubkey,
+    game_id: &str,
+    instruction: ContractInstruction,
+) -> Instruction {
+    let (game, _) = Pubkey::find_program_address(&[GAME_SEED, game_id.as_bytes()], program_id);
+    Instruction::new_with_bytes(
+        *program_id,
+        &instruction.pack(),
+        vec![
+            AccountMeta::new_readonly(*contract, false),
+            AccountMeta::new_readonly(*admin, true),
+            AccountMeta::new(game, false),
+        ],
+    )
+}
+
+pub fn build_activate_game(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    admin: &Pubkey,
+    game_id: &str,
+) -> Instruction {
+    build_game_setting(
+        program_id,
+        contract,
+        admin,
+        game_id,
+        ContractInstruction::ActivateGame {
+            game_id: game_id.to_string(),
+        },
+    )
+}
+
+pub fn build_set_default_royalty(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    admin: &Pubkey,
+    bps: u16,
+    recipient: &Pubkey,
+) -> Instruction {
+    build_contract_setting(
+        program_id,
+        contract,
+        admin,
+        ContractInstruction::SetDefaultRoyalty {
+            bps,
+            recipient: *recipient,
+        },
+    )
+}
+
+pub fn build_set_grant_cooldown(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    admin: &Pubkey,
+    slots: u64,
+) -> Instruction {
+    build_contract_setting(
+        program_id,
+        contract,
+        admin,
+        ContractInstruction::SetGrantCooldown { slots },
+    )
+}
+
+// The game queries below all read the game PDA alone.
+fn build_game_query(
+    program_id: &Pubkey,
+    game_id: &str,
+    instruction: ContractInstruction,
+) -> Instruction {
+    let (game, _) = Pubkey::find_program_address(&[GAME_SEED, game_id.as_bytes()], program_id);
+    Instruction::new_with_bytes(
+        *program_id,
+        &instruction.pack(),
+        vec![AccountMeta::new_readonly(game, false)],
+    )
+}
+
+pub fn build_get_holder_count(program_id: &Pubkey, game_id: &str) -> Instruction {
+    build_game_query(
+        program_id,
+        game_id,
+        ContractInstruction::GetHolderCount {
+            game_id: game_id.to_string(),
+        },
+    )
+}
+
+pub fn build_set_max_metadata_rent(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    admin: &Pubkey,
+    lamports: u64,
+) -> Instruction {
+    build_contract_setting(
+        program_id,
+        contract,
+        admin,
+        ContractInstruction::SetMaxMetadataRent { lamports },
+    )
+}
+
+pub fn build_reassign_permission(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    admin: &Pubkey,
+    game_id: &str,
+    old_user: &Pubkey,
+    new_user: &Pubkey,
+) -> Instruction {
+    let permission = |user: &Pubkey| {
+        Pubkey::find_program_address(
+            &[PERMISSION_SEED, user.as_ref(), game_id.as_bytes()],
+            program_id,
+        )
+        .0
+    };
+    let instruction = ContractInstruction::ReassignPermission {
+        game_id: game_id.to_string(),
+        old_user: *old_user,
+        new_user: *new_user,
+    };
+    Instruction::new_with_bytes(
+        *program_id,
+        &instruction.pack(),
+        vec![
+            AccountMeta::new_readonly(*contract, false),
+            AccountMeta::new(*admin, true),
+            AccountMeta::new(permission(old_user), false),
+            AccountMeta::new(permission(new_user), false),
+            AccountMeta::new_readonly(solana_program::system_program::id(), false),
+        ],
+    )
+}
+
+pub fn build_peek_batch_ids(program_id: &Pubkey, contract: &Pubkey, count: u64) -> Instruction {
+    Instruction::new_with_bytes(
+        *program_id,
+        &ContractInstruction::PeekBatchIds { count }.pack(),
+        vec![AccountMeta::new_readonly(*contract, false)],
+    )
+}
+
+pub fn build_batch_update_uris(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    admin: &Pubkey,
+    updates: &[(u64, String)],
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*contract, false),
+        AccountMeta::new_readonly(*admin, true),
+    ];
+    for &(token_id, _) in updates {
+        accounts.push(AccountMeta::new_readonly(
+            token_record_address(program_id, token_id).0,
+            false,
+        ));
+        accounts.push(AccountMeta::new(
+            token_uri_address(program_id, token_id).0,
+            false,
+        ));
+    }
+    let instruction = ContractInstruction::BatchUpdateUris {
+        updates: updates.to_vec(),
+    };
+    Instruction::new_with_bytes(*program_id, &instruction.pack(), accounts)
+}
+
+pub fn build_set_allowed_token_programs(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    admin: &Pubkey,
+    programs: &[Pubkey],
+) -> Instruction {
+    build_contract_setting(
+        program_id,
+        contract,
+        admin,
+        ContractInstruction::SetAllowedTokenPrograms {
+            programs: programs.to_vec(),
+        },
+    )
+}
+
+pub fn build_get_total_fees(program_id: &Pubkey, contract: &Pubkey) -> Instruction {
+    Instruction::new_with_bytes(
+        *program_id,
+        &ContractInstruction::GetTotalFees.pack(),
+        vec![AccountMeta::new_readonly(*contract, false)],
+    )
+}
+
+pub fn build_verify_bundle_ownership(
+    program_id: &Pubkey,
+    token_ids: &[u64],
+    owner: &Pubkey,
+) -> Instruction {
+    let accounts = token_ids
+        .iter()
+        .map(|&token_id| {
+            AccountMeta::new_readonly(token_record_address(program_id, token_id).0, false)
+        })
+        .collect();
+    let instruction = ContractInstruction::VerifyBundleOwnership {
+        token_ids: token_ids.to_vec(),
+        owner: *owner,
+    };
+    Instruction::new_with_bytes(*program_id, &instruction.pack(), accounts)
+}
+
+// For games without a burn callback. `payer` signs and pays for a new history
+// chunk; it may be the owner.
+#[allow(clippy::too_many_arguments)]
+pub fn build_burn_expired(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    token_id: u64,
+    game_id: &str,
+    owner: &Pubkey,
+    owner_token: &Pubkey,
+    history_chunk_index: u32,
+    payer: &Pubkey,
+) -> Instruction {
+    let (record, _) = token_record_address(program_id, token_id);
+    let (mint, _) = token_mint_address(program_id, token_id);
+    let (authority, _) =
+        Pubkey::find_program_address(&[MINT_AUTHORITY_SEED, mint.as_ref()], program_id);
+    let (metadata, _) = Pubkey::find_program_address(&[METADATA_SEED, mint.as_ref()], program_id);
+    let (uri, _) = token_uri_address(program_id, token_id);
+    let (game, _) = Pubkey::find_program_address(&[GAME_SEED, game_id.as_bytes()], program_id);
+    let (holding, _) = Pubkey::find_program_address(
+        &[HOLDING_SEED, game_id.as_bytes(), owner.as_ref()],
+        program_id,
+    );
+    let (history, _) = token_history_chunk_address(program_id, token_id, history_chunk_index);
+    Instruction::new_with_bytes(
+        *program_id,
+        &ContractInstruction::BurnExpired { token_id }.pack(),
+        vec![
+            AccountMeta::new(*contract, false),
+            AccountMeta::new(record, false),
+            AccountMeta::new_readonly(mint, false),
+            AccountMeta::new(*owner_token, false),
+            AccountMeta::new_readonly(authority, false),
+            AccountMeta::new(metadata, false),
+            AccountMeta::new(uri, false),
+            AccountMeta::new(*owner, false),
+            AccountMeta::new(game, false),
+            AccountMeta::new(holding, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new(history, false),
+            AccountMeta::new(*payer, true),
+            AccountMeta::new_readonly(solana_program::system_program::id(), false),
+        ],
+    )
+}
+
+pub fn build_freeze_transfers_until(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    admin: &Pubkey,
+    slot: u64,
+) -> Instruction {
+    build_contract_setting(
+        program_id,
+        contract,
+        admin,
+        ContractInstruction::FreezeTransfersUntil { slot },
+    )
+}
+
+pub fn build_get_game_config(program_id: &Pubkey, game_id: &str) -> Instruction {
+    build_game_query(
+        program_id,
+        game_id,
+        ContractInstruction::GetGameConfig {
+            game_id: game_id.to_string(),
+        },
+    )
+}
+
+// Moves the permissions of `users` from old_game_id to new_game_id.
+pub fn build_rename_game(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    admin: &Pubkey,
+    old_game_id: &str,
+    new_game_id: &str,
+    users: &[Pubkey],
+) -> Instruction {
+    let permission = |user: &Pubkey, game_id: &str| {
+        Pubkey::find_program_address(
+            &[PERMISSION_SEED, user.as_ref(), game_id.as_bytes()],
+            program_id,
+        )
+        .0
+    };
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*contract, false),
+        AccountMeta::new(*admin, true),
+        AccountMeta::new_readonly(solana_program::system_program::id(), false),
+    ];
+    for user in users {
+        accounts.push(AccountMeta::new(permission(user, old_game_id), false));
+        accounts.push(AccountMeta::new(permission(user, new_game_id), false));
+    }
+    let instruction = ContractInstruction::RenameGame {
+        old_game_id: old_game_id.to_string(),
+        new_game_id: new_game_id.to_string(),
+    };
+    Instruction::new_with_bytes(*program_id, &instruction.pack(), accounts)
+}
+
+// For games without a required token mint; append the receiver's token
+// account for that mint when one is set.
+pub fn build_can_mint(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    user: &Pubkey,
+    game_id: &str,
+    receiver: &Pubkey,
+) -> Instruction {
+    let (permission, _) = Pubkey::find_program_address(
+        &[PERMISSION_SEED, user.as_ref(), game_id.as_bytes()],
+        program_id,
+    );
+    let (game, _) = Pubkey::find_program_address(&[GAME_SEED, game_id.as_bytes()], program_id);
+    let (kyc, _) = Pubkey::find_program_address(&[KYC_SEED, receiver.as_ref()], program_id);
+    let instruction = ContractInstruction::CanMint {
+        user: *user,
+        game_id: game_id.to_string(),
+        receiver: *receiver,
+    };
+    Instruction::new_with_bytes(
+        *program_id,
+        &instruction.pack(),
+        vec![
+            AccountMeta::new_readonly(*contract, false),
+            AccountMeta::new_readonly(permission, false),
+            AccountMeta::new_readonly(game, false),
+            AccountMeta::new_readonly(kyc, false),
+        ],
+    )
+}
+
+pub fn build_set_merkle_root(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    admin: &Pubkey,
+    game_id: &str,
+    root: &[u8; 32],
+) -> Instruction {
+    build_game_setting(
+        program_id,
+        contract,
+        admin,
+        game_id,
+        ContractInstruction::SetMerkleRoot {
+            game_id: game_id.to_string(),
+            root: *root,
+        },
+    )
+}
+
+pub fn build_get_merkle_root(program_id: &Pubkey, game_id: &str) -> Instruction {
+    build_game_query(
+        program_id,
+        game_id,
+        ContractInstruction::GetMerkleRoot {
+            game_id: game_id.to_string(),
+        },
+    )
+}
+
+pub fn build_get_token_history(
+    program_id: &Pubkey,
+    token_id: u64,
+    start: u64,
+    limit: u64,
+) -> Instruction {
+    let (record, _) = token_record_address(program_id, token_id);
+    let end = start.saturating_add(limit.min(MAX_TOKEN_HISTORY_PAGE));
+    let mut accounts = vec![AccountMeta::new_readonly(record, false)];
+    if end > start {
+        accounts.extend(
+            (start / TOKEN_HISTORY_PER_CHUNK..=(end - 1) / TOKEN_HISTORY_PER_CHUNK).map(
+                |chunk_index| {
+                    let (chunk, _) =
+                        token_history_chunk_address(program_id, token_id, chunk_index as u32);
+                    AccountMeta::new_readonly(chunk, false)
+                },
+            ),
+        );
+    }
+    let instruction = ContractInstruction::GetTokenHistory {
+        token_id,
+        start,
+        limit,
+    };
+    Instruction::new_with_bytes(*program_id, &instruction.pack(), accounts)
+}
+
+pub fn build_bootstrap(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    owner: &Pubkey,
+    game_id: &str,
+    token_uri: &str,
+) -> Instruction {
+    let (permission, _) = Pubkey::find_program_address(
+        &[PERMISSION_SEED, owner.as_ref(), game_id.as_bytes()],
+        program_id,
+    );
+    let (game, _) = Pubkey::find_program_address(&[GAME_SEED, game_id.as_bytes()], program_id);
+    let instruction = ContractInstruction::Bootstrap {
+        owner: *owner,
+        game_id: game_id.to_string(),
+        token_uri: token_uri.to_string(),
+    };
+    Instruction::new_with_bytes(
+        *program_id,
+        &instruction.pack(),
+        vec![
+            AccountMeta::new(*contract, false),
+            AccountMeta::new_readonly(solana_program::sysvar::rent::id(), false),
+            AccountMeta::new(*owner, true),
+            AccountMeta::new(permission, false),
+            AccountMeta::new(game, false),
+            AccountMeta::new_readonly(solana_program::system_program::id(), false),
+            AccountMeta::new_readonly(program_data_address(program_id), false),
+        ],
+    )
+}
+
+pub fn build_release_reserved(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    admin: &Pubkey,
+    game_id: &str,
+    amount: u64,
+) -> Instruction {
+    build_game_setting(
+        program_id,
+        contract,
+        admin,
+        game_id,
+        ContractInstruction::ReleaseReserved {
+            game_id: game_id.to_string(),
+            amount,
+        },
+    )
+}
+
+pub fn build_get_token_records(program_id: &Pubkey, token_ids: &[u64]) -> Instruction {
+    let accounts = token_ids
+        .iter()
+        .map(|&token_id| {
+            AccountMeta::new_readonly(token_record_address(program_id, token_id).0, false)
+        })
+        .collect();
+    let instruction = ContractInstruction::GetTokenRecords {
+        token_ids: token_ids.to_vec(),
+    };
+    Instruction::new_with_bytes(*program_id, &instruction.pack(), accounts)
+}
+
+pub fn build_propose_change(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    admin: &Pubkey,
+    change: &ProposedChange,
+) -> Instruction {
+    let instruction = ContractInstruction::ProposeChange {
+        change: change.clone(),
+    };
+    Instruction::new_with_bytes(
+        *program_id,
+        &instruction.pack(),
+        vec![
+            AccountMeta::new_readonly(*contract, false),
+            AccountMeta::new(*admin, true),
+            AccountMeta::new(pending_change_address(program_id).0, false),
+            AccountMeta::new_readonly(solana_program::system_program::id(), false),
+        ],
+    )
+}
+
+pub fn build_execute_change(program_id: &Pubkey, contract: &Pubkey, admin: &Pubkey) -> Instruction {
+    Instruction::new_with_bytes(
+        *program_id,
+        &ContractInstruction::ExecuteChange.pack(),
+        vec![
+            AccountMeta::new(*contract, false),
+            AccountMeta::new(*admin, true),
+            AccountMeta::new(pending_change_address(program_id).0, false),
+        ],
+    )
+}
+
+pub fn build_close_contract(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    admin: &Pubkey,
+    destination: &Pubkey,
+    force: bool,
+    pdas: &[ProgramPda],
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(*contract, false),
+        AccountMeta::new_readonly(*admin, true),
+        AccountMeta::new(*destination, false),
+    ];
+    accounts.extend(
+        pdas.iter()
+            .map(|pda| AccountMeta::new(pda.address(program_id), false)),
+    );
+    let instruction = ContractInstruction::CloseContract {
+        force,
+        pdas: pdas.to_vec(),
+    };
+    Instruction::new_with_bytes(*program_id, &instruction.pack(), accounts)
+}
+
+pub fn build_set_paused(
+    program_id: &Pubkey,
+    contract: &Pubkey,
+    admin: &Pubkey,
+    paused: bool,
+) -> Instruction {
+    build_contract_setting(
+        program_id,
+        contract,
+        admin,
+        ContractInstruction::SetPaused { paused },
+    )
+}
+
+// Entry point
+#[cfg(not(feature = "no-entrypoint"))]
+entrypoint!(process_instruction);
+fn process_instruction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction_data: &[u8],
+) -> ProgramResult {
+    let instruction = ContractInstruction::unpack(instruction_data)?;
+
+    match instruction {
+        ContractInstruction::InitializeContract { owner, max_supply } => {
+            initialize_contract(program_id, accounts, owner, max_supply)
+        }
+        ContractInstruction::GrantMint {
+            user,
+            game_id,
+            token_uri,
+            name,
+            overwrite,
+        } => grant_mint(
+            program_id, accounts, user, game_id, token_uri, name, overwrite,
+        ),
+        ContractInstruction::Mint { receiver, game_id } => {
+            mint(program_id, accounts, receiver, game_id)
+        }
+        ContractInstruction::Transfer {
+            token_id,
+            owner,
+            receiver,
+        } => transfer(program_id, accounts, token_id, owner, receiver),
+        ContractInstruction::Burn { token_id } => burn(program_id, accounts, token_id),
+        ContractInstruction::GetGameTokens {
+            game_id,
+            start,
+            limit,
+        } => get_game_tokens(program_id, accounts, game_id, start, limit),
+        ContractInstruction::SetGameConfig { game_id, config } => {
+            set_game_config(program_id, accounts, game_id, *config)
+        }
+        ContractInstruction::VerifyAuthentic { token_id, mint } => {
+            verify_authentic(program_id, accounts, token_id, mint)
+        }
+        ContractInstruction::SetAllowedUriSchemes { schemes } => {
+            set_allowed_uri_schemes(program_id, accounts, schemes)
+        }
+        ContractInstruction::UpdateTokenUri {
+            token_id,
+            token_uri,
+        } => update_token_uri(program_id, accounts, token_id, token_uri),
+        ContractInstruction::EnqueueBurn { token_id } => {
+            enqueue_burn(program_id, accounts, token_id)
+        }
+        ContractInstruction::ProcessBurnQueue => process_burn_queue(program_id, accounts),
+        ContractInstruction::TransferWithPayment { token_id, price } => {
+            transfer_with_payment(program_id, accounts, token_id, price)
+        }
+        ContractInstruction::BatchGrant { grants } => batch_grant(program_id, accounts, grants),
+        ContractInstruction::SetMintingEnabled { enabled } => {
+            set_minting_enabled(program_id, accounts, enabled)
+        }
+        ContractInstruction::IsBurned { token_id } => is_burned(program_id, accounts, token_id),
+        ContractInstruction::SetTransferApprovalThreshold { threshold } => {
+            set_transfer_approval_threshold(program_id, accounts, threshold)
+        }
+        ContractInstruction::RecordSnapshot { snapshot_slot } => {
+            record_snapshot(program_id, accounts, snapshot_slot)
+        }
+        ContractInstruction::ClaimSnapshotReward { token_id } => {
+            claim_snapshot_reward(program_id, accounts, token_id)
+        }
+        ContractInstruction::SetGameMetadataTemplate { game_id, template } => {
+            set_game_metadata_template(program_id, accounts, game_id, template)
+        }
+        ContractInstruction::GetContractPda => get_contract_pda(program_id),
+        ContractInstruction::SetTreasurySplit { recipients } => {
+            set_treasury_split(program_id, accounts, recipients)
+        }
+        ContractInstruction::DisableMinting { token_id } => {
+            disable_minting(program_id, accounts, token_id)
+        }
+        ContractInstruction::SetKycGate {
+            required,
+            authority,
+        } => set_kyc_gate(program_id, accounts, required, authority),
+        ContractInstruction::AttestKyc { subject, valid } => {
+            attest_kyc(program_id, accounts, subject, valid)
+        }
+        ContractInstruction::CompactGameTokens { game_id } => {
+            compact_game_tokens(program_id, accounts, game_id)
+        }
+        ContractInstruction::ExportState { page } => export_state(program_id, accounts, page),
+        ContractInstruction::ActivateGame { game_id } => {
+            activate_game(program_id, accounts, game_id)
+        }
+        ContractInstruction::SetDefaultRoyalty { bps, recipient } => {
+            set_default_royalty(program_id, accounts, bps, recipient)
+        }
+        ContractInstruction::SetGrantCooldown { slots } => {
+            set_grant_cooldown(program_id, accounts, slots)
+        }
+        ContractInstruction::GetHolderCount { game_id } => {
+            get_holder_count(program_id, accounts, game_id)
+        }
+        ContractInstruction::SetMaxMetadataRent { lamports } => {
+            set_max_metadata_rent(program_id, accounts, lamports)
+        }
+        ContractInstruction::ReassignPermission {
+            game_id,
+            old_user,
+            new_user,
+        } => reassign_permission(program_id, accounts, game_id, old_user, new_user),
+        ContractInstruction::PeekBatchIds { count } => peek_batch_ids(program_id, accounts, count),
+        ContractInstruction::BatchUpdateUris { updates } => {
+            batch_update_uris(program_id, accounts, updates)
+        }
+        ContractInstruction::SetAllowedTokenPrograms { programs } => {
+            set_allowed_token_programs(program_id, accounts, programs)
+        }
+        ContractInstruction::GetTotalFees => get_total_fees(program_id, accounts),
+        ContractInstruction::VerifyBundleOwnership { token_ids, owner } => {
+            verify_bundle_ownership(program_id, accounts, token_ids, owner)
+        }
+        ContractInstruction::BurnExpired { token_id } => {
+            burn_expired(program_id, accounts, token_id)
+        }
+        ContractInstruction::FreezeTransfersUntil { slot } => {
+            freeze_transfers_until(program_id, accounts, slot)
+        }
+        ContractInstruction::GetGameConfig { game_id } => {
+            get_game_config(program_id, accounts, game_id)
+        }
+        ContractInstruction::RenameGame {
+            old_game_id,
+            new_game_id,
+        } => rename_game(program_id, accounts, old_game_id, new_game_id),
+        ContractInstruction::CanMint {
+            user,
+            game_id,
+            receiver,
+        } => can_mint(program_id, accounts, user, game_id, receiver),
+        ContractInstruction::SetMerkleRoot { game_id, root } => {
+            set_merkle_root(program_id, accounts, game_id, root)
+        }
+        ContractInstruction::GetMerkleRoot { game_id } => {
+            get_merkle_root(program_id, accounts, game_id)
+        }
+        ContractInstruction::GetTokenHistory {
+            token_id,
+            start,
+            limit,
+        } => get_token_history(program_id, accounts, token_id, start, limit),
+        ContractInstruction::Bootstrap {
+            owner,
+            game_id,
+            token_uri,
+        } => bootstrap(program_id, accounts, owner, game_id, token_uri),
+        ContractInstruction::ReleaseReserved { game_id, amount } => {
+            release_reserved(program_id, accounts, game_id, amount)
+        }
+        ContractInstruction::RevokeMint { user, game_id } => {
+            revoke_mint(program_id, accounts, user, game_id)
+        }
+        ContractInstruction::TransferOwnership { new_owner } => {
+            transfer_ownership(program_id, accounts, new_owner)
+        }
+        ContractInstruction::GetTokenRecords { token_ids } => {
+            get_token_records(program_id, accounts, token_ids)
+        }
+        ContractInstruction::ProposeChange { change } => {
+            propose_change(program_id, accounts, change)
+        }
+        ContractInstruction::ExecuteChange => execute_change(program_id, accounts),
+        ContractInstruction::GetDashboard => get_dashboard(program_id, accounts),
+        ContractInstruction::BatchMint {
+            receiver,
+            game_id,
+            count,
+        } => batch_mint(program_id, accounts, receiver, game_id, count),
+        ContractInstruction::CloseContract { force, pdas } => {
+            close_contract(program_id, accounts, force, pdas)
+        }
+        ContractInstruction::SetPaused { paused } => set_paused(program_id, accounts, paused),
+    }
+}
+
+// Verifies `contract_state` was read from the contract PDA, and that
+// `admin_account` is its owner and signed the transaction
+fn assert_owner(
+    program_id: &Pubkey,
+    contract_account: &AccountInfo,
+    contract_state: &ContractState,
+    admin_account: &AccountInfo,
+) -> ProgramResult {
+    assert_contract_account(program_id, contract_account)?;
+    assert_owner_signed(contract_state, admin_account)
+}
+
+// The signature half of `assert_owner`, for callers that have already checked
+// the contract account
+fn assert_owner_signed(
+    contract_state: &ContractState,
+    admin_account: &AccountInfo,
+) -> ProgramResult {
+    if contract_state.contract_owner != *admin_account.key {
+        return Err(CustomError::NotAdmin.into());
+    }
+    if !admin_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    Ok(())
+}
+
+// Builds a token's URI from its game's template: `{id}` placeholders are
+// replaced by the token id, and without any the id is appended.
+fn template_token_uri(uri_prefix: &str, token_id: u64) -> Result<String, ProgramError> {
+    let uri = if uri_prefix.contains(TOKEN_ID_PLACEHOLDER) {
+        uri_prefix.replace(TOKEN_ID_PLACEHOLDER, &token_id.to_string())
+    } else {
+        format!("{}{}", uri_prefix, token_id)
+    };
+    if uri.len() > MAX_TOKEN_URI_LEN {
+        return Err(CustomError::UriTooLong.into());
+    }
+    Ok(uri)
+}
+
+// Checks a token URI's length and that its scheme is one the contract allows
+fn validate_token_uri(contract_state: &ContractState, token_uri: &str) -> ProgramResult {
+    if token_uri.len() > MAX_TOKEN_URI_LEN {
+        return Err(CustomError::InvalidInstruction.into());
+    }
+    if contract_state.allowed_uri_schemes == 0 {
+        return Ok(());
+    }
+    let allowed = URI_SCHEMES.iter().any(|(flag, prefix)| {
+        contract_state.allowed_uri_schemes & flag != 0 && token_uri.starts_with(prefix)
+    });
+    if !allowed {
+        return Err(CustomError::UriSchemeNotAllowed.into());
+    }
+    Ok(())
+}
+
+fn assert_not_paused(contract_state: &ContractState) -> ProgramResult {
+    if contract_state.paused {
+        return Err(CustomError::ContractPaused.into());
+    }
+    Ok(())
+}
+
+fn assert_transfers_open(contract_state: &ContractState) -> ProgramResult {
+    assert_not_paused(contract_state)?;
+    if Clock::get()?.slot < contract_state.freeze_transfers_until_slot {
+        return Err(CustomError::TransfersFrozen.into());
+    }
+    Ok(())
+}
+
+fn is_expired(record: &TokenRecord) -> Result<bool, ProgramError> {
+    Ok(record.expires_at_slot != 0 && Clock::get()?.slot >= record.expires_at_slot)
+}
+
+fn assert_not_expired(record: &TokenRecord) -> ProgramResult {
+    if is_expired(record)? {
+        return Err(CustomError::TokenExpired.into());
+    }
+    Ok(())
+}
+
+// A capped game can mint until only its reserved supply is left.
+fn assert_supply_available(game_record: &GameRecord) -> ProgramResult {
+    let config = &game_record.config;
+    if config.max_supply > 0
+        && game_record.minted >= config.max_supply.saturating_sub(config.reserved_supply)
+    {
+        return Err(CustomError::SupplyExhausted.into());
+    }
+    Ok(())
+}
+
+// The contract's `max_supply` has to leave room for `ids` more token ids.
+fn assert_token_ids_available(contract_state: &ContractState, ids: u64) -> ProgramResult {
+    if contract_state.max_supply > 0
+        && contract_state.last_token_id.saturating_add(ids) > contract_state.max_supply
+    {
+        return Err(CustomError::SupplyExhausted.into());
+    }
+    Ok(())
+}
+
+// Burning one of the game's tokens frees up its supply when the game refills on burn.
+fn refill_supply(game_record: &mut GameRecord) {
+    if game_record.config.refill_on_burn {
+        game_record.minted = game_record.minted.saturating_sub(1);
+    }
+}
+
+// Points a token instruction at `token_program`. Token-2022 keeps classic SPL
+// Token's layout for every instruction the contract sends, but spl_token only
+// builds them for its own program id.
+fn token_instruction(mut instruction: Instruction, token_program: &AccountInfo) -> Instruction {
+    instruction.program_id = *token_program.key;
+    instruction
+}
+
+// Whether `key` is classic SPL Token or Token-2022
+fn is_token_program(key: &Pubkey) -> bool {
+    *key == spl_token::id() || *key == TOKEN_2022_PROGRAM_ID
+}
+
+// The token program passed for CPIs has to be classic SPL Token or Token-2022,
+// and one the contract allows.
+fn assert_token_program(
+    contract_state: &ContractState,
+    token_program: &AccountInfo,
+) -> ProgramResult {
+    if !is_token_program(token_program.key) {
+        return Err(CustomError::InvalidInstruction.into());
+    }
+    if !contract_state
+        .allowed_token_programs
+        .contains(token_program.key)
+    {
+        return Err(CustomError::TokenProgramNotAllowed.into());
+    }
+    Ok(())
+}
+
+// Tokens valued above the contract's transfer approval threshold can only move
+// with the contract owner co-signing as `admin_account`.
+fn assert_transfer_approved(
+    contract_state: &ContractState,
+    value: u64,
+    admin_account: Option<&AccountInfo>,
+) -> ProgramResult {
+    let threshold = contract_state.transfer_approval_threshold;
+    if threshold == 0 || value <= threshold {
+        return Ok(());
+    }
+    match admin_account {
+        Some(admin_account) => assert_owner_signed(contract_state, admin_account),
+        None => Err(CustomError::TransferApprovalRequired.into()),
+    }
+}
+
+// With the KYC gate on, `subject` must have a valid attestation from the
+// contract's current KYC authority in `attestation_account`.
+fn assert_kyc_attested(
+    program_id: &Pubkey,
+    contract_state: &ContractState,
+    subject: &Pubkey,
+    attestation_account: &AccountInfo,
+) -> ProgramResult {
+    if !contract_state.kyc_required {
+        return Ok(());
+    }
+    let (attestation_address, _) =
+        Pubkey::find_program_address(&[KYC_SEED, subject.as_ref()], program_id);
+    if attestation_address != *attestation_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if attestation_account.owner != program_id || attestation_account.data_is_empty() {
+        return Err(CustomError::KycRequired.into());
+    }
+    let attestation: KycAttestation =
+        load_account(attestation_account, CustomError::StateCorrupted)?;
+    if !attestation.valid
+        || attestation.subject != *subject
+        || attestation.authority != contract_state.kyc_authority
+    {
+        return Err(CustomError::KycRequired.into());
+    }
+    Ok(())
+}
+
+// Checks `mint_account` is an NFT mint: no decimals and exactly `supply` tokens.
+fn assert_nft_mint(mint_account: &AccountInfo, supply: u64) -> ProgramResult {
+    let mint = Mint::unpack(&mint_account.data.borrow())?;
+    if mint.decimals != 0 || mint.supply != supply {
+        return Err(CustomError::NotAnNft.into());
+    }
+    Ok(())
+}
+
+// Initializes `token_account` for `mint_account` and `owner`, unless the
+// client already did, in which case the CPI is skipped. An account already
+// initialized for another mint or owner fails with InvalidAccountData.
+fn init_token_account<'a>(
+    token_account: &AccountInfo<'a>,
+    mint_account: &AccountInfo<'a>,
+    owner: &Pubkey,
+    token_program: &AccountInfo<'a>,
+) -> ProgramResult {
+    if let Ok(existing) = TokenAccount::unpack(&token_account.data.borrow()) {
+        if token_account.owner != token_program.key
+            || existing.mint != *mint_account.key
+            || existing.owner != *owner
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        return Ok(());
+    }
+    invoke(
+        &token_instruction(
+            initialize_account3(&spl_token::id(), token_account.key, mint_account.key, owner)?,
+            token_program,
+        ),
+        &[
+            token_account.clone(),
+            mint_account.clone(),
+            token_program.clone(),
+        ],
+    )
+}
+
+// Deserializes an account's data, mapping Borsh failures to `error` and logging
+// which account was malformed
+fn load_account<T: BorshDeserialize>(
+    account: &AccountInfo,
+    error: CustomError,
+) -> Result<T, ProgramError> {
+    T::deserialize(&mut &account.data.borrow()[..]).map_err(|_| {
+        msg!("{:?}: failed to deserialize account {}", error, account.key);
+        error.into()
+    })
+}
+
+// Checks `contract_account` is the contract PDA, the only account the
+// program keeps its ContractState in, so state read from it can be trusted
+fn assert_contract_account(program_id: &Pubkey, contract_account: &AccountInfo) -> ProgramResult {
+    if contract_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if contract_pda_address(program_id).0 != *contract_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    Ok(())
+}
+
+fn load_contract(
+    program_id: &Pubkey,
+    contract_account: &AccountInfo,
+) -> Result<ContractState, ProgramError> {
+    assert_contract_account(program_id, contract_account)?;
+    load_account(contract_account, CustomError::StateCorrupted)
+}
+
+// The ProgramData account the upgradeable loader keeps the program's code and
+// upgrade authority in
+pub fn program_data_address(program_id: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id()).0
+}
+
+// The upgrade authority recorded in the program's ProgramData account, None
+// once the program is immutable
+fn upgrade_authority(
+    program_id: &Pubkey,
+    program_data_account: &AccountInfo,
+) -> Result<Option<Pubkey>, ProgramError> {
+    if program_data_address(program_id) != *program_data_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if *program_data_account.owner != bpf_loader_upgradeable::id() {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    // bincode layout: u32 variant (3 is ProgramData), u64 slot, then the
+    // authority as an Option<Pubkey>
+    let data = program_data_account.data.borrow();
+    match data.get(..UpgradeableLoaderState::size_of_programdata_metadata()) {
+        Some([3, 0, 0, 0, _, _, _, _, _, _, _, _, 0, ..]) => Ok(None),
+        Some([3, 0, 0, 0, _, _, _, _, _, _, _, _, 1, authority @ ..]) => Ok(Some(
+            Pubkey::try_from(authority).map_err(|_| ProgramError::InvalidAccountData)?,
+        )),
+        _ => Err(ProgramError::InvalidAccountData),
+    }
+}
+
+// Serializes `value` into an account's data, mapping Borsh failures (e.g. the
+// account being too small) to `error` and logging which account failed
+fn store_account<T: BorshSerialize>(
+    value: &T,
+    account: &AccountInfo,
+    error: CustomError,
+) -> ProgramResult {
+    value
+        .serialize(&mut &mut account.data.borrow_mut()[..])
+        .map_err(|_| {
+            msg!("{:?}: failed to serialize account {}", error, account.key);
+            error.into()
+        })
+}
+
+// Rejects writing into an account that isn't rent exempt, which the runtime
+// could otherwise purge along with the state stored in it
+fn assert_rent_exempt(account: &AccountInfo, rent: &Rent) -> ProgramResult {
+    if !rent.is_exempt(account.lamports(), account.data_len()) {
+        msg!("account {} is not rent exempt", account.key);
+        return Err(ProgramError::AccountNotRentExempt);
+    }
+    Ok(())
+}
+
+// Creates a PDA owned by `owner`, funded for rent exemption by `payer`.
+// `seeds` must include the bump.
+fn create_pda_account<'a>(
+    owner: &Pubkey,
+    payer: &AccountInfo<'a>,
+    pda_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    space: usize,
+    seeds: &[&[u8]],
+) -> ProgramResult {
+    let rent = Rent::get()?;
+    invoke_signed(
+        &system_instruction::create_account(
+            payer.key,
+            pda_account.key,
+            rent.minimum_balance(space),
+            space as u64,
+            owner,
+        ),
+        &[payer.clone(), pda_account.clone(), system_program.clone()],
+        &[seeds],
+    )
+}
+
+// Bytes a mint allocates for the token's metadata and URI PDAs. The program
+// keeps its own metadata, with no creator list, and sizes both accounts for
+// the longest name, symbol and URI allowed, so UpdateTokenUri never has to
+// realloc. The size is the same for every token whatever it holds.
+pub const fn metadata_accounts_size() -> usize {
+    TokenMetadata::LEN + TokenUri::LEN
+}
+
+// Lamports a mint locks up as rent for the token's metadata and URI PDAs.
+// As their size is fixed, see `metadata_accounts_size`, the cost only moves
+// with the rent parameters; a long URI costs no more than a short one.
+pub fn metadata_rent(rent: &Rent) -> u64 {
+    rent.minimum_balance(TokenMetadata::LEN) + rent.minimum_balance(TokenUri::LEN)
+}
+
+// Checks `game_account` is the game PDA for `game_id`, creating it with a default
+// config on first use
+fn open_game_account<'a>(
+    program_id: &Pubkey,
+    payer: &AccountInfo<'a>,
+    game_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    game_id: &str,
+) -> ProgramResult {
+    let (game_address, game_bump) =
+        Pubkey::find_program_address(&[GAME_SEED, game_id.as_bytes()], program_id);
+    if game_address != *game_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if game_account.data_is_empty() {
+        create_pda_account(
+            program_id,
+            payer,
+            game_account,
+            system_program,
+            GameRecord::LEN,
+            &[GAME_SEED, game_id.as_bytes(), &[game_bump]],
+        )?;
+        let game_record = GameRecord {
+            game_id: game_id.to_string(),
+            token_count: 0,
+            config: GameConfig::default(),
+            active: false,
+            holder_count: 0,
+            minted: 0,
+        };
+        store_account(&game_record, game_account, CustomError::StateCorrupted)?;
+    }
+    Ok(())
+}
+
+// Pays the game's referral reward from the treasury to the mint's referrer,
+// when the minter passed one, up to what is left of the referrer's cap. The
+// referrer can be neither the minter nor the receiver.
+//
+// Accounts, all optional and only read when the game sets a reward:
+//   0. `[writable]` referrer wallet
+//   1. `[writable]` referrer's referral PDA for the game, created on their first
+//      referral (paid by `payer`)
+//   2. `[signer, writable]` treasury
+fn pay_referral_reward<'a, 'b>(
+    program_id: &Pubkey,
+    game_record: &GameRecord,
+    contract_state: &ContractState,
+    receiver: &Pubkey,
+    payer: &AccountInfo<'a>,
+    account_info_iter: &mut std::slice::Iter<'b, AccountInfo<'a>>,
+    system_program: &AccountInfo<'a>,
+) -> ProgramResult {
+    let reward = game_record.config.referral_reward_lamports;
+    if reward == 0 {
+        return Ok(());
+    }
+    let Some(referrer_account) = account_info_iter.next() else {
+        return Ok(());
+    };
+    let referral_account = next_account_info(account_info_iter)?;
+    let treasury_account = next_account_info(account_info_iter)?;
+    if referrer_account.key == payer.key || referrer_account.key == receiver {
+        return Err(CustomError::InvalidReferrer.into());
+    }
+    if *treasury_account.key != contract_state.treasury {
+        return Err(CustomError::InvalidTreasury.into());
+    }
+
+    let game_id = game_record.game_id.as_bytes();
+    let (referral_address, referral_bump) = Pubkey::find_program_address(
+        &[REFERRAL_SEED, game_id, referrer_account.key.as_ref()],
+        program_id,
+    );
+    if referral_address != *referral_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let mut referral = if referral_account.data_is_empty() {
+        create_pda_account(
+            program_id,
+            payer,
+            referral_account,
+            system_program,
+            ReferralRecord::LEN,
+            &[
+                REFERRAL_SEED,
+                game_id,
+                referrer_account.key.as_ref(),
+                &[referral_bump],
+            ],
+        )?;
+        ReferralRecord {
+            game_id: game_record.game_id.clone(),
+            referrer: *referrer_account.key,
+            rewards_paid: 0,
+        }
+    } else {
+        load_account(referral_account, CustomError::StateCorrupted)?
+    };
+
+    let cap = game_record.config.referral_reward_cap;
+    let amount = if cap == 0 {
+        reward
+    } else {
+        reward.min(cap.saturating_sub(referral.rewards_paid))
+    };
+    if amount > 0 {
+        invoke(
+            &system_instruction::transfer(treasury_account.key, referrer_account.key, amount),
+            &[
+                treasury_account.clone(),
+                referrer_account.clone(),
+                system_program.clone(),
+            ],
+        )?;
+        referral.rewards_paid += amount;
+    }
+    store_account(&referral, referral_account, CustomError::StateCorrupted)
+}
+
+// The price `minter` pays for a mint of the game: `price_amount`, or the
+// lamports `price_usd_cents` buys at the price feed's rate, less the game's
+// holder discount when the minter already holds one of its tokens.
+//
+// Accounts, each only read when the game uses it:
+//   0. `[]` minter's game holding PDA, possibly never created, when the game
+//      sets a holder discount
+//   1. `[]` the game's price feed, when the game sets a USD price
+fn mint_price<'a, 'b>(
+    program_id: &Pubkey,
+    game_record: &GameRecord,
+    minter: &Pubkey,
+    account_info_iter: &mut std::slice::Iter<'b, AccountInfo<'a>>,
+) -> Result<u64, ProgramError> {
+    let config = &game_record.config;
+    let holds = if config.holder_discount_bps == 0 {
+        false
+    } else {
+        let holding_account = next_account_info(account_info_iter)?;
+        let (holding_address, _) = Pubkey::find_program_address(
+            &[
+                HOLDING_SEED,
+                game_record.game_id.as_bytes(),
+                minter.as_ref(),
+            ],
+            program_id,
+        );
+        if holding_address != *holding_account.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        holding_account.owner == program_id
+            && !holding_account.data_is_empty()
+            && load_account::<GameHolding>(holding_account, CustomError::StateCorrupted)?.count > 0
+    };
+    let price = if config.price_usd_cents == 0 {
+        config.price_amount
+    } else {
+        usd_price_in_lamports(config, next_account_info(account_info_iter)?)?
+    };
+    if !holds {
+        return Ok(price);
+    }
+    let discount = price as u128 * config.holder_discount_bps as u128 / MAX_BPS as u128;
+    Ok(price - discount as u64)
+}
+
+// Converts the game's `price_usd_cents` to lamports at the rate quoted by its
+// price feed, rounding up. Fails with InvalidPriceFeed for the wrong account or
+// an unusable quote, and StalePriceFeed for a quote older than
+// MAX_PRICE_FEED_AGE_SLOTS.
+fn usd_price_in_lamports(
+    config: &GameConfig,
+    feed_account: &AccountInfo,
+) -> Result<u64, ProgramError> {
+    if *feed_account.key != config.price_feed {
+        return Err(CustomError::InvalidPriceFeed.into());
+    }
+    let feed = PriceFeed::deserialize(&mut &feed_account.data.borrow()[..])
+        .map_err(|_| CustomError::InvalidPriceFeed)?;
+    if feed.price <= 0 {
+        return Err(CustomError::InvalidPriceFeed.into());
+    }
+    if Clock::get()?.slot.saturating_sub(feed.publish_slot) > MAX_PRICE_FEED_AGE_SLOTS {
+        return Err(CustomError::StalePriceFeed.into());
+    }
+
+    // lamports = cents * 10^7 / (price * 10^expo)
+    let scale = LAMPORTS_PER_CENT_EXPO - feed.expo as i64;
+    let power = |exponent: i64| {
+        u32::try_from(exponent)
+            .ok()
+            .and_then(|exponent| 10u128.checked_pow(exponent))
+    };
+    let (numerator, denominator) = if scale >= 0 {
+        (
+            power(scale).and_then(|p| (config.price_usd_cents as u128).checked_mul(p)),
+            Some(feed.price as u128),
+        )
+    } else {
+        (
+            Some(config.price_usd_cents as u128),
+            power(-scale).and_then(|p| (feed.price as u128).checked_mul(p)),
+        )
+    };
+    let (Some(numerator), Some(denominator)) = (numerator, denominator) else {
+        return Err(CustomError::InvalidPriceFeed.into());
+    };
+    u64::try_from(numerator.div_ceil(denominator)).map_err(|_| CustomError::InvalidPriceFeed.into())
+}
+
+// Charges `payer` the mint `price` (see `mint_price`), split across the
+// contract's treasury recipients (or all to `treasury` when no split is set):
+// lamport transfers when `price_mint` is the default pubkey, otherwise SPL
+// transfers of `price_mint` into each recipient's token account. Rounding dust
+// goes to the last recipient. The charge is added to `total_fees_collected`.
+//
+// Accounts, only read when the price is non-zero:
+//   0.. `[writable]` each recipient's wallet, or its token account for `price_mint`,
+//       in split order
+//   n. `[writable]` payer's token account for `price_mint` (SPL prices only)
+#[allow(clippy::too_many_arguments)]
+fn collect_mint_fee<'a, 'b>(
+    config: &GameConfig,
+    price: u64,
+    contract_state: &mut ContractState,
+    payer: &AccountInfo<'a>,
+    account_info_iter: &mut std::slice::Iter<'b, AccountInfo<'a>>,
+    token_program: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+) -> ProgramResult {
+    if price == 0 {
+        return Ok(());
+    }
+    contract_state.total_fees_collected = contract_state.total_fees_collected.saturating_add(price);
+    let recipients = if contract_state.treasury_split.is_empty() {
+        vec![TreasuryShare {
+            recipient: contract_state.treasury,
+            bps: MAX_BPS,
+        }]
+    } else {
+        contract_state.treasury_split.clone()
+    };
+
+    let mut shares = Vec::with_capacity(recipients.len());
+    let mut remaining = price;
+    for (index, share) in recipients.iter().enumerate() {
+        let amount = if index + 1 == recipients.len() {
+            remaining
+        } else {
+            (price as u128 * share.bps as u128 / MAX_BPS as u128) as u64
+        };
+        remaining -= amount;
+        shares.push((
+            next_account_info(account_info_iter)?,
+            share.recipient,
+            amount,
+        ));
+    }
+
+    if config.price_mint == Pubkey::default() {
+        for (recipient_account, recipient, amount) in shares {
+            if *recipient_account.key != recipient {
+                return Err(CustomError::InvalidTreasury.into());
+            }
+            if amount == 0 {
+                continue;
+            }
+            invoke(
+                &system_instruction::transfer(payer.key, &recipient, amount),
+                &[
+                    payer.clone(),
+                    recipient_account.clone(),
+                    system_program.clone(),
+                ],
+            )?;
+        }
+        return Ok(());
+    }
+
+    for (recipient_account, recipient, _) in &shares {
+        let recipient_token = TokenAccount::unpack(&recipient_account.data.borrow())
+            .map_err(|_| CustomError::InvalidTreasury)?;
+        if recipient_token.mint != config.price_mint || recipient_token.owner != *recipient {
+            return Err(CustomError::InvalidTreasury.into());
+        }
+    }
+    let payer_token_account = next_account_info(account_info_iter)?;
+    for (recipient_account, _, amount) in shares {
+        if amount == 0 {
+            continue;
+        }
+        invoke(
+            &token_instruction(
+                spl_token::instruction::transfer(
+                    &spl_token::id(),
+                    payer_token_account.key,
+                    recipient_account.key,
+                    payer.key,
+                    &[],
+                    amount,
+                )?,
+                token_program,
+            ),
+            &[
+                payer_token_account.clone(),
+                recipient_account.clone(),
+                payer.clone(),
+                token_program.clone(),
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+// Closes a program-owned account, moving its lamports to `destination` and
+// zeroing its data so the runtime reclaims it.
+fn close_pda_account(account: &AccountInfo, destination: &AccountInfo) -> ProgramResult {
+    let lamports = account.lamports();
+    **destination.try_borrow_mut_lamports()? += lamports;
+    **account.try_borrow_mut_lamports()? = 0;
+    account.try_borrow_mut_data()?.fill(0);
+    Ok(())
+}
+
+// Invokes the game's burn callback program, when it sets one, with a
+// `BurnNotification` for the burned token.
+//
+// Accounts, only read when the game sets a burn callback:
+//   0. `[]` the game's burn callback program
+fn notify_burn<'a, 'b>(
+    game_record: &GameRecord,
+    game_account: &AccountInfo<'a>,
+    record: &TokenRecord,
+    account_info_iter: &mut std::slice::Iter<'b, AccountInfo<'a>>,
+) -> ProgramResult {
+    let callback_program = game_record.config.burn_callback_program;
+    if callback_program == Pubkey::default() {
+        return Ok(());
+    }
+    let callback_account = next_account_info(account_info_iter)?;
+    if *callback_account.key != callback_program {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let notification = BurnNotification {
+        game_id: record.game_id.clone(),
+        token_id: record.token_id,
+        former_owner: record.owner,
+    };
+    invoke(
+        &Instruction::new_with_bytes(
+            callback_program,
+            &notification.try_to_vec()?,
+            vec![AccountMeta::new_readonly(*game_account.key, false)],
+        ),
+        &[game_account.clone(), callback_account.clone()],
+    )
+}
+
+// Clients find a token's record, mint and URI PDAs from its id with these
+pub fn token_record_address(program_id: &Pubkey, token_id: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[TOKEN_SEED, &token_id.to_le_bytes()], program_id)
+}
+
+pub fn token_mint_address(program_id: &Pubkey, token_id: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[MINT_SEED, &token_id.to_le_bytes()], program_id)
+}
+
+pub fn token_uri_address(program_id: &Pubkey, token_id: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[URI_SEED, &token_id.to_le_bytes()], program_id)
+}
+
+fn token_history_chunk_address(
+    program_id: &Pubkey,
+    token_id: u64,
+    chunk_index: u32,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            HISTORY_SEED,
+            &token_id.to_le_bytes(),
+            &chunk_index.to_le_bytes(),
+        ],
+        program_id,
+    )
+}
+
+// The contract PDA holds the program's one ContractState and signs for the
+// contract, e.g. as burn delegate and metadata update authority
+pub fn contract_pda_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[CONTRACT_SEED], program_id)
+}
+
+pub fn pending_change_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    let contract = contract_pda_address(program_id).0;
+    Pubkey::find_program_address(&[PENDING_CHANGE_SEED, contract.as_ref()], program_id)
+}
+
+fn game_tokens_chunk_address(program_id: &Pubkey, game_id: &str, chunk_index: u32) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            GAME_TOKENS_SEED,
+            game_id.as_bytes(),
+            &chunk_index.to_le_bytes(),
+        ],
+        program_id,
+    )
+}
+
+// Creates and initializes the contract PDA, failing with AlreadyInitialized
+// once it exists. Only the program's upgrade authority can do so, so nobody
+// can claim a fresh deployment before its owner does.
+//
+// Accounts:
+//   0. `[writable]` contract PDA
+//   1. `[]` rent sysvar
+//   2. `[signer, writable]` owner, the program's upgrade authority; pays for
+//      the contract PDA
+//   3. `[]` system program
+//   4. `[]` the program's ProgramData account
+fn initialize_contract(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    owner: Pubkey,
+    max_supply: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let rent_account = next_account_info(account_info_iter)?;
+    let owner_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+    let program_data_account = next_account_info(account_info_iter)?;
+    if *owner_account.key != owner || !owner_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let (contract_address, contract_bump) = contract_pda_address(program_id);
+    if contract_address != *contract_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    // nothing but this creates the contract PDA, and it initializes it too
+    if !contract_account.data_is_empty() {
+        return Err(CustomError::AlreadyInitialized.into());
+    }
+    if upgrade_authority(program_id, program_data_account)? != Some(owner) {
+        return Err(CustomError::NotAdmin.into());
+    }
+
+    create_pda_account(
+        program_id,
+        owner_account,
+        contract_account,
+        system_program,
+        ContractState::LEN,
+        &[CONTRACT_SEED, &[contract_bump]],
+    )?;
+    let rent = &Rent::from_account_info(rent_account)?;
+    assert_rent_exempt(contract_account, rent)?;
+
+    let mut contract_state: ContractState =
+        load_account(contract_account, CustomError::StateCorrupted)?;
+    contract_state.is_initialized = true;
+    contract_state.contract_owner = owner;
+    contract_state.last_token_id = 0;
+    contract_state.treasury = owner;
+    contract_state.minting_enabled = true;
+    contract_state.allowed_token_programs = vec![spl_token::id()];
+    contract_state.max_supply = max_supply;
+    store_account(
+        &contract_state,
+        contract_account,
+        CustomError::StateCorrupted,
+    )?;
+
+    Ok(())
+}
+
+// Sets up a fresh deployment in one transaction: initializes the contract for
+// `owner` without a supply cap, opens and activates game_id with the default
+// config, and grants `owner` permission to mint it with token_uri. Fails with
+// AlreadyInitialized when the contract already is.
+//
+// Accounts:
+//   0. `[writable]` contract PDA
+//   1. `[]` rent sysvar
+//   2. `[signer, writable]` owner, the program's upgrade authority; pays for
+//      new PDAs
+//   3. `[writable]` permission PDA for (owner, game_id)
+//   4. `[writable]` game PDA for game_id
+//   5. `[]` system program
+//   6. `[]` the program's ProgramData account
+fn bootstrap(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    owner: Pubkey,
+    game_id: String,
+    token_uri: String,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let rent_account = next_account_info(account_info_iter)?;
+    let owner_account = next_account_info(account_info_iter)?;
+    let permission_account = next_account_info(account_info_iter)?;
+    let game_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+    let program_data_account = next_account_info(account_info_iter)?;
+
+    initialize_contract(
+        program_id,
+        &[
+            contract_account.clone(),
+            rent_account.clone(),
+            owner_account.clone(),
+            system_program.clone(),
+            program_data_account.clone(),
+        ],
+        owner,
+        0,
+    )?;
+
+    let mut contract_state: ContractState =
+        load_account(contract_account, CustomError::StateCorrupted)?;
+    let mint_permission = MintPermission {
+        user: owner,
+        game_id: game_id.clone(),
+        token_uri,
+        name: String::new(),
+        minted: 0,
+    };
+    write_mint_permission(
+        program_id,
+        &mut contract_state,
+        mint_permission,
+        false,
+        owner_account,
+        permission_account,
+        game_account,
+        system_program,
+    )?;
+    store_account(
+        &contract_state,
+        contract_account,
+        CustomError::StateCorrupted,
+    )?;
+
+    let mut game_record: GameRecord = load_account(game_account, CustomError::StateCorrupted)?;
+    game_record.active = true;
+    store_account(&game_record, game_account, CustomError::StateCorrupted)
+}
+
+// Accounts:
+//   0. `[writable]` contract state account
+//   1. `[signer, writable]` admin (contract owner), pays for new PDAs
+//   2. `[writable]` permission PDA for (user, game_id)
+//   3. `[writable]` game PDA for game_id, created on the first grant
+//   4. `[]` system program
+//   5. `[writable]` user grant PDA for user, only while a grant cooldown is set
+fn grant_mint(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    user: Pubkey,
+    game_id: String,
+    token_uri: String,
+    name: String,
+    overwrite: bool,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let admin_account = next_account_info(account_info_iter)?;
+    let permission_account = next_account_info(account_info_iter)?;
+    let game_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+
+    let mut contract_state: ContractState =
+        load_account(contract_account, CustomError::StateCorrupted)?;
+    assert_owner(program_id, contract_account, &contract_state, admin_account)?;
+    if contract_state.grant_cooldown_slots > 0 {
+        let user_grant_account = next_account_info(account_info_iter)?;
+        record_grant_slot(
+            program_id,
+            &contract_state,
+            &user,
+            admin_account,
+            user_grant_account,
+            system_program,
+        )?;
+    }
+
+    let mint_permission = MintPermission {
+        user,
+        game_id,
+        token_uri,
+        name,
+        minted: 0,
+    };
+    write_mint_permission(
+        program_id,
+        &mut contract_state,
+        mint_permission,
+        overwrite,
+        admin_account,
+        permission_account,
+        game_account,
+        system_program,
+    )?;
+    store_account(
+        &contract_state,
+        contract_account,
+        CustomError::StateCorrupted,
+    )
+}
+
+// Fails with GrantCooldownActive when `user` was granted less than
+// `grant_cooldown_slots` ago, otherwise records the current slot in the user's
+// grant PDA, creating it on the user's first grant.
+fn record_grant_slot<'a>(
+    program_id: &Pubkey,
+    contract_state: &ContractState,
+    user: &Pubkey,
+    payer: &AccountInfo<'a>,
+    user_grant_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+) -> ProgramResult {
+    let (user_grant_address, user_grant_bump) =
+        Pubkey::find_program_address(&[USER_GRANT_SEED, user.as_ref()], program_id);
+    if user_grant_address != *user_grant_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    let slot = Clock::get()?.slot;
+    if user_grant_account.data_is_empty() {
+        create_pda_account(
+            program_id,
+            payer,
+            user_grant_account,
+            system_program,
+            UserGrantRecord::LEN,
+            &[USER_GRANT_SEED, user.as_ref(), &[user_grant_bump]],
+        )?;
+    } else {
+        let previous: UserGrantRecord =
+            load_account(user_grant_account, CustomError::StateCorrupted)?;
+        if slot
+            < previous
+                .last_grant_slot
+                .saturating_add(contract_state.grant_cooldown_slots)
+        {
+            return Err(CustomError::GrantCooldownActive.into());
+        }
+    }
+
+    store_account(
+        &UserGrantRecord {
+            user: *user,
+            last_grant_slot: slot,
+        },
+        user_grant_account,
+        CustomError::StateCorrupted,
+    )
+}
+
+// Validates `mint_permission` and writes it into its permission PDA, creating
+// the PDA and the game account when missing, paid for by `payer`. An existing
+// permission is only replaced when `overwrite` is set, and keeps its `minted`
+// count; otherwise it fails with GrantExists. New permissions are counted in
+// `total_grants`, which the caller stores.
+#[allow(clippy::too_many_arguments)]
+fn write_mint_permission<'a>(
+    program_id: &Pubkey,
+    contract_state: &mut ContractState,
+    mut mint_permission: MintPermission,
+    overwrite: bool,
+    payer: &AccountInfo<'a>,
+    permission_account: &AccountInfo<'a>,
+    game_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+) -> ProgramResult {
+    let user = &mint_permission.user;
+    let game_id = &mint_permission.game_id;
+    if game_id.len() > MAX_GAME_ID_LEN || mint_permission.name.len() > MAX_TOKEN_NAME_LEN {
+        return Err(CustomError::InvalidInstruction.into());
+    }
+    if !mint_permission.token_uri.is_empty() {
+        validate_token_uri(contract_state, &mint_permission.token_uri)?;
+    }
+
+    let (permission_address, permission_bump) = Pubkey::find_program_address(
+        &[PERMISSION_SEED, user.as_ref(), game_id.as_bytes()],
+        program_id,
+    );
+    if permission_address != *permission_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if !permission_account.data_is_empty() {
+        if !overwrite {
+            return Err(CustomError::GrantExists.into());
+        }
+        let existing: MintPermission =
+            load_account(permission_account, CustomError::PermissionCorrupted)?;
+        mint_permission.minted = existing.minted;
+    } else {
+        mint_permission.minted = 0;
+        contract_state.total_grants += 1;
+        create_pda_account(
+            program_id,
+            payer,
+            permission_account,
+            system_program,
+            MintPermission::LEN,
+            &[
+                PERMISSION_SEED,
+                user.as_ref(),
+                game_id.as_bytes(),
+                &[permission_bump],
+            ],
+        )?;
+    }
+    open_game_account(program_id, payer, game_account, system_program, game_id)?;
+
+    assert_rent_exempt(permission_account, &Rent::get()?)?;
+    store_account(
+        &mint_permission,
+        permission_account,
+        CustomError::PermissionCorrupted,
+    )
+}
+
+// Accounts:
+//   0. `[writable]` contract state account
+//   1. `[writable]` mint PDA for the new token id, created here
+//   2. `[writable]` receiver token account, allocated for the token program; it
+//      is initialized for the new mint and `receiver` unless it already is
+//   3. `[writable]` permission PDA for (minter, game_id)
+//   4. `[signer, writable]` minter (the permitted user), pays for new accounts
+//   5. `[]` mint authority PDA for the mint
+//   6. `[writable]` game PDA for game_id
+//   7. `[writable]` game token-list chunk PDA the new token id lands in
+//   8. `[writable]` token record PDA for the new token id
+//   9. `[writable]` token metadata PDA for the mint
+//   10. `[writable]` token URI PDA for the new token id
+//   11. `[]` game metadata template PDA for game_id, possibly never set
+//   12. `[]` token program the new token lives on, classic SPL Token or
+//       Token-2022; transfers and burns pass the same one
+//   13. `[]` system program
+//   14. `[]` receiver's KYC attestation PDA, only checked with the KYC gate on
+//   15. `[writable]` receiver's game holding PDA, created on their first token
+//   16. `[writable]` token history chunk PDA 0 for the new token id
+//   17. `[]` receiver's token account for the game's required token mint, only
+//       passed when the game sets one
+//   17/18. `[]` the game's validator program, only passed when the game sets one
+//   17-19.. receipt accounts, only passed when the game mints receipts, see
+//       `mint_receipt`
+//   17-24.. minter's game holding PDA and the game's price feed, each only
+//       passed when the game sets a holder discount or a USD price, see
+//       `mint_price`
+//   17-26.. fee accounts, see `collect_mint_fee`
+//   then, optionally and only read when the game pays referral rewards, the
+//   referral accounts, see `pay_referral_reward`
+//
+// The new token id is `last_token_id + 1`; clients derive the per-token PDAs
+// from it, so concurrent mints racing for the same id fail and can be retried.
+//
+// When the game's `reserve_every_n` makes this its Nth token, it goes to the
+// treasury instead of `receiver`: accounts 14 and 15 are then the treasury's,
+// the required token, fee and permission count are skipped, and the minter
+// pays only for the new accounts.
+fn mint(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    receiver: Pubkey,
+    game_id: String,
+) -> ProgramResult {
+    mint_next(program_id, &mut accounts.iter(), receiver, game_id, true)
+}
+
+// Mints one token from the next accounts `account_info_iter` yields, laid out
+// as for Mint. The trailing referral accounts are only read when
+// `pay_referral` is set.
+fn mint_next<'a, 'b>(
+    program_id: &Pubkey,
+    account_info_iter: &mut std::slice::Iter<'b, AccountInfo<'a>>,
+    receiver: Pubkey,
+    game_id: String,
+    pay_referral: bool,
+) -> ProgramResult {
+    let contract_account = next_account_info(account_info_iter)?;
+    let mint_account = next_account_info(account_info_iter)?;
+    let receiver_account = next_account_info(account_info_iter)?;
+    let permission_account = next_account_info(account_info_iter)?;
+    let minter_account = next_account_info(account_info_iter)?;
+    let mint_authority = next_account_info(account_info_iter)?;
+    let game_account = next_account_info(account_info_iter)?;
+    let chunk_account = next_account_info(account_info_iter)?;
+    let record_account = next_account_info(account_info_iter)?;
+    let metadata_account = next_account_info(account_info_iter)?;
+    let uri_account = next_account_info(account_info_iter)?;
+    let template_account = next_account_info(account_info_iter)?;
+    let token_program = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+    let kyc_account = next_account_info(account_info_iter)?;
+    let holding_account = next_account_info(account_info_iter)?;
+    let history_account = next_account_info(account_info_iter)?;
+    if !minter_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    // the PDAs created below are funded by `create_pda_account`; the ones
+    // already there are checked before they're written
+    let rent = Rent::get()?;
+
+    let mut contract_state: ContractState = load_contract(program_id, contract_account)?;
+    assert_not_paused(&contract_state)?;
+    if !contract_state.minting_enabled {
+        return Err(CustomError::MintingDisabled.into());
+    }
+    assert_token_program(&contract_state, token_program)?;
+
+    let (permission_address, _) = Pubkey::find_program_address(
+        &[
+            PERMISSION_SEED,
+            minter_account.key.as_ref(),
+            game_id.as_bytes(),
+        ],
+        program_id,
+    );
+    if permission_address != *permission_account.key
+        || permission_account.owner != program_id
+        || permission_account.data_is_empty()
+    {
+        return Err(CustomError::MintNotPermitted.into());
+    }
+    let mut mint_permission: MintPermission =
+        load_account(permission_account, CustomError::PermissionCorrupted)?;
+
+    if mint_permission.user != *minter_account.key || mint_permission.game_id != game_id {
+        return Err(CustomError::MintNotPermitted.into());
+    }
+
+    let (game_address, _) =
+        Pubkey::find_program_address(&[GAME_SEED, game_id.as_bytes()], program_id);
+    if game_address != *game_account.key || game_account.owner != program_id {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let mut game_record: GameRecord = load_account(game_account, CustomError::StateCorrupted)?;
+    if !game_record.active {
+        return Err(CustomError::GameNotActive.into());
+    }
+    assert_supply_available(&game_record)?;
+    assert_token_ids_available(
+        &contract_state,
+        if game_record.config.mint_receipt {
+            2
+        } else {
+            1
+        },
+    )?;
+
+    let reserve_every_n = game_record.config.reserve_every_n;
+    let reserved =
+        reserve_every_n > 0 && (game_record.token_count + 1).is_multiple_of(reserve_every_n);
+    let receiver = if reserved {
+        contract_state.treasury
+    } else {
+        assert_kyc_attested(program_id, &contract_state, &receiver, kyc_account)?;
+        receiver
+    };
+
+    let (template_address, _) =
+        Pubkey::find_program_address(&[TEMPLATE_SEED, game_id.as_bytes()], program_id);
+    if template_address != *template_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let template: GameMetadataTemplate =
+        if template_account.owner == program_id && !template_account.data_is_empty() {
+            load_account(template_account, CustomError::StateCorrupted)?
+        } else {
+            GameMetadataTemplate::default()
+        };
+
+    let token_id = contract_state.last_token_id + 1;
+    let (mint_address, mint_bump) = token_mint_address(program_id, token_id);
+    if mint_address != *mint_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let (authority_address, authority_bump) = Pubkey::find_program_address(
+        &[MINT_AUTHORITY_SEED, mint_account.key.as_ref()],
+        program_id,
+    );
+    if authority_address != *mint_authority.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let (record_address, record_bump) = token_record_address(program_id, token_id);
+    if record_address != *record_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let (metadata_address, metadata_bump) =
+        Pubkey::find_program_address(&[METADATA_SEED, mint_account.key.as_ref()], program_id);
+    if metadata_address != *metadata_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let (uri_address, uri_bump) = token_uri_address(program_id, token_id);
+    if uri_address != *uri_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let chunk_index = (game_record.token_count / GAME_TOKENS_PER_CHUNK) as u32;
+    let (chunk_address, chunk_bump) = game_tokens_chunk_address(program_id, &game_id, chunk_index);
+    if chunk_address != *chunk_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    // A token held by one of the contract's own accounts could never be moved
+    // again, as none of them signs transfers
+    if [*contract_account.key, authority_address, game_address].contains(&receiver) {
+        return Err(CustomError::InvalidReceiver.into());
+    }
+    if !mint_account.data_is_empty()
+        || !record_account.data_is_empty()
+        || !metadata_account.data_is_empty()
+        || !uri_account.data_is_empty()
+    {
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+    let uri = if mint_permission.token_uri.is_empty() {
+        template_token_uri(&template.uri_prefix, token_id)?
+    } else {
+        mint_permission.token_uri.clone()
+    };
+
+    let required_mint = game_record.config.required_token_mint;
+    if required_mint != Pubkey::default() && !reserved {
+        let required_account = next_account_info(account_info_iter)?;
+        if required_account.owner != token_program.key {
+            return Err(CustomError::RequiredTokenMissing.into());
+        }
+        let holding = TokenAccount::unpack(&required_account.data.borrow())
+            .map_err(|_| CustomError::RequiredTokenMissing)?;
+        if holding.mint != required_mint || holding.owner != receiver || holding.amount == 0 {
+            return Err(CustomError::RequiredTokenMissing.into());
+        }
+    }
+
+    if contract_state.max_metadata_rent > 0
+        && metadata_rent(&Rent::get()?) > contract_state.max_metadata_rent
+    {
+        return Err(CustomError::MetadataRentTooHigh.into());
+    }
+
+    let validator_program = game_record.config.validator_program;
+    if validator_program != Pubkey::default() {
+        let validator_account = next_account_info(account_info_iter)?;
+        if *validator_account.key != validator_program {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let validation = MintValidation {
+            game_id: game_id.clone(),
+            token_id,
+            minter: *minter_account.key,
+            receiver,
+        };
+        invoke(
+            &Instruction::new_with_bytes(
+                validator_program,
+                &validation.try_to_vec()?,
+                vec![
+                    AccountMeta::new_readonly(*game_account.key, false),
+                    AccountMeta::new_readonly(*minter_account.key, false),
+                ],
+            ),
+            &[
+                game_account.clone(),
+                minter_account.clone(),
+                validator_account.clone(),
+            ],
+        )?;
+    }
+
+    let receipt_accounts = if game_record.config.mint_receipt {
+        Some([
+            next_account_info(account_info_iter)?,
+            next_account_info(account_info_iter)?,
+            next_account_info(account_info_iter)?,
+            next_account_info(account_info_iter)?,
+            next_account_info(account_info_iter)?,
+        ])
+    } else {
+        None
+    };
+
+    // Every precondition has been checked, so the fee is only charged for a
+    // mint that can go through. Anything failing past this point (including
+    // the token program CPIs) fails the transaction, which reverts the fee.
+    let mut price = game_record.config.price_amount;
+    if !reserved {
+        price = mint_price(
+            program_id,
+            &game_record,
+            minter_account.key,
+            account_info_iter,
+        )?;
+        collect_mint_fee(
+            &game_record.config,
+            price,
+            &mut contract_state,
+            minter_account,
+            account_info_iter,
+            token_program,
+            system_program,
+        )?;
+        if pay_referral {
+            pay_referral_reward(
+                program_id,
+                &game_record,
+                &contract_state,
+                &receiver,
+                minter_account,
+                account_info_iter,
+                system_program,
+            )?;
+        }
+        mint_permission.minted += 1;
+        assert_rent_exempt(permission_account, &rent)?;
+        store_account(
+            &mint_permission,
+            permission_account,
+            CustomError::PermissionCorrupted,
+        )?;
+    }
+
+    contract_state.last_token_id = if receipt_accounts.is_some() {
+        contract_state.active_tokens += 2;
+        token_id
+            .checked_add(1)
+            .ok_or(CustomError::InvalidInstruction)?
+    } else {
+        contract_state.active_tokens += 1;
+        token_id
+    };
+    store_account(
+        &contract_state,
+        contract_account,
+        CustomError::StateCorrupted,
+    )?;
+
+    // Append the new id to the game's token list, opening a new chunk when the
+    // current one is full
+    let mut chunk = if chunk_account.data_is_empty() {
+        create_pda_account(
+            program_id,
+            minter_account,
+            chunk_account,
+            system_program,
+            GameTokenChunk::LEN,
+            &[
+                GAME_TOKENS_SEED,
+                game_id.as_bytes(),
+                &chunk_index.to_le_bytes(),
+                &[chunk_bump],
+            ],
+        )?;
+        GameTokenChunk { token_ids: vec![] }
+    } else {
+        load_account(chunk_account, CustomError::StateCorrupted)?
+    };
+    chunk.token_ids.push(token_id);
+    assert_rent_exempt(chunk_account, &rent)?;
+    store_account(&chunk, chunk_account, CustomError::StateCorrupted)?;
+    game_record.token_count += 1;
+    game_record.minted += 1;
+    credit_holding(
+        program_id,
+        &mut game_record,
+        &receiver,
+        holding_account,
+        minter_account,
+        system_program,
+    )?;
+    assert_rent_exempt(game_account, &rent)?;
+    store_account(&game_record, game_account, CustomError::StateCorrupted)?;
+
+    create_pda_account(
+        program_id,
+        minter_account,
+        record_account,
+        system_program,
+        TokenRecord::LEN,
+        &[TOKEN_SEED, &token_id.to_le_bytes(), &[record_bump]],
+    )?;
+    let lifetime = game_record.config.token_lifetime_slots;
+    let expires_at_slot = if lifetime == 0 {
+        0
+    } else {
+        Clock::get()?.slot.saturating_add(lifetime)
+    };
+    let mut token_record = TokenRecord {
+        version: TOKEN_RECORD_VERSION,
+        token_id,
+        owner: receiver,
+        game_id: game_id.clone(),
+        mint: *mint_account.key,
+        burn_queued: false,
+        burned: false,
+        uri_account: uri_address,
+        value: price,
+        expires_at_slot,
+        soulbound: game_record.config.soulbound,
+        is_receipt: false,
+        cooldown_ends_slot: 0,
+        history_len: 0,
+    };
+    append_history(
+        program_id,
+        &mut token_record,
+        HistoryEvent::Mint,
+        Pubkey::default(),
+        receiver,
+        history_account,
+        minter_account,
+        system_program,
+    )?;
+    store_account(&token_record, record_account, CustomError::StateCorrupted)?;
+
+    create_pda_account(
+        program_id,
+        minter_account,
+        uri_account,
+        system_program,
+        TokenUri::LEN,
+        &[URI_SEED, &token_id.to_le_bytes(), &[uri_bump]],
+    )?;
+    let token_uri = TokenUri { token_id, uri };
+    store_account(&token_uri, uri_account, CustomError::StateCorrupted)?;
+
+    create_pda_account(
+        program_id,
+        minter_account,
+        metadata_account,
+        system_program,
+        TokenMetadata::LEN,
+        &[METADATA_SEED, mint_account.key.as_ref(), &[metadata_bump]],
+    )?;
+    let (royalty_bps, royalty_recipient) =
+        if game_record.config.royalty_recipient == Pubkey::default() {
+            (
+                contract_state.default_royalty_bps,
+                contract_state.default_royalty_recipient,
+            )
+        } else {
+            (
+                game_record.config.royalty_bps,
+                game_record.config.royalty_recipient,
+            )
+        };
+    let metadata = TokenMetadata {
+        mint: *mint_account.key,
+        update_authority: contract_pda_address(program_id).0,
+        uri_account: uri_address,
+        name: if mint_permission.name.is_empty() {
+            format!("{} #{}", template.name_template, token_id)
+        } else {
+            mint_permission.name
+        },
+        symbol: template.symbol,
+        royalty_bps,
+        royalty_recipient,
+    };
+    store_account(&metadata, metadata_account, CustomError::StateCorrupted)?;
+
+    create_pda_account(
+        token_program.key,
+        minter_account,
+        mint_account,
+        system_program,
+        Mint::LEN,
+        &[MINT_SEED, &token_id.to_le_bytes(), &[mint_bump]],
+    )?;
+    invoke(
+        &token_instruction(
+            initialize_mint2(
+                &spl_token::id(),
+                mint_account.key,
+                mint_authority.key,
+                // lets BurnExpired freeze the token once it expires
+                (expires_at_slot != 0).then_some(mint_authority.key),
+                0,
+            )?,
+            token_program,
+        ),
+        &[mint_account.clone(), token_program.clone()],
+    )?;
+    assert_nft_mint(mint_account, 0)?;
+    init_token_account(receiver_account, mint_account, &receiver, token_program)?;
+    invoke_signed(
+        &token_instruction(
+            mint_to(
+                &spl_token::id(),
+                mint_account.key,
+                receiver_account.key,
+                mint_authority.key,
+                &[],
+                1,
+            )?,
+            token_program,
+        ),
+        &[
+            mint_account.clone(),
+            receiver_account.clone(),
+            mint_authority.clone(),
+            token_program.clone(),
+        ],
+        &[&[
+            MINT_AUTHORITY_SEED,
+            mint_account.key.as_ref(),
+            &[authority_bump],
+        ]],
+    )?;
+    assert_nft_mint(mint_account, 1)?;
+    msg!(
+        "{}:MINT:{}:{}:{}",
+        EVENT_LOG_PREFIX,
+        token_id,
+        receiver,
+        game_id
+    );
+
+    if let Some(receipt_accounts) = receipt_accounts {
+        mint_receipt(
+            program_id,
+            token_id + 1,
+            game_id,
+            minter_account,
+            receipt_accounts,
+            token_program,
+            system_program,
+        )?;
+    }
+
+    Ok(())
+}
+
+// Mints `count` tokens of game_id to `receiver` in one go, each exactly as a
+// Mint would, so they get consecutive ids and every one is checked against
+// the supply caps, charged and logged. Batches pay no referral rewards.
+//
+// Accounts:
+//   0.. one group per token, in id order: the accounts Mint takes for it,
+//       without the referral accounts
+fn batch_mint(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    receiver: Pubkey,
+    game_id: String,
+    count: u16,
+) -> ProgramResult {
+    if count == 0 || count > MAX_BATCH_MINT {
+        return Err(CustomError::InvalidInstruction.into());
+    }
+    let account_info_iter = &mut accounts.iter();
+    for _ in 0..count {
+        mint_next(
+            program_id,
+            account_info_iter,
+            receiver,
+            game_id.clone(),
+            false,
+        )?;
+    }
+
+    Ok(())
+}
+
+// Mints `minter` the receipt for a game mint, as token `receipt_id`. The
+// receipt is soulbound and gets only a token record, flagged `is_receipt`.
+//
+// Accounts:
+//   0. `[writable]` mint PDA for receipt_id, created here
+//   1. `[]` mint authority PDA for that mint
+//   2. `[writable]` minter's token account for the receipt, allocated for the
+//      token program and initialized here unless it already is
+//   3. `[writable]` token record PDA for receipt_id
+//   4. `[writable]` token history chunk PDA 0 for receipt_id
+fn mint_receipt<'a>(
+    program_id: &Pubkey,
+    receipt_id: u64,
+    game_id: String,
+    minter_account: &AccountInfo<'a>,
+    receipt_accounts: [&AccountInfo<'a>; 5],
+    token_program: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+) -> ProgramResult {
+    let [mint_account, mint_authority, token_account, record_account, history_account] =
+        receipt_accounts;
+    let (mint_address, mint_bump) = token_mint_address(program_id, receipt_id);
+    let (authority_address, authority_bump) =
+        Pubkey::find_program_address(&[MINT_AUTHORITY_SEED, mint_address.as_ref()], program_id);
+    let (record_address, record_bump) = token_record_address(program_id, receipt_id);
+    if mint_address != *mint_account.key
+        || authority_address != *mint_authority.key
+        || record_address != *record_account.key
+    {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if !mint_account.data_is_empty() || !record_account.data_is_empty() {
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    create_pda_account(
+        program_id,
+        minter_account,
+        record_account,
+        system_program,
+        TokenRecord::LEN,
+        &[TOKEN_SEED, &receipt_id.to_le_bytes(), &[record_bump]],
+    )?;
+    let mut receipt_record = TokenRecord {
+        version: TOKEN_RECORD_VERSION,
+        token_id: receipt_id,
+        owner: *minter_account.key,
+        game_id,
+        mint: mint_address,
+        burn_queued: false,
+        burned: false,
+        uri_account: Pubkey::default(),
+        value: 0,
+        expires_at_slot: 0,
+        soulbound: true,
+        is_receipt: true,
+        cooldown_ends_slot: 0,
+        history_len: 0,
+    };
+    append_history(
+        program_id,
+        &mut receipt_record,
+        HistoryEvent::Mint,
+        Pubkey::default(),
+        *minter_account.key,
+        history_account,
+        minter_account,
+        system_program,
+    )?;
+    store_account(&receipt_record, record_account, CustomError::StateCorrupted)?;
+
+    create_pda_account(
+        token_program.key,
+        minter_account,
+        mint_account,
+        system_program,
+        Mint::LEN,
+        &[MINT_SEED, &receipt_id.to_le_bytes(), &[mint_bump]],
+    )?;
+    invoke(
+        &token_instruction(
+            initialize_mint2(
+                &spl_token::id(),
+                mint_account.key,
+                mint_authority.key,
+                None,
+                0,
+            )?,
+            token_program,
+        ),
+        &[mint_account.clone(), token_program.clone()],
+    )?;
+    init_token_account(
+        token_account,
+        mint_account,
+        minter_account.key,
+        token_program,
+    )?;
+    invoke_signed(
+        &token_instruction(
+            mint_to(
+                &spl_token::id(),
+                mint_account.key,
+                token_account.key,
+                mint_authority.key,
+                &[],
+                1,
+            )?,
+            token_program,
+        ),
+        &[
+            mint_account.clone(),
+            token_account.clone(),
+            mint_authority.clone(),
+            token_program.clone(),
+        ],
+        &[&[
+            MINT_AUTHORITY_SEED,
+            mint_account.key.as_ref(),
+            &[authority_bump],
+        ]],
+    )?;
+    assert_nft_mint(mint_account, 1)?;
+    msg!(
+        "{}:MINT:{}:{}:{}",
+        EVENT_LOG_PREFIX,
+        receipt_id,
+        minter_account.key,
+        receipt_record.game_id
+    );
+    Ok(())
+}
+
+// Accounts:
+//   0. `[]` contract state account
+//   1. `[writable]` token record PDA for token_id
+//   2. `[writable]` owner's token account holding the token
+//   3. `[writable]` receiver's token account for the token's mint
+//   4. `[signer, writable]` owner, the token's recorded owner; pays for the
+//      receiver's holding PDA
+//   5. `[]` token program
+//   6. `[]` receiver's KYC attestation PDA, only checked with the KYC gate on
+//   7. `[writable]` game PDA for the token's game
+//   8. `[writable]` owner's game holding PDA
+//   9. `[writable]` receiver's game holding PDA, created on their first token
+//   10. `[]` system program
+//   11. `[writable]` token history chunk PDA the transfer is logged in
+//   12. `[signer]` admin (contract owner), only needed when the token's value
+//      is above the transfer approval threshold
+fn transfer(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    token_id: u64,
+    owner: Pubkey,
+    receiver: Pubkey,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let record_account = next_account_info(account_info_iter)?;
+    let owner_token_account = next_account_info(account_info_iter)?;
+    let receiver_token_account = next_account_info(account_info_iter)?;
+    let owner_account = next_account_info(account_info_iter)?;
+    let token_program = next_account_info(account_info_iter)?;
+    let kyc_account = next_account_info(account_info_iter)?;
+    let game_account = next_account_info(account_info_iter)?;
+    let owner_holding_account = next_account_info(account_info_iter)?;
+    let receiver_holding_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+    let history_account = next_account_info(account_info_iter)?;
+
+    if *owner_account.key != owner {
+        return Err(CustomError::NotTokenOwner.into());
+    }
+    if token_record_address(program_id, token_id).0 != *record_account.key
+        || record_account.owner != program_id
+    {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let mut record: TokenRecord = load_account(record_account, CustomError::StateCorrupted)?;
+    // The record must describe this token and its mint PDA, or the owner
+    // update below would land on the wrong token
+    if record.token_id != token_id || record.mint != token_mint_address(program_id, token_id).0 {
+        return Err(CustomError::StateCorrupted.into());
+    }
+    if record.burned || record.burn_queued {
+        return Err(CustomError::TokenBurned.into());
+    }
+    assert_not_expired(&record)?;
+    if record.soulbound {
+        return Err(CustomError::Soulbound.into());
+    }
+    if record.owner != owner {
+        return Err(CustomError::NotTokenOwner.into());
+    }
+    if !owner_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let contract_state: ContractState = load_contract(program_id, contract_account)?;
+    assert_token_program(&contract_state, token_program)?;
+    assert_transfers_open(&contract_state)?;
+    assert_transfer_approved(&contract_state, record.value, account_info_iter.next())?;
+    assert_kyc_attested(program_id, &contract_state, &receiver, kyc_account)?;
+
+    let owner_holding = TokenAccount::unpack(&owner_token_account.data.borrow())?;
+    if owner_holding.mint != record.mint {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    // The owner could otherwise move someone else's copy as their delegate
+    if owner_holding.owner != record.owner {
+        return Err(CustomError::NotTokenOwner.into());
+    }
+    let receiver_holding = TokenAccount::unpack(&receiver_token_account.data.borrow())?;
+    if receiver_holding.mint != record.mint || receiver_holding.owner != receiver {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    invoke(
+        &token_instruction(
+            spl_token::instruction::transfer(
+                &spl_token::id(),
+                owner_token_account.key,
+                receiver_token_account.key,
+                owner_account.key,
+                &[],
+                1,
+            )?,
+            token_program,
+        ),
+        &[
+            owner_token_account.clone(),
+            receiver_token_account.clone(),
+            owner_account.clone(),
+            token_program.clone(),
+        ],
+    )?;
+
+    let rent = Rent::get()?;
+    assert_rent_exempt(game_account, &rent)?;
+    let game_record = move_holding(
+        program_id,
+        &record.game_id,
+        game_account,
+        &owner,
+        owner_holding_account,
+        &receiver,
+        receiver_holding_account,
+        owner_account,
+        system_program,
+    )?;
+
+    append_history(
+        program_id,
+        &mut record,
+        HistoryEvent::Transfer,
+        owner,
+        receiver,
+        history_account,
+        owner_account,
+        system_program,
+    )?;
+    record.owner = receiver;
+    if game_record.config.reset_cooldown_on_transfer {
+        record.cooldown_ends_slot = Clock::get()?.slot;
+    }
+    assert_rent_exempt(record_account, &rent)?;
+    store_account(&record, record_account, CustomError::StateCorrupted)?;
+    msg!(
+        "{}:TRANSFER:{}:{}:{}",
+        EVENT_LOG_PREFIX,
+        token_id,
+        owner,
+        receiver
+    );
+
+    Ok(())
+}
+
+// Burns the owner's token outright and closes its metadata and URI PDAs,
+// refunding their rent to the owner. The token record is kept, marked burned,
+// so CompactGameTokens, RecordSnapshot and VerifyBundleOwnership still see the
+// token as burned; its history chunks are left in place too, ending in the
+// Burn entry.
+//
+// Accounts:
+//   0. `[signer, writable]` the token's recorded owner, pays for a new history
+//      chunk
+//   1. `[writable]` the token's mint
+//   2. `[writable]` owner's token account holding the token
+//   3. `[writable]` token record PDA for token_id
+//   4. `[writable]` contract state account
+//   5. `[writable]` game PDA for the token's game
+//   6. `[writable]` owner's game holding PDA
+//   7. `[writable]` token metadata PDA for the mint
+//   8. `[writable]` token URI PDA for token_id
+//   9. `[]` token program
+//   10. `[writable]` token history chunk PDA the Burn entry lands in
+//   11. `[]` system program
+//   12. `[]` the game's burn callback program, only passed when the game sets
+//       one, see `notify_burn`
+fn burn(program_id: &Pubkey, accounts: &[AccountInfo], token_id: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner_account = next_account_info(account_info_iter)?;
+    let mint_account = next_account_info(account_info_iter)?;
+    let token_account = next_account_info(account_info_iter)?;
+    let record_account = next_account_info(account_info_iter)?;
+    let contract_account = next_account_info(account_info_iter)?;
+    let game_account = next_account_info(account_info_iter)?;
+    let holding_account = next_account_info(account_info_iter)?;
+    let metadata_account = next_account_info(account_info_iter)?;
+    let uri_account = next_account_info(account_info_iter)?;
+    let token_program = next_account_info(account_info_iter)?;
+    let history_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+    if !owner_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    if token_record_address(program_id, token_id).0 != *record_account.key
+        || record_account.owner != program_id
+    {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let mut record: TokenRecord = load_account(record_account, CustomError::StateCorrupted)?;
+    if record.burned {
+        return Err(CustomError::TokenBurned.into());
+    }
+    if record.owner != *owner_account.key {
+        return Err(CustomError::NotTokenOwner.into());
+    }
+    if record.mint != *mint_account.key {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let mut contract_state: ContractState = load_contract(program_id, contract_account)?;
+    assert_token_program(&contract_state, token_program)?;
+    // The owner could otherwise burn someone else's copy as their delegate
+    let holding = TokenAccount::unpack(&token_account.data.borrow())?;
+    if holding.mint != record.mint || holding.owner != record.owner {
+        return Err(CustomError::NotTokenOwner.into());
+    }
+
+    invoke(
+        &token_instruction(
+            spl_burn(
+                &spl_token::id(),
+                token_account.key,
+                mint_account.key,
+                owner_account.key,
+                &[],
+                1,
+            )?,
+            token_program,
+        ),
+        &[
+            token_account.clone(),
+            mint_account.clone(),
+            owner_account.clone(),
+            token_program.clone(),
+        ],
+    )?;
+
+    if record.burn_queued {
+        record.burn_queued = false;
+        contract_state.queued_burns = contract_state
+            .queued_burns
+            .checked_sub(1)
+            .ok_or(CustomError::StateCorrupted)?;
+    }
+    contract_state.active_tokens = contract_state
+        .active_tokens
+        .checked_sub(1)
+        .ok_or(CustomError::StateCorrupted)?;
+    let owner = record.owner;
+    append_history(
+        program_id,
+        &mut record,
+        HistoryEvent::Burn,
+        owner,
+        Pubkey::default(),
+        history_account,
+        owner_account,
+        system_program,
+    )?;
+    record.burned = true;
+    store_account(&record, record_account, CustomError::StateCorrupted)?;
+    store_account(
+        &contract_state,
+        contract_account,
+        CustomError::StateCorrupted,
+    )?;
+
+    let mut game_record = load_game(program_id, &record.game_id, game_account)?;
+    // Receipts have no holding, metadata or URI
+    if !record.is_receipt {
+        let (metadata_address, _) =
+            Pubkey::find_program_address(&[METADATA_SEED, mint_account.key.as_ref()], program_id);
+        if metadata_address != *metadata_account.key || record.uri_account != *uri_account.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        debit_holding(program_id, &mut game_record, &record.owner, holding_account)?;
+        refill_supply(&mut game_record);
+        store_account(&game_record, game_account, CustomError::StateCorrupted)?;
+        close_pda_account(metadata_account, owner_account)?;
+        close_pda_account(uri_account, owner_account)?;
+    }
+    msg!("{}:BURN:{}", EVENT_LOG_PREFIX, token_id);
+
+    notify_burn(&game_record, game_account, &record, account_info_iter)
+}
+
+// Accounts:
+//   0. `[]` game PDA for game_id
+//   1.. `[]` the game token-list chunk PDAs covering the page, in order
+fn get_game_tokens(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    game_id: String,
+    start: u64,
+    limit: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let game_account = next_account_info(account_info_iter)?;
+
+    let (game_address, _) =
+        Pubkey::find_program_address(&[GAME_SEED, game_id.as_bytes()], program_id);
+    if game_address != *game_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    let mut token_ids: Vec<u64> = vec![];
+    if !game_account.data_is_empty() {
+        let game_record: GameRecord = load_account(game_account, CustomError::StateCorrupted)?;
+        let end = start
+            .saturating_add(limit.min(MAX_GAME_TOKENS_PAGE))
+            .min(game_record.token_count);
+
+        let mut position = start;
+        while position < end {
+            let chunk_index = position / GAME_TOKENS_PER_CHUNK;
+            let chunk_account = next_account_info(account_info_iter)?;
+            let (chunk_address, _) =
+                game_tokens_chunk_address(program_id, &game_id, chunk_index as u32);
+            if chunk_address != *chunk_account.key {
+                return Err(ProgramError::InvalidSeeds);
+            }
+            let chunk: GameTokenChunk = load_account(chunk_account, CustomError::StateCorrupted)?;
+
+            let chunk_start = chunk_index * GAME_TOKENS_PER_CHUNK;
+            let chunk_end = (chunk_start + GAME_TOKENS_PER_CHUNK).min(end);
+            token_ids.extend_from_slice(
+                &chunk.token_ids
+                    [(position - chunk_start) as usize..(chunk_end - chunk_start) as usize],
+            );
+            position = chunk_end;
+        }
+    }
+
+    set_return_data(&token_ids.try_to_vec()?);
+
+    Ok(())
+}
+
+// Accounts:
+//   0. `[]` contract state account
+//   1. `[signer, writable]` admin (contract owner), pays if the game PDA is new
+//   2. `[writable]` game PDA for game_id
+//   3. `[]` system program
+fn set_game_config(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    game_id: String,
+    config: GameConfig,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let admin_account = next_account_info(account_info_iter)?;
+    let game_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+
+    let contract_state: ContractState =
+        load_account(contract_account, CustomError::StateCorrupted)?;
+    assert_owner(program_id, contract_account, &contract_state, admin_account)?;
+    if game_id.len() > MAX_GAME_ID_LEN {
+        return Err(CustomError::InvalidInstruction.into());
+    }
+    if config.royalty_bps > MAX_BPS {
+        return Err(CustomError::InvalidRoyalty.into());
+    }
+    if config.holder_discount_bps > MAX_BPS {
+        return Err(CustomError::InvalidDiscount.into());
+    }
+    if config.price_usd_cents > 0 && config.price_mint != Pubkey::default() {
+        return Err(CustomError::InvalidPriceFeed.into());
+    }
+
+    open_game_account(
+        program_id,
+        admin_account,
+        game_account,
+        system_program,
+        &game_id,
+    )?;
+    let mut game_record: GameRecord = load_account(game_account, CustomError::StateCorrupted)?;
+    game_record.config = config;
+    store_account(&game_record, game_account, CustomError::StateCorrupted)?;
+
+    Ok(())
+}
+
+// Accounts:
+//   0. `[]` token record PDA for token_id
+//   1. `[]` token metadata PDA for `mint`
+//
+// Returns a Borsh `bool` via return data: true when `mint` is the canonical
+// mint PDA for token_id, a record of the token exists for that mint, and the
+// mint's metadata names the contract PDA as update authority.
+fn verify_authentic(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    token_id: u64,
+    mint: Pubkey,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let record_account = next_account_info(account_info_iter)?;
+    let metadata_account = next_account_info(account_info_iter)?;
+
+    if token_record_address(program_id, token_id).0 != *record_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let (metadata_address, _) =
+        Pubkey::find_program_address(&[METADATA_SEED, mint.as_ref()], program_id);
+    if metadata_address != *metadata_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    let mut authentic = mint == token_mint_address(program_id, token_id).0
+        && record_account.owner == program_id
+        && metadata_account.owner == program_id;
+    if authentic {
+        let record: TokenRecord = load_account(record_account, CustomError::StateCorrupted)?;
+        let metadata: TokenMetadata = load_account(metadata_account, CustomError::StateCorrupted)?;
+        authentic = record.mint == mint
+            && metadata.mint == mint
+            && metadata.update_authority == contract_pda_address(program_id).0;
+    }
+
+    set_return_data(&authentic.try_to_vec()?);
+
+    Ok(())
+}
+
+// Accounts:
+//   0. `[writable]` contract state account
+//   1. `[signer]` admin (contract owner)
+fn set_allowed_uri_schemes(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    schemes: u8,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let admin_account = next_account_info(account_info_iter)?;
+
+    let mut contract_state: ContractState =
+        load_account(contract_account, CustomError::StateCorrupted)?;
+    assert_owner(program_id, contract_account, &contract_state, admin_account)?;
+
+    contract_state.allowed_uri_schemes = schemes;
+    store_account(
+        &contract_state,
+        contract_account,
+        CustomError::StateCorrupted,
+    )?;
+
+    Ok(())
+}
+
+// Accounts:
+//   0. `[]` contract state account
+//   1. `[signer]` admin (contract owner)
+//   2. `[]` token record PDA for token_id
+//   3. `[writable]` token URI PDA for token_id
+//
+// Only the URI PDA is rewritten; the permission and metadata are left as is.
+fn update_token_uri(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    token_id: u64,
+    token_uri: String,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let admin_account = next_account_info(account_info_iter)?;
+    let record_account = next_account_info(account_info_iter)?;
+    let uri_account = next_account_info(account_info_iter)?;
+
+    let contract_state: ContractState =
+        load_account(contract_account, CustomError::StateCorrupted)?;
+    assert_owner(program_id, contract_account, &contract_state, admin_account)?;
+    validate_token_uri(&contract_state, &token_uri)?;
+
+    write_token_uri(program_id, token_id, token_uri, record_account, uri_account)
+}
+
+// Stores `token_uri` in token_id's URI PDA, found through its token record.
+fn write_token_uri(
+    program_id: &Pubkey,
+    token_id: u64,
+    token_uri: String,
+    record_account: &AccountInfo,
+    uri_account: &AccountInfo,
+) -> ProgramResult {
+    if token_record_address(program_id, token_id).0 != *record_account.key
+        || record_account.owner != program_id
+    {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let record: TokenRecord = load_account(record_account, CustomError::StateCorrupted)?;
+    if record.uri_account != *uri_account.key || uri_account.owner != program_id {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    let mut stored_uri: TokenUri = load_account(uri_account, CustomError::StateCorrupted)?;
+    stored_uri.uri = token_uri;
+    store_account(&stored_uri, uri_account, CustomError::StateCorrupted)
+}
+
+// Rewrites many tokens' URIs at once, e.g. after moving assets to a new host.
+//
+// Accounts:
+//   0. `[]` contract state account
+//   1. `[signer]` admin (contract owner)
+//   2.. one pair per update, in order:
+//      `[]` token record PDA for the token id, `[writable]` its token URI PDA
+fn batch_update_uris(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    updates: Vec<(u64, String)>,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let admin_account = next_account_info(account_info_iter)?;
+
+    let contract_state: ContractState =
+        load_account(contract_account, CustomError::StateCorrupted)?;
+    assert_owner(program_id, contract_account, &contract_state, admin_account)?;
+
+    let pairs = account_info_iter.as_slice();
+    if updates.is_empty()
+        || updates.len() > MAX_BATCH_URI_UPDATES
+        || pairs.len() != updates.len() * 2
+    {
+        return Err(CustomError::InvalidInstruction.into());
+    }
+
+    for ((token_id, token_uri), pair) in updates.into_iter().zip(pairs.chunks(2)) {
+        validate_token_uri(&contract_state, &token_uri)?;
+        write_token_uri(program_id, token_id, token_uri, &pair[0], &pair[1])?;
+    }
+
+    Ok(())
+}
+
+// Accounts:
+//   0. `[writable]` contract state account
+//   1. `[writable]` token record PDA for token_id
+//   2. `[writable]` owner's token account holding the token
+//   3. `[signer]` token owner
+//   4. `[]` contract PDA, approved as burn delegate for the token account
+//   5. `[]` token program
+fn enqueue_burn(program_id: &Pubkey, accounts: &[AccountInfo], token_id: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let record_account = next_account_info(account_info_iter)?;
+    let token_account = next_account_info(account_info_iter)?;
+    let owner_account = next_account_info(account_info_iter)?;
+    let contract_pda = next_account_info(account_info_iter)?;
+    let token_program = next_account_info(account_info_iter)?;
+
+    if token_record_address(program_id, token_id).0 != *record_account.key
+        || record_account.owner != program_id
+    {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if contract_pda_address(program_id).0 != *contract_pda.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let mut record: TokenRecord = load_account(record_account, CustomError::StateCorrupted)?;
+    if record.burned {
+        return Err(CustomError::TokenBurned.into());
+    }
+    if record.owner != *owner_account.key {
+        return Err(CustomError::NotTokenOwner.into());
+    }
+    if !owner_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if record.burn_queued {
+        return Ok(());
+    }
+    let mut contract_state: ContractState = load_contract(program_id, contract_account)?;
+    assert_token_program(&contract_state, token_program)?;
+
+    let holding = TokenAccount::unpack(&token_account.data.borrow())?;
+    if holding.mint != record.mint || holding.owner != record.owner || holding.amount == 0 {
+        return Err(CustomError::NotTokenOwner.into());
+    }
+
+    invoke(
+        &token_instruction(
+            approve(
+                &spl_token::id(),
+                token_account.key,
+                contract_pda.key,
+                owner_account.key,
+                &[],
+                1,
+            )?,
+            token_program,
+        ),
+        &[
+            token_account.clone(),
+            contract_pda.clone(),
+            owner_account.clone(),
+            token_program.clone(),
+        ],
+    )?;
+
+    record.burn_queued = true;
+    store_account(&record, record_account, CustomError::StateCorrupted)?;
+
+    contract_state.queued_burns += 1;
+    store_account(
+        &contract_state,
+        contract_account,
+        CustomError::StateCorrupted,
+    )?;
+
+    Ok(())
+}
+
+// Accounts:
+//   0. `[writable]` contract state account
+//   1. `[]` contract PDA, the burn delegate approved by EnqueueBurn
+//   2. `[]` token program
+//   3. `[signer, writable]` payer for any new token history chunks
+//   4. `[]` system program
+//   5.. up to MAX_BURN_BATCH groups of:
+//      `[writable]` token record PDA, `[writable]` mint, `[writable]` token account,
+//      `[writable]` game PDA for the token's game, `[writable]` owner's game holding PDA,
+//      `[writable]` token history chunk PDA the burn is logged in, then the
+//      game's burn callback program when it sets one (see `notify_burn`)
+fn process_burn_queue(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let contract_pda = next_account_info(account_info_iter)?;
+    let token_program = next_account_info(account_info_iter)?;
+    let payer_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+
+    let (contract_pda_key, contract_pda_bump) = contract_pda_address(program_id);
+    if contract_pda_key != *contract_pda.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    let mut contract_state: ContractState = load_contract(program_id, contract_account)?;
+    assert_token_program(&contract_state, token_program)?;
+
+    let mut burned = 0;
+    while account_info_iter.len() > 0 {
+        if burned == MAX_BURN_BATCH {
+            return Err(CustomError::InvalidInstruction.into());
+        }
+        burned += 1;
+        let record_account = next_account_info(account_info_iter)?;
+        let mint_account = next_account_info(account_info_iter)?;
+        let token_account = next_account_info(account_info_iter)?;
+        let game_account = next_account_info(account_info_iter)?;
+        let holding_account = next_account_info(account_info_iter)?;
+        let history_account = next_account_info(account_info_iter)?;
+        if record_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut record: TokenRecord = load_account(record_account, CustomError::StateCorrupted)?;
+        if token_record_address(program_id, record.token_id).0 != *record_account.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        if !record.burn_queued {
+            return Err(CustomError::BurnNotQueued.into());
+        }
+        if record.mint != *mint_account.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        // The approval EnqueueBurn made is lost once the token leaves the
+        // owner's account or the owner revokes it. Such entries are dequeued
+        // rather than failing the batch; an account that doesn't hold the
+        // token says nothing about the entry, so it is left queued.
+        let holding = if token_account.owner == token_program.key {
+            TokenAccount::unpack(&token_account.data.borrow()).ok()
+        } else {
+            None
+        }
+        .filter(|holding| holding.mint == record.mint && holding.amount > 0);
+        let approved = holding.map(|holding| {
+            holding.owner == record.owner
+                && holding.delegate == COption::Some(*contract_pda.key)
+                && holding.delegated_amount > 0
+                && !holding.is_frozen()
+        });
+        if approved != Some(true) {
+            if approved == Some(false) {
+                record.burn_queued = false;
+                store_account(&record, record_account, CustomError::StateCorrupted)?;
+                contract_state.queued_burns = contract_state
+                    .queued_burns
+                    .checked_sub(1)
+                    .ok_or(CustomError::StateCorrupted)?;
+                msg!("burn of token {} dequeued, approval lost", record.token_id);
+            }
+            // Keep the layout independent of the outcome
+            if !record.is_receipt {
+                let game_record = load_game(program_id, &record.game_id, game_account)?;
+                if game_record.config.burn_callback_program != Pubkey::default() {
+                    next_account_info(account_info_iter)?;
+                }
+            }
+            continue;
+        }
+
+        invoke_signed(
+            &token_instruction(
+                spl_burn(
+                    &spl_token::id(),
+                    token_account.key,
+                    mint_account.key,
+                    contract_pda.key,
+                    &[],
+                    1,
+                )?,
+                token_program,
+            ),
+            &[
+                token_account.clone(),
+                mint_account.clone(),
+                contract_pda.clone(),
+                token_program.clone(),
+            ],
+            &[&[CONTRACT_SEED, &[contract_pda_bump]]],
+        )?;
+
+        // Receipts are never counted towards a holding
+        let game_record = if record.is_receipt {
+            None
+        } else {
+            let mut game_record = load_game(program_id, &record.game_id, game_account)?;
+            debit_holding(program_id, &mut game_record, &record.owner, holding_account)?;
+            refill_supply(&mut game_record);
+            store_account(&game_record, game_account, CustomError::StateCorrupted)?;
+            Some(game_record)
+        };
+
+        let owner = record.owner;
+        append_history(
+            program_id,
+            &mut record,
+            HistoryEvent::Burn,
+            owner,
+            Pubkey::default(),
+            history_account,
+            payer_account,
+            system_program,
+        )?;
+        record.burn_queued = false;
+        record.burned = true;
+        store_account(&record, record_account, CustomError::StateCorrupted)?;
+        contract_state.queued_burns = contract_state
+            .queued_burns
+            .checked_sub(1)
+            .ok_or(CustomError::StateCorrupted)?;
+        contract_state.active_tokens = contract_state
+            .active_tokens
+            .checked_sub(1)
+            .ok_or(CustomError::StateCorrupted)?;
+        msg!("{}:BURN:{}", EVENT_LOG_PREFIX, record.token_id);
+
+        if let Some(game_record) = game_record {
+            notify_burn(&game_record, game_account, &record, account_info_iter)?;
+        }
+    }
+
+    store_account(
+        &contract_state,
+        contract_account,
+        CustomError::StateCorrupted,
+    )?;
+
+    Ok(())
+}
+
+// Sells a token for `price` lamports, paid by the buyer straight to the seller.
+//
+// Accounts:
+//   0. `[writable]` token record PDA for token_id
+//   1. `[writable]` game PDA for the token's game
+//   2. `[writable]` seller's token account holding the token
+//   3. `[writable]` buyer's token account for the token's mint
+//   4. `[signer, writable]` seller, the token's recorded owner
+//   5. `[signer, writable]` buyer
+//   6. `[]` token program
+//   7. `[]` system program
+//   8. `[]` contract state account
+//   9. `[]` buyer's KYC attestation PDA, only checked with the KYC gate on
+//   10. `[writable]` seller's game holding PDA
+//   11. `[writable]` buyer's game holding PDA, created on their first token
+//   12. `[writable]` token history chunk PDA the sale is logged in
+//   13. `[signer]` admin (contract owner), only needed when the token's value
+//      is above the transfer approval threshold
+fn transfer_with_payment(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    token_id: u64,
+    price: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let record_account = next_account_info(account_info_iter)?;
+    let game_account = next_account_info(account_info_iter)?;
+    let seller_token_account = next_account_info(account_info_iter)?;
+    let buyer_token_account = next_account_info(account_info_iter)?;
+    let seller_account = next_account_info(account_info_iter)?;
+    let buyer_account = next_account_info(account_info_iter)?;
+    let token_program = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+    let contract_account = next_account_info(account_info_iter)?;
+    let kyc_account = next_account_info(account_info_iter)?;
+    let seller_holding_account = next_account_info(account_info_iter)?;
+    let buyer_holding_account = next_account_info(account_info_iter)?;
+    let history_account = next_account_info(account_info_iter)?;
+
+    if token_record_address(program_id, token_id).0 != *record_account.key
+        || record_account.owner != program_id
+    {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let mut record: TokenRecord = load_account(record_account, CustomError::StateCorrupted)?;
+    if record.burned || record.burn_queued {
+        return Err(CustomError::TokenBurned.into());
+    }
+    assert_not_expired(&record)?;
+    if record.soulbound {
+        return Err(CustomError::Soulbound.into());
+    }
+    if record.owner != *seller_account.key {
+        return Err(CustomError::NotTokenOwner.into());
+    }
+    if !seller_account.is_signer || !buyer_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let (game_key, _) =
+        Pubkey::find_program_address(&[GAME_SEED, record.game_id.as_bytes()], program_id);
+    if game_key != *game_account.key || game_account.owner != program_id {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let game_record: GameRecord = load_account(game_account, CustomError::StateCorrupted)?;
+    if price < game_record.config.min_sale_price {
+        return Err(CustomError::PriceBelowFloor.into());
+    }
+    let contract_state: ContractState = load_contract(program_id, contract_account)?;
+    assert_token_program(&contract_state, token_program)?;
+    assert_transfers_open(&contract_state)?;
+    assert_transfer_approved(
+        &contract_state,
+        record.value.max(price),
+        account_info_iter.next(),
+    )?;
+    assert_kyc_attested(program_id, &contract_state, buyer_account.key, kyc_account)?;
+
+    let seller_holding = TokenAccount::unpack(&seller_token_account.data.borrow())?;
+    let buyer_holding = TokenAccount::unpack(&buyer_token_account.data.borrow())?;
+    if seller_holding.mint != record.mint || seller_holding.owner != record.owner {
+        return Err(CustomError::NotTokenOwner.into());
+    }
+    if buyer_holding.mint != record.mint || buyer_holding.owner != *buyer_account.key {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if price > 0 {
+        invoke(
+            &system_instruction::transfer(buyer_account.key, seller_account.key, price),
+            &[
+                buyer_account.clone(),
+                seller_account.clone(),
+                system_program.clone(),
+            ],
+        )?;
+    }
+    invoke(
+        &token_instruction(
+            spl_token::instruction::transfer(
+                &spl_token::id(),
+                seller_token_account.key,
+                buyer_token_account.key,
+                seller_account.key,
+                &[],
+                1,
+            )?,
+            token_program,
+        ),
+        &[
+            seller_token_account.clone(),
+            buyer_token_account.clone(),
+            seller_account.clone(),
+            token_program.clone(),
+        ],
+    )?;
+
+    let game_record = move_holding(
+        program_id,
+        &record.game_id,
+        game_account,
+        seller_account.key,
+        seller_holding_account,
+        buyer_account.key,
+        buyer_holding_account,
+        buyer_account,
+        system_program,
+    )?;
+
+    append_history(
+        program_id,
+        &mut record,
+        HistoryEvent::Transfer,
+        *seller_account.key,
+        *buyer_account.key,
+        history_account,
+        buyer_account,
+        system_program,
+    )?;
+    record.owner = *buyer_account.key;
+    record.value = price;
+    if game_record.config.reset_cooldown_on_transfer {
+        record.cooldown_ends_slot = Clock::get()?.slot;
+    }
+    store_account(&record, record_account, CustomError::StateCorrupted)?;
+    msg!(
+        "{}:TRANSFER:{}:{}:{}",
+        EVENT_LOG_PREFIX,
+        token_id,
+        seller_account.key,
+        buyer_account.key
+    );
+
+    Ok(())
+}
+
+// Accounts:
+//   0. `[writable]` contract state account
+//   1. `[signer, writable]` admin (contract owner), pays for new accounts
+//   2. `[]` system program
+//   3.. one pair per grant, in order; grants never overwrite existing permissions:
+//      `[writable]` permission PDA for (user, game_id), `[writable]` game PDA for game_id,
+//      followed while a grant cooldown is set by `[writable]` user grant PDA for user
+fn batch_grant(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    grants: Vec<MintPermission>,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let admin_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+
+    let mut contract_state: ContractState =
+        load_account(contract_account, CustomError::StateCorrupted)?;
+    assert_owner(program_id, contract_account, &contract_state, admin_account)?;
+
+    let pairs = account_info_iter.as_slice();
+    let stride = if contract_state.grant_cooldown_slots > 0 {
+        3
+    } else {
+        2
+    };
+    if grants.is_empty() || grants.len() > MAX_BATCH_GRANTS || pairs.len() != grants.len() * stride
+    {
+        return Err(CustomError::InvalidInstruction.into());
+    }
+
+    for (mint_permission, pair) in grants.into_iter().zip(pairs.chunks(stride)) {
+        if let Some(user_grant_account) = pair.get(2) {
+            record_grant_slot(
+                program_id,
+                &contract_state,
+                &mint_permission.user,
+                admin_account,
+                user_grant_account,
+                system_program,
+            )?;
+        }
+        write_mint_permission(
+            program_id,
+            &mut contract_state,
+            mint_permission,
+            false,
+            admin_account,
+            &pair[0],
+            &pair[1],
+            system_program,
+        )?;
+    }
+
+    store_account(
+        &contract_state,
+        contract_account,
+        CustomError::StateCorrupted,
+    )
+}
+
+// Accounts:
+//   0. `[writable]` contract state account
+//   1. `[signer]` admin (contract owner)
+fn set_minting_enabled(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    enabled: bool,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let admin_account = next_account_info(account_info_iter)?;
+
+    let mut contract_state: ContractState =
+        load_account(contract_account, CustomError::StateCorrupted)?;
+    assert_owner(program_id, contract_account, &contract_state, admin_account)?;
+
+    contract_state.minting_enabled = enabled;
+    store_account(
+        &contract_state,
+        contract_account,
+        CustomError::StateCorrupted,
+    )?;
+
+    Ok(())
+}
+
+// Halts or resumes mints and transfers, see `ContractState::paused`. Not
+// subject to the timelock, so it takes effect in the same transaction.
+//
+// Accounts:
+//   0. `[writable]` contract state account
+//   1. `[signer]` admin (contract owner)
+fn set_paused(program_id: &Pubkey, accounts: &[AccountInfo], paused: bool) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let admin_account = next_account_info(account_info_iter)?;
+
+    let mut contract_state: ContractState =
+        load_account(contract_account, CustomError::StateCorrupted)?;
+    assert_owner(program_id, contract_account, &contract_state, admin_account)?;
+
+    contract_state.paused = paused;
+    msg!("{}:PAUSED:{}", EVENT_LOG_PREFIX, paused);
+    store_account(
+        &contract_state,
+        contract_account,
+        CustomError::StateCorrupted,
+    )
+}
+
+// Accounts:
+//   0. `[]` token record PDA for token_id
+//   1. `[]` contract state account
+//
+// Returns one byte via return data: 1 if the token was burned, 0 if it is
+// live, or TOKEN_NOT_MINTED for an id past `last_token_id`. A missing record
+// for an id already handed out, e.g. one closed by CloseContract, means burned.
+fn is_burned(program_id: &Pubkey, accounts: &[AccountInfo], token_id: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let record_account = next_account_info(account_info_iter)?;
+    let contract_account = next_account_info(account_info_iter)?;
+
+    if token_record_address(program_id, token_id).0 != *record_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let contract_state: ContractState = load_contract(program_id, contract_account)?;
+
+    let status = if token_id == 0 || token_id > contract_state.last_token_id {
+        TOKEN_NOT_MINTED
+    } else if record_account.owner != program_id || record_account.data_is_empty() {
+        1
+    } else {
+        let record: TokenRecord = load_account(record_account, CustomError::StateCorrupted)?;
+        record.burned as u8
+    };
+    set_return_data(&[status]);
+
+    Ok(())
+}
+
+// Hands the contract to `new_owner`, e.g. when rotating the admin key. The
+// treasury is left as it is. Fails with TimelockRequired while the contract
+// has a timelock; propose the change instead.
+//
+// Accounts:
+//   0. `[writable]` contract state account
+//   1. `[signer]` admin (current contract owner)
+fn transfer_ownership(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    new_owner: Pubkey,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let admin_account = next_account_info(account_info_iter)?;
+
+    let mut contract_state: ContractState =
+        load_account(contract_account, CustomError::StateCorrupted)?;
+    assert_owner(program_id, contract_account, &contract_state, admin_account)?;
+    if contract_state.timelock_slots > 0 {
+        return Err(CustomError::TimelockRequired.into());
+    }
+
+    apply_change(
+        &mut contract_state,
+        &ProposedChange::TransferOwnership { new_owner },
+    );
+    store_account(
+        &contract_state,
+        contract_account,
+        CustomError::StateCorrupted,
+    )?;
+
+    Ok(())
+}
+
+fn apply_change(contract_state: &mut ContractState, change: &ProposedChange) {
+    match *change {
+        ProposedChange::TransferOwnership { new_owner } => {
+            contract_state.contract_owner = new_owner;
+        }
+        ProposedChange::SetTimelock { slots } => contract_state.timelock_slots = slots,
+        ProposedChange::SetTreasurySplit { ref recipients } => {
+            contract_state.treasury_split = recipients.clone();
+        }
+        ProposedChange::SetKycGate {
+            required,
+            authority,
+        } => {
+            contract_state.kyc_required = required;
+            contract_state.kyc_authority = authority;
+        }
+    }
+}
+
+// Queues `change` to be applied by ExecuteChange once `timelock_slots` have
+// passed, replacing any change still pending.
+//
+// Accounts:
+//   0. `[]` contract state account
+//   1. `[signer, writable]` admin (contract owner), pays if the PDA is new
+//   2. `[writable]` pending change PDA
+//   3. `[]` system program
+fn propose_change(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    change: ProposedChange,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let admin_account = next_account_info(account_info_iter)?;
+    let pending_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+
+    let contract_state: ContractState =
+        load_account(contract_account, CustomError::StateCorrupted)?;
+    assert_owner(program_id, contract_account, &contract_state, admin_account)?;
+    if let ProposedChange::SetTreasurySplit { ref recipients } = change {
+        validate_treasury_split(recipients)?;
+    }
+
+    let (pending_address, pending_bump) = pending_change_address(program_id);
+    if pending_address != *pending_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if pending_account.data_is_empty() {
+        create_pda_account(
+            program_id,
+            admin_account,
+            pending_account,
+            system_program,
+            PendingChange::LEN,
+            &[
+                PENDING_CHANGE_SEED,
+                contract_account.key.as_ref(),
+                &[pending_bump],
+            ],
+        )?;
+    }
+
+    let pending = PendingChange {
+        change,
+        execute_after_slot: Clock::get()?
+            .slot
+            .saturating_add(contract_state.timelock_slots),
+    };
+    store_account(&pending, pending_account, CustomError::StateCorrupted)
+}
+
+// Applies the pending change once its timelock has run out, and closes the
+// pending change PDA. Fails with TimelockActive before `execute_after_slot`.
+//
+// Accounts:
+//   0. `[writable]` contract state account
+//   1. `[signer, writable]` admin (contract owner), receives the PDA's rent
+//   2. `[writable]` pending change PDA
+fn execute_change(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let admin_account = next_account_info(account_info_iter)?;
+    let pending_account = next_account_info(account_info_iter)?;
+
+    let mut contract_state: ContractState =
+        load_account(contract_account, CustomError::StateCorrupted)?;
+    assert_owner(program_id, contract_account, &contract_state, admin_account)?;
+
+    let (pending_address, _) = pending_change_address(program_id);
+    if pending_address != *pending_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if pending_account.owner != program_id || pending_account.data_is_empty() {
+        return Err(CustomError::NoPendingChange.into());
+    }
+    let pending: PendingChange = load_account(pending_account, CustomError::StateCorrupted)?;
+    if Clock::get()?.slot < pending.execute_after_slot {
+        return Err(CustomError::TimelockActive.into());
+    }
+
+    apply_change(&mut contract_state, &pending.change);
+    store_account(
+        &contract_state,
+        contract_account,
+        CustomError::StateCorrupted,
+    )?;
+    close_pda_account(pending_account, admin_account)
+}
+
+// Accounts:
+//   0. `[writable]` contract state account
+//   1. `[signer]` admin (contract owner)
+fn set_transfer_approval_threshold(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    threshold: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let admin_account = next_account_info(account_info_iter)?;
+
+    let mut contract_state: ContractState =
+        load_account(contract_account, CustomError::StateCorrupted)?;
+    assert_owner(program_id, contract_account, &contract_state, admin_account)?;
+
+    contract_state.transfer_approval_threshold = threshold;
+    store_account(
+        &contract_state,
+        contract_account,
+        CustomError::StateCorrupted,
+    )?;
+
+    Ok(())
+}
+
+// Freezes the current owners of the given tokens into the snapshot for
+// `snapshot_slot`, which has to be the current slot. Large holder sets are
+// recorded over several instructions landing in that slot, each appending its
+// tokens; burned tokens are skipped.
+//
+// Accounts:
+//   0. `[]` contract state account
+//   1. `[signer, writable]` admin (contract owner), pays for the snapshot PDA
+//   2. `[writable]` snapshot PDA for snapshot_slot
+//   3. `[]` system program
+//   4.. `[]` token record PDAs of the tokens to record
+fn record_snapshot(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    snapshot_slot: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let admin_account = next_account_info(account_info_iter)?;
+    let snapshot_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+
+    let contract_state: ContractState =
+        load_account(contract_account, CustomError::StateCorrupted)?;
+    assert_owner(program_id, contract_account, &contract_state, admin_account)?;
+
+    // Holdings can only be read as they are now, so a snapshot for any other
+    // slot would record the wrong owners
+    if snapshot_slot != Clock::get()?.slot {
+        return Err(CustomError::InvalidInstruction.into());
+    }
+
+    let (snapshot_address, snapshot_bump) =
+        Pubkey::find_program_address(&[SNAPSHOT_SEED, &snapshot_slot.to_le_bytes()], program_id);
+    if snapshot_address != *snapshot_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let mut snapshot = if snapshot_account.data_is_empty() {
+        create_pda_account(
+            program_id,
+            admin_account,
+            snapshot_account,
+            system_program,
+            Snapshot::LEN,
+            &[
+                SNAPSHOT_SEED,
+                &snapshot_slot.to_le_bytes(),
+                &[snapshot_bump],
+            ],
+        )?;
+        Snapshot {
+            snapshot_slot,
+            holdings: vec![],
+        }
+    } else {
+        load_account(snapshot_account, CustomError::StateCorrupted)?
+    };
+
+    for record_account in account_info_iter {
+        if record_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let record: TokenRecord = load_account(record_account, CustomError::StateCorrupted)?;
+        if token_record_address(program_id, record.token_id).0 != *record_account.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        if record.burned
+            || snapshot
+                .holdings
+                .iter()
+                .any(|holding| holding.token_id == record.token_id)
+        {
+            continue;
+        }
+        if snapshot.holdings.len() == MAX_SNAPSHOT_HOLDERS {
+            return Err(CustomError::InvalidInstruction.into());
+        }
+        snapshot.holdings.push(SnapshotHolding {
+            token_id: record.token_id,
+            owner: record.owner,
+            claimed: false,
+        });
+    }
+    store_account(&snapshot, snapshot_account, CustomError::StateCorrupted)?;
+
+    Ok(())
+}
+
+// Marks the snapshot reward for token_id claimed, provided the claimant
+// held the token at the snapshot.
+//
+// Accounts:
+//   0. `[writable]` snapshot PDA
+//   1. `[signer]` claimant
+fn claim_snapshot_reward(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    token_id: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let snapshot_account = next_account_info(account_info_iter)?;
+    let claimant_account = next_account_info(account_info_iter)?;
+
+    if snapshot_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let mut snapshot: Snapshot = load_account(snapshot_account, CustomError::StateCorrupted)?;
+    let (snapshot_address, _) = Pubkey::find_program_address(
+        &[SNAPSHOT_SEED, &snapshot.snapshot_slot.to_le_bytes()],
+        program_id,
+    );
+    if snapshot_address != *snapshot_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if !claimant_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let holding = snapshot
+        .holdings
+        .iter_mut()
+        .find(|holding| holding.token_id == token_id && holding.owner == *claimant_account.key)
+        .ok_or(CustomError::NotSnapshotHolder)?;
+    if holding.claimed {
+        return Err(CustomError::SnapshotRewardClaimed.into());
+    }
+    holding.claimed = true;
+    store_account(&snapshot, snapshot_account, CustomError::StateCorrupted)?;
+
+    Ok(())
+}
+
+// Accounts:
+//   0. `[]` contract state account
+//   1. `[signer, writable]` admin (contract owner), pays for the template PDA
+//   2. `[writable]` game metadata template PDA for game_id
+//   3. `[]` system program
+fn set_game_metadata_template(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    game_id: String,
+    template: GameMetadataTemplate,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let admin_account = next_account_info(account_info_iter)?;
+    let template_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+
+    let contract_state: ContractState =
+        load_account(contract_account, CustomError::StateCorrupted)?;
+    assert_owner(program_id, contract_account, &contract_state, admin_account)?;
+
+    // leave room for the token id a plain prefix is completed with; URIs
+    // interpolating `{id}` have their length checked at each mint
+    let uri_room = if template.uri_prefix.contains(TOKEN_ID_PLACEHOLDER) {
+        0
+    } else {
+        TOKEN_ID_DIGITS
+    };
+    if game_id.len() > MAX_GAME_ID_LEN
+        || template.name_template.len() > MAX_TOKEN_NAME_LEN
+        || template.symbol.len() > MAX_TOKEN_SYMBOL_LEN
+        || template.uri_prefix.len() + uri_room > MAX_TOKEN_URI_LEN
+    {
+        return Err(CustomError::InvalidInstruction.into());
+    }
+    if !template.uri_prefix.is_empty() {
+        validate_token_uri(&contract_state, &template.uri_prefix)?;
+    }
+
+    let (template_address, template_bump) =
+        Pubkey::find_program_address(&[TEMPLATE_SEED, game_id.as_bytes()], program_id);
+    if template_address != *template_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if template_account.data_is_empty() {
+        create_pda_account(
+            program_id,
+            admin_account,
+            template_account,
+            system_program,
+            GameMetadataTemplate::LEN,
+            &[TEMPLATE_SEED, game_id.as_bytes(), &[template_bump]],
+        )?;
+    }
+    store_account(&template, template_account, CustomError::StateCorrupted)?;
+
+    Ok(())
+}
+
+// Takes no accounts. Returns the contract PDA derived from [CONTRACT_SEED] and
+// its bump as a Borsh `(Pubkey, u8)` via return data.
+fn get_contract_pda(program_id: &Pubkey) -> ProgramResult {
+    set_return_data(&contract_pda_address(program_id).try_to_vec()?);
+
+    Ok(())
+}
+
+// An empty split sends mint fees back to the single `treasury`. Fails with
+// TimelockRequired while the contract has a timelock; propose the change
+// instead.
+//
+// Accounts:
+//   0. `[writable]` contract state account
+//   1. `[signer]` admin (contract owner)
+fn set_treasury_split(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    recipients: Vec<TreasuryShare>,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let admin_account = next_account_info(account_info_iter)?;
+
+    let mut contract_state: ContractState =
+        load_account(contract_account, CustomError::StateCorrupted)?;
+    assert_owner(program_id, contract_account, &contract_state, admin_account)?;
+    if contract_state.timelock_slots > 0 {
+        return Err(CustomError::TimelockRequired.into());
+    }
+    validate_treasury_split(&recipients)?;
+
+    apply_change(
+        &mut contract_state,
+        &ProposedChange::SetTreasurySplit { recipients },
+    );
+    store_account(
+        &contract_state,
+        contract_account,
+        CustomError::StateCorrupted,
+    )?;
+
+    Ok(())
+}
+
+fn validate_treasury_split(recipients: &[TreasuryShare]) -> ProgramResult {
+    let total: u32 = recipients.iter().map(|share| share.bps as u32).sum();
+    if recipients.len() > MAX_TREASURY_RECIPIENTS
+        || (!recipients.is_empty() && total != MAX_BPS as u32)
+    {
+        return Err(CustomError::InvalidTreasurySplit.into());
+    }
+
+    Ok(())
+}
+
+// Clears the mint authority of token_id's mint, permanently fixing its
+// supply. This can't be undone.
+//
+// Accounts:
+//   0. `[]` contract state account
+//   1. `[signer]` admin (contract owner)
+//   2. `[writable]` mint PDA for token_id
+//   3. `[]` mint authority PDA for the mint
+//   4. `[]` token program
+fn disable_minting(program_id: &Pubkey, accounts: &[AccountInfo], token_id: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let admin_account = next_account_info(account_info_iter)?;
+    let mint_account = next_account_info(account_info_iter)?;
+    let mint_authority = next_account_info(account_info_iter)?;
+    let token_program = next_account_info(account_info_iter)?;
+
+    let contract_state: ContractState =
+        load_account(contract_account, CustomError::StateCorrupted)?;
+    assert_owner(program_id, contract_account, &contract_state, admin_account)?;
+    assert_token_program(&contract_state, token_program)?;
+
+    if token_mint_address(program_id, token_id).0 != *mint_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let (authority_address, authority_bump) = Pubkey::find_program_address(
+        &[MINT_AUTHORITY_SEED, mint_account.key.as_ref()],
+        program_id,
+    );
+    if authority_address != *mint_authority.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    invoke_signed(
+        &token_instruction(
+            set_authority(
+                &spl_token::id(),
+                mint_account.key,
+                None,
+                AuthorityType::MintTokens,
+                mint_authority.key,
+                &[],
+            )?,
+            token_program,
+        ),
+        &[
+            mint_account.clone(),
+            mint_authority.clone(),
+            token_program.clone(),
+        ],
+        &[&[
+            MINT_AUTHORITY_SEED,
+            mint_account.key.as_ref(),
+            &[authority_bump],
+        ]],
+    )?;
+
+    Ok(())
+}
+
+// Fails with TimelockRequired while the contract has a timelock; propose the
+// change instead.
+//
+// Accounts:
+//   0. `[writable]` contract state account
+//   1. `[signer]` admin (contract owner)
+fn set_kyc_gate(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    required: bool,
+    authority: Pubkey,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let admin_account = next_account_info(account_info_iter)?;
+
+    let mut contract_state: ContractState =
+        load_account(contract_account, CustomError::StateCorrupted)?;
+    assert_owner(program_id, contract_account, &contract_state, admin_account)?;
+    if contract_state.timelock_slots > 0 {
+        return Err(CustomError::TimelockRequired.into());
+    }
+
+    apply_change(
+        &mut contract_state,
+        &ProposedChange::SetKycGate {
+            required,
+            authority,
+        },
+    );
+    store_account(
+        &contract_state,
+        contract_account,
+        CustomError::StateCorrupted,
+    )?;
+
+    Ok(())
+}
+
+// Accounts:
+//   0. `[]` contract state account
+//   1. `[signer, writable]` the contract's KYC authority, pays for the attestation PDA
+//   2. `[writable]` KYC attestation PDA for subject
+//   3. `[]` system program
+fn attest_kyc(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    subject: Pubkey,
+    valid: bool,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let authority_account = next_account_info(account_info_iter)?;
+    let attestation_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+
+    let contract_state: ContractState = load_contract(program_id, contract_account)?;
+    if contract_state.kyc_authority != *authority_account.key {
+        return Err(CustomError::NotAdmin.into());
+    }
+    if !authority_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let (attestation_address, attestation_bump) =
+        Pubkey::find_program_address(&[KYC_SEED, subject.as_ref()], program_id);
+    if attestation_address != *attestation_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if attestation_account.data_is_empty() {
+        create_pda_account(
+            program_id,
+            authority_account,
+            attestation_account,
+            system_program,
+            KycAttestation::LEN,
+            &[KYC_SEED, subject.as_ref(), &[attestation_bump]],
+        )?;
+    }
+    let attestation = KycAttestation {
+        subject,
+        authority: *authority_account.key,
+        valid,
+    };
+    store_account(
+        &attestation,
+        attestation_account,
+        CustomError::StateCorrupted,
+    )?;
+
+    Ok(())
+}
+
+// Drops burned ids from a game's token list and repacks the rest into as few
+// chunks as needed, closing the chunks left empty and refunding their rent to
+// the admin.
+//
+// Accounts:
+//   0. `[]` contract state account
+//   1. `[signer, writable]` admin (contract owner), receives reclaimed rent
+//   2. `[writable]` game PDA for game_id
+//   3.. `[writable]` every chunk PDA of the game's token list, in order
+//   then `[]` token record PDAs of the burned tokens to drop
+fn compact_game_tokens(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    game_id: String,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let admin_account = next_account_info(account_info_iter)?;
+    let game_account = next_account_info(account_info_iter)?;
+
+    let contract_state: ContractState =
+        load_account(contract_account, CustomError::StateCorrupted)?;
+    assert_owner(program_id, contract_account, &contract_state, admin_account)?;
+
+    let (game_address, _) =
+        Pubkey::find_program_address(&[GAME_SEED, game_id.as_bytes()], program_id);
+    if game_address != *game_account.key || game_account.owner != program_id {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let mut game_record: GameRecord = load_account(game_account, CustomError::StateCorrupted)?;
+
+    let chunk_count = game_record.token_count.div_ceil(GAME_TOKENS_PER_CHUNK) as usize;
+    let mut chunk_accounts = Vec::with_capacity(chunk_count);
+    let mut token_ids: Vec<u64> = vec![];
+    for chunk_index in 0..chunk_count {
+        let chunk_account = next_account_info(account_info_iter)?;
+        let (chunk_address, _) =
+            game_tokens_chunk_address(program_id, &game_id, chunk_index as u32);
+        if chunk_address != *chunk_account.key || chunk_account.owner != program_id {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        let chunk: GameTokenChunk = load_account(chunk_account, CustomError::StateCorrupted)?;
+        token_ids.extend(chunk.token_ids);
+        chunk_accounts.push(chunk_account);
+    }
+
+    for record_account in account_info_iter {
+        if record_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let record: TokenRecord = load_account(record_account, CustomError::StateCorrupted)?;
+        if token_record_address(program_id, record.token_id).0 != *record_account.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        if !record.burned || record.game_id != game_id {
+            return Err(CustomError::InvalidInstruction.into());
+        }
+        token_ids.retain(|&token_id| token_id != record.token_id);
+    }
+
+    let mut packed = token_ids.chunks(GAME_TOKENS_PER_CHUNK as usize);
+    for chunk_account in chunk_accounts {
+        match packed.next() {
+            Some(ids) => {
+                let chunk = GameTokenChunk {
+                    token_ids: ids.to_vec(),
+                };
+                store_account(&chunk, chunk_account, CustomError::StateCorrupted)?;
+            }
+            None => close_pda_account(chunk_account, admin_account)?,
+        }
+    }
+    game_record.token_count = token_ids.len() as u64;
+    store_account(&game_record, game_account, CustomError::StateCorrupted)?;
+
+    Ok(())
+}
+
+// Accounts:
+//   0. `[]` contract state account
+//
+// Returns a Borsh `StateExport` via return data covering token ids
+// `page * EXPORT_TOKENS_PER_PAGE + 1` onwards; callers page until `has_more`
+// is false.
+fn export_state(program_id: &Pubkey, accounts: &[AccountInfo], page: u32) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+
+    let state = load_contract(program_id, contract_account)?;
+
+    let first = page as u64 * EXPORT_TOKENS_PER_PAGE + 1;
+    let last = (first + EXPORT_TOKENS_PER_PAGE - 1).min(state.last_token_id);
+    let tokens = (first..=last)
+        .map(|token_id| {
+            let mint = token_mint_address(program_id, token_id).0;
+            ExportedToken {
+                token_id,
+                record: token_record_address(program_id, token_id).0,
+                mint,
+                metadata: Pubkey::find_program_address(&[METADATA_SEED, mint.as_ref()], program_id)
+                    .0,
+            }
+        })
+        .collect();
+    let export = StateExport {
+        has_more: last < state.last_token_id,
+        state,
+        contract_pda: contract_pda_address(program_id).0,
+        tokens,
+    };
+    set_return_data(&export.try_to_vec()?);
+
+    Ok(())
+}
+
+// Opens a game for minting. Activating an active game does nothing.
+//
+// Accounts:
+//   0. `[]` contract state account
+//   1. `[signer]` admin (contract owner)
+//   2. `[writable]` game PDA for game_id
+fn activate_game(program_id: &Pubkey, accounts: &[AccountInfo], game_id: String) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let admin_account = next_account_info(account_info_iter)?;
+    let game_account = next_account_info(account_info_iter)?;
+
+    let contract_state: ContractState =
+        load_account(contract_account, CustomError::StateCorrupted)?;
+    assert_owner(program_id, contract_account, &contract_state, admin_account)?;
+
+    let (game_address, _) =
+        Pubkey::find_program_address(&[GAME_SEED, game_id.as_bytes()], program_id);
+    if game_address != *game_account.key || game_account.owner != program_id {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let mut game_record: GameRecord = load_account(game_account, CustomError::StateCorrupted)?;
+    if !game_record.active {
+        game_record.active = true;
+        store_account(&game_record, game_account, CustomError::StateCorrupted)?;
+    }
+
+    Ok(())
+}
+
+// Stores the whitelist Merkle root for game_id's drop in its config.
+//
+// Accounts:
+//   0. `[]` contract state account
+//   1. `[signer]` admin (contract owner)
+//   2. `[writable]` game PDA for game_id
+fn set_merkle_root(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    game_id: String,
+    root: [u8; 32],
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let admin_account = next_account_info(account_info_iter)?;
+    let game_account = next_account_info(account_info_iter)?;
+
+    let contract_state: ContractState =
+        load_account(contract_account, CustomError::StateCorrupted)?;
+    assert_owner(program_id, contract_account, &contract_state, admin_account)?;
+
+    let mut game_record = load_game(program_id, &game_id, game_account)?;
+    game_record.config.merkle_root = root;
+    store_account(&game_record, game_account, CustomError::StateCorrupted)?;
+
+    Ok(())
+}
+
+// Releases `amount` of game_id's reserved supply to the current phase. Fails
+// with InvalidInstruction when less than `amount` is reserved.
+//
+// Accounts:
+//   0. `[]` contract state account
+//   1. `[signer]` admin (contract owner)
+//   2. `[writable]` game PDA for game_id
+fn release_reserved(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    game_id: String,
+    amount: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let admin_account = next_account_info(account_info_iter)?;
+    let game_account = next_account_info(account_info_iter)?;
+
+    let contract_state: ContractState =
+        load_account(contract_account, CustomError::StateCorrupted)?;
+    assert_owner(program_id, contract_account, &contract_state, admin_account)?;
+
+    let mut game_record = load_game(program_id, &game_id, game_account)?;
+    game_record.config.reserved_supply = game_record
+        .config
+        .reserved_supply
+        .checked_sub(amount)
+        .ok_or(CustomError::InvalidInstruction)?;
+    store_account(&game_record, game_account, CustomError::StateCorrupted)?;
+
+    Ok(())
+}
+
+// Accounts:
+//   0. `[]` game PDA for game_id
+//
+// Returns the game's 32-byte Merkle root via return data, all zeroes when
+// none was set.
+fn get_merkle_root(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    game_id: String,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let game_account = next_account_info(account_info_iter)?;
+
+    let game_record = load_game(program_id, &game_id, game_account)?;
+    set_return_data(&game_record.config.merkle_root);
+
+    Ok(())
+}
+
+// Accounts:
+//   0. `[writable]` contract state account
+//   1. `[signer]` admin (contract owner)
+fn set_default_royalty(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    bps: u16,
+    recipient: Pubkey,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let admin_account = next_account_info(account_info_iter)?;
+
+    let mut contract_state: ContractState =
+        load_account(contract_account, CustomError::StateCorrupted)?;
+    assert_owner(program_id, contract_account, &contract_state, admin_account)?;
+    if bps > MAX_BPS {
+        return Err(CustomError::InvalidRoyalty.into());
+    }
+
+    contract_state.default_royalty_bps = bps;
+    contract_state.default_royalty_recipient = recipient;
+    store_account(
+        &contract_state,
+        contract_account,
+        CustomError::StateCorrupted,
+    )?;
+
+    Ok(())
+}
+
+// Sets the slot transfers stay frozen until; a slot already passed, like 0,
+// lifts the freeze.
+//
+// Accounts:
+//   0. `[writable]` contract state account
+//   1. `[signer]` admin (contract owner)
+fn freeze_transfers_until(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    slot: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let admin_account = next_account_info(account_info_iter)?;
+
+    let mut contract_state: ContractState =
+        load_account(contract_account, CustomError::StateCorrupted)?;
+    assert_owner(program_id, contract_account, &contract_state, admin_account)?;
+
+    contract_state.freeze_transfers_until_slot = slot;
+    store_account(
+        &contract_state,
+        contract_account,
+        CustomError::StateCorrupted,
+    )?;
+
+    Ok(())
+}
+
+// Accounts:
+//   0. `[writable]` contract state account
+//   1. `[signer]` admin (contract owner)
+fn set_grant_cooldown(program_id: &Pubkey, accounts: &[AccountInfo], slots: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let admin_account = next_account_info(account_info_iter)?;
+
+    let mut contract_state: ContractState =
+        load_account(contract_account, CustomError::StateCorrupted)?;
+    assert_owner(program_id, contract_account, &contract_state, admin_account)?;
+
+    contract_state.grant_cooldown_slots = slots;
+    store_account(
+        &contract_state,
+        contract_account,
+        CustomError::StateCorrupted,
+    )?;
+
+    Ok(())
+}
+
+// Accounts:
+//   0. `[]` game PDA for game_id
+//
+// Returns the game's `holder_count` as a Borsh `u64` via return data.
+fn get_holder_count(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    game_id: String,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let game_account = next_account_info(account_info_iter)?;
+
+    let game_record = load_game(program_id, &game_id, game_account)?;
+    set_return_data(&game_record.holder_count.to_le_bytes());
+
+    Ok(())
+}
+
+// Accounts:
+//   0. `[]` game PDA for game_id
+//
+// Returns the Borsh-serialized `GameRecord`, config included, via return
+// data; the result is empty when the game was never granted.
+fn get_game_config(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    game_id: String,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let game_account = next_account_info(account_info_iter)?;
+
+    let (game_address, _) =
+        Pubkey::find_program_address(&[GAME_SEED, game_id.as_bytes()], program_id);
+    if game_address != *game_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if game_account.owner != program_id {
+        set_return_data(&[]);
+        return Ok(());
+    }
+    let game_record: GameRecord = load_account(game_account, CustomError::StateCorrupted)?;
+    set_return_data(&game_record.try_to_vec()?);
+
+    Ok(())
+}
+
+// Runs `mint`'s preconditions for `user` minting game_id's next token to
+// `receiver`, in the order `mint` checks them, without changing anything.
+// The validator program and the fee payment aren't checked.
+//
+// Accounts:
+//   0. `[]` contract state account
+//   1. `[]` permission PDA for (user, game_id)
+//   2. `[]` game PDA for game_id
+//   3. `[]` receiver's KYC attestation PDA
+//   4. `[]` receiver's token account for the game's required token mint, only
+//      passed when the game sets one
+//
+// Returns a Borsh `Option<u32>` via return data: `None` when the mint would
+// go through, otherwise the code of the first `CustomError` it would fail
+// with.
+fn can_mint(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    user: Pubkey,
+    game_id: String,
+    receiver: Pubkey,
+) -> ProgramResult {
+    let failure = match check_mint(program_id, accounts, &user, &game_id, receiver) {
+        Ok(()) => None,
+        Err(ProgramError::Custom(code)) => Some(code),
+        Err(err) => return Err(err),
+    };
+    set_return_data(&failure.try_to_vec()?);
+
+    Ok(())
+}
+
+fn check_mint(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    user: &Pubkey,
+    game_id: &str,
+    receiver: Pubkey,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let permission_account = next_account_info(account_info_iter)?;
+    let game_account = next_account_info(account_info_iter)?;
+    let kyc_account = next_account_info(account_info_iter)?;
+
+    let contract_state: ContractState = load_contract(program_id, contract_account)?;
+    assert_not_paused(&contract_state)?;
+    if !contract_state.minting_enabled {
+        return Err(CustomError::MintingDisabled.into());
+    }
+
+    let (permission_address, _) = Pubkey::find_program_address(
+        &[PERMISSION_SEED, user.as_ref(), game_id.as_bytes()],
+        program_id,
+    );
+    if permission_address != *permission_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if permission_account.owner != program_id || permission_account.data_is_empty() {
+        return Err(CustomError::MintNotPermitted.into());
+    }
+    let mint_permission: MintPermission =
+        load_account(permission_account, CustomError::PermissionCorrupted)?;
+    if mint_permission.user != *user || mint_permission.game_id != game_id {
+        return Err(CustomError::MintNotPermitted.into());
+    }
+
+    let (game_address, _) =
+        Pubkey::find_program_address(&[GAME_SEED, game_id.as_bytes()], program_id);
+    if game_address != *game_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if game_account.owner != program_id {
+        return Err(CustomError::GameNotActive.into());
+    }
+    let game_record: GameRecord = load_account(game_account, CustomError::StateCorrupted)?;
+    if !game_record.active {
+        return Err(CustomError::GameNotActive.into());
+    }
+    assert_supply_available(&game_record)?;
+    assert_token_ids_available(
+        &contract_state,
+        if game_record.config.mint_receipt {
+            2
+        } else {
+            1
+        },
+    )?;
+
+    let reserve_every_n = game_record.config.reserve_every_n;
+    let reserved =
+        reserve_every_n > 0 && (game_record.token_count + 1).is_multiple_of(reserve_every_n);
+    let receiver = if reserved {
+        contract_state.treasury
+    } else {
+        assert_kyc_attested(program_id, &contract_state, &receiver, kyc_account)?;
+        receiver
+    };
+
+    let token_id = contract_state.last_token_id + 1;
+    let (mint_address, _) = token_mint_address(program_id, token_id);
+    let (authority_address, _) =
+        Pubkey::find_program_address(&[MINT_AUTHORITY_SEED, mint_address.as_ref()], program_id);
+    if [*contract_account.key, authority_address, game_address].contains(&receiver) {
+        return Err(CustomError::InvalidReceiver.into());
+    }
+
+    let required_mint = game_record.config.required_token_mint;
+    if required_mint != Pubkey::default() && !reserved {
+        let required_account = next_account_info(account_info_iter)?;
+        if !is_token_program(required_account.owner) {
+            return Err(CustomError::RequiredTokenMissing.into());
+        }
+        let holding = TokenAccount::unpack(&required_account.data.borrow())
+            .map_err(|_| CustomError::RequiredTokenMissing)?;
+        if holding.mint != required_mint || holding.owner != receiver || holding.amount == 0 {
+            return Err(CustomError::RequiredTokenMissing.into());
+        }
+    }
+
+    if contract_state.max_metadata_rent > 0
+        && metadata_rent(&Rent::get()?) > contract_state.max_metadata_rent
+    {
+        return Err(CustomError::MetadataRentTooHigh.into());
+    }
+
+    Ok(())
+}
+
+// Loads the game record for game_id, checking the account is its PDA.
+fn load_game(
+    program_id: &Pubkey,
+    game_id: &str,
+    game_account: &AccountInfo,
+) -> Result<GameRecord, ProgramError> {
+    let (game_address, _) =
+        Pubkey::find_program_address(&[GAME_SEED, game_id.as_bytes()], program_id);
+    if game_address != *game_account.key || game_account.owner != program_id {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    load_account(game_account, CustomError::StateCorrupted)
+}
+
+// Adds a token to `holder`'s holding of the game, creating the holding PDA on
+// their first token (paid by `payer`) and counting them as a new holder when
+// they had none.
+fn credit_holding<'a>(
+    program_id: &Pubkey,
+    game_record: &mut GameRecord,
+    holder: &Pubkey,
+    holding_account: &AccountInfo<'a>,
+    payer: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+) -> ProgramResult {
+    let (holding_address, holding_bump) = Pubkey::find_program_address(
+        &[
+            HOLDING_SEED,
+            game_record.game_id.as_bytes(),
+            holder.as_ref(),
+        ],
+        program_id,
+    );
+    if holding_address != *holding_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let mut holding = if holding_account.data_is_empty() {
+        create_pda_account(
+            program_id,
+            payer,
+            holding_account,
+            system_program,
+            GameHolding::LEN,
+            &[
+                HOLDING_SEED,
+                game_record.game_id.as_bytes(),
+                holder.as_ref(),
+                &[holding_bump],
+            ],
+        )?;
+        GameHolding {
+            game_id: game_record.game_id.clone(),
+            holder: *holder,
+            count: 0,
+        }
+    } else {
+        load_account(holding_account, CustomError::StateCorrupted)?
+    };
+
+    if holding.count == 0 {
+        game_record.holder_count += 1;
+    }
+    holding.count += 1;
+    store_account(&holding, holding_account, CustomError::StateCorrupted)
+}
+
+// Removes a token from `holder`'s holding of the game, counting one holder
+// fewer when it was their last.
+fn debit_holding(
+    program_id: &Pubkey,
+    game_record: &mut GameRecord,
+    holder: &Pubkey,
+    holding_account: &AccountInfo,
+) -> ProgramResult {
+    let (holding_address, _) = Pubkey::find_program_address(
+        &[
+            HOLDING_SEED,
+            game_record.game_id.as_bytes(),
+            holder.as_ref(),
+        ],
+        program_id,
+    );
+    if holding_address != *holding_account.key || holding_account.owner != program_id {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let mut holding: GameHolding = load_account(holding_account, CustomError::StateCorrupted)?;
+    holding.count = holding
+        .count
+        .checked_sub(1)
+        .ok_or(CustomError::StateCorrupted)?;
+    if holding.count == 0 {
+        game_record.holder_count -= 1;
+    }
+    store_account(&holding, holding_account, CustomError::StateCorrupted)
+}
+
+// Appends an event to the token's history, opening its next history chunk
+// when the current one is full. The caller stores `record`.
+#[allow(clippy::too_many_arguments)]
+fn append_history<'a>(
+    program_id: &Pubkey,
+    record: &mut TokenRecord,
+    event: HistoryEvent,
+    from: Pubkey,
+    to: Pubkey,
+    history_account: &AccountInfo<'a>,
+    payer: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+) -> ProgramResult {
+    let chunk_index = (record.history_len / TOKEN_HISTORY_PER_CHUNK) as u32;
+    let (history_address, history_bump) =
+        token_history_chunk_address(program_id, record.token_id, chunk_index);
+    if history_address != *history_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let mut chunk = if history_account.data_is_empty() {
+        create_pda_account(
+            program_id,
+            payer,
+            history_account,
+            system_program,
+            TokenHistoryChunk::LEN,
+            &[
+                HISTORY_SEED,
+                &record.token_id.to_le_bytes(),
+                &chunk_index.to_le_bytes(),
+                &[history_bump],
+            ],
+        )?;
+        TokenHistoryChunk { entries: vec![] }
+    } else {
+        load_account(history_account, CustomError::StateCorrupted)?
+    };
+    chunk.entries.push(HistoryEntry {
+        event,
+        slot: Clock::get()?.slot,
+        from,
+        to,
+    });
+    store_account(&chunk, history_account, CustomError::StateCorrupted)?;
+    record.history_len += 1;
+    Ok(())
+}
+
+// Accounts:
+//   0. `[]` token record PDA for token_id
+//   1.. `[]` the token history chunk PDAs covering the page, in order
+//
+// Returns a Borsh `Vec<HistoryEntry>` via return data: up to `limit` of the
+// token's history entries from `start`, oldest first, capped at
+// MAX_TOKEN_HISTORY_PAGE. Tokens never minted have no history.
+fn get_token_history(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    token_id: u64,
+    start: u64,
+    limit: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let record_account = next_account_info(account_info_iter)?;
+
+    if token_record_address(program_id, token_id).0 != *record_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    let mut entries: Vec<HistoryEntry> = vec![];
+    if record_account.owner == program_id && !record_account.data_is_empty() {
+        let record: TokenRecord = load_account(record_account, CustomError::StateCorrupted)?;
+        let end = start
+            .saturating_add(limit.min(MAX_TOKEN_HISTORY_PAGE))
+            .min(record.history_len);
+
+        let mut position = start;
+        while position < end {
+            let chunk_index = position / TOKEN_HISTORY_PER_CHUNK;
+            let chunk_account = next_account_info(account_info_iter)?;
+            let (chunk_address, _) =
+                token_history_chunk_address(program_id, token_id, chunk_index as u32);
+            if chunk_address != *chunk_account.key {
+                return Err(ProgramError::InvalidSeeds);
+            }
+            let chunk: TokenHistoryChunk =
+                load_account(chunk_account, CustomError::StateCorrupted)?;
+
+            let chunk_start = chunk_index * TOKEN_HISTORY_PER_CHUNK;
+            let chunk_end = (chunk_start + TOKEN_HISTORY_PER_CHUNK).min(end);
+            entries.extend_from_slice(
+                &chunk.entries
+                    [(position - chunk_start) as usize..(chunk_end - chunk_start) as usize],
+            );
+            position = chunk_end;
+        }
+    }
+
+    set_return_data(&entries.try_to_vec()?);
+
+    Ok(())
+}
+
+// Moves one of the game's tokens from `from`'s holding to `to`'s, keeping the
+// game's holder count in step, and returns the updated game record.
+#[allow(clippy::too_many_arguments)]
+fn move_holding<'a>(
+    program_id: &Pubkey,
+    game_id: &str,
+    game_account: &AccountInfo<'a>,
+    from: &Pubkey,
+    from_holding: &AccountInfo<'a>,
+    to: &Pubkey,
+    to_holding: &AccountInfo<'a>,
+    payer: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+) -> Result<GameRecord, ProgramError> {
+    let mut game_record = load_game(program_id, game_id, game_account)?;
+    debit_holding(program_id, &mut game_record, from, from_holding)?;
+    credit_holding(
+        program_id,
+        &mut game_record,
+        to,
+        to_holding,
+        payer,
+        system_program,
+    )?;
+    store_account(&game_record, game_account, CustomError::StateCorrupted)?;
+    Ok(game_record)
+}
+
+// Mints fail with MetadataRentTooHigh once `metadata_rent` at the current
+// rent parameters goes over `lamports`.
+//
+// Accounts:
+//   0. `[writable]` contract state account
+//   1. `[signer]` admin (contract owner)
+fn set_max_metadata_rent(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    lamports: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let admin_account = next_account_info(account_info_iter)?;
+
+    let mut contract_state: ContractState =
+        load_account(contract_account, CustomError::StateCorrupted)?;
+    assert_owner(program_id, contract_account, &contract_state, admin_account)?;
+
+    contract_state.max_metadata_rent = lamports;
+    store_account(
+        &contract_state,
+        contract_account,
+        CustomError::StateCorrupted,
+    )?;
+
+    Ok(())
+}
+
+// Takes back user's permission to mint game_id by closing its permission PDA;
+// a later grant starts the user's `minted` count over.
+//
+// Accounts:
+//   0. `[]` contract state account
+//   1. `[signer, writable]` admin (contract owner), receives the PDA's rent
+//   2. `[writable]` permission PDA for (user, game_id)
+fn revoke_mint(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    user: Pubkey,
+    game_id: String,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let admin_account = next_account_info(account_info_iter)?;
+    let permission_account = next_account_info(account_info_iter)?;
+
+    let contract_state: ContractState =
+        load_account(contract_account, CustomError::StateCorrupted)?;
+    assert_owner(program_id, contract_account, &contract_state, admin_account)?;
+
+    let (permission_address, _) = Pubkey::find_program_address(
+        &[PERMISSION_SEED, user.as_ref(), game_id.as_bytes()],
+        program_id,
+    );
+    if permission_address != *permission_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if permission_account.owner != program_id || permission_account.data_is_empty() {
+        return Err(CustomError::MintNotPermitted.into());
+    }
+
+    close_pda_account(permission_account, admin_account)
+}
+
+// Moves old_user's permission for game_id to new_user, keeping its URI, name
+// and `minted` count, and closes the old permission PDA.
+//
+// Accounts:
+//   0. `[]` contract state account
+//   1. `[signer, writable]` admin (contract owner), pays for the new PDA and
+//      receives the old one's rent
+//   2. `[writable]` permission PDA for (old_user, game_id)
+//   3. `[writable]` permission PDA for (new_user, game_id), must not exist yet
+//   4. `[]` system program
+fn reassign_permission(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    game_id: String,
+    old_user: Pubkey,
+    new_user: Pubkey,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let admin_account = next_account_info(account_info_iter)?;
+    let old_permission_account = next_account_info(account_info_iter)?;
+    let new_permission_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+
+    let contract_state: ContractState =
+        load_account(contract_account, CustomError::StateCorrupted)?;
+    assert_owner(program_id, contract_account, &contract_state, admin_account)?;
+
+    let (old_address, _) = Pubkey::find_program_address(
+        &[PERMISSION_SEED, old_user.as_ref(), game_id.as_bytes()],
+        program_id,
+    );
+    let (new_address, new_bump) = Pubkey::find_program_address(
+        &[PERMISSION_SEED, new_user.as_ref(), game_id.as_bytes()],
+        program_id,
+    );
+    if old_address != *old_permission_account.key || new_address != *new_permission_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if old_user == new_user {
+        return Err(CustomError::InvalidInstruction.into());
+    }
+    if old_permission_account.owner != program_id || old_permission_account.data_is_empty() {
+        return Err(CustomError::MintNotPermitted.into());
+    }
+    if !new_permission_account.data_is_empty() {
+        return Err(CustomError::GrantExists.into());
+    }
+
+    let mut mint_permission: MintPermission =
+        load_account(old_permission_account, CustomError::PermissionCorrupted)?;
+    mint_permission.user = new_user;
+    create_pda_account(
+        program_id,
+        admin_account,
+        new_permission_account,
+        system_program,
+        MintPermission::LEN,
+        &[
+            PERMISSION_SEED,
+            new_user.as_ref(),
+            game_id.as_bytes(),
+            &[new_bump],
+        ],
+    )?;
+    store_account(
+        &mint_permission,
+        new_permission_account,
+        CustomError::PermissionCorrupted,
+    )?;
+    close_pda_account(old_permission_account, admin_account)?;
+
+    Ok(())
+}
+
+// Moves permissions from old_game_id to new_game_id, e.g. after a rebrand:
+// each is re-created under the new game id with its user, URI, name and
+// `minted` count, and its old PDA is closed. Only the permissions passed are
+// moved; the game record and minted tokens keep the old id.
+//
+// Accounts:
+//   0. `[]` contract state account
+//   1. `[signer, writable]` admin (contract owner), pays for the new PDAs and
+//      receives the old ones' rent
+//   2. `[]` system program
+//   3.. one pair per permission:
+//      `[writable]` permission PDA for (user, old_game_id),
+//      `[writable]` permission PDA for (user, new_game_id), must not exist yet
+fn rename_game(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    old_game_id: String,
+    new_game_id: String,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let admin_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+
+    let contract_state: ContractState =
+        load_account(contract_account, CustomError::StateCorrupted)?;
+    assert_owner(program_id, contract_account, &contract_state, admin_account)?;
+
+    let pairs = account_info_iter.as_slice();
+    if new_game_id.is_empty()
+        || new_game_id.len() > MAX_GAME_ID_LEN
+        || new_game_id == old_game_id
+        || pairs.is_empty()
+        || !pairs.len().is_multiple_of(2)
+    {
+        return Err(CustomError::InvalidInstruction.into());
+    }
+
+    for pair in pairs.chunks(2) {
+        let (old_permission_account, new_permission_account) = (&pair[0], &pair[1]);
+        if old_permission_account.owner != program_id || old_permission_account.data_is_empty() {
+            return Err(CustomError::MintNotPermitted.into());
+        }
+        let mut mint_permission: MintPermission =
+            load_account(old_permission_account, CustomError::PermissionCorrupted)?;
+        let user = mint_permission.user;
+        let (old_address, _) = Pubkey::find_program_address(
+            &[PERMISSION_SEED, user.as_ref(), old_game_id.as_bytes()],
+            program_id,
+        );
+        let (new_address, new_bump) = Pubkey::find_program_address(
+            &[PERMISSION_SEED, user.as_ref(), new_game_id.as_bytes()],
+            program_id,
+        );
+        if old_address != *old_permission_account.key || new_address != *new_permission_account.key
+        {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        if !new_permission_account.data_is_empty() {
+            return Err(CustomError::GrantExists.into());
+        }
+
+        mint_permission.game_id = new_game_id.clone();
+        create_pda_account(
+            program_id,
+            admin_account,
+            new_permission_account,
+            system_program,
+            MintPermission::LEN,
+            &[
+                PERMISSION_SEED,
+                user.as_ref(),
+                new_game_id.as_bytes(),
+                &[new_bump],
+            ],
+        )?;
+        store_account(
+            &mint_permission,
+            new_permission_account,
+            CustomError::PermissionCorrupted,
+        )?;
+        close_pda_account(old_permission_account, admin_account)?;
+    }
+
+    Ok(())
+}
+
+// Accounts:
+//   0. `[]` contract state account
+//
+// Returns a Borsh `Vec<u64>` via return data: the ids the next `count` mints
+// would be assigned, in order. The list stops early when the id space runs
+// out or reaches the contract's `max_supply`, and at MAX_PEEK_BATCH_IDS.
+fn peek_batch_ids(program_id: &Pubkey, accounts: &[AccountInfo], count: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+
+    let contract_state: ContractState = load_contract(program_id, contract_account)?;
+    let available = if contract_state.max_supply > 0 {
+        contract_state
+            .max_supply
+            .saturating_sub(contract_state.last_token_id)
+    } else {
+        u64::MAX - contract_state.last_token_id
+    };
+    let count = count.min(available).min(MAX_PEEK_BATCH_IDS);
+    let ids: Vec<u64> = (1..=count)
+        .map(|offset| contract_state.last_token_id + offset)
+        .collect();
+    set_return_data(&ids.try_to_vec()?);
+
+    Ok(())
+}
+
+// Accounts:
+//   0. `[writable]` contract state account
+//   1. `[signer]` admin (contract owner)
+fn set_allowed_token_programs(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    programs: Vec<Pubkey>,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let admin_account = next_account_info(account_info_iter)?;
+
+    let mut contract_state: ContractState =
+        load_account(contract_account, CustomError::StateCorrupted)?;
+    assert_owner(program_id, contract_account, &contract_state, admin_account)?;
+    // An empty list would leave every mint, transfer and burn failing
+    if programs.is_empty()
+        || programs.len() > MAX_TOKEN_PROGRAMS
+        || !programs.iter().all(is_token_program)
+    {
+        return Err(CustomError::InvalidInstruction.into());
+    }
+
+    contract_state.allowed_token_programs = programs;
+    store_account(
+        &contract_state,
+        contract_account,
+        CustomError::StateCorrupted,
+    )?;
+
+    Ok(())
+}
+
+// Accounts:
+//   0. `[]` contract state account
+//
+// Returns `total_fees_collected` as a Borsh `u64` via return data.
+fn get_total_fees(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+
+    let contract_state: ContractState = load_contract(program_id, contract_account)?;
+    set_return_data(&contract_state.total_fees_collected.to_le_bytes());
+
+    Ok(())
+}
+
+// Shuts the contract down, sending the rent of the contract PDA and of the
+// `pdas` passed along to `destination`. Fails with TokensOutstanding while
+// tokens are still live, unless `force` is set.
+//
+// Accounts:
+//   0. `[writable]` contract PDA
+//   1. `[signer]` admin (contract owner)
+//   2. `[writable]` destination for the reclaimed lamports
+//   3.. `[writable]` the account of each of `pdas`, in order
+fn close_contract(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    force: bool,
+    pdas: Vec<ProgramPda>,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let admin_account = next_account_info(account_info_iter)?;
+    let destination_account = next_account_info(account_info_iter)?;
+
+    let contract_state = load_contract(program_id, contract_account)?;
+    assert_owner_signed(&contract_state, admin_account)?;
+    if contract_state.active_tokens > 0 && !force {
+        return Err(CustomError::TokensOutstanding.into());
+    }
+    let pda_accounts = account_info_iter.as_slice();
+    if pda_accounts.len() != pdas.len() {
+        return Err(CustomError::InvalidInstruction.into());
+    }
+    for (pda, account) in pdas.iter().zip(pda_accounts) {
+        if pda.address(program_id) != *account.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+    }
+
+    for account in std::iter::once(contract_account).chain(pda_accounts) {
+        if account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        if account.key == destination_account.key {
+            return Err(CustomError::InvalidInstruction.into());
+        }
+        close_pda_account(account, destination_account)?;
+    }
+
+    Ok(())
+}
+
+// Accounts:
+//   0. `[]` contract state account
+//
+// Returns the contract's `Dashboard` statistics, Borsh-encoded, via return data.
+fn get_dashboard(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+
+    let contract_state: ContractState = load_contract(program_id, contract_account)?;
+    let dashboard = Dashboard {
+        last_token_id: contract_state.last_token_id,
+        active_tokens: contract_state.active_tokens,
+        total_grants: contract_state.total_grants,
+        total_fees_collected: contract_state.total_fees_collected,
+        paused: contract_state.paused,
+        minting_enabled: contract_state.minting_enabled,
+        freeze_transfers_until_slot: contract_state.freeze_transfers_until_slot,
+    };
+    set_return_data(&dashboard.try_to_vec()?);
+
+    Ok(())
+}
+
+// Accounts:
+//   0.. `[]` token record PDA for each of token_ids, in order
+//
+// Returns a little-endian `u64` bitmask via return data, with bit i set when
+// token_ids[i] is a live token recorded as owned by `owner`.
+fn verify_bundle_ownership(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    token_ids: Vec<u64>,
+    owner: Pubkey,
+) -> ProgramResult {
+    if token_ids.len() > MAX_BUNDLE_TOKENS || accounts.len() != token_ids.len() {
+        return Err(CustomError::InvalidInstruction.into());
+    }
+
+    let mut owned: u64 = 0;
+    for (bit, (token_id, record_account)) in token_ids.iter().zip(accounts).enumerate() {
+        if token_record_address(program_id, *token_id).0 != *record_account.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        if record_account.owner != program_id || record_account.data_is_empty() {
+            continue;
+        }
+        let record: TokenRecord = load_account(record_account, CustomError::StateCorrupted)?;
+        if record.owner == owner && !record.burned {
+            owned |= 1 << bit;
+        }
+    }
+    set_return_data(&owned.to_le_bytes());
+
+    Ok(())
+}
+
+// Accounts:
+//   0.. `[]` token record PDA for each of token_ids, in order
+//
+// Returns a Borsh `Vec<Option<TokenRecord>>` via return data, one entry per
+// id, `None` where no record exists (never minted, or closed by CloseContract).
+fn get_token_records(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    token_ids: Vec<u64>,
+) -> ProgramResult {
+    if token_ids.len() > MAX_TOKEN_RECORDS_PAGE || accounts.len() != token_ids.len() {
+        return Err(CustomError::InvalidInstruction.into());
+    }
+
+    let mut records = Vec::with_capacity(token_ids.len());
+    for (token_id, record_account) in token_ids.iter().zip(accounts) {
+        if token_record_address(program_id, *token_id).0 != *record_account.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        let record = if record_account.owner != program_id || record_account.data_is_empty() {
+            None
+        } else {
+            Some(load_account::<TokenRecord>(
+                record_account,
+                CustomError::StateCorrupted,
+            )?)
+        };
+        records.push(record);
+    }
+    set_return_data(&records.try_to_vec()?);
+
+    Ok(())
+}
+
+// Burns an expired token; anyone can call it. The token is marked burned,
+// its account frozen through the mint's freeze authority (the owner never
+// approved a burn delegate), and the rent of its metadata and URI PDAs is
+// refunded to the owner.
+//
+// Accounts:
+//   0. `[writable]` contract state account
+//   1. `[writable]` token record PDA for token_id
+//   2. `[]` the token's mint
+//   3. `[writable]` owner's token account holding the token
+//   4. `[]` mint authority PDA for the mint, its freeze authority
+//   5. `[writable]` token metadata PDA for the mint
+//   6. `[writable]` token URI PDA for token_id
+//   7. `[writable]` the token's recorded owner, receiving the refund
+//   8. `[writable]` game PDA for the token's game
+//   9. `[writable]` owner's game holding PDA
+//   10. `[]` token program
+//   11. `[writable]` token history chunk PDA the burn is logged in
+//   12. `[signer, writable]` caller, pays if the history needs a new chunk
+//   13. `[]` system program
+//   14. `[]` the game's burn callback program, only passed when the game sets
+//       one, see `notify_burn`
+fn burn_expired(program_id: &Pubkey, accounts: &[AccountInfo], token_id: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let contract_account = next_account_info(account_info_iter)?;
+    let record_account = next_account_info(account_info_iter)?;
+    let mint_account = next_account_info(account_info_iter)?;
+    let token_account = next_account_info(account_info_iter)?;
+    let mint_authority = next_account_info(account_info_iter)?;
+    let metadata_account = next_account_info(account_info_iter)?;
+    let uri_account = next_account_info(account_info_iter)?;
+    let owner_account = next_account_info(account_info_iter)?;
+    let game_account = next_account_info(account_info_iter)?;
+    let holding_account = next_account_info(account_info_iter)?;
+    let token_program = next_account_info(account_info_iter)?;
+    let history_account = next_account_info(account_info_iter)?;
+    let payer_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+
+    if token_record_address(program_id, token_id).0 != *record_account.key
+        || record_account.owner != program_id
+    {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let mut record: TokenRecord = load_account(record_account, CustomError::StateCorrupted)?;
+    if record.burned {
+        return Err(CustomError::TokenBurned.into());
+    }
+    if !is_expired(&record)? {
+        return Err(CustomError::TokenNotExpired.into());
+    }
+    if record.mint != *mint_account.key
+        || record.uri_account != *uri_account.key
+        || record.owner != *owner_account.key
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    let (authority_address, authority_bump) = Pubkey::find_program_address(
+        &[MINT_AUTHORITY_SEED, mint_account.key.as_ref()],
+        program_id,
+    );
+    let (metadata_address, _) =
+        Pubkey::find_program_address(&[METADATA_SEED, mint_account.key.as_ref()], program_id);
+    if authority_address != *mint_authority.key || metadata_address != *metadata_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    let mut contract_state: ContractState = load_contract(program_id, contract_account)?;
+    assert_token_program(&contract_state, token_program)?;
+    let holding = TokenAccount::unpack(&token_account.data.borrow())?;
+    if holding.mint != record.mint || holding.owner != record.owner || holding.amount == 0 {
+        return Err(CustomError::NotTokenOwner.into());
+    }
+
+    invoke_signed(
+        &token_instruction(
+            freeze_account(
+                &spl_token::id(),
+                token_account.key,
+                mint_account.key,
+                mint_authority.key,
+                &[],
+            )?,
+            token_program,
+        ),
+        &[
+            token_account.clone(),
+            mint_account.clone(),
+            mint_authority.clone(),
+            token_program.clone(),
+        ],
+        &[&[
+            MINT_AUTHORITY_SEED,
+            mint_account.key.as_ref(),
+            &[authority_bump],
+        ]],
+    )?;
+
+    let mut game_record = load_game(program_id, &record.game_id, game_account)?;
+    debit_holding(program_id, &mut game_record, &record.owner, holding_account)?;
+    refill_supply(&mut game_record);
+    store_account(&game_record, game_account, CustomError::StateCorrupted)?;
+
+    let owner = record.owner;
+    append_history(
+        program_id,
+        &mut record,
+        HistoryEvent::Burn,
+        owner,
+        Pubkey::default(),
+        history_account,
+        payer_account,
+        system_program,
+    )?;
+    if record.burn_queued {
+        record.burn_queued = false;
+        contract_state.queued_burns = contract_state
+            .queued_burns
+            .checked_sub(1)
+            .ok_or(CustomError::StateCorrupted)?;
+    }
+    contract_state.active_tokens = contract_state
+        .active_tokens
+        .checked_sub(1)
+        .ok_or(CustomError::StateCorrupted)?;
+    record.burned = true;
+    store_account(&record, record_account, CustomError::StateCorrupted)?;
+    store_account(
+        &contract_state,
+        contract_account,
+        CustomError::StateCorrupted,
+    )?;
+
+    close_pda_account(metadata_account, owner_account)?;
+    close_pda_account(uri_account, owner_account)?;
+    msg!("{}:BURN:{}", EVENT_LOG_PREFIX, token_id);
+
+    notify_burn(&game_record, game_account, &record, account_info_iter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_program::{
+        entrypoint::SUCCESS,
+        instruction::{AccountMeta, Instruction},
+        program_option::COption,
+        program_pack::Pack,
+        program_stubs::{set_syscall_stubs, SyscallStubs},
+        program_utils::limited_deserialize,
+        rent::Rent,
+        system_instruction::SystemInstruction,
+        system_program, sysvar,
+    };
+    use spl_token::state::{Account, AccountState, Mint};
+    use std::{cell::RefCell, collections::HashMap, sync::Once};
+
+    const PROGRAM_ID: Pubkey = Pubkey::new_from_array([7; 32]);
+    // Mock game validator programs: one approves every mint it's shown, the
+    // other rejects them all
+    const APPROVING_VALIDATOR: Pubkey = Pubkey::new_from_array([8; 32]);
+    const REJECTING_VALIDATOR: Pubkey = Pubkey::new_from_array([9; 32]);
+    // Mock burn callback program recording every notification it receives
+    const BURN_CALLBACK: Pubkey = Pubkey::new_from_array([10; 32]);
+
+    thread_local! {
+        static LOGS: RefCell<Vec<String>> = const { RefCell::new(vec![]) };
+        static VALIDATIONS: RefCell<Vec<MintValidation>> = const { RefCell::new(vec![]) };
+        static BURN_NOTIFICATIONS: RefCell<Vec<BurnNotification>> = const { RefCell::new(vec![]) };
+        static RETURN_DATA: RefCell<Option<(Pubkey, Vec<u8>)>> = const { RefCell::new(None) };
+        static SLOT: RefCell<u64> = const { RefCell::new(0) };
+    }
+
+    // Syscall stubs standing in for the runtime: sysvars, return data, and CPIs
+    // into the system and token programs
+    struct TestSyscallStubs;
+
+    impl SyscallStubs for TestSyscallStubs {
+        fn sol_log(&self, message: &str) {
+            LOGS.with(|logs| logs.borrow_mut().push(message.to_string()));
+        }
+
+        fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+            unsafe { *(var_addr as *mut Rent) = Rent::default() };
+            SUCCESS
+        }
+
+        fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+            let clock = Clock {
+                slot: SLOT.with(|slot| *slot.borrow()),
+                ..Clock::default()
+            };
+            unsafe { *(var_addr as *mut Clock) = clock };
+            SUCCESS
+        }
+
+        fn sol_set_return_data(&self, data: &[u8]) {
+            RETURN_DATA.with(|r| *r.borrow_mut() = Some((PROGRAM_ID, data.to_vec())));
+        }
+
+        fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+            RETURN_DATA.with(|r| r.borrow().clone())
+        }
+
+        fn sol_invoke_signed(
+            &self,
+            instruction: &Instruction,
+            account_infos: &[AccountInfo],
+            signers_seeds: &[&[&[u8]]],
+        ) -> ProgramResult {
+            let signers = signers_seeds
+                .iter()
+                .map(|seeds| Pubkey::create_program_address(seeds, &PROGRAM_ID))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| ProgramError::InvalidSeeds)?;
+
+            let mut infos = vec![];
+            for meta in &instruction.accounts {
+                let mut info = account_infos
+                    .iter()
+                    .find(|info| *info.key == meta.pubkey)
+                    .ok_or(ProgramError::NotEnoughAccountKeys)?
+                    .clone();
+                if meta.is_signer {
+                    if !info.is_signer && !signers.contains(info.key) {
+                        return Err(ProgramError::MissingRequiredSignature);
+                    }
+                    info.is_signer = true;
+                }
+                if meta.is_writable && !info.is_writable {
+                    return Err(ProgramError::InvalidArgument);
+                }
+                infos.push(info);
+            }
+
+            if instruction.program_id == system_program::id() {
+                process_system_instruction(&infos, &instruction.data)
+            } else if is_token_program(&instruction.program_id) {
+                // Token-2022 runs the classic processor: the contract sends
+                // only instructions the two share, on extension-less accounts
+                spl_token::processor::Processor::process(
+                    &instruction.program_id,
+                    &infos,
+                    &instruction.data,
+                )
+            } else if instruction.program_id == APPROVING_VALIDATOR {
+                let validation = MintValidation::try_from_slice(&instruction.data)
+                    .map_err(|_| ProgramError::InvalidInstructionData)?;
+                VALIDATIONS.with(|v| v.borrow_mut().push(validation));
+                Ok(())
+            } else if instruction.program_id == BURN_CALLBACK {
+                let notification = BurnNotification::try_from_slice(&instruction.data)
+                    .map_err(|_| ProgramError::InvalidInstructionData)?;
+                BURN_NOTIFICATIONS.with(|n| n.borrow_mut().push(notification));
+                Ok(())
+            } else if instruction.program_id == REJECTING_VALIDATOR {
+                Err(ProgramError::Custom(0xdead))
+            } else {
+                Err(ProgramError::IncorrectProgramId)
+            }
+        }
+    }
+
+    fn process_system_instruction(accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+        let instruction: SystemInstruction =
+            limited_deserialize(data, 1024).map_err(|_| ProgramError::InvalidInstructionData)?;
+        match instruction {
+            SystemInstruction::CreateAccount {
+                lamports,
+                space,
+                owner,
+            } => {
+                let (from, to) = (&accounts[0], &accounts[1]);
+                if to.lamports() > 0 || !to.data_is_empty() {
+                    return Err(ProgramError::AccountAlreadyInitialized);
+                }
+                move_lamports(from, to, lamports)?;
+                *to.data.borrow_mut() = Box::leak(vec![0; space as usize].into_boxed_slice());
+                to.assign(&owner);
+                Ok(())
+            }
+            SystemInstruction::Transfer { lamports } => {
+                move_lamports(&accounts[0], &accounts[1], lamports)
+            }
+            _ => Err(ProgramError::InvalidInstructionData),
+        }
+    }
+
+    fn move_lamports(from: &AccountInfo, to: &AccountInfo, lamports: u64) -> ProgramResult {
+        if *from.owner != system_program::id() || !from.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if from.lamports() < lamports {
+            return Err(ProgramError::InsufficientFunds);
+        }
+        **from.lamports.borrow_mut() -= lamports;
+        **to.lamports.borrow_mut() += lamports;
+        Ok(())
+    }
+
+    #[derive(Clone, Debug)]
+    struct TestAccount {
+        lamports: u64,
+        data: Vec<u8>,
+        owner: Pubkey,
+    }
+
+    // A minimal bank: instructions run against the stored accounts and are
+    // committed only when they succeed, like a single-instruction transaction
+    #[derive(Default)]
+    struct TestBank {
+        accounts: HashMap<Pubkey, TestAccount>,
+    }
+
+    impl TestBank {
+        fn new() -> Self {
+            static STUBS: Once = Once::new();
+            STUBS.call_once(|| {
+                set_syscall_stubs(Box::new(TestSyscallStubs));
+            });
+            let mut bank = Self::default();
+            bank.set_sysvar(&sysvar::rent::id(), &Rent::default());
+            bank
+        }
+
+        fn warp_to_slot(&mut self, slot: u64) {
+            SLOT.with(|s| *s.borrow_mut() = slot);
+        }
+
+        // Records `upgrade_authority` in the program's ProgramData account, as
+        // deploying the program would
+        fn deploy(&mut self, upgrade_authority: &Pubkey) {
+            let mut data = vec![3, 0, 0, 0];
+            data.extend_from_slice(&0u64.to_le_bytes());
+            data.push(1);
+            data.extend_from_slice(upgrade_authority.as_ref());
+            self.set_account(
+                program_data_address(&PROGRAM_ID),
+                bpf_loader_upgradeable::id(),
+                data,
+            );
+        }
+
+        fn set_sysvar<S: Sysvar>(&mut self, key: &Pubkey, sysvar: &S) {
+            let (mut lamports, mut data, owner) = (0, vec![0; S::size_of()], sysvar::id());
+            let mut info = AccountInfo::new(
+                key,
+                false,
+                false,
+                &mut lamports,
+                &mut data,
+                &owner,
+                false,
+                0,
+            );
+            sysvar.to_account_info(&mut info).unwrap();
+            self.set_account(*key, owner, data);
+        }
+
+        fn set_account(&mut self, key: Pubkey, owner: Pubkey, data: Vec<u8>) {
+            let lamports = Rent::default().minimum_balance(data.len());
+            self.accounts.insert(
+                key,
+                TestAccount {
+                    lamports,
+                    data,
+                    owner,
+                },
+            );
+        }
+
+        fn airdrop(&mut self, key: Pubkey, lamports: u64) {
+            self.accounts
+                .entry(key)
+                .or_insert(TestAccount {
+                    lamports: 0,
+                    data: vec![],
+                    owner: system_program::id(),
+                })
+                .lamports += lamports;
+        }
+
+        fn data(&self, key: &Pubkey) -> &[u8] {
+            self.accounts.get(key).map_or(&[], |a| &a.data)
+        }
+
+        fn state<T: BorshDeserialize>(&self, key: &Pubkey) -> T {
+            T::deserialize(&mut self.data(key)).unwrap()
+        }
+
+        fn create_mint(&mut self, mint_authority: &Pubkey) -> Pubkey {
+            let mint = Pubkey::new_unique();
+            self.set_mint(&mint, mint_authority);
+            mint
+        }
+
+        fn set_mint(&mut self, mint: &Pubkey, mint_authority: &Pubkey) {
+            let mut data = vec![0; Mint::LEN];
+            Mint {
+                mint_authority: Some(*mint_authority).into(),
+                decimals: 0,
+                is_initialized: true,
+                ..Mint::default()
+            }
+            .pack_into_slice(&mut data);
+            self.set_account(*mint, spl_token::id(), data);
+        }
+
+        fn create_token_account(&mut self, mint: &Pubkey, owner: &Pubkey) -> Pubkey {
+            let token_account = Pubkey::new_unique();
+            let mut data = vec![0; Account::LEN];
+            Account {
+                mint: *mint,
+                owner: *owner,
+                state: AccountState::Initialized,
+                ..Account::default()
+            }
+            .pack_into_slice(&mut data);
+            self.set_account(token_account, spl_token::id(), data);
+            token_account
+        }
+
+        // A token account allocated for the token program but not initialized,
+        // as a client would create it ahead of a mint
+        fn allocate_token_account(&mut self) -> Pubkey {
+            let token_account = Pubkey::new_unique();
+            self.set_account(token_account, spl_token::id(), vec![0; Account::LEN]);
+            token_account
+        }
+
+        fn set_token_balance(&mut self, token_account: &Pubkey, amount: u64) {
+            let data = &mut self.accounts.get_mut(token_account).unwrap().data;
+            let mut account = Account::unpack(data).unwrap();
+            account.amount = amount;
+            account.pack_into_slice(data);
+        }
+
+        fn lamports(&self, key: &Pubkey) -> u64 {
+            self.accounts.get(key).map_or(0, |a| a.lamports)
+        }
+
+        fn token_balance(&self, token_account: &Pubkey) -> u64 {
+            Account::unpack(self.data(token_account)).unwrap().amount
+        }
+
+        fn process(&mut self, instruction: &Instruction) -> ProgramResult {
+            LOGS.with(|logs| logs.borrow_mut().clear());
+            RETURN_DATA.with(|r| *r.borrow_mut() = None);
+
+            let mut keys: Vec<Pubkey> = vec![];
+            for meta in &instruction.accounts {
+                if !keys.contains(&meta.pubkey) {
+                    keys.push(meta.pubkey);
+                }
+            }
+            let infos: Vec<AccountInfo<'static>> = keys
+                .iter()
+                .map(|key| {
+                    let account = self.accounts.get(key).cloned().unwrap_or(TestAccount {
+                        lamports: 0,
+                        data: vec![],
+                        owner: system_program::id(),
+                    });
+                    let metas = instruction.accounts.iter().filter(|m| m.pubkey == *key);
+                    let is_signer = metas.clone().any(|m| m.is_signer);
+                    let is_writable = metas.clone().any(|m| m.is_writable);
+                    AccountInfo::new(
+                        Box::leak(Box::new(*key)),
+                        is_signer,
+                        is_writable,
+                        Box::leak(Box::new(account.lamports)),
+                        Box::leak(account.data.into_boxed_slice()),
+                        Box::leak(Box::new(account.owner)),
+                        false,
+                        0,
+                    )
+                })
+                .collect();
+            let accounts: Vec<AccountInfo<'static>> = instruction
+                .accounts
+                .iter()
+                .map(|meta| infos[keys.iter().position(|k| *k == meta.pubkey).unwrap()].clone())
+                .collect();
+
+            // Token program instructions run directly, e.g. to act as a leaked key
+            if instruction.program_id == spl_token::id() {
+                spl_token::processor::Processor::process(
+                    &instruction.program_id,
+                    &accounts,
+                    &instruction.data,
+                )?;
+            } else {
+                process_instruction(&instruction.program_id, &accounts, &instruction.data)?;
+            }
+
+            let lamports_before: u64 = keys
+                .iter()
+                .filter_map(|key| self.accounts.get(key))
+                .map(|a| a.lamports)
+                .sum();
+            let lamports_after: u64 = infos.iter().map(|info| info.lamports()).sum();
+            assert_eq!(lamports_before, lamports_after, "lamports not conserved");
+
+            for info in &infos {
+                let account = TestAccount {
+                    lamports: info.lamports(),
+                    data: info.data.borrow().to_vec(),
+                    owner: *info.owner,
+                };
+                if !info.is_writable {
+                    let unchanged = self
+                        .accounts
+                        .get(info.key)
+                        .map_or(account.lamports == 0 && account.data.is_empty(), |before| {
+                            before.lamports == account.lamports && before.data == account.data
+                        });
+                    assert!(unchanged, "read-only account {} modified", info.key);
+                }
+                if account.lamports == 0 {
+                    self.accounts.remove(info.key);
+                } else {
+                    self.accounts.insert(*info.key, account);
+                }
+            }
+            Ok(())
+        }
+
+        fn logs(&self) -> Vec<String> {
+            LOGS.with(|logs| logs.borrow().clone())
+        }
+
+        fn set_data(&mut self, key: &Pubkey, data: Vec<u8>) {
+            self.accounts.get_mut(key).unwrap().data = data;
+        }
+
+        fn return_data(&self) -> Vec<u8> {
+            RETURN_DATA
+                .with(|r| r.borrow().clone())
+                .map(|(_, data)| data)
+                .unwrap_or_default()
+        }
+    }
+
+    fn pda(seeds: &[&[u8]]) -> Pubkey {
+        Pubkey::find_program_address(seeds, &PROGRAM_ID).0
+    }
+
+    fn pending_change_pda() -> Pubkey {
+        pda(&[PENDING_CHANGE_SEED, contract_pda().as_ref()])
+    }
+
+    fn permission_pda(user: &Pubkey, game_id: &str) -> Pubkey {
+        pda(&[PERMISSION_SEED, user.as_ref(), game_id.as_bytes()])
+    }
+
+    fn game_pda(game_id: &str) -> Pubkey {
+        pda(&[GAME_SEED, game_id.as_bytes()])
+    }
+
+    fn chunk_pda(game_id: &str, chunk_index: u32) -> Pubkey {
+        pda(&[
+            GAME_TOKENS_SEED,
+            game_id.as_bytes(),
+            &chunk_index.to_le_bytes(),
+        ])
+    }
+
+    fn initialize_ix(contract: &Pubkey, owner: &Pubkey) -> Instruction {
+        build_initialize_contract(&PROGRAM_ID, contract, owner, 0)
+    }
+
+    fn bootstrap_ix(
+        contract: &Pubkey,
+        owner: &Pubkey,
+        game_id: &str,
+        token_uri: &str,
+    ) -> Instruction {
+        build_bootstrap(&PROGRAM_ID, contract, owner, game_id, token_uri)
+    }
+
+    fn grant_mint_ix(
+        contract: &Pubkey,
+        admin: &Pubkey,
+        user: &Pubkey,
+        game_id: &str,
+        token_uri: &str,
+    ) -> Instruction {
+        build_grant_mint(
+            &PROGRAM_ID,
+            contract,
+            admin,
+            user,
+            game_id,
+            token_uri,
+            "",
+            false,
+        )
+    }
+
+    fn set_allowed_token_programs_ix(
+        contract: &Pubkey,
+        admin: &Pubkey,
+        programs: &[Pubkey],
+    ) -> Instruction {
+        build_set_allowed_token_programs(&PROGRAM_ID, contract, admin, programs)
+    }
+
+    fn verify_bundle_ownership_ix(token_ids: &[u64], owner: &Pubkey) -> Instruction {
+        build_verify_bundle_ownership(&PROGRAM_ID, token_ids, owner)
+    }
+
+    fn get_token_records_ix(token_ids: &[u64]) -> Instruction {
+        build_get_token_records(&PROGRAM_ID, token_ids)
+    }
+
+    fn batch_update_uris_ix(
+        contract: &Pubkey,
+        admin: &Pubkey,
+        updates: &[(u64, String)],
+    ) -> Instruction {
+        build_batch_update_uris(&PROGRAM_ID, contract, admin, updates)
+    }
+
+    fn peeked_ids(t: &mut TestContract, count: u64) -> Vec<u64> {
+        let ix = build_peek_batch_ids(&PROGRAM_ID, &t.contract, count);
+        t.bank.process(&ix).unwrap();
+        Vec::<u64>::try_from_slice(&t.bank.return_data()).unwrap()
+    }
+
+    fn revoke_mint_ix(
+        contract: &Pubkey,
+        admin: &Pubkey,
+        user: &Pubkey,
+        game_id: &str,
+    ) -> Instruction {
+        build_revoke_mint(&PROGRAM_ID, contract, admin, user, game_id)
+    }
+
+    fn reassign_permission_ix(
+        contract: &Pubkey,
+        admin: &Pubkey,
+        game_id: &str,
+        old_user: &Pubkey,
+        new_user: &Pubkey,
+    ) -> Instruction {
+        build_reassign_permission(&PROGRAM_ID, contract, admin, game_id, old_user, new_user)
+    }
+
+    fn rename_game_ix(
+        contract: &Pubkey,
+        admin: &Pubkey,
+        old_game_id: &str,
+        new_game_id: &str,
+        users: &[Pubkey],
+    ) -> Instruction {
+        build_rename_game(
+            &PROGRAM_ID,
+            contract,
+            admin,
+            old_game_id,
+            new_game_id,
+            users,
+        )
+    }
+
+    fn set_max_metadata_rent_ix(contract: &Pubkey, admin: &Pubkey, lamports: u64) -> Instruction {
+        build_set_max_metadata_rent(&PROGRAM_ID, contract, admin, lamports)
+    }
+
+    fn user_grant_pda(user: &Pubkey) -> Pubkey {
+        pda(&[USER_GRANT_SEED, user.as_ref()])
+    }
+
+    fn set_grant_cooldown_ix(contract: &Pubkey, admin: &Pubkey, slots: u64) -> Instruction {
+        build_set_grant_cooldown(&PROGRAM_ID, contract, admin, slots)
+    }
+
+    fn freeze_transfers_until_ix(contract: &Pubkey, admin: &Pubkey, slot: u64) -> Instruction {
+        build_freeze_transfers_until(&PROGRAM_ID, contract, admin, slot)
+    }
+
+    fn mint_pda(token_id: u64) -> Pubkey {
+        pda(&[MINT_SEED, &token_id.to_le_bytes()])
+    }
+
+    fn record_pda(token_id: u64) -> Pubkey {
+        pda(&[TOKEN_SEED, &token_id.to_le_bytes()])
+    }
+
+    fn metadata_pda(mint: &Pubkey) -> Pubkey {
+        pda(&[METADATA_SEED, mint.as_ref()])
+    }
+
+    fn kyc_pda(subject: &Pubkey) -> Pubkey {
+        pda(&[KYC_SEED, subject.as_ref()])
+    }
+
+    fn template_pda(game_id: &str) -> Pubkey {
+        pda(&[TEMPLATE_SEED, game_id.as_bytes()])
+    }
+
+    fn uri_pda(token_id: u64) -> Pubkey {
+        pda(&[URI_SEED, &token_id.to_le_bytes()])
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn mint_ix(
+        contract: &Pubkey,
+        token_id: u64,
+        receiver_token: &Pubkey,
+        receiver: &Pubkey,
+        minter: &Pubkey,
+        game_id: &str,
+        chunk_index: u32,
+    ) -> Instruction {
+        build_mint(
+            &PROGRAM_ID,
+            contract,
+            minter,
+            receiver,
+            receiver_token,
+            game_id,
+            token_id,
+            chunk_index,
+        )
+    }
+
+    fn history_pda(token_id: u64, chunk_index: u32) -> Pubkey {
+        pda(&[
+            HISTORY_SEED,
+            &token_id.to_le_bytes(),
+            &chunk_index.to_le_bytes(),
+        ])
+    }
+
+    fn get_token_history_ix(token_id: u64, start: u64, limit: u64, chunks: &[u32]) -> Instruction {
+        let mut data = vec![45];
+        data.extend_from_slice(&token_id.to_le_bytes());
+        data.extend_from_slice(&start.to_le_bytes());
+        data.extend_from_slice(&limit.to_le_bytes());
+        let mut accounts = vec![AccountMeta::new_readonly(record_pda(token_id), false)];
+        accounts.extend(
+            chunks
+                .iter()
+                .map(|&index| AccountMeta::new_readonly(history_pda(token_id, index), false)),
+        );
+        Instruction::new_with_bytes(PROGRAM_ID, &data, accounts)
+    }
+
+    fn holding_pda(game_id: &str, holder: &Pubkey) -> Pubkey {
+        pda(&[HOLDING_SEED, game_id.as_bytes(), holder.as_ref()])
+    }
+
+    fn get_holder_count_ix(game_id: &str) -> Instruction {
+        build_get_holder_count(&PROGRAM_ID, game_id)
+    }
+
+    fn get_game_config_ix(game_id: &str) -> Instruction {
+        build_get_game_config(&PROGRAM_ID, game_id)
+    }
+
+    fn can_mint_ix(
+        contract: &Pubkey,
+        user: &Pubkey,
+        game_id: &str,
+        receiver: &Pubkey,
+        extra_accounts: Vec<AccountMeta>,
+    ) -> Instruction {
+        let mut ix = build_can_mint(&PROGRAM_ID, contract, user, game_id, receiver);
+        ix.accounts.extend(extra_accounts);
+        ix
+    }
+
+    fn get_game_tokens_ix(game_id: &str, start: u64, limit: u64, chunks: &[u32]) -> Instruction {
+        let mut data = vec![5];
+        data.extend(game_id.to_string().try_to_vec().unwrap());
+        data.extend_from_slice(&start.to_le_bytes());
+        data.extend_from_slice(&limit.to_le_bytes());
+        let mut accounts = vec![AccountMeta::new_readonly(game_pda(game_id), false)];
+        accounts.extend(
+            chunks
+                .iter()
+                .map(|&index| AccountMeta::new_readonly(chunk_pda(game_id, index), false)),
+        );
+        Instruction::new_with_bytes(PROGRAM_ID, &data, accounts)
+    }
+
+    fn activate_game_ix(contract: &Pubkey, admin: &Pubkey, game_id: &str) -> Instruction {
+        build_activate_game(&PROGRAM_ID, contract, admin, game_id)
+    }
+
+    fn set_merkle_root_ix(
+        contract: &Pubkey,
+        admin: &Pubkey,
+        game_id: &str,
+        root: &[u8; 32],
+    ) -> Instruction {
+        build_set_merkle_root(&PROGRAM_ID, contract, admin, game_id, root)
+    }
+
+    fn release_reserved_ix(
+        contract: &Pubkey,
+        admin: &Pubkey,
+        game_id: &str,
+        amount: u64,
+    ) -> Instruction {
+        build_release_reserved(&PROGRAM_ID, contract, admin, game_id, amount)
+    }
+
+    fn get_merkle_root_ix(game_id: &str) -> Instruction {
+        build_get_merkle_root(&PROGRAM_ID, game_id)
+    }
+
+    fn set_default_royalty_ix(
+        contract: &Pubkey,
+        admin: &Pubkey,
+        bps: u16,
+        recipient: &Pubkey,
+    ) -> Instruction {
+        build_set_default_royalty(&PROGRAM_ID, contract, admin, bps, recipient)
+    }
+
+    fn set_game_config_ix(
+        contract: &Pubkey,
+        admin: &Pubkey,
+        game_id: &str,
+        config: &GameConfig,
+    ) -> Instruction {
+        build_set_game_config(&PROGRAM_ID, contract, admin, game_id, config)
+    }
+
+    // An initialized contract owned by `admin`, with the admin funded for fees
+    struct TestContract {
+        bank: TestBank,
+        contract: Pubkey,
+        admin: Pubkey,
+    }
+
+    impl TestContract {
+        fn new() -> Self {
+            Self::with_max_supply(0)
+        }
+
+        fn with_max_supply(max_supply: u64) -> Self {
+            let mut bank = TestBank::new();
+            let contract = contract_pda();
+            let admin = Pubkey::new_unique();
+            bank.deploy(&admin);
+            bank.airdrop(admin, 10_000_000_000);
+            bank.process(&build_initialize_contract(
+                &PROGRAM_ID,
+                &contract,
+                &admin,
+                max_supply,
+            ))
+            .unwrap();
+            Self {
+                bank,
+                contract,
+                admin,
+            }
+        }
+
+        // Grants `user` a mint permission and makes sure the game is active
+        fn grant(&mut self, user: &Pubkey, game_id: &str, token_uri: &str) -> ProgramResult {
+            let ix = grant_mint_ix(&self.contract, &self.admin, user, game_id, token_uri);
+            self.bank.process(&ix)?;
+            self.activate(game_id)
+        }
+
+        fn activate(&mut self, game_id: &str) -> ProgramResult {
+            let ix = activate_game_ix(&self.contract, &self.admin, game_id);
+            self.bank.process(&ix)
+        }
+
+        // Mints one token of `game_id` to a fresh token account owned by `user`,
+        // returning (mint, token account)
+        fn mint_to_user(
+            &mut self,
+            user: &Pubkey,
+            game_id: &str,
+        ) -> Result<(Pubkey, Pubkey), ProgramError> {
+            self.mint_to_user_with(user, game_id, vec![])
+        }
+
+        // Like `mint_to_user`, appending `extra_accounts` (e.g. fee accounts)
+        fn mint_to_user_with(
+            &mut self,
+            user: &Pubkey,
+            game_id: &str,
+            extra_accounts: Vec<AccountMeta>,
+        ) -> Result<(Pubkey, Pubkey), ProgramError> {
+            let token_id = self.contract_state().last_token_id + 1;
+            let token_account = self.bank.allocate_token_account();
+            let token_count = self.game(game_id).map_or(0, |g| g.token_count);
+            let chunk_index = (token_count / GAME_TOKENS_PER_CHUNK) as u32;
+            let mut ix = mint_ix(
+                &self.contract,
+                token_id,
+                &token_account,
+                user,
+                user,
+                game_id,
+                chunk_index,
+            );
+            ix.accounts.extend(extra_accounts);
+            self.bank.process(&ix)?;
+            Ok((mint_pda(token_id), token_account))
+        }
+
+        fn configure(&mut self, game_id: &str, config: &GameConfig) -> ProgramResult {
+            let ix = set_game_config_ix(&self.contract, &self.admin, game_id, config);
+            self.bank.process(&ix)
+        }
+
+        fn game(&self, game_id: &str) -> Option<GameRecord> {
+            let key = game_pda(game_id);
+            self.bank
+                .accounts
+                .contains_key(&key)
+                .then(|| self.bank.state(&key))
+        }
+
+        fn contract_state(&self) -> ContractState {
+            self.bank.state(&self.contract)
+        }
+    }
+
+    #[test]
+    fn test_get_game_tokens_paginates_across_chunks() {
+        let mut t = TestContract::new();
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        t.grant(&user, "racer", "ipfs://racer").unwrap();
+
+        // Interleave another game so the arena ids aren't contiguous
+        let mut arena_ids = vec![];
+        for i in 0..40 {
+            let (_, token_account) = t.mint_to_user(&user, "arena").unwrap();
+            assert_eq!(t.bank.token_balance(&token_account), 1);
+            arena_ids.push(t.contract_state().last_token_id);
+            if i % 10 == 0 {
+                t.mint_to_user(&user, "racer").unwrap();
+            }
+        }
+        assert_eq!(t.game("arena").unwrap().token_count, 40);
+
+        // A page that straddles the boundary between chunk 0 and chunk 1
+        t.bank
+            .process(&get_game_tokens_ix("arena", 25, 10, &[0, 1]))
+            .unwrap();
+        let page = Vec::<u64>::try_from_slice(&t.bank.return_data()).unwrap();
+        assert_eq!(page, arena_ids[25..35]);
+
+        // Walk the whole list in pages of 16
+        let mut collected = vec![];
+        for start in (0..40).step_by(16) {
+            let first = (start / GAME_TOKENS_PER_CHUNK) as u32;
+            let last = ((start + 15).min(39) / GAME_TOKENS_PER_CHUNK) as u32;
+            let chunks: Vec<u32> = (first..=last).collect();
+            t.bank
+                .process(&get_game_tokens_ix("arena", start, 16, &chunks))
+                .unwrap();
+            collected.extend(Vec::<u64>::try_from_slice(&t.bank.return_data()).unwrap());
+        }
+        assert_eq!(collected, arena_ids);
+
+        // The final short page only returns what exists
+        t.bank
+            .process(&get_game_tokens_ix("arena", 38, 10, &[1]))
+            .unwrap();
+        let page = Vec::<u64>::try_from_slice(&t.bank.return_data()).unwrap();
+        assert_eq!(page, arena_ids[38..]);
+    }
+
+    #[test]
+    fn test_get_game_tokens_empty_results() {
+        let mut t = TestContract::new();
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        t.mint_to_user(&user, "arena").unwrap();
+
+        // Past the end of the list
+        t.bank
+            .process(&get_game_tokens_ix("arena", 5, 10, &[]))
+            .unwrap();
+        assert_eq!(
+            Vec::<u64>::try_from_slice(&t.bank.return_data()).unwrap(),
+            Vec::<u64>::new()
+        );
+
+        // Zero limit
+        t.bank
+            .process(&get_game_tokens_ix("arena", 0, 0, &[]))
+            .unwrap();
+        assert_eq!(
+            Vec::<u64>::try_from_slice(&t.bank.return_data()).unwrap(),
+            Vec::<u64>::new()
+        );
+
+        // A game nobody has been granted
+        t.bank
+            .process(&get_game_tokens_ix("unknown", 0, 10, &[]))
+            .unwrap();
+        assert_eq!(
+            Vec::<u64>::try_from_slice(&t.bank.return_data()).unwrap(),
+            Vec::<u64>::new()
+        );
+
+        // Passing the wrong chunk is rejected
+        assert_eq!(
+            t.bank.process(&get_game_tokens_ix("arena", 0, 10, &[3])),
+            Err(ProgramError::InvalidSeeds)
+        );
+    }
+
+    #[test]
+    fn test_corrupted_contract_state_is_reported() {
+        let mut t = TestContract::new();
+        let user = Pubkey::new_unique();
+        let contract = t.contract;
+        t.bank.set_data(&contract, vec![0xff; 3]);
+
+        assert_eq!(
+            t.grant(&user, "arena", "ipfs://arena"),
+            Err(CustomError::StateCorrupted.into())
+        );
+        let expected = format!("StateCorrupted: failed to deserialize account {}", contract);
+        assert!(t.bank.logs().contains(&expected));
+    }
+
+    #[test]
+    fn test_corrupted_permission_is_reported() {
+        let mut t = TestContract::new();
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+
+        // A game_id whose bytes aren't valid UTF-8
+        let permission = permission_pda(&user, "arena");
+        let mut data = user.to_bytes().to_vec();
+        data.extend_from_slice(&4u32.to_le_bytes());
+        data.extend_from_slice(&[0xff; 4]);
+        data.resize(MintPermission::LEN, 0);
+        t.bank.set_data(&permission, data);
+
+        assert_eq!(
+            t.mint_to_user(&user, "arena"),
+            Err(CustomError::PermissionCorrupted.into())
+        );
+        let expected = format!(
+            "PermissionCorrupted: failed to deserialize account {}",
+            permission
+        );
+        assert!(t.bank.logs().contains(&expected));
+    }
+
+    #[test]
+    fn test_mint_price_paid_in_spl_token() {
+        let mut t = TestContract::new();
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+
+        let gold = t.bank.create_mint(&Pubkey::new_unique());
+        let treasury_gold = t.bank.create_token_account(&gold, &t.admin);
+        let user_gold = t.bank.create_token_account(&gold, &user);
+        t.bank.set_token_balance(&user_gold, 250);
+        let config = GameConfig {
+            price_mint: gold,
+            price_amount: 100,
+            ..GameConfig::default()
+        };
+        t.configure("arena", &config).unwrap();
+        assert_eq!(t.game("arena").unwrap().config, config);
+
+        let fee_accounts = vec![
+            AccountMeta::new(treasury_gold, false),
+            AccountMeta::new(user_gold, false),
+        ];
+        let (_, token_account) = t
+            .mint_to_user_with(&user, "arena", fee_accounts.clone())
+            .unwrap();
+        assert_eq!(t.bank.token_balance(&token_account), 1);
+        assert_eq!(t.bank.token_balance(&treasury_gold), 100);
+        assert_eq!(t.bank.token_balance(&user_gold), 150);
+
+        t.mint_to_user_with(&user, "arena", fee_accounts.clone())
+            .unwrap();
+        assert_eq!(t.bank.token_balance(&treasury_gold), 200);
+
+        // Not enough left for a third mint; nothing is minted or charged
+        assert!(t.mint_to_user_with(&user, "arena", fee_accounts).is_err());
+        assert_eq!(t.bank.token_balance(&treasury_gold), 200);
+        assert_eq!(t.contract_state().last_token_id, 2);
+    }
+
+    #[test]
+    fn test_mint_price_rejects_foreign_treasury_token_account() {
+        let mut t = TestContract::new();
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+
+        let gold = t.bank.create_mint(&Pubkey::new_unique());
+        let user_gold = t.bank.create_token_account(&gold, &user);
+        let attacker_gold = t.bank.create_token_account(&gold, &Pubkey::new_unique());
+        t.bank.set_token_balance(&user_gold, 250);
+        t.configure(
+            "arena",
+            &GameConfig {
+                price_mint: gold,
+                price_amount: 100,
+                ..GameConfig::default()
+            },
+        )
+        .unwrap();
+
+        let fee_accounts = vec![
+            AccountMeta::new(attacker_gold, false),
+            AccountMeta::new(user_gold, false),
+        ];
+        assert_eq!(
+            t.mint_to_user_with(&user, "arena", fee_accounts),
+            Err(CustomError::InvalidTreasury.into())
+        );
+    }
+
+    #[test]
+    fn test_mint_price_defaults_to_lamports() {
+        let mut t = TestContract::new();
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        t.configure(
+            "arena",
+            &GameConfig {
+                price_mint: Pubkey::default(),
+                price_amount: 5_000_000,
+                ..GameConfig::default()
+            },
+        )
+        .unwrap();
+
+        let treasury_before = t.bank.lamports(&t.admin);
+        let admin = t.admin;
+        t.mint_to_user_with(&user, "arena", vec![AccountMeta::new(admin, false)])
+            .unwrap();
+        assert_eq!(t.bank.lamports(&t.admin), treasury_before + 5_000_000);
+    }
+
+    #[test]
+    fn test_failed_mint_cpi_keeps_no_fee() {
+        let mut t = TestContract::new();
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        t.configure(
+            "arena",
+            &GameConfig {
+                price_amount: 5_000_000,
+                ..GameConfig::default()
+            },
+        )
+        .unwrap();
+
+        // A receiver token account that's already initialized for another
+        // mint fails the mint after the fee transfer
+        let other_mint = t.bank.create_mint(&Pubkey::new_unique());
+        let receiver_token = t.bank.create_token_account(&other_mint, &user);
+        let admin = t.admin;
+        let mut ix = mint_ix(&t.contract, 1, &receiver_token, &user, &user, "arena", 0);
+        ix.accounts.push(AccountMeta::new(admin, false));
+
+        let treasury_before = t.bank.lamports(&admin);
+        let user_before = t.bank.lamports(&user);
+        assert_eq!(t.bank.process(&ix), Err(ProgramError::InvalidAccountData));
+        assert_eq!(t.bank.lamports(&admin), treasury_before);
+        assert_eq!(t.bank.lamports(&user), user_before);
+        assert_eq!(t.contract_state().last_token_id, 0);
+    }
+
+    fn verify_authentic_ix(token_id: u64, mint: &Pubkey) -> Instruction {
+        build_verify_authentic(&PROGRAM_ID, token_id, mint)
+    }
+
+    #[test]
+    fn test_verify_authentic_genuine_token() {
+        let mut t = TestContract::new();
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        let (mint, token_account) = t.mint_to_user(&user, "arena").unwrap();
+
+        let token_account_state = Account::unpack(t.bank.data(&token_account)).unwrap();
+        assert_eq!(token_account_state.mint, mint);
+        assert_eq!(token_account_state.owner, user);
+        let record: TokenRecord = t.bank.state(&record_pda(1));
+        assert_eq!(
+            record,
+            TokenRecord {
+                version: TOKEN_RECORD_VERSION,
+                token_id: 1,
+                owner: user,
+                game_id: "arena".to_string(),
+                mint,
+                burn_queued: false,
+                burned: false,
+                uri_account: uri_pda(1),
+                value: 0,
+                expires_at_slot: 0,
+                soulbound: false,
+                is_receipt: false,
+                cooldown_ends_slot: 0,
+                history_len: 1,
+            }
+        );
+
+        t.bank.process(&verify_authentic_ix(1, &mint)).unwrap();
+        assert!(bool::try_from_slice(&t.bank.return_data()).unwrap());
+    }
+
+    #[test]
+    fn test_verify_authentic_spoofed_mint() {
+        let mut t = TestContract::new();
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        let (mint, _) = t.mint_to_user(&user, "arena").unwrap();
+
+        // A look-alike mint that didn't come from this program
+        let spoofed = t.bank.create_mint(&Pubkey::new_unique());
+        t.bank.process(&verify_authentic_ix(1, &spoofed)).unwrap();
+        assert!(!bool::try_from_slice(&t.bank.return_data()).unwrap());
+
+        // A genuine mint presented under another token id
+        t.bank.process(&verify_authentic_ix(2, &mint)).unwrap();
+        assert!(!bool::try_from_slice(&t.bank.return_data()).unwrap());
+    }
+
+    fn set_allowed_uri_schemes_ix(contract: &Pubkey, admin: &Pubkey, schemes: u8) -> Instruction {
+        build_set_allowed_uri_schemes(&PROGRAM_ID, contract, admin, schemes)
+    }
+
+    fn update_token_uri_ix(
+        contract: &Pubkey,
+        admin: &Pubkey,
+        token_id: u64,
+        token_uri: &str,
+    ) -> Instruction {
+        build_update_token_uri(&PROGRAM_ID, contract, admin, token_id, token_uri)
+    }
+
+    #[test]
+    fn test_grant_enforces_uri_scheme_allowlist() {
+        let mut t = TestContract::new();
+        let user = Pubkey::new_unique();
+        let (contract, admin) = (t.contract, t.admin);
+
+        // Any scheme goes until the owner restricts them
+        t.grant(&user, "arena", "https://cdn.game/1.json").unwrap();
+
+        t.bank
+            .process(&set_allowed_uri_schemes_ix(
+                &contract,
+                &admin,
+                URI_SCHEME_IPFS,
+            ))
+            .unwrap();
+        assert_eq!(
+            t.grant(&user, "arena", "https://cdn.game/1.json"),
+            Err(CustomError::UriSchemeNotAllowed.into())
+        );
+        t.grant(&user, "racer", "ipfs://bafy/1.json").unwrap();
+
+        t.bank
+            .process(&set_allowed_uri_schemes_ix(
+                &contract,
+                &admin,
+                URI_SCHEME_IPFS | URI_SCHEME_HTTPS,
+            ))
+            .unwrap();
+        t.grant(&user, "quest", "https://cdn.game/1.json").unwrap();
+    }
+
+    #[test]
+    fn test_update_token_uri_enforces_uri_scheme_allowlist() {
+        let mut t = TestContract::new();
+        let user = Pubkey::new_unique();
+        let (contract, admin) = (t.contract, t.admin);
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://bafy/1.json").unwrap();
+        t.mint_to_user(&user, "arena").unwrap();
+        t.bank
+            .process(&set_allowed_uri_schemes_ix(
+                &contract,
+                &admin,
+                URI_SCHEME_IPFS,
+            ))
+            .unwrap();
+
+        assert_eq!(
+            t.bank.process(&update_token_uri_ix(
+                &contract,
+                &admin,
+                1,
+                "https://cdn.game/1.json"
+            )),
+            Err(CustomError::UriSchemeNotAllowed.into())
+        );
+        t.bank
+            .process(&update_token_uri_ix(
+                &contract,
+                &admin,
+                1,
+                "ipfs://bafy/2.json",
+            ))
+            .unwrap();
+        let token_uri: TokenUri = t.bank.state(&uri_pda(1));
+        assert_eq!(token_uri.uri, "ipfs://bafy/2.json");
+
+        // Only the owner may change the allowlist
+        assert_eq!(
+            t.bank
+                .process(&set_allowed_uri_schemes_ix(&contract, &user, 0)),
+            Err(CustomError::NotAdmin.into())
+        );
+    }
+
+    fn contract_pda() -> Pubkey {
+        pda(&[CONTRACT_SEED])
+    }
+
+    fn burn_expired_ix(
+        contract: &Pubkey,
+        token_id: u64,
+        game_id: &str,
+        token_account: &Pubkey,
+        owner: &Pubkey,
+    ) -> Instruction {
+        build_burn_expired(
+            &PROGRAM_ID,
+            contract,
+            token_id,
+            game_id,
+            owner,
+            token_account,
+            0,
+            owner,
+        )
+    }
+
+    fn enqueue_burn_ix(
+        contract: &Pubkey,
+        token_id: u64,
+        token_account: &Pubkey,
+        owner: &Pubkey,
+    ) -> Instruction {
+        build_enqueue_burn(&PROGRAM_ID, contract, token_id, owner, token_account)
+    }
+
+    // Burns the queued `(token_id, token account)` pairs, all `owner`'s tokens of game_id
+    fn process_burn_queue_ix(
+        contract: &Pubkey,
+        game_id: &str,
+        owner: &Pubkey,
+        queued: &[(u64, Pubkey)],
+    ) -> Instruction {
+        let burns: Vec<_> = queued
+            .iter()
+            .map(|&(token_id, token_account)| (token_id, game_id, *owner, token_account, 0))
+            .collect();
+        build_process_burn_queue(&PROGRAM_ID, contract, owner, &burns)
+    }
+
+    #[test]
+    fn test_burn_queue_processes_batch() {
+        let mut t = TestContract::new();
+        let user = Pubkey::new_unique();
+        let contract = t.contract;
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+
+        let mut queued = vec![];
+        for token_id in 1..=3 {
+            let (_, token_account) = t.mint_to_user(&user, "arena").unwrap();
+            t.bank
+                .process(&enqueue_burn_ix(&contract, token_id, &token_account, &user))
+                .unwrap();
+            let record: TokenRecord = t.bank.state(&record_pda(token_id));
+            assert!(record.burn_queued);
+            queued.push((token_id, token_account));
+        }
+        assert_eq!(t.contract_state().queued_burns, 3);
+
+        t.bank
+            .process(&process_burn_queue_ix(&contract, "arena", &user, &queued))
+            .unwrap();
+        for (token_id, token_account) in &queued {
+            assert_eq!(t.bank.token_balance(token_account), 0);
+            let record: TokenRecord = t.bank.state(&record_pda(*token_id));
+            assert!(record.burned);
+            assert!(!record.burn_queued);
+        }
+        assert_eq!(t.contract_state().queued_burns, 0);
+
+        // Already processed, so they can't be burned again
+        assert_eq!(
+            t.bank.process(&process_burn_queue_ix(
+                &contract,
+                "arena",
+                &user,
+                &queued[..1]
+            )),
+            Err(CustomError::BurnNotQueued.into())
+        );
+    }
+
+    #[test]
+    fn test_burn_queue_dequeues_lost_approvals() {
+        let mut t = TestContract::new();
+        let user = Pubkey::new_unique();
+        let contract = t.contract;
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+
+        let mut queued = vec![];
+        for token_id in 1..=3 {
+            let (_, token_account) = t.mint_to_user(&user, "arena").unwrap();
+            t.bank
+                .process(&enqueue_burn_ix(&contract, token_id, &token_account, &user))
+                .unwrap();
+            queued.push((token_id, token_account));
+        }
+        // The owner revokes the burn delegate of token 2 after queueing it
+        t.bank
+            .process(
+                &spl_token::instruction::revoke(&spl_token::id(), &queued[1].1, &user, &[])
+                    .unwrap(),
+            )
+            .unwrap();
+        // and token 3 is passed with an account that doesn't hold it
+        let empty = t.bank.create_token_account(&mint_pda(3), &user);
+        let batch = [queued[0], queued[1], (3, empty)];
+
+        t.bank
+            .process(&process_burn_queue_ix(&contract, "arena", &user, &batch))
+            .unwrap();
+        let records: Vec<TokenRecord> = (1..=3)
+            .map(|token_id| t.bank.state(&record_pda(token_id)))
+            .collect();
+        assert!(records[0].burned && !records[0].burn_queued);
+        assert!(!records[1].burned && !records[1].burn_queued);
+        assert!(!records[2].burned && records[2].burn_queued);
+        assert_eq!(t.bank.token_balance(&queued[1].1), 1);
+        assert_eq!(t.contract_state().queued_burns, 1);
+
+        t.bank
+            .process(&process_burn_queue_ix(
+                &contract,
+                "arena",
+                &user,
+                &queued[2..],
+            ))
+            .unwrap();
+        assert!(t.bank.state::<TokenRecord>(&record_pda(3)).burned);
+        assert_eq!(t.contract_state().queued_burns, 0);
+    }
+
+    #[test]
+    fn test_transfer_and_burn_refuse_another_holders_account() {
+        let mut t = TestContract::new();
+        let contract = t.contract;
+        let (seller, buyer, other) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        for wallet in [seller, buyer, other] {
+            t.bank.airdrop(wallet, 10_000_000_000);
+        }
+        t.grant(&seller, "arena", "ipfs://arena").unwrap();
+        let (mint, seller_token) = t.mint_to_user(&seller, "arena").unwrap();
+
+        // Sold outside the program, so the record still names the seller, and
+        // the buyer then approves the seller as delegate
+        let buyer_token = t.bank.create_token_account(&mint, &buyer);
+        t.bank
+            .process(
+                &spl_token::instruction::transfer(
+                    &spl_token::id(),
+                    &seller_token,
+                    &buyer_token,
+                    &seller,
+                    &[],
+                    1,
+                )
+                .unwrap(),
+            )
+            .unwrap();
+        t.bank
+            .process(
+                &spl_token::instruction::approve(
+                    &spl_token::id(),
+                    &buyer_token,
+                    &seller,
+                    &buyer,
+                    &[],
+                    1,
+                )
+                .unwrap(),
+            )
+            .unwrap();
+
+        let other_token = t.bank.create_token_account(&mint, &other);
+        assert_eq!(
+            t.bank.process(&transfer_ix(
+                &contract,
+                1,
+                "arena",
+                &buyer_token,
+                &other_token,
+                &seller,
+                &other,
+            )),
+            Err(CustomError::NotTokenOwner.into())
+        );
+        assert_eq!(
+            t.bank
+                .process(&burn_ix(&contract, 1, "arena", &buyer_token, &seller)),
+            Err(CustomError::NotTokenOwner.into())
+        );
+        assert_eq!(t.bank.token_balance(&buyer_token), 1);
+    }
+
+    #[test]
+    fn test_burn_queue_requires_owner_and_queueing() {
+        let mut t = TestContract::new();
+        let user = Pubkey::new_unique();
+        let contract = t.contract;
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        let (_, token_account) = t.mint_to_user(&user, "arena").unwrap();
+
+        let stranger = Pubkey::new_unique();
+        assert_eq!(
+            t.bank
+                .process(&enqueue_burn_ix(&contract, 1, &token_account, &stranger)),
+            Err(CustomError::NotTokenOwner.into())
+        );
+        assert_eq!(
+            t.bank.process(&process_burn_queue_ix(
+                &contract,
+                "arena",
+                &user,
+                &[(1, token_account)]
+            )),
+            Err(CustomError::BurnNotQueued.into())
+        );
+        assert_eq!(t.bank.token_balance(&token_account), 1);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn transfer_with_payment_ix(
+        contract: &Pubkey,
+        token_id: u64,
+        price: u64,
+        game_id: &str,
+        seller_token: &Pubkey,
+        buyer_token: &Pubkey,
+        seller: &Pubkey,
+        buyer: &Pubkey,
+    ) -> Instruction {
+        build_transfer_with_payment(
+            &PROGRAM_ID,
+            contract,
+            token_id,
+            price,
+            game_id,
+            seller,
+            seller_token,
+            buyer,
+            buyer_token,
+            0,
+        )
+    }
+
+    #[test]
+    fn test_transfer_with_payment_enforces_floor() {
+        let mut t = TestContract::new();
+        let contract = t.contract;
+        let seller = Pubkey::new_unique();
+        let buyer = Pubkey::new_unique();
+        t.bank.airdrop(seller, 10_000_000_000);
+        t.bank.airdrop(buyer, 10_000_000_000);
+        t.grant(&seller, "arena", "ipfs://arena").unwrap();
+        t.configure(
+            "arena",
+            &GameConfig {
+                min_sale_price: 1_000_000,
+                ..GameConfig::default()
+            },
+        )
+        .unwrap();
+        let (mint, seller_token) = t.mint_to_user(&seller, "arena").unwrap();
+        let buyer_token = t.bank.create_token_account(&mint, &buyer);
+
+        let sell = |price| {
+            transfer_with_payment_ix(
+                &contract,
+                1,
+                price,
+                "arena",
+                &seller_token,
+                &buyer_token,
+                &seller,
+                &buyer,
+            )
+        };
+
+        // Below the floor
+        let seller_lamports = t.bank.lamports(&seller);
+        assert_eq!(
+            t.bank.process(&sell(999_999)),
+            Err(CustomError::PriceBelowFloor.into())
+        );
+        assert_eq!(t.bank.lamports(&seller), seller_lamports);
+        assert_eq!(t.bank.token_balance(&seller_token), 1);
+
+        // At the floor
+        t.bank.process(&sell(1_000_000)).unwrap();
+        assert_eq!(t.bank.lamports(&seller), seller_lamports + 1_000_000);
+        assert_eq!(t.bank.token_balance(&buyer_token), 1);
+        let record: TokenRecord = t.bank.state(&record_pda(1));
+        assert_eq!(record.owner, buyer);
+
+        // Above the floor, sold back
+        let buyer_lamports = t.bank.lamports(&buyer);
+        t.bank
+            .process(&transfer_with_payment_ix(
+                &contract,
+                1,
+                2_500_000,
+                "arena",
+                &buyer_token,
+                &seller_token,
+                &buyer,
+                &seller,
+            ))
+            .unwrap();
+        assert_eq!(t.bank.lamports(&buyer), buyer_lamports + 2_500_000);
+        assert_eq!(t.bank.token_balance(&seller_token), 1);
+    }
+
+    #[test]
+    fn test_transfer_with_payment_zero_floor_allows_free_sale() {
+        let mut t = TestContract::new();
+        let contract = t.contract;
+        let seller = Pubkey::new_unique();
+        let buyer = Pubkey::new_unique();
+        t.bank.airdrop(seller, 10_000_000_000);
+        t.bank.airdrop(buyer, 10_000_000_000);
+        t.grant(&seller, "arena", "ipfs://arena").unwrap();
+        let (mint, seller_token) = t.mint_to_user(&seller, "arena").unwrap();
+        let buyer_token = t.bank.create_token_account(&mint, &buyer);
+
+        t.bank
+            .process(&transfer_with_payment_ix(
+                &contract,
+                1,
+                0,
+                "arena",
+                &seller_token,
+                &buyer_token,
+                &seller,
+                &buyer,
+            ))
+            .unwrap();
+        assert_eq!(t.bank.token_balance(&buyer_token), 1);
+    }
+
+    fn batch_grant_ix(contract: &Pubkey, admin: &Pubkey, grants: &[MintPermission]) -> Instruction {
+        build_batch_grant(&PROGRAM_ID, contract, admin, grants)
+    }
+
+    #[test]
+    fn test_batch_grant_writes_each_permission() {
+        let mut t = TestContract::new();
+        let grants: Vec<MintPermission> = ["arena", "arena", "racer"]
+            .iter()
+            .enumerate()
+            .map(|(i, game_id)| MintPermission {
+                user: Pubkey::new_unique(),
+                game_id: game_id.to_string(),
+                token_uri: format!("ipfs://cohort/{}", i),
+                name: String::new(),
+                minted: 0,
+            })
+            .collect();
+
+        t.bank
+            .process(&batch_grant_ix(&t.contract, &t.admin, &grants))
+            .unwrap();
+        for grant in &grants {
+            let stored: MintPermission = t.bank.state(&permission_pda(&grant.user, &grant.game_id));
+            assert_eq!(stored.user, grant.user);
+            assert_eq!(stored.game_id, grant.game_id);
+            assert_eq!(stored.token_uri, grant.token_uri);
+        }
+        assert!(t.game("racer").is_some());
+
+        // A granted user can mint
+        t.bank.airdrop(grants[0].user, 10_000_000_000);
+        t.activate("arena").unwrap();
+        t.mint_to_user(&grants[0].user, "arena").unwrap();
+
+        // Accounts must line up with the grants
+        let mut misaligned = batch_grant_ix(&t.contract, &t.admin, &grants);
+        misaligned.accounts.pop();
+        assert_eq!(
+            t.bank.process(&misaligned),
+            Err(CustomError::InvalidInstruction.into())
+        );
+    }
+
+    fn set_minting_enabled_ix(contract: &Pubkey, admin: &Pubkey, enabled: bool) -> Instruction {
+        build_set_minting_enabled(&PROGRAM_ID, contract, admin, enabled)
+    }
+
+    #[test]
+    fn test_minting_switch_leaves_transfers_and_burns() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let user = Pubkey::new_unique();
+        let buyer = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.bank.airdrop(buyer, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        let (first_mint, first_token) = t.mint_to_user(&user, "arena").unwrap();
+        let (_, second_token) = t.mint_to_user(&user, "arena").unwrap();
+
+        t.bank
+            .process(&set_minting_enabled_ix(&contract, &admin, false))
+            .unwrap();
+        assert!(!t.contract_state().minting_enabled);
+        assert_eq!(
+            t.mint_to_user(&user, "arena"),
+            Err(CustomError::MintingDisabled.into())
+        );
+
+        // Transfers and burns still go through
+        let buyer_token = t.bank.create_token_account(&first_mint, &buyer);
+        t.bank
+            .process(&transfer_with_payment_ix(
+                &contract,
+                1,
+                0,
+                "arena",
+                &first_token,
+                &buyer_token,
+                &user,
+                &buyer,
+            ))
+            .unwrap();
+        assert_eq!(t.bank.token_balance(&buyer_token), 1);
+        t.bank
+            .process(&enqueue_burn_ix(&contract, 2, &second_token, &user))
+            .unwrap();
+        t.bank
+            .process(&process_burn_queue_ix(
+                &contract,
+                "arena",
+                &user,
+                &[(2, second_token)],
+            ))
+            .unwrap();
+        assert_eq!(t.bank.token_balance(&second_token), 0);
+
+        // Only the owner can flip it back
+        assert_eq!(
+            t.bank
+                .process(&set_minting_enabled_ix(&contract, &user, true)),
+            Err(CustomError::NotAdmin.into())
+        );
+        t.bank
+            .process(&set_minting_enabled_ix(&contract, &admin, true))
+            .unwrap();
+        t.mint_to_user(&user, "arena").unwrap();
+        assert_eq!(t.contract_state().last_token_id, 3);
+    }
+
+    #[test]
+    fn test_token_uri_lives_in_its_own_pda() {
+        let mut t = TestContract::new();
+        let user = Pubkey::new_unique();
+        let (contract, admin) = (t.contract, t.admin);
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://bafy/1.json").unwrap();
+        let (mint, _) = t.mint_to_user(&user, "arena").unwrap();
+
+        let record: TokenRecord = t.bank.state(&record_pda(1));
+        let metadata: TokenMetadata = t.bank.state(&metadata_pda(&mint));
+        assert_eq!(record.uri_account, uri_pda(1));
+        assert_eq!(metadata.uri_account, uri_pda(1));
+        assert_eq!(
+            t.bank.state::<TokenUri>(&uri_pda(1)),
+            TokenUri {
+                token_id: 1,
+                uri: "ipfs://bafy/1.json".to_string(),
+            }
+        );
+
+        let permission_before = t.bank.data(&permission_pda(&user, "arena")).to_vec();
+        let metadata_before = t.bank.data(&metadata_pda(&mint)).to_vec();
+        t.bank
+            .process(&update_token_uri_ix(
+                &contract,
+                &admin,
+                1,
+                "ipfs://bafy/1-v2.json",
+            ))
+            .unwrap();
+        assert_eq!(
+            t.bank.state::<TokenUri>(&uri_pda(1)).uri,
+            "ipfs://bafy/1-v2.json"
+        );
+        assert_eq!(
+            t.bank.data(&permission_pda(&user, "arena")),
+            &permission_before[..]
+        );
+        assert_eq!(t.bank.data(&metadata_pda(&mint)), &metadata_before[..]);
+
+        // Another token's URI PDA is rejected
+        t.mint_to_user(&user, "arena").unwrap();
+        let mut ix = update_token_uri_ix(&contract, &admin, 1, "ipfs://bafy/x.json");
+        ix.accounts[3] = AccountMeta::new(uri_pda(2), false);
+        assert_eq!(t.bank.process(&ix), Err(ProgramError::InvalidSeeds));
+    }
+
+    fn is_burned_ix(contract: &Pubkey, token_id: u64) -> Instruction {
+        build_is_burned(&PROGRAM_ID, contract, token_id)
+    }
+
+    fn burn_ix(
+        contract: &Pubkey,
+        token_id: u64,
+        game_id: &str,
+        token_account: &Pubkey,
+        owner: &Pubkey,
+    ) -> Instruction {
+        build_burn(
+            &PROGRAM_ID,
+            contract,
+            token_id,
+            game_id,
+            owner,
+            token_account,
+            0,
+        )
+    }
+
+    #[test]
+    fn test_is_burned_reports_live_burned_and_unminted() {
+        let mut t = TestContract::new();
+        let user = Pubkey::new_unique();
+        let contract = t.contract;
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        t.mint_to_user(&user, "arena").unwrap();
+        let (_, burned_token) = t.mint_to_user(&user, "arena").unwrap();
+        t.bank
+            .process(&enqueue_burn_ix(&contract, 2, &burned_token, &user))
+            .unwrap();
+        t.bank
+            .process(&process_burn_queue_ix(
+                &contract,
+                "arena",
+                &user,
+                &[(2, burned_token)],
+            ))
+            .unwrap();
+
+        t.bank.process(&is_burned_ix(&contract, 1)).unwrap();
+        assert_eq!(t.bank.return_data(), vec![0]);
+        t.bank.process(&is_burned_ix(&contract, 2)).unwrap();
+        assert_eq!(t.bank.return_data(), vec![1]);
+        t.bank.process(&is_burned_ix(&contract, 3)).unwrap();
+        assert_eq!(t.bank.return_data(), vec![TOKEN_NOT_MINTED]);
+    }
+
+    fn transfer_ix(
+        contract: &Pubkey,
+        token_id: u64,
+        game_id: &str,
+        owner_token: &Pubkey,
+        receiver_token: &Pubkey,
+        owner: &Pubkey,
+        receiver: &Pubkey,
+    ) -> Instruction {
+        build_transfer(
+            &PROGRAM_ID,
+            contract,
+            token_id,
+            game_id,
+            owner,
+            owner_token,
+            receiver,
+            receiver_token,
+            0,
+        )
+    }
+
+    fn transfer_ownership_ix(contract: &Pubkey, admin: &Pubkey, new_owner: &Pubkey) -> Instruction {
+        build_transfer_ownership(&PROGRAM_ID, contract, admin, new_owner)
+    }
+
+    fn propose_change_ix(
+        contract: &Pubkey,
+        admin: &Pubkey,
+        change: &ProposedChange,
+    ) -> Instruction {
+        build_propose_change(&PROGRAM_ID, contract, admin, change)
+    }
+
+    fn execute_change_ix(contract: &Pubkey, admin: &Pubkey) -> Instruction {
+        build_execute_change(&PROGRAM_ID, contract, admin)
+    }
+
+    fn set_transfer_approval_threshold_ix(
+        contract: &Pubkey,
+        admin: &Pubkey,
+        threshold: u64,
+    ) -> Instruction {
+        build_set_transfer_approval_threshold(&PROGRAM_ID, contract, admin, threshold)
+    }
+
+    #[test]
+    fn test_transfer_above_threshold_needs_admin_cosign() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let user = Pubkey::new_unique();
+        let receiver = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        t.grant(&user, "relic", "ipfs://relic").unwrap();
+        t.configure(
+            "relic",
+            &GameConfig {
+                price_amount: 5_000_000,
+                ..GameConfig::default()
+            },
+        )
+        .unwrap();
+        t.bank
+            .process(&set_transfer_approval_threshold_ix(
+                &contract, &admin, 1_000_000,
+            ))
+            .unwrap();
+
+        // A free token is below the threshold, so the owner alone can move it
+        let (cheap_mint, cheap_token) = t.mint_to_user(&user, "arena").unwrap();
+        let cheap_receiver = t.bank.create_token_account(&cheap_mint, &receiver);
+        t.bank
+            .process(&transfer_ix(
+                &contract,
+                1,
+                "arena",
+                &cheap_token,
+                &cheap_receiver,
+                &user,
+                &receiver,
+            ))
+            .unwrap();
+        assert_eq!(t.bank.token_balance(&cheap_receiver), 1);
+        assert_eq!(t.bank.state::<TokenRecord>(&record_pda(1)).owner, receiver);
+
+        // The relic was minted for more than the threshold
+        let (relic_mint, relic_token) = t
+            .mint_to_user_with(&user, "relic", vec![AccountMeta::new(admin, false)])
+            .unwrap();
+        assert_eq!(t.bank.state::<TokenRecord>(&record_pda(2)).value, 5_000_000);
+        let relic_receiver = t.bank.create_token_account(&relic_mint, &receiver);
+        let mut ix = transfer_ix(
+            &contract,
+            2,
+            "relic",
+            &relic_token,
+            &relic_receiver,
+            &user,
+            &receiver,
+        );
+        assert_eq!(
+            t.bank.process(&ix),
+            Err(CustomError::TransferApprovalRequired.into())
+        );
+        ix.accounts.push(AccountMeta::new_readonly(admin, false));
+        assert_eq!(
+            t.bank.process(&ix),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        ix.accounts.pop();
+        ix.accounts.push(AccountMeta::new_readonly(admin, true));
+        t.bank.process(&ix).unwrap();
+        assert_eq!(t.bank.token_balance(&relic_receiver), 1);
+    }
+
+    #[test]
+    fn test_transfer_rejects_non_owner() {
+        let mut t = TestContract::new();
+        let contract = t.contract;
+        let user = Pubkey::new_unique();
+        let thief = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        let (mint, token) = t.mint_to_user(&user, "arena").unwrap();
+        let thief_token = t.bank.create_token_account(&mint, &thief);
+
+        assert_eq!(
+            t.bank.process(&transfer_ix(
+                &contract,
+                1,
+                "arena",
+                &token,
+                &thief_token,
+                &thief,
+                &thief,
+            )),
+            Err(CustomError::NotTokenOwner.into())
+        );
+        assert_eq!(t.bank.token_balance(&token), 1);
+    }
+
+    #[test]
+    fn test_transfer_rejects_mismatched_record() {
+        let mut t = TestContract::new();
+        let contract = t.contract;
+        let user = Pubkey::new_unique();
+        let receiver = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        let (first_mint, first_token) = t.mint_to_user(&user, "arena").unwrap();
+        let (second_mint, second_token) = t.mint_to_user(&user, "arena").unwrap();
+        let receiver_token = t.bank.create_token_account(&first_mint, &receiver);
+
+        // Token 1 with token 2's record
+        let mut ix = transfer_ix(
+            &contract,
+            1,
+            "arena",
+            &first_token,
+            &receiver_token,
+            &user,
+            &receiver,
+        );
+        ix.accounts[1].pubkey = record_pda(2);
+        assert_eq!(t.bank.process(&ix), Err(ProgramError::InvalidSeeds));
+
+        // Token 2's record and receiver with token 1's holding
+        let second_receiver = t.bank.create_token_account(&second_mint, &receiver);
+        assert_eq!(
+            t.bank.process(&transfer_ix(
+                &contract,
+                2,
+                "arena",
+                &first_token,
+                &second_receiver,
+                &user,
+                &receiver,
+            )),
+            Err(ProgramError::InvalidAccountData)
+        );
+
+        // A record whose stored mint no longer matches its token id
+        let mut record = t.bank.state::<TokenRecord>(&record_pda(1));
+        record.mint = second_mint;
+        let mut data = t.bank.data(&record_pda(1)).to_vec();
+        record.serialize(&mut &mut data[..]).unwrap();
+        t.bank.set_data(&record_pda(1), data);
+        assert_eq!(
+            t.bank.process(&transfer_ix(
+                &contract,
+                1,
+                "arena",
+                &first_token,
+                &receiver_token,
+                &user,
+                &receiver,
+            )),
+            Err(CustomError::StateCorrupted.into())
+        );
+        assert_eq!(t.bank.token_balance(&first_token), 1);
+        assert_eq!(t.bank.token_balance(&second_token), 1);
+    }
+
+    fn snapshot_pda(snapshot_slot: u64) -> Pubkey {
+        pda(&[SNAPSHOT_SEED, &snapshot_slot.to_le_bytes()])
+    }
+
+    fn record_snapshot_ix(
+        contract: &Pubkey,
+        admin: &Pubkey,
+        snapshot_slot: u64,
+        token_ids: &[u64],
+    ) -> Instruction {
+        build_record_snapshot(&PROGRAM_ID, contract, admin, snapshot_slot, token_ids)
+    }
+
+    fn claim_snapshot_reward_ix(
+        snapshot_slot: u64,
+        token_id: u64,
+        claimant: &Pubkey,
+    ) -> Instruction {
+        build_claim_snapshot_reward(&PROGRAM_ID, snapshot_slot, token_id, claimant)
+    }
+
+    #[test]
+    fn test_snapshot_reward_goes_to_holder_at_snapshot() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let holder = Pubkey::new_unique();
+        let buyer = Pubkey::new_unique();
+        t.bank.airdrop(holder, 10_000_000_000);
+        t.grant(&holder, "arena", "ipfs://arena").unwrap();
+        let (mint, token) = t.mint_to_user(&holder, "arena").unwrap();
+
+        t.bank.warp_to_slot(100);
+        for slot in [99, 101] {
+            assert_eq!(
+                t.bank
+                    .process(&record_snapshot_ix(&contract, &admin, slot, &[1])),
+                Err(CustomError::InvalidInstruction.into())
+            );
+        }
+        t.bank
+            .process(&record_snapshot_ix(&contract, &admin, 100, &[1]))
+            .unwrap();
+
+        // Sold on after the snapshot
+        let buyer_token = t.bank.create_token_account(&mint, &buyer);
+        t.bank
+            .process(&transfer_ix(
+                &contract,
+                1,
+                "arena",
+                &token,
+                &buyer_token,
+                &holder,
+                &buyer,
+            ))
+            .unwrap();
+
+        assert_eq!(
+            t.bank.process(&claim_snapshot_reward_ix(100, 1, &buyer)),
+            Err(CustomError::NotSnapshotHolder.into())
+        );
+        t.bank
+            .process(&claim_snapshot_reward_ix(100, 1, &holder))
+            .unwrap();
+        assert_eq!(
+            t.bank.process(&claim_snapshot_reward_ix(100, 1, &holder)),
+            Err(CustomError::SnapshotRewardClaimed.into())
+        );
+    }
+
+    fn set_game_metadata_template_ix(
+        contract: &Pubkey,
+        admin: &Pubkey,
+        game_id: &str,
+        template: &GameMetadataTemplate,
+    ) -> Instruction {
+        build_set_game_metadata_template(&PROGRAM_ID, contract, admin, game_id, template)
+    }
+
+    #[test]
+    fn test_mint_builds_metadata_from_game_template() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let user = Pubkey::new_unique();
+        let champion = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.bank.airdrop(champion, 10_000_000_000);
+        let template = GameMetadataTemplate {
+            name_template: "Arena Relic".to_string(),
+            symbol: "RELIC".to_string(),
+            uri_prefix: "ipfs://bafy/relics/".to_string(),
+        };
+        t.bank
+            .process(&set_game_metadata_template_ix(
+                &contract, &admin, "arena", &template,
+            ))
+            .unwrap();
+
+        // Grants without a URI or name inherit the template
+        t.grant(&user, "arena", "").unwrap();
+        let (first, _) = t.mint_to_user(&user, "arena").unwrap();
+        let (second, _) = t.mint_to_user(&user, "arena").unwrap();
+        for (token_id, mint) in [(1, first), (2, second)] {
+            let metadata: TokenMetadata = t.bank.state(&metadata_pda(&mint));
+            assert_eq!(metadata.name, format!("Arena Relic #{}", token_id));
+            assert_eq!(metadata.symbol, "RELIC");
+            assert_eq!(
+                t.bank.state::<TokenUri>(&uri_pda(token_id)).uri,
+                format!("ipfs://bafy/relics/{}", token_id)
+            );
+        }
+
+        // A per-token name and URI override the template
+        let ix = build_grant_mint(
+            &PROGRAM_ID,
+            &contract,
+            &admin,
+            &champion,
+            "arena",
+            "ipfs://bafy/champion",
+            "Champion's Blade",
+            false,
+        );
+        t.bank.process(&ix).unwrap();
+        let (blade, _) = t.mint_to_user(&champion, "arena").unwrap();
+        let metadata: TokenMetadata = t.bank.state(&metadata_pda(&blade));
+        assert_eq!(metadata.name, "Champion's Blade");
+        assert_eq!(metadata.symbol, "RELIC");
+        assert_eq!(
+            t.bank.state::<TokenUri>(&uri_pda(3)).uri,
+            "ipfs://bafy/champion"
+        );
+    }
+
+    #[test]
+    fn test_get_contract_pda_matches_derivation() {
+        let mut t = TestContract::new();
+        t.bank
+            .process(&Instruction::new_with_bytes(PROGRAM_ID, &[20], vec![]))
+            .unwrap();
+        let (address, bump) = <(Pubkey, u8)>::try_from_slice(&t.bank.return_data()).unwrap();
+        assert_eq!(
+            (address, bump),
+            Pubkey::find_program_address(&[CONTRACT_SEED], &PROGRAM_ID)
+        );
+        assert_eq!(
+            Pubkey::create_program_address(&[CONTRACT_SEED, &[bump]], &PROGRAM_ID).unwrap(),
+            contract_pda()
+        );
+    }
+
+    fn regrant_ix(
+        contract: &Pubkey,
+        admin: &Pubkey,
+        user: &Pubkey,
+        game_id: &str,
+        token_uri: &str,
+        overwrite: bool,
+    ) -> Instruction {
+        build_grant_mint(
+            &PROGRAM_ID,
+            contract,
+            admin,
+            user,
+            game_id,
+            token_uri,
+            "",
+            overwrite,
+        )
+    }
+
+    #[test]
+    fn test_regrant_needs_overwrite_and_keeps_minted() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://bafy/1.json").unwrap();
+        t.mint_to_user(&user, "arena").unwrap();
+        t.mint_to_user(&user, "arena").unwrap();
+
+        let permission = permission_pda(&user, "arena");
+        assert_eq!(t.bank.state::<MintPermission>(&permission).minted, 2);
+        assert_eq!(
+            t.grant(&user, "arena", "ipfs://bafy/2.json"),
+            Err(CustomError::GrantExists.into())
+        );
+        assert_eq!(
+            t.bank.process(&regrant_ix(
+                &contract,
+                &admin,
+                &user,
+                "arena",
+                "ipfs://bafy/2.json",
+                false
+            )),
+            Err(CustomError::GrantExists.into())
+        );
+        assert_eq!(
+            t.bank.state::<MintPermission>(&permission).token_uri,
+            "ipfs://bafy/1.json"
+        );
+
+        t.bank
+            .process(&regrant_ix(
+                &contract,
+                &admin,
+                &user,
+                "arena",
+                "ipfs://bafy/2.json",
+                true,
+            ))
+            .unwrap();
+        let updated: MintPermission = t.bank.state(&permission);
+        assert_eq!(updated.token_uri, "ipfs://bafy/2.json");
+        assert_eq!(updated.minted, 2);
+    }
+
+    fn set_treasury_split_ix(
+        contract: &Pubkey,
+        admin: &Pubkey,
+        recipients: &[TreasuryShare],
+    ) -> Instruction {
+        build_set_treasury_split(&PROGRAM_ID, contract, admin, recipients)
+    }
+
+    #[test]
+    fn test_mint_fee_splits_across_treasury_recipients() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let user = Pubkey::new_unique();
+        let recipients: Vec<Pubkey> = (0..3).map(|_| Pubkey::new_unique()).collect();
+        t.bank.airdrop(user, 10_000_000_000);
+        for recipient in &recipients {
+            t.bank.airdrop(*recipient, 1_000_000);
+        }
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        t.configure(
+            "arena",
+            &GameConfig {
+                price_amount: 1_000_001,
+                ..GameConfig::default()
+            },
+        )
+        .unwrap();
+
+        let split: Vec<TreasuryShare> = recipients
+            .iter()
+            .zip([5_000, 3_000, 2_000])
+            .map(|(recipient, bps)| TreasuryShare {
+                recipient: *recipient,
+                bps,
+            })
+            .collect();
+        t.bank
+            .process(&set_treasury_split_ix(&contract, &admin, &split))
+            .unwrap();
+
+        t.mint_to_user_with(
+            &user,
+            "arena",
+            recipients
+                .iter()
+                .map(|recipient| AccountMeta::new(*recipient, false))
+                .collect(),
+        )
+        .unwrap();
+        // 50% / 30% / 20%, the last recipient picking up the rounding dust
+        assert_eq!(t.bank.lamports(&recipients[0]), 1_000_000 + 500_000);
+        assert_eq!(t.bank.lamports(&recipients[1]), 1_000_000 + 300_000);
+        assert_eq!(t.bank.lamports(&recipients[2]), 1_000_000 + 200_001);
+
+        // Recipients out of order are rejected
+        assert_eq!(
+            t.mint_to_user_with(
+                &user,
+                "arena",
+                recipients
+                    .iter()
+                    .rev()
+                    .map(|recipient| AccountMeta::new(*recipient, false))
+                    .collect(),
+            ),
+            Err(CustomError::InvalidTreasury.into())
+        );
+    }
+
+    #[test]
+    fn test_treasury_split_must_sum_to_whole() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let split = [
+            TreasuryShare {
+                recipient: Pubkey::new_unique(),
+                bps: 6_000,
+            },
+            TreasuryShare {
+                recipient: Pubkey::new_unique(),
+                bps: 3_000,
+            },
+        ];
+        assert_eq!(
+            t.bank
+                .process(&set_treasury_split_ix(&contract, &admin, &split)),
+            Err(CustomError::InvalidTreasurySplit.into())
+        );
+        let five: Vec<TreasuryShare> = (0..5)
+            .map(|_| TreasuryShare {
+                recipient: Pubkey::new_unique(),
+                bps: 2_000,
+            })
+            .collect();
+        assert_eq!(
+            t.bank
+                .process(&set_treasury_split_ix(&contract, &admin, &five)),
+            Err(CustomError::InvalidTreasurySplit.into())
+        );
+        assert!(t.contract_state().treasury_split.is_empty());
+    }
+
+    fn disable_minting_ix(contract: &Pubkey, admin: &Pubkey, token_id: u64) -> Instruction {
+        build_disable_minting(&PROGRAM_ID, contract, admin, token_id)
+    }
+
+    #[test]
+    fn test_disable_minting_fixes_supply() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        let (mint, token_account) = t.mint_to_user(&user, "arena").unwrap();
+
+        // Whoever holds the mint authority's signature could mint more
+        let authority = pda(&[MINT_AUTHORITY_SEED, mint.as_ref()]);
+        let leaked_mint_to =
+            mint_to(&spl_token::id(), &mint, &token_account, &authority, &[], 1).unwrap();
+        t.bank.process(&leaked_mint_to).unwrap();
+        assert_eq!(t.bank.token_balance(&token_account), 2);
+
+        assert_eq!(
+            t.bank.process(&disable_minting_ix(&contract, &user, 1)),
+            Err(CustomError::NotAdmin.into())
+        );
+        t.bank
+            .process(&disable_minting_ix(&contract, &admin, 1))
+            .unwrap();
+        let state = Mint::unpack(t.bank.data(&mint)).unwrap();
+        assert!(state.mint_authority.is_none());
+
+        assert_eq!(
+            t.bank.process(&leaked_mint_to),
+            Err(spl_token::error::TokenError::FixedSupply.into())
+        );
+        assert_eq!(t.bank.token_balance(&token_account), 2);
+    }
+
+    fn set_kyc_gate_ix(
+        contract: &Pubkey,
+        admin: &Pubkey,
+        required: bool,
+        authority: &Pubkey,
+    ) -> Instruction {
+        build_set_kyc_gate(&PROGRAM_ID, contract, admin, required, authority)
+    }
+
+    fn attest_kyc_ix(
+        contract: &Pubkey,
+        authority: &Pubkey,
+        subject: &Pubkey,
+        valid: bool,
+    ) -> Instruction {
+        build_attest_kyc(&PROGRAM_ID, contract, authority, subject, valid)
+    }
+
+    #[test]
+    fn test_kyc_gate_requires_attested_receivers() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let kyc_authority = Pubkey::new_unique();
+        let verified = Pubkey::new_unique();
+        let anonymous = Pubkey::new_unique();
+        t.bank.airdrop(kyc_authority, 1_000_000_000);
+        t.bank.airdrop(verified, 10_000_000_000);
+        t.bank.airdrop(anonymous, 10_000_000_000);
+        t.grant(&verified, "arena", "ipfs://arena").unwrap();
+        t.grant(&anonymous, "arena", "ipfs://arena").unwrap();
+
+        t.bank
+            .process(&set_kyc_gate_ix(&contract, &admin, true, &kyc_authority))
+            .unwrap();
+        // Only the KYC authority can attest
+        assert_eq!(
+            t.bank
+                .process(&attest_kyc_ix(&contract, &admin, &verified, true)),
+            Err(CustomError::NotAdmin.into())
+        );
+        t.bank
+            .process(&attest_kyc_ix(&contract, &kyc_authority, &verified, true))
+            .unwrap();
+
+        let (mint, token_account) = t.mint_to_user(&verified, "arena").unwrap();
+        assert_eq!(
+            t.mint_to_user(&anonymous, "arena"),
+            Err(CustomError::KycRequired.into())
+        );
+
+        let anonymous_token = t.bank.create_token_account(&mint, &anonymous);
+        assert_eq!(
+            t.bank.process(&transfer_ix(
+                &contract,
+                1,
+                "arena",
+                &token_account,
+                &anonymous_token,
+                &verified,
+                &anonymous,
+            )),
+            Err(CustomError::KycRequired.into())
+        );
+
+        // A revoked attestation no longer passes
+        t.bank
+            .process(&attest_kyc_ix(&contract, &kyc_authority, &verified, false))
+            .unwrap();
+        assert_eq!(
+            t.mint_to_user(&verified, "arena"),
+            Err(CustomError::KycRequired.into())
+        );
+
+        // With the gate off anyone can receive
+        t.bank
+            .process(&set_kyc_gate_ix(&contract, &admin, false, &kyc_authority))
+            .unwrap();
+        t.mint_to_user(&anonymous, "arena").unwrap();
+    }
+
+    fn compact_game_tokens_ix(
+        contract: &Pubkey,
+        admin: &Pubkey,
+        game_id: &str,
+        chunk_count: u32,
+        burned: &[u64],
+    ) -> Instruction {
+        build_compact_game_tokens(&PROGRAM_ID, contract, admin, game_id, chunk_count, burned)
+    }
+
+    #[test]
+    fn test_compact_game_tokens_drops_burned_and_closes_chunks() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 20_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        let mut token_accounts = vec![];
+        for _ in 0..40 {
+            token_accounts.push(t.mint_to_user(&user, "arena").unwrap().1);
+        }
+
+        // Burn every fourth token, leaving gaps across both chunks
+        let burned: Vec<u64> = (1..=40).filter(|id| id % 4 == 0).collect();
+        let queued: Vec<(u64, Pubkey)> = burned
+            .iter()
+            .map(|&id| (id, token_accounts[id as usize - 1]))
+            .collect();
+        for (token_id, token_account) in &queued {
+            t.bank
+                .process(&enqueue_burn_ix(&contract, *token_id, token_account, &user))
+                .unwrap();
+        }
+        t.bank
+            .process(&process_burn_queue_ix(&contract, "arena", &user, &queued))
+            .unwrap();
+
+        // Only burned tokens can be dropped
+        assert_eq!(
+            t.bank
+                .process(&compact_game_tokens_ix(&contract, &admin, "arena", 2, &[1])),
+            Err(CustomError::InvalidInstruction.into())
+        );
+
+        let admin_before = t.bank.lamports(&admin);
+        let chunk_rent = t.bank.lamports(&chunk_pda("arena", 1));
+        t.bank
+            .process(&compact_game_tokens_ix(
+                &contract, &admin, "arena", 2, &burned,
+            ))
+            .unwrap();
+        assert_eq!(t.bank.lamports(&admin), admin_before + chunk_rent);
+        assert!(!t.bank.accounts.contains_key(&chunk_pda("arena", 1)));
+        assert_eq!(t.game("arena").unwrap().token_count, 30);
+
+        t.bank
+            .process(&get_game_tokens_ix("arena", 0, 64, &[0]))
+            .unwrap();
+        let live: Vec<u64> = (1..=40).filter(|id| id % 4 != 0).collect();
+        assert_eq!(
+            Vec::<u64>::try_from_slice(&t.bank.return_data()).unwrap(),
+            live
+        );
+
+        // New mints keep appending after the compacted list
+        t.mint_to_user(&user, "arena").unwrap();
+        let chunk: GameTokenChunk = t.bank.state(&chunk_pda("arena", 0));
+        assert_eq!(chunk.token_ids.last(), Some(&41));
+    }
+
+    #[test]
+    fn test_mint_requires_holding_required_token() {
+        let mut t = TestContract::new();
+        let holder = Pubkey::new_unique();
+        let outsider = Pubkey::new_unique();
+        t.bank.airdrop(holder, 10_000_000_000);
+        t.bank.airdrop(outsider, 10_000_000_000);
+        t.grant(&holder, "sequel", "ipfs://sequel").unwrap();
+        t.grant(&outsider, "sequel", "ipfs://sequel").unwrap();
+
+        // Holding a token of the first collection unlocks the sequel
+        let pass = t.bank.create_mint(&Pubkey::new_unique());
+        let holder_pass = t.bank.create_token_account(&pass, &holder);
+        t.bank.set_token_balance(&holder_pass, 1);
+        let outsider_pass = t.bank.create_token_account(&pass, &outsider);
+        t.configure(
+            "sequel",
+            &GameConfig {
+                required_token_mint: pass,
+                ..GameConfig::default()
+            },
+        )
+        .unwrap();
+
+        t.mint_to_user_with(
+            &holder,
+            "sequel",
+            vec![AccountMeta::new_readonly(holder_pass, false)],
+        )
+        .unwrap();
+        assert_eq!(
+            t.mint_to_user_with(
+                &outsider,
+                "sequel",
+                vec![AccountMeta::new_readonly(outsider_pass, false)],
+            ),
+            Err(CustomError::RequiredTokenMissing.into())
+        );
+        // Someone else's holding doesn't count
+        assert_eq!(
+            t.mint_to_user_with(
+                &outsider,
+                "sequel",
+                vec![AccountMeta::new_readonly(holder_pass, false)],
+            ),
+            Err(CustomError::RequiredTokenMissing.into())
+        );
+    }
+
+    #[test]
+    fn test_assert_nft_mint_rejects_fungible_mints() {
+        let check = |decimals: u8, supply: u64, expected_supply: u64| {
+            let mut data = vec![0; Mint::LEN];
+            Mint::pack(
+                Mint {
+                    mint_authority: COption::Some(Pubkey::new_unique()),
+                    supply,
+                    decimals,
+                    is_initialized: true,
+                    freeze_authority: COption::None,
+                },
+                &mut data,
+            )
+            .unwrap();
+            let (key, owner, mut lamports) = (Pubkey::new_unique(), spl_token::id(), 0);
+            let info = AccountInfo::new(
+                &key,
+                false,
+                false,
+                &mut lamports,
+                &mut data,
+                &owner,
+                false,
+                0,
+            );
+            assert_nft_mint(&info, expected_supply)
+        };
+
+        assert_eq!(check(0, 1, 1), Ok(()));
+        assert_eq!(check(2, 1, 1), Err(CustomError::NotAnNft.into()));
+        assert_eq!(check(0, 2, 1), Err(CustomError::NotAnNft.into()));
+        assert_eq!(check(0, 0, 0), Ok(()));
+    }
+
+    fn export_state_ix(contract: &Pubkey, page: u32) -> Instruction {
+        build_export_state(&PROGRAM_ID, contract, page)
+    }
+
+    #[test]
+    fn test_export_state_decodes_to_live_state() {
+        let mut t = TestContract::new();
+        let contract = t.contract;
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        for _ in 0..10 {
+            t.mint_to_user(&user, "arena").unwrap();
+        }
+
+        let mut exported = vec![];
+        for page in 0.. {
+            t.bank.process(&export_state_ix(&contract, page)).unwrap();
+            let data = t.bank.return_data();
+            assert!(data.len() <= solana_program::program::MAX_RETURN_DATA);
+            let export = StateExport::try_from_slice(&data).unwrap();
+            assert_eq!(
+                export.state.try_to_vec().unwrap(),
+                t.contract_state().try_to_vec().unwrap()
+            );
+            assert_eq!(export.contract_pda, contract_pda());
+            exported.extend(export.tokens);
+            if !export.has_more {
+                break;
+            }
+        }
+
+        let expected: Vec<ExportedToken> = (1..=10)
+            .map(|token_id| ExportedToken {
+                token_id,
+                record: record_pda(token_id),
+                mint: mint_pda(token_id),
+                metadata: metadata_pda(&mint_pda(token_id)),
+            })
+            .collect();
+        assert_eq!(exported, expected);
+    }
+
+    #[test]
+    fn test_mint_waits_for_game_activation() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.bank
+            .process(&grant_mint_ix(
+                &contract,
+                &admin,
+                &user,
+                "arena",
+                "ipfs://arena",
+            ))
+            .unwrap();
+        assert!(!t.game("arena").unwrap().active);
+        assert_eq!(
+            t.mint_to_user(&user, "arena"),
+            Err(CustomError::GameNotActive.into())
+        );
+
+        assert_eq!(
+            t.bank.process(&activate_game_ix(&contract, &user, "arena")),
+            Err(CustomError::NotAdmin.into())
+        );
+        t.activate("arena").unwrap();
+        t.mint_to_user(&user, "arena").unwrap();
+
+        // Activating again changes nothing
+        let game_before = t.bank.data(&game_pda("arena")).to_vec();
+        t.activate("arena").unwrap();
+        assert_eq!(t.bank.data(&game_pda("arena")), &game_before[..]);
+        t.mint_to_user(&user, "arena").unwrap();
+        assert_eq!(t.game("arena").unwrap().token_count, 2);
+    }
+
+    #[test]
+    fn test_mint_falls_back_to_default_royalty() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let user = Pubkey::new_unique();
+        let studio = Pubkey::new_unique();
+        let label = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        t.grant(&user, "relic", "ipfs://relic").unwrap();
+
+        assert_eq!(
+            t.bank.process(&set_default_royalty_ix(
+                &contract,
+                &admin,
+                MAX_BPS + 1,
+                &label
+            )),
+            Err(CustomError::InvalidRoyalty.into())
+        );
+        assert_eq!(
+            t.bank
+                .process(&set_default_royalty_ix(&contract, &user, 250, &label)),
+            Err(CustomError::NotAdmin.into())
+        );
+        t.bank
+            .process(&set_default_royalty_ix(&contract, &admin, 250, &label))
+            .unwrap();
+        t.configure(
+            "relic",
+            &GameConfig {
+                royalty_bps: 700,
+                royalty_recipient: studio,
+                ..GameConfig::default()
+            },
+        )
+        .unwrap();
+
+        // The relic sets its own royalty
+        let (relic_mint, _) = t.mint_to_user(&user, "relic").unwrap();
+        let metadata = t.bank.state::<TokenMetadata>(&metadata_pda(&relic_mint));
+        assert_eq!(
+            (metadata.royalty_bps, metadata.royalty_recipient),
+            (700, studio)
+        );
+
+        // The arena has none, so the contract default applies
+        let (arena_mint, _) = t.mint_to_user(&user, "arena").unwrap();
+        let metadata = t.bank.state::<TokenMetadata>(&metadata_pda(&arena_mint));
+        assert_eq!(
+            (metadata.royalty_bps, metadata.royalty_recipient),
+            (250, label)
+        );
+    }
+
+    #[test]
+    fn test_grant_cooldown_spaces_grants_to_a_user() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let user = Pubkey::new_unique();
+        let other = Pubkey::new_unique();
+        t.bank.warp_to_slot(100);
+        t.bank
+            .process(&set_grant_cooldown_ix(&contract, &admin, 50))
+            .unwrap();
+        let grant = |user: &Pubkey, game_id: &str| {
+            let mut ix = grant_mint_ix(&contract, &admin, user, game_id, "ipfs://game");
+            ix.accounts
+                .push(AccountMeta::new(user_grant_pda(user), false));
+            ix
+        };
+
+        // The user grant PDA is required while the cooldown is on
+        assert_eq!(
+            t.bank.process(&grant_mint_ix(
+                &contract,
+                &admin,
+                &user,
+                "arena",
+                "ipfs://game"
+            )),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+        t.bank.process(&grant(&user, "arena")).unwrap();
+        assert_eq!(
+            t.bank.state::<UserGrantRecord>(&user_grant_pda(&user)),
+            UserGrantRecord {
+                user,
+                last_grant_slot: 100
+            }
+        );
+
+        // A second grant inside the window fails, even for another game
+        t.bank.warp_to_slot(149);
+        assert_eq!(
+            t.bank.process(&grant(&user, "racer")),
+            Err(CustomError::GrantCooldownActive.into())
+        );
+        // Other users aren't affected
+        t.bank.process(&grant(&other, "racer")).unwrap();
+
+        t.bank.warp_to_slot(150);
+        t.bank.process(&grant(&user, "racer")).unwrap();
+        assert_eq!(
+            t.bank
+                .state::<UserGrantRecord>(&user_grant_pda(&user))
+                .last_grant_slot,
+            150
+        );
+
+        // Zero turns the cooldown off
+        t.bank
+            .process(&set_grant_cooldown_ix(&contract, &admin, 0))
+            .unwrap();
+        t.bank
+            .process(&grant_mint_ix(
+                &contract,
+                &admin,
+                &user,
+                "quest",
+                "ipfs://game",
+            ))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_holder_count_tracks_mints_transfers_and_burns() {
+        let mut t = TestContract::new();
+        let contract = t.contract;
+        let alice = Pubkey::new_unique();
+        let bob = Pubkey::new_unique();
+        t.bank.airdrop(alice, 10_000_000_000);
+        t.bank.airdrop(bob, 10_000_000_000);
+        t.grant(&alice, "arena", "ipfs://arena").unwrap();
+        t.grant(&bob, "arena", "ipfs://arena").unwrap();
+        let holder_count = |t: &mut TestContract| {
+            t.bank.process(&get_holder_count_ix("arena")).unwrap();
+            u64::try_from_slice(&t.bank.return_data()).unwrap()
+        };
+        assert_eq!(holder_count(&mut t), 0);
+
+        // Alice's second token doesn't make her a second holder
+        let (first_mint, first_token) = t.mint_to_user(&alice, "arena").unwrap();
+        let (_, second_token) = t.mint_to_user(&alice, "arena").unwrap();
+        assert_eq!(holder_count(&mut t), 1);
+        assert_eq!(
+            t.bank
+                .state::<GameHolding>(&holding_pda("arena", &alice))
+                .count,
+            2
+        );
+
+        // Bob becomes a holder; Alice still has one token left
+        let bob_token = t.bank.create_token_account(&first_mint, &bob);
+        t.bank
+            .process(&transfer_ix(
+                &contract,
+                1,
+                "arena",
+                &first_token,
+                &bob_token,
+                &alice,
+                &bob,
+            ))
+            .unwrap();
+        assert_eq!(holder_count(&mut t), 2);
+
+        // Burning Alice's last token drops her
+        t.bank
+            .process(&enqueue_burn_ix(&contract, 2, &second_token, &alice))
+            .unwrap();
+        t.bank
+            .process(&process_burn_queue_ix(
+                &contract,
+                "arena",
+                &alice,
+                &[(2, second_token)],
+            ))
+            .unwrap();
+        assert_eq!(holder_count(&mut t), 1);
+        assert_eq!(t.game("arena").unwrap().holder_count, 1);
+
+        // Handing the token back moves the only holding to Alice
+        let alice_token = t.bank.create_token_account(&first_mint, &alice);
+        t.bank
+            .process(&transfer_ix(
+                &contract,
+                1,
+                "arena",
+                &bob_token,
+                &alice_token,
+                &bob,
+                &alice,
+            ))
+            .unwrap();
+        assert_eq!(holder_count(&mut t), 1);
+        assert_eq!(
+            t.bank
+                .state::<GameHolding>(&holding_pda("arena", &bob))
+                .count,
+            0
+        );
+    }
+
+    #[test]
+    fn test_mint_enforces_max_metadata_rent() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+
+        let rent = Rent::default();
+        let expected =
+            rent.minimum_balance(TokenMetadata::LEN) + rent.minimum_balance(TokenUri::LEN);
+        assert_eq!(metadata_rent(&rent), expected);
+        assert_eq!(
+            rent.minimum_balance(metadata_accounts_size()),
+            expected - rent.minimum_balance(0)
+        );
+        // Doubling the rate doubles the cost
+        let pricier = Rent {
+            lamports_per_byte_year: rent.lamports_per_byte_year * 2,
+            ..rent
+        };
+        assert_eq!(metadata_rent(&pricier), expected * 2);
+
+        t.bank
+            .process(&set_max_metadata_rent_ix(&contract, &admin, expected - 1))
+            .unwrap();
+        assert_eq!(
+            t.mint_to_user(&user, "arena"),
+            Err(CustomError::MetadataRentTooHigh.into())
+        );
+
+        // The bound is inclusive
+        t.bank
+            .process(&set_max_metadata_rent_ix(&contract, &admin, expected))
+            .unwrap();
+        let (mint, _) = t.mint_to_user(&user, "arena").unwrap();
+        assert_eq!(
+            t.bank.lamports(&metadata_pda(&mint)) + t.bank.lamports(&uri_pda(1)),
+            expected
+        );
+
+        // The accounts have a fixed size, so the longest URI costs the same
+        let long_uri = format!("ipfs://{}", "a".repeat(MAX_TOKEN_URI_LEN - 7));
+        let collector = Pubkey::new_unique();
+        t.bank.airdrop(collector, 10_000_000_000);
+        t.grant(&collector, "arena", &long_uri).unwrap();
+        let (mint, _) = t.mint_to_user(&collector, "arena").unwrap();
+        assert_eq!(
+            t.bank.lamports(&metadata_pda(&mint)) + t.bank.lamports(&uri_pda(2)),
+            expected
+        );
+
+        t.bank
+            .process(&set_max_metadata_rent_ix(&contract, &admin, 0))
+            .unwrap();
+        t.mint_to_user(&user, "arena").unwrap();
+    }
+
+    #[test]
+    fn test_reassign_permission_keeps_counters() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let old_wallet = Pubkey::new_unique();
+        let new_wallet = Pubkey::new_unique();
+        let other = Pubkey::new_unique();
+        t.bank.airdrop(old_wallet, 10_000_000_000);
+        t.bank.airdrop(new_wallet, 10_000_000_000);
+        t.grant(&old_wallet, "arena", "ipfs://arena").unwrap();
+        t.grant(&other, "arena", "ipfs://arena").unwrap();
+        t.mint_to_user(&old_wallet, "arena").unwrap();
+        t.mint_to_user(&old_wallet, "arena").unwrap();
+
+        assert_eq!(
+            t.bank.process(&reassign_permission_ix(
+                &contract,
+                &old_wallet,
+                "arena",
+                &old_wallet,
+                &new_wallet,
+            )),
+            Err(CustomError::NotAdmin.into())
+        );
+        // The new wallet can't already hold a permission for the game
+        assert_eq!(
+            t.bank.process(&reassign_permission_ix(
+                &contract,
+                &admin,
+                "arena",
+                &old_wallet,
+                &other,
+            )),
+            Err(CustomError::GrantExists.into())
+        );
+
+        t.bank
+            .process(&reassign_permission_ix(
+                &contract,
+                &admin,
+                "arena",
+                &old_wallet,
+                &new_wallet,
+            ))
+            .unwrap();
+        assert!(!t
+            .bank
+            .accounts
+            .contains_key(&permission_pda(&old_wallet, "arena")));
+        let moved = t
+            .bank
+            .state::<MintPermission>(&permission_pda(&new_wallet, "arena"));
+        assert_eq!(moved.user, new_wallet);
+        assert_eq!(moved.token_uri, "ipfs://arena");
+        assert_eq!(moved.minted, 2);
+
+        // The new wallet mints under the moved permission, the old one can't
+        t.mint_to_user(&new_wallet, "arena").unwrap();
+        assert_eq!(
+            t.bank
+                .state::<MintPermission>(&permission_pda(&new_wallet, "arena"))
+                .minted,
+            3
+        );
+        assert_eq!(
+            t.mint_to_user(&old_wallet, "arena"),
+            Err(CustomError::MintNotPermitted.into())
+        );
+    }
+
+    #[test]
+    fn test_mint_runs_game_validator() {
+        let mut t = TestContract::new();
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        t.grant(&user, "relic", "ipfs://relic").unwrap();
+        for (game_id, validator) in [
+            ("arena", APPROVING_VALIDATOR),
+            ("relic", REJECTING_VALIDATOR),
+        ] {
+            t.configure(
+                game_id,
+                &GameConfig {
+                    validator_program: validator,
+                    ..GameConfig::default()
+                },
+            )
+            .unwrap();
+        }
+        VALIDATIONS.with(|v| v.borrow_mut().clear());
+
+        // The validator program must be passed
+        assert_eq!(
+            t.mint_to_user(&user, "arena"),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+        assert_eq!(
+            t.mint_to_user_with(
+                &user,
+                "arena",
+                vec![AccountMeta::new_readonly(REJECTING_VALIDATOR, false)]
+            ),
+            Err(ProgramError::IncorrectProgramId)
+        );
+
+        t.mint_to_user_with(
+            &user,
+            "arena",
+            vec![AccountMeta::new_readonly(APPROVING_VALIDATOR, false)],
+        )
+        .unwrap();
+        assert_eq!(
+            VALIDATIONS.with(|v| v.take()),
+            vec![MintValidation {
+                game_id: "arena".to_string(),
+                token_id: 1,
+                minter: user,
+                receiver: user,
+            }]
+        );
+
+        // A rejection fails the mint and leaves nothing behind
+        assert_eq!(
+            t.mint_to_user_with(
+                &user,
+                "relic",
+                vec![AccountMeta::new_readonly(REJECTING_VALIDATOR, false)]
+            ),
+            Err(ProgramError::Custom(0xdead))
+        );
+        assert_eq!(t.contract_state().last_token_id, 1);
+        assert_eq!(t.game("relic").unwrap().token_count, 0);
+    }
+
+    #[test]
+    fn test_peek_batch_ids_previews_next_ids() {
+        let mut t = TestContract::new();
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        assert_eq!(peeked_ids(&mut t, 3), vec![1, 2, 3]);
+
+        // The preview matches what minting hands out
+        t.mint_to_user(&user, "arena").unwrap();
+        t.mint_to_user(&user, "arena").unwrap();
+        assert_eq!(peeked_ids(&mut t, 4), vec![3, 4, 5, 6]);
+        t.mint_to_user(&user, "arena").unwrap();
+        assert_eq!(t.contract_state().last_token_id, 3);
+        assert!(peeked_ids(&mut t, 0).is_empty());
+        assert_eq!(
+            peeked_ids(&mut t, u64::MAX).len() as u64,
+            MAX_PEEK_BATCH_IDS
+        );
+
+        // Near the end of the id space the list is cut short
+        let mut state = t.contract_state();
+        state.last_token_id = u64::MAX - 2;
+        let mut data = t.bank.data(&t.contract).to_vec();
+        state.serialize(&mut &mut data[..]).unwrap();
+        let contract = t.contract;
+        t.bank.set_data(&contract, data);
+        assert_eq!(peeked_ids(&mut t, 5), vec![u64::MAX - 1, u64::MAX]);
+    }
+
+    #[test]
+    fn test_mint_rejects_contract_accounts_as_receiver() {
+        let mut t = TestContract::new();
+        let contract = t.contract;
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+
+        let mint_authority = pda(&[MINT_AUTHORITY_SEED, mint_pda(1).as_ref()]);
+        for receiver in [contract_pda(), contract, mint_authority, game_pda("arena")] {
+            let token_account = t.bank.allocate_token_account();
+            assert_eq!(
+                t.bank.process(&mint_ix(
+                    &contract,
+                    1,
+                    &token_account,
+                    &receiver,
+                    &user,
+                    "arena",
+                    0,
+                )),
+                Err(CustomError::InvalidReceiver.into())
+            );
+        }
+
+        // Another wallet is fine
+        let wallet = Pubkey::new_unique();
+        let token_account = t.bank.allocate_token_account();
+        t.bank
+            .process(&mint_ix(
+                &contract,
+                1,
+                &token_account,
+                &wallet,
+                &user,
+                "arena",
+                0,
+            ))
+            .unwrap();
+        assert_eq!(
+            TokenAccount::unpack(t.bank.data(&token_account))
+                .unwrap()
+                .owner,
+            wallet
+        );
+    }
+
+    #[test]
+    fn test_batch_update_uris_rewrites_each_token() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        for _ in 0..4 {
+            t.mint_to_user(&user, "arena").unwrap();
+        }
+
+        let updates: Vec<(u64, String)> = [1, 2, 4]
+            .iter()
+            .map(|&id| (id, format!("https://cdn.example/arena/{}", id)))
+            .collect();
+        assert_eq!(
+            t.bank
+                .process(&batch_update_uris_ix(&contract, &user, &updates)),
+            Err(CustomError::NotAdmin.into())
+        );
+        t.bank
+            .process(&batch_update_uris_ix(&contract, &admin, &updates))
+            .unwrap();
+        for (token_id, uri) in &updates {
+            assert_eq!(&t.bank.state::<TokenUri>(&uri_pda(*token_id)).uri, uri);
+        }
+        assert_eq!(t.bank.state::<TokenUri>(&uri_pda(3)).uri, "ipfs://arena");
+
+        // One bad URI fails the whole batch
+        let too_long = vec![
+            (1, "ipfs://fine".to_string()),
+            (2, "x".repeat(MAX_TOKEN_URI_LEN + 1)),
+        ];
+        assert_eq!(
+            t.bank
+                .process(&batch_update_uris_ix(&contract, &admin, &too_long)),
+            Err(CustomError::InvalidInstruction.into())
+        );
+        assert_eq!(
+            t.bank.state::<TokenUri>(&uri_pda(1)).uri,
+            "https://cdn.example/arena/1"
+        );
+
+        let oversized: Vec<(u64, String)> = (0..=MAX_BATCH_URI_UPDATES)
+            .map(|_| (1, "ipfs://again".to_string()))
+            .collect();
+        assert_eq!(
+            t.bank
+                .process(&batch_update_uris_ix(&contract, &admin, &oversized)),
+            Err(CustomError::InvalidInstruction.into())
+        );
+    }
+
+    #[test]
+    fn test_token_program_must_be_allowlisted() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let user = Pubkey::new_unique();
+        let receiver = Pubkey::new_unique();
+        let rogue = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        assert_eq!(
+            t.contract_state().allowed_token_programs,
+            vec![spl_token::id()]
+        );
+
+        let swap_token_program = |ix: &mut Instruction| {
+            for meta in ix.accounts.iter_mut() {
+                if meta.pubkey == spl_token::id() {
+                    meta.pubkey = TOKEN_2022_PROGRAM_ID;
+                }
+            }
+        };
+        let token_account = t.bank.allocate_token_account();
+        let mut ix = mint_ix(&contract, 1, &token_account, &user, &user, "arena", 0);
+        swap_token_program(&mut ix);
+        assert_eq!(
+            t.bank.process(&ix),
+            Err(CustomError::TokenProgramNotAllowed.into())
+        );
+        // and a program that is neither token program is rejected outright
+        ix.accounts[12].pubkey = rogue;
+        assert_eq!(
+            t.bank.process(&ix),
+            Err(CustomError::InvalidInstruction.into())
+        );
+        let (mint, token) = t.mint_to_user(&user, "arena").unwrap();
+
+        let receiver_token = t.bank.create_token_account(&mint, &receiver);
+        let mut ix = transfer_ix(
+            &contract,
+            1,
+            "arena",
+            &token,
+            &receiver_token,
+            &user,
+            &receiver,
+        );
+        swap_token_program(&mut ix);
+        assert_eq!(
+            t.bank.process(&ix),
+            Err(CustomError::TokenProgramNotAllowed.into())
+        );
+        let mut ix = enqueue_burn_ix(&contract, 1, &token, &user);
+        swap_token_program(&mut ix);
+        assert_eq!(
+            t.bank.process(&ix),
+            Err(CustomError::TokenProgramNotAllowed.into())
+        );
+
+        // The list is admin-set, can't be emptied, and holds at most two programs
+        assert_eq!(
+            t.bank
+                .process(&set_allowed_token_programs_ix(&contract, &admin, &[])),
+            Err(CustomError::InvalidInstruction.into())
+        );
+        assert_eq!(
+            t.bank.process(&set_allowed_token_programs_ix(
+                &contract,
+                &admin,
+                &[spl_token::id(), TOKEN_2022_PROGRAM_ID, rogue]
+            )),
+            Err(CustomError::InvalidInstruction.into())
+        );
+        assert_eq!(
+            t.bank
+                .process(&set_allowed_token_programs_ix(&contract, &user, &[rogue])),
+            Err(CustomError::NotAdmin.into())
+        );
+        t.bank
+            .process(&set_allowed_token_programs_ix(
+                &contract,
+                &admin,
+                &[spl_token::id(), TOKEN_2022_PROGRAM_ID],
+            ))
+            .unwrap();
+        t.bank
+            .process(&transfer_ix(
+                &contract,
+                1,
+                "arena",
+                &token,
+                &receiver_token,
+                &user,
+                &receiver,
+            ))
+            .unwrap();
+        assert_eq!(t.bank.token_balance(&receiver_token), 1);
+    }
+
+    #[test]
+    fn test_total_fees_accumulate_across_paid_mints() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        t.grant(&user, "relic", "ipfs://relic").unwrap();
+        t.configure(
+            "arena",
+            &GameConfig {
+                price_amount: 1_000_000,
+                ..GameConfig::default()
+            },
+        )
+        .unwrap();
+        t.configure(
+            "relic",
+            &GameConfig {
+                price_amount: 2_500_000,
+                ..GameConfig::default()
+            },
+        )
+        .unwrap();
+        let total_fees = |t: &mut TestContract| {
+            t.bank
+                .process(&Instruction::new_with_bytes(
+                    PROGRAM_ID,
+                    &[36],
+                    vec![AccountMeta::new_readonly(contract, false)],
+                ))
+                .unwrap();
+            u64::try_from_slice(&t.bank.return_data()).unwrap()
+        };
+        assert_eq!(total_fees(&mut t), 0);
+
+        let treasury_before = t.bank.lamports(&admin);
+        let treasury = vec![AccountMeta::new(admin, false)];
+        t.mint_to_user_with(&user, "arena", treasury.clone())
+            .unwrap();
+        t.mint_to_user_with(&user, "relic", treasury.clone())
+            .unwrap();
+        t.mint_to_user_with(&user, "arena", treasury).unwrap();
+        assert_eq!(total_fees(&mut t), 4_500_000);
+        assert_eq!(t.bank.lamports(&admin) - treasury_before, 4_500_000);
+
+        // A failed mint charges nothing
+        assert!(t.mint_to_user(&user, "relic").is_err());
+        assert_eq!(total_fees(&mut t), 4_500_000);
+    }
+
+    #[test]
+    fn test_every_nth_mint_is_reserved_for_treasury() {
+        let mut t = TestContract::new();
+        let (contract, treasury) = (t.contract, t.admin);
+        let users: Vec<Pubkey> = (0..2).map(|_| Pubkey::new_unique()).collect();
+        for user in &users {
+            t.bank.airdrop(*user, 10_000_000_000);
+            t.grant(user, "arena", "ipfs://arena").unwrap();
+        }
+        t.configure(
+            "arena",
+            &GameConfig {
+                price_amount: 1_000_000,
+                reserve_every_n: 5,
+                ..GameConfig::default()
+            },
+        )
+        .unwrap();
+
+        let fee = vec![AccountMeta::new(treasury, false)];
+        for token_id in 1..=4 {
+            let user = &users[token_id as usize % 2];
+            let (_, token_account) = t.mint_to_user_with(user, "arena", fee.clone()).unwrap();
+            assert_eq!(
+                TokenAccount::unpack(t.bank.data(&token_account))
+                    .unwrap()
+                    .owner,
+                *user
+            );
+            assert_eq!(
+                t.bank.state::<TokenRecord>(&record_pda(token_id)).owner,
+                *user
+            );
+        }
+
+        // The 5th goes to the treasury, free, and doesn't count against the minter
+        let minted_before = t
+            .bank
+            .state::<MintPermission>(&permission_pda(&users[1], "arena"))
+            .minted;
+        let token_account = t.bank.allocate_token_account();
+        let mut ix = mint_ix(
+            &contract,
+            5,
+            &token_account,
+            &users[1],
+            &users[1],
+            "arena",
+            0,
+        );
+        assert_eq!(
+            t.bank.process(&ix),
+            Err(ProgramError::InvalidSeeds),
+            "the treasury's holding PDA is required"
+        );
+        let holding = ix.accounts.len() - 2;
+        ix.accounts[holding].pubkey = holding_pda("arena", &treasury);
+        t.bank.process(&ix).unwrap();
+        assert_eq!(
+            TokenAccount::unpack(t.bank.data(&token_account))
+                .unwrap()
+                .owner,
+            treasury
+        );
+        assert_eq!(t.bank.state::<TokenRecord>(&record_pda(5)).owner, treasury);
+        assert_eq!(t.contract_state().total_fees_collected, 4_000_000);
+        assert_eq!(
+            t.bank
+                .state::<MintPermission>(&permission_pda(&users[1], "arena"))
+                .minted,
+            minted_before
+        );
+
+        // Then back to requesters
+        let (_, token_account) = t
+            .mint_to_user_with(&users[0], "arena", fee.clone())
+            .unwrap();
+        assert_eq!(
+            TokenAccount::unpack(t.bank.data(&token_account))
+                .unwrap()
+                .owner,
+            users[0]
+        );
+    }
+
+    #[test]
+    fn test_verify_bundle_ownership_reports_each_token() {
+        let mut t = TestContract::new();
+        let contract = t.contract;
+        let lister = Pubkey::new_unique();
+        let other = Pubkey::new_unique();
+        t.bank.airdrop(lister, 10_000_000_000);
+        t.grant(&lister, "arena", "ipfs://arena").unwrap();
+        let mut tokens = vec![];
+        for _ in 0..4 {
+            tokens.push(t.mint_to_user(&lister, "arena").unwrap());
+        }
+        let bundle = |t: &mut TestContract, token_ids: &[u64]| {
+            t.bank
+                .process(&verify_bundle_ownership_ix(token_ids, &lister))
+                .unwrap();
+            u64::try_from_slice(&t.bank.return_data()).unwrap()
+        };
+        assert_eq!(bundle(&mut t, &[1, 2, 3, 4]), 0b1111);
+
+        // Token 2 is sold, token 4 burned, and token 9 was never minted
+        let (mint, token) = tokens[1];
+        let other_token = t.bank.create_token_account(&mint, &other);
+        t.bank
+            .process(&transfer_ix(
+                &contract,
+                2,
+                "arena",
+                &token,
+                &other_token,
+                &lister,
+                &other,
+            ))
+            .unwrap();
+        t.bank
+            .process(&enqueue_burn_ix(&contract, 4, &tokens[3].1, &lister))
+            .unwrap();
+        t.bank
+            .process(&process_burn_queue_ix(
+                &contract,
+                "arena",
+                &lister,
+                &[(4, tokens[3].1)],
+            ))
+            .unwrap();
+        assert_eq!(bundle(&mut t, &[1, 2, 3, 4, 9]), 0b00101);
+        assert_eq!(bundle(&mut t, &[3, 1]), 0b11);
+
+        let oversized: Vec<u64> = (1..=MAX_BUNDLE_TOKENS as u64 + 1).collect();
+        assert_eq!(
+            t.bank
+                .process(&verify_bundle_ownership_ix(&oversized, &lister)),
+            Err(CustomError::InvalidInstruction.into())
+        );
+    }
+
+    #[test]
+    fn test_expired_tokens_stop_moving_and_burn() {
+        let mut t = TestContract::new();
+        let contract = t.contract;
+        let user = Pubkey::new_unique();
+        let receiver = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "event", "ipfs://event").unwrap();
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        t.configure(
+            "event",
+            &GameConfig {
+                token_lifetime_slots: 100,
+                ..GameConfig::default()
+            },
+        )
+        .unwrap();
+        t.bank.warp_to_slot(10);
+        let (mint, token) = t.mint_to_user(&user, "event").unwrap();
+        let (_, lasting_token) = t.mint_to_user(&user, "arena").unwrap();
+        assert_eq!(
+            t.bank.state::<TokenRecord>(&record_pda(1)).expires_at_slot,
+            110
+        );
+        assert_eq!(
+            t.bank.state::<TokenRecord>(&record_pda(2)).expires_at_slot,
+            0
+        );
+
+        // Not yet expired
+        t.bank.warp_to_slot(109);
+        assert_eq!(
+            t.bank
+                .process(&burn_expired_ix(&contract, 1, "event", &token, &user)),
+            Err(CustomError::TokenNotExpired.into())
+        );
+
+        t.bank.warp_to_slot(110);
+        let receiver_token = t.bank.create_token_account(&mint, &receiver);
+        assert_eq!(
+            t.bank.process(&transfer_ix(
+                &contract,
+                1,
+                "event",
+                &token,
+                &receiver_token,
+                &user,
+                &receiver,
+            )),
+            Err(CustomError::TokenExpired.into())
+        );
+
+        // Anyone can burn it, and the owner gets the metadata and URI rent back
+        let refund = t.bank.lamports(&metadata_pda(&mint)) + t.bank.lamports(&uri_pda(1));
+        let owner_before = t.bank.lamports(&user);
+        t.bank
+            .process(&burn_expired_ix(&contract, 1, "event", &token, &user))
+            .unwrap();
+        assert_eq!(t.bank.lamports(&user), owner_before + refund);
+        assert!(t.bank.state::<TokenRecord>(&record_pda(1)).burned);
+        assert_eq!(
+            Account::unpack(t.bank.data(&token)).unwrap().state,
+            AccountState::Frozen
+        );
+        assert_eq!(t.game("event").unwrap().holder_count, 0);
+        assert_eq!(
+            t.bank
+                .process(&burn_expired_ix(&contract, 1, "event", &token, &user)),
+            Err(CustomError::TokenBurned.into())
+        );
+
+        // Tokens without a lifetime never expire
+        t.bank.warp_to_slot(1_000_000);
+        assert_eq!(
+            t.bank.process(&burn_expired_ix(
+                &contract,
+                2,
+                "arena",
+                &lasting_token,
+                &user
+            )),
+            Err(CustomError::TokenNotExpired.into())
+        );
+    }
+
+    #[test]
+    fn test_transfer_freeze_window() {
+        let mut t = TestContract::new();
+        let contract = t.contract;
+        let admin = t.admin;
+        let user = Pubkey::new_unique();
+        let receiver = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        t.grant(&user, "event", "ipfs://event").unwrap();
+        let (mint, token) = t.mint_to_user(&user, "arena").unwrap();
+        let receiver_token = t.bank.create_token_account(&mint, &receiver);
+
+        t.bank.warp_to_slot(10);
+        t.bank
+            .process(&freeze_transfers_until_ix(&contract, &admin, 100))
+            .unwrap();
+        let transfer = transfer_ix(
+            &contract,
+            1,
+            "arena",
+            &token,
+            &receiver_token,
+            &user,
+            &receiver,
+        );
+        assert_eq!(
+            t.bank.process(&transfer),
+            Err(CustomError::TransfersFrozen.into())
+        );
+        // Unlike a pause, minting still works during the freeze
+        t.mint_to_user(&user, "event").unwrap();
+
+        t.bank.warp_to_slot(100);
+        t.bank.process(&transfer).unwrap();
+        assert_eq!(t.bank.token_balance(&receiver_token), 1);
+    }
+
+    #[test]
+    fn test_soulbound_tokens_burn_but_never_transfer() {
+        let mut t = TestContract::new();
+        let contract = t.contract;
+        let user = Pubkey::new_unique();
+        let receiver = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "achievements", "ipfs://badge").unwrap();
+        t.configure(
+            "achievements",
+            &GameConfig {
+                soulbound: true,
+                ..GameConfig::default()
+            },
+        )
+        .unwrap();
+        let (mint, token) = t.mint_to_user(&user, "achievements").unwrap();
+        assert!(t.bank.state::<TokenRecord>(&record_pda(1)).soulbound);
+
+        let receiver_token = t.bank.create_token_account(&mint, &receiver);
+        assert_eq!(
+            t.bank.process(&transfer_ix(
+                &contract,
+                1,
+                "achievements",
+                &token,
+                &receiver_token,
+                &user,
+                &receiver,
+            )),
+            Err(CustomError::Soulbound.into())
+        );
+        assert_eq!(t.bank.token_balance(&token), 1);
+
+        t.bank
+            .process(&enqueue_burn_ix(&contract, 1, &token, &user))
+            .unwrap();
+        t.bank
+            .process(&process_burn_queue_ix(
+                &contract,
+                "achievements",
+                &user,
+                &[(1, token)],
+            ))
+            .unwrap();
+        assert_eq!(t.bank.token_balance(&token), 0);
+        assert!(t.bank.state::<TokenRecord>(&record_pda(1)).burned);
+    }
+
+    #[test]
+    fn test_get_game_config_returns_the_game_record() {
+        let mut t = TestContract::new();
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        let config = GameConfig {
+            price_mint: Pubkey::new_unique(),
+            price_amount: 250,
+            min_sale_price: 1_000,
+            required_token_mint: Pubkey::new_unique(),
+            royalty_bps: 750,
+            royalty_recipient: Pubkey::new_unique(),
+            validator_program: Pubkey::new_unique(),
+            reserve_every_n: 10,
+            token_lifetime_slots: 5_000,
+            soulbound: true,
+            referral_reward_lamports: 1_000,
+            referral_reward_cap: 10_000,
+            mint_receipt: true,
+            merkle_root: [7; 32],
+            reset_cooldown_on_transfer: true,
+            holder_discount_bps: 1_000,
+            max_supply: 100,
+            reserved_supply: 20,
+            price_usd_cents: 0,
+            price_feed: Pubkey::new_unique(),
+            burn_callback_program: Pubkey::new_unique(),
+            refill_on_burn: true,
+        };
+        t.configure("arena", &config).unwrap();
+
+        t.bank.process(&get_game_config_ix("arena")).unwrap();
+        let record = GameRecord::try_from_slice(&t.bank.return_data()).unwrap();
+        assert_eq!(record.game_id, "arena");
+        assert_eq!(record.config, config);
+        assert!(record.active);
+        assert_eq!(record.token_count, 0);
+
+        t.bank.process(&get_game_config_ix("unknown")).unwrap();
+        assert!(t.bank.return_data().is_empty());
+    }
+
+    #[test]
+    fn test_referral_reward_paid_only_with_a_referrer() {
+        let mut t = TestContract::new();
+        let treasury = t.admin;
+        let user = Pubkey::new_unique();
+        let referrer = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.bank.airdrop(referrer, 1_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        t.configure(
+            "arena",
+            &GameConfig {
+                referral_reward_lamports: 2_000,
+                referral_reward_cap: 5_000,
+                ..GameConfig::default()
+            },
+        )
+        .unwrap();
+        let referral = vec![
+            AccountMeta::new(referrer, false),
+            AccountMeta::new(pda(&[REFERRAL_SEED, b"arena", referrer.as_ref()]), false),
+            AccountMeta::new(treasury, true),
+        ];
+
+        let (referrer_before, treasury_before) =
+            (t.bank.lamports(&referrer), t.bank.lamports(&treasury));
+        t.mint_to_user(&user, "arena").unwrap();
+        assert_eq!(t.bank.lamports(&referrer), referrer_before);
+        assert_eq!(t.bank.lamports(&treasury), treasury_before);
+
+        t.mint_to_user_with(&user, "arena", referral.clone())
+            .unwrap();
+        assert_eq!(t.bank.lamports(&referrer), referrer_before + 2_000);
+        assert_eq!(t.bank.lamports(&treasury), treasury_before - 2_000);
+
+        // The cap trims the third referral and stops the fourth
+        for _ in 0..3 {
+            t.mint_to_user_with(&user, "arena", referral.clone())
+                .unwrap();
+        }
+        assert_eq!(t.bank.lamports(&referrer), referrer_before + 5_000);
+        let record: ReferralRecord =
+            t.bank
+                .state(&pda(&[REFERRAL_SEED, b"arena", referrer.as_ref()]));
+        assert_eq!(record.rewards_paid, 5_000);
+
+        // Minters can't refer themselves
+        let mut self_referral = referral;
+        self_referral[0].pubkey = user;
+        self_referral[1].pubkey = pda(&[REFERRAL_SEED, b"arena", user.as_ref()]);
+        assert_eq!(
+            t.mint_to_user_with(&user, "arena", self_referral),
+            Err(CustomError::InvalidReferrer.into())
+        );
+    }
+
+    #[test]
+    fn test_rename_game_moves_permissions() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let alice = Pubkey::new_unique();
+        let bob = Pubkey::new_unique();
+        t.bank.airdrop(alice, 10_000_000_000);
+        t.grant(&alice, "arena", "ipfs://alice").unwrap();
+        t.grant(&bob, "arena", "ipfs://bob").unwrap();
+        t.mint_to_user(&alice, "arena").unwrap();
+
+        assert_eq!(
+            t.bank.process(&rename_game_ix(
+                &contract,
+                &alice,
+                "arena",
+                "colosseum",
+                &[alice, bob],
+            )),
+            Err(CustomError::NotAdmin.into())
+        );
+        t.bank
+            .process(&rename_game_ix(
+                &contract,
+                &admin,
+                "arena",
+                "colosseum",
+                &[alice, bob],
+            ))
+            .unwrap();
+
+        for (user, uri, minted) in [(alice, "ipfs://alice", 1), (bob, "ipfs://bob", 0)] {
+            assert!(!t
+                .bank
+                .accounts
+                .contains_key(&permission_pda(&user, "arena")));
+            let moved = t
+                .bank
+                .state::<MintPermission>(&permission_pda(&user, "colosseum"));
+            assert_eq!(moved.user, user);
+            assert_eq!(moved.game_id, "colosseum");
+            assert_eq!(moved.token_uri, uri);
+            assert_eq!(moved.minted, minted);
+        }
+
+        // Nothing is left under the old id to move again
+        assert_eq!(
+            t.bank.process(&rename_game_ix(
+                &contract,
+                &admin,
+                "arena",
+                "colosseum",
+                &[alice],
+            )),
+            Err(CustomError::MintNotPermitted.into())
+        );
+    }
+
+    #[test]
+    fn test_mint_receipt_alongside_the_asset() {
+        let mut t = TestContract::new();
+        let contract = t.contract;
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        t.configure(
+            "arena",
+            &GameConfig {
+                mint_receipt: true,
+                ..GameConfig::default()
+            },
+        )
+        .unwrap();
+
+        // Without the receipt accounts the mint fails
+        assert_eq!(
+            t.mint_to_user(&user, "arena"),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+
+        let receipt_token = t.bank.allocate_token_account();
+        let receipt_mint = mint_pda(2);
+        let receipt = vec![
+            AccountMeta::new(receipt_mint, false),
+            AccountMeta::new_readonly(pda(&[MINT_AUTHORITY_SEED, receipt_mint.as_ref()]), false),
+            AccountMeta::new(receipt_token, false),
+            AccountMeta::new(record_pda(2), false),
+            AccountMeta::new(history_pda(2, 0), false),
+        ];
+        let (asset_mint, asset_token) = t.mint_to_user_with(&user, "arena", receipt).unwrap();
+        assert_eq!(asset_mint, mint_pda(1));
+        assert_eq!(t.contract_state().last_token_id, 2);
+        assert_eq!(t.bank.token_balance(&asset_token), 1);
+        assert_eq!(t.bank.token_balance(&receipt_token), 1);
+
+        let asset: TokenRecord = t.bank.state(&record_pda(1));
+        let receipt: TokenRecord = t.bank.state(&record_pda(2));
+        assert!(!asset.is_receipt);
+        assert!(receipt.is_receipt && receipt.soulbound);
+        assert_eq!(receipt.owner, user);
+        assert_eq!(receipt.mint, receipt_mint);
+        assert_eq!(receipt.game_id, "arena");
+
+        // Only the asset counts towards the game
+        let game = t.game("arena").unwrap();
+        assert_eq!(game.token_count, 1);
+        assert_eq!(
+            t.bank
+                .state::<GameHolding>(&holding_pda("arena", &user))
+                .count,
+            1
+        );
+
+        let other = Pubkey::new_unique();
+        let other_token = t.bank.create_token_account(&receipt_mint, &other);
+        assert_eq!(
+            t.bank.process(&transfer_ix(
+                &contract,
+                2,
+                "arena",
+                &receipt_token,
+                &other_token,
+                &user,
+                &other,
+            )),
+            Err(CustomError::Soulbound.into())
+        );
+    }
+
+    #[test]
+    fn test_can_mint_reports_the_first_failing_check() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let user = Pubkey::new_unique();
+        let kyc_authority = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.bank.airdrop(kyc_authority, 1_000_000_000);
+        let can_mint = |t: &mut TestContract, receiver: &Pubkey, extra: Vec<AccountMeta>| {
+            let ix = can_mint_ix(&t.contract, &user, "arena", receiver, extra);
+            t.bank.process(&ix).unwrap();
+            Option::<u32>::try_from_slice(&t.bank.return_data()).unwrap()
+        };
+        let code = |err: CustomError| Some(err as u32);
+
+        t.bank
+            .process(&set_minting_enabled_ix(&contract, &admin, false))
+            .unwrap();
+        assert_eq!(
+            can_mint(&mut t, &user, vec![]),
+            code(CustomError::MintingDisabled)
+        );
+        t.bank
+            .process(&set_minting_enabled_ix(&contract, &admin, true))
+            .unwrap();
+        assert_eq!(
+            can_mint(&mut t, &user, vec![]),
+            code(CustomError::MintNotPermitted)
+        );
+
+        t.bank
+            .process(&grant_mint_ix(
+                &contract,
+                &admin,
+                &user,
+                "arena",
+                "ipfs://arena",
+            ))
+            .unwrap();
+        assert_eq!(
+            can_mint(&mut t, &user, vec![]),
+            code(CustomError::GameNotActive)
+        );
+        t.activate("arena").unwrap();
+        assert_eq!(
+            can_mint(&mut t, &contract, vec![]),
+            code(CustomError::InvalidReceiver)
+        );
+
+        t.bank
+            .process(&set_kyc_gate_ix(&contract, &admin, true, &kyc_authority))
+            .unwrap();
+        assert_eq!(
+            can_mint(&mut t, &user, vec![]),
+            code(CustomError::KycRequired)
+        );
+        t.bank
+            .process(&attest_kyc_ix(&contract, &kyc_authority, &user, true))
+            .unwrap();
+
+        let pass = t.bank.create_mint(&Pubkey::new_unique());
+        let user_pass = t.bank.create_token_account(&pass, &user);
+        t.configure(
+            "arena",
+            &GameConfig {
+                required_token_mint: pass,
+                ..GameConfig::default()
+            },
+        )
+        .unwrap();
+        let holding = vec![AccountMeta::new_readonly(user_pass, false)];
+        assert_eq!(
+            can_mint(&mut t, &user, holding.clone()),
+            code(CustomError::RequiredTokenMissing)
+        );
+        t.bank.set_token_balance(&user_pass, 1);
+
+        t.bank
+            .process(&set_max_metadata_rent_ix(&contract, &admin, 1))
+            .unwrap();
+        assert_eq!(
+            can_mint(&mut t, &user, holding.clone()),
+            code(CustomError::MetadataRentTooHigh)
+        );
+        t.bank
+            .process(&set_max_metadata_rent_ix(&contract, &admin, 0))
+            .unwrap();
+
+        // Every check passes, and the mint itself goes through
+        let state_before = t.bank.data(&contract).to_vec();
+        assert_eq!(can_mint(&mut t, &user, holding.clone()), None);
+        assert_eq!(t.bank.data(&contract), &state_before[..]);
+        t.mint_to_user_with(&user, "arena", holding).unwrap();
+    }
+
+    #[test]
+    fn test_template_uri_interpolates_token_id() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        let template = GameMetadataTemplate {
+            uri_prefix: "https://api.game/meta/{id}.json".to_string(),
+            ..GameMetadataTemplate::default()
+        };
+        t.bank
+            .process(&set_game_metadata_template_ix(
+                &contract, &admin, "arena", &template,
+            ))
+            .unwrap();
+        t.grant(&user, "arena", "").unwrap();
+        for token_id in 1..=3 {
+            t.mint_to_user(&user, "arena").unwrap();
+            assert_eq!(
+                t.bank.state::<TokenUri>(&uri_pda(token_id)).uri,
+                format!("https://api.game/meta/{}.json", token_id)
+            );
+        }
+
+        // Jump ahead to a six-digit id
+        let mut state = t.contract_state();
+        state.last_token_id = 99_999;
+        let mut data = t.bank.data(&contract).to_vec();
+        state.serialize(&mut &mut data[..]).unwrap();
+        t.bank.set_data(&contract, data);
+        t.mint_to_user(&user, "arena").unwrap();
+        assert_eq!(
+            t.bank.state::<TokenUri>(&uri_pda(100_000)).uri,
+            "https://api.game/meta/100000.json"
+        );
+
+        // A template that only fits while ids are short
+        let prefix = "https://x/";
+        let long = GameMetadataTemplate {
+            uri_prefix: format!(
+                "{prefix}{}{{id}}",
+                "a".repeat(MAX_TOKEN_URI_LEN - prefix.len() - TOKEN_ID_PLACEHOLDER.len())
+            ),
+            ..GameMetadataTemplate::default()
+        };
+        t.bank
+            .process(&set_game_metadata_template_ix(
+                &contract, &admin, "arena", &long,
+            ))
+            .unwrap();
+        assert_eq!(
+            t.mint_to_user(&user, "arena"),
+            Err(CustomError::UriTooLong.into())
+        );
+    }
+
+    #[test]
+    fn test_set_and_get_merkle_root() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let user = Pubkey::new_unique();
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+
+        t.bank.process(&get_merkle_root_ix("arena")).unwrap();
+        assert_eq!(t.bank.return_data(), [0; 32]);
+
+        let root = solana_program::hash::hashv(&[b"whitelist", user.as_ref()]).to_bytes();
+        assert_eq!(
+            t.bank
+                .process(&set_merkle_root_ix(&contract, &user, "arena", &root)),
+            Err(CustomError::NotAdmin.into())
+        );
+        t.bank
+            .process(&set_merkle_root_ix(&contract, &admin, "arena", &root))
+            .unwrap();
+        t.bank.process(&get_merkle_root_ix("arena")).unwrap();
+        assert_eq!(t.bank.return_data(), root);
+        assert_eq!(t.game("arena").unwrap().config.merkle_root, root);
+    }
+
+    #[test]
+    fn test_transfer_resets_cooldown_when_configured() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let user = Pubkey::new_unique();
+        let receiver = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        t.grant(&user, "relic", "ipfs://relic").unwrap();
+        t.configure(
+            "arena",
+            &GameConfig {
+                reset_cooldown_on_transfer: true,
+                ..GameConfig::default()
+            },
+        )
+        .unwrap();
+        let (arena_mint, arena_token) = t.mint_to_user(&user, "arena").unwrap();
+        let (relic_mint, relic_token) = t.mint_to_user(&user, "relic").unwrap();
+        for token_id in [1, 2] {
+            let mut record: TokenRecord = t.bank.state(&record_pda(token_id));
+            record.cooldown_ends_slot = 500;
+            let mut data = t.bank.data(&record_pda(token_id)).to_vec();
+            record.serialize(&mut &mut data[..]).unwrap();
+            t.bank.set_data(&record_pda(token_id), data);
+        }
+        let cooldown = |t: &TestContract, token_id| {
+            t.bank
+                .state::<TokenRecord>(&record_pda(token_id))
+                .cooldown_ends_slot
+        };
+
+        // A transfer rejected by the freeze window leaves the cooldown alone
+        t.bank.warp_to_slot(20);
+        let arena_receiver = t.bank.create_token_account(&arena_mint, &receiver);
+        let transfer = transfer_ix(
+            &contract,
+            1,
+            "arena",
+            &arena_token,
+            &arena_receiver,
+            &user,
+            &receiver,
+        );
+        t.bank
+            .process(&freeze_transfers_until_ix(&contract, &admin, 30))
+            .unwrap();
+        assert_eq!(
+            t.bank.process(&transfer),
+            Err(CustomError::TransfersFrozen.into())
+        );
+        assert_eq!(cooldown(&t, 1), 500);
+
+        t.bank.warp_to_slot(40);
+        t.bank.process(&transfer).unwrap();
+        assert_eq!(cooldown(&t, 1), 40);
+
+        // Games without the toggle keep the cooldown across transfers
+        let relic_receiver = t.bank.create_token_account(&relic_mint, &receiver);
+        t.bank
+            .process(&transfer_ix(
+                &contract,
+                2,
+                "relic",
+                &relic_token,
+                &relic_receiver,
+                &user,
+                &receiver,
+            ))
+            .unwrap();
+        assert_eq!(cooldown(&t, 2), 500);
+    }
+
+    #[test]
+    fn test_token_history_records_mint_transfer_burn() {
+        let mut t = TestContract::new();
+        let contract = t.contract;
+        let user = Pubkey::new_unique();
+        let buyer = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.bank.airdrop(buyer, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+
+        t.bank.warp_to_slot(5);
+        let (mint, token) = t.mint_to_user(&user, "arena").unwrap();
+        t.bank.warp_to_slot(9);
+        let buyer_token = t.bank.create_token_account(&mint, &buyer);
+        t.bank
+            .process(&transfer_ix(
+                &contract,
+                1,
+                "arena",
+                &token,
+                &buyer_token,
+                &user,
+                &buyer,
+            ))
+            .unwrap();
+        t.bank.warp_to_slot(12);
+        t.bank
+            .process(&burn_ix(&contract, 1, "arena", &buyer_token, &buyer))
+            .unwrap();
+
+        t.bank
+            .process(&get_token_history_ix(1, 0, u64::MAX, &[0]))
+            .unwrap();
+        let history = Vec::<HistoryEntry>::try_from_slice(&t.bank.return_data()).unwrap();
+        let entry = |event, slot, from, to| HistoryEntry {
+            event,
+            slot,
+            from,
+            to,
+        };
+        assert_eq!(
+            history,
+            vec![
+                entry(HistoryEvent::Mint, 5, Pubkey::default(), user),
+                entry(HistoryEvent::Transfer, 9, user, buyer),
+                entry(HistoryEvent::Burn, 12, buyer, Pubkey::default()),
+            ]
+        );
+
+        // Burning through the queue records the burn the same way
+        let (_, token) = t.mint_to_user(&user, "arena").unwrap();
+        t.bank.warp_to_slot(15);
+        t.bank
+            .process(&enqueue_burn_ix(&contract, 2, &token, &user))
+            .unwrap();
+        t.bank
+            .process(&process_burn_queue_ix(
+                &contract,
+                "arena",
+                &user,
+                &[(2, token)],
+            ))
+            .unwrap();
+        t.bank
+            .process(&get_token_history_ix(2, 1, 1, &[0]))
+            .unwrap();
+        assert_eq!(
+            Vec::<HistoryEntry>::try_from_slice(&t.bank.return_data()).unwrap(),
+            vec![entry(HistoryEvent::Burn, 15, user, Pubkey::default())]
+        );
+
+        // Pages start anywhere, and tokens never minted have no history
+        t.bank
+            .process(&get_token_history_ix(1, 1, 1, &[0]))
+            .unwrap();
+        let page = Vec::<HistoryEntry>::try_from_slice(&t.bank.return_data()).unwrap();
+        assert_eq!(page, history[1..2]);
+        t.bank
+            .process(&get_token_history_ix(7, 0, 10, &[]))
+            .unwrap();
+        assert!(Vec::<HistoryEntry>::try_from_slice(&t.bank.return_data())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_token_history_spills_into_new_chunks() {
+        let mut t = TestContract::new();
+        let contract = t.contract;
+        let alice = Pubkey::new_unique();
+        let bob = Pubkey::new_unique();
+        t.bank.airdrop(alice, 10_000_000_000);
+        t.bank.airdrop(bob, 10_000_000_000);
+        t.grant(&alice, "arena", "ipfs://arena").unwrap();
+        let (mint, alice_token) = t.mint_to_user(&alice, "arena").unwrap();
+        let bob_token = t.bank.create_token_account(&mint, &bob);
+
+        // Pass the token back and forth until the history fills two chunks
+        let moves = 2 * TOKEN_HISTORY_PER_CHUNK;
+        for n in 1..moves {
+            let (from, from_token, to, to_token) = if n % 2 == 1 {
+                (alice, alice_token, bob, bob_token)
+            } else {
+                (bob, bob_token, alice, alice_token)
+            };
+            let mut ix = transfer_ix(&contract, 1, "arena", &from_token, &to_token, &from, &to);
+            ix.accounts[11].pubkey = history_pda(1, (n / TOKEN_HISTORY_PER_CHUNK) as u32);
+            t.bank.process(&ix).unwrap();
+        }
+        assert_eq!(
+            t.bank.state::<TokenRecord>(&record_pda(1)).history_len,
+            moves
+        );
+
+        // A transfer logging to the wrong chunk is rejected
+        let mut ix = transfer_ix(
+            &contract,
+            1,
+            "arena",
+            &bob_token,
+            &alice_token,
+            &bob,
+            &alice,
+        );
+        ix.accounts[11].pubkey = history_pda(1, 1);
+        assert_eq!(t.bank.process(&ix), Err(ProgramError::InvalidSeeds));
+
+        t.bank
+            .process(&get_token_history_ix(1, 6, 4, &[0, 1]))
+            .unwrap();
+        let page = Vec::<HistoryEntry>::try_from_slice(&t.bank.return_data()).unwrap();
+        assert_eq!(page.len(), 4);
+        assert_eq!((page[0].from, page[0].to), (bob, alice));
+        assert_eq!((page[3].from, page[3].to), (alice, bob));
+    }
+
+    #[test]
+    fn test_grant_mint_unpack_round_trips_strings() {
+        let user = Pubkey::new_unique();
+        let ix = grant_mint_ix(
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &user,
+            "arena",
+            "ipfs://bafy/relic",
+        );
+        match ContractInstruction::unpack(&ix.data).unwrap() {
+            ContractInstruction::GrantMint {
+                user: decoded_user,
+                game_id,
+                token_uri,
+                name,
+                overwrite,
+            } => {
+                assert_eq!(decoded_user, user);
+                assert_eq!(game_id, "arena");
+                assert_eq!(token_uri, "ipfs://bafy/relic");
+                assert_eq!(name, "");
+                assert!(!overwrite);
+            }
+            _ => panic!("expected GrantMint"),
+        }
+
+        // A cut-off length prefix, or a length past the end of the data
+        let game_id_at = 1 + 32;
+        for len in [game_id_at + 2, game_id_at + 4 + 3] {
+            assert_eq!(
+                ContractInstruction::unpack(&ix.data[..len]).err(),
+                Some(CustomError::InvalidInstruction.into())
+            );
+        }
+        let mut oversized = ix.data.clone();
+        oversized[game_id_at..game_id_at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            ContractInstruction::unpack(&oversized).err(),
+            Some(CustomError::InvalidInstruction.into())
+        );
+    }
+
+    #[test]
+    fn test_holders_get_mint_discount() {
+        let mut t = TestContract::new();
+        let treasury = t.admin;
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        assert_eq!(
+            t.configure(
+                "arena",
+                &GameConfig {
+                    holder_discount_bps: MAX_BPS + 1,
+                    ..GameConfig::default()
+                },
+            ),
+            Err(CustomError::InvalidDiscount.into())
+        );
+        t.configure(
+            "arena",
+            &GameConfig {
+                price_amount: 1_000_000,
+                holder_discount_bps: 2_500,
+                ..GameConfig::default()
+            },
+        )
+        .unwrap();
+        let accounts = vec![
+            AccountMeta::new_readonly(holding_pda("arena", &user), false),
+            AccountMeta::new(treasury, false),
+        ];
+
+        // First-time minters pay the full price
+        let before = t.bank.lamports(&treasury);
+        t.mint_to_user_with(&user, "arena", accounts.clone())
+            .unwrap();
+        assert_eq!(t.bank.lamports(&treasury) - before, 1_000_000);
+        assert_eq!(t.bank.state::<TokenRecord>(&record_pda(1)).value, 1_000_000);
+
+        // Holders get 25% off
+        let before = t.bank.lamports(&treasury);
+        t.mint_to_user_with(&user, "arena", accounts).unwrap();
+        assert_eq!(t.bank.lamports(&treasury) - before, 750_000);
+        assert_eq!(t.bank.state::<TokenRecord>(&record_pda(2)).value, 750_000);
+        assert_eq!(t.contract_state().total_fees_collected, 1_750_000);
+    }
+
+    #[test]
+    fn test_grant_keeps_contract_state_apart_from_permission() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        let state_before = t.contract_state();
+
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        let permission = permission_pda(&user, "arena");
+        assert_ne!(permission, contract);
+        let granted: MintPermission = t.bank.state(&permission);
+        assert_eq!(granted.user, user);
+        assert_eq!(granted.game_id, "arena");
+        let state = t.contract_state();
+        assert_eq!(state.contract_owner, admin);
+        assert_eq!(state.last_token_id, state_before.last_token_id);
+
+        for token_id in 1..=2 {
+            t.mint_to_user(&user, "arena").unwrap();
+            let state = t.contract_state();
+            assert_eq!(state.contract_owner, admin);
+            assert_eq!(state.last_token_id, token_id);
+            assert_eq!(t.bank.state::<MintPermission>(&permission).minted, token_id);
+        }
+    }
+
+    #[test]
+    fn test_bootstrap_sets_up_a_deployment_once() {
+        let mut bank = TestBank::new();
+        let contract = contract_pda();
+        let owner = Pubkey::new_unique();
+        bank.deploy(&owner);
+        bank.airdrop(owner, 10_000_000_000);
+        bank.process(&bootstrap_ix(&contract, &owner, "arena", "ipfs://arena"))
+            .unwrap();
+
+        let mut t = TestContract {
+            bank,
+            contract,
+            admin: owner,
+        };
+        let state = t.contract_state();
+        assert_eq!(state.contract_owner, owner);
+        assert_eq!(state.treasury, owner);
+        assert!(state.minting_enabled);
+        let game = t.game("arena").unwrap();
+        assert!(game.active);
+        let permission: MintPermission = t.bank.state(&permission_pda(&owner, "arena"));
+        assert_eq!(permission.user, owner);
+        assert_eq!(permission.token_uri, "ipfs://arena");
+
+        // the owner can mint straight away
+        t.mint_to_user(&owner, "arena").unwrap();
+        assert_eq!(t.contract_state().last_token_id, 1);
+
+        let attacker = Pubkey::new_unique();
+        t.bank.airdrop(attacker, 10_000_000_000);
+        for caller in [owner, attacker] {
+            let ix = bootstrap_ix(&contract, &caller, "other", "ipfs://other");
+            assert_eq!(
+                t.bank.process(&ix),
+                Err(CustomError::AlreadyInitialized.into())
+            );
+        }
+        assert_eq!(t.contract_state().contract_owner, owner);
+    }
+
+    // Marks the account at `index` as not having signed
+    fn unsigned(mut ix: Instruction, index: usize) -> Instruction {
+        ix.accounts[index].is_signer = false;
+        ix
+    }
+
+    #[test]
+    fn test_privileged_accounts_must_sign() {
+        let mut bank = TestBank::new();
+        let contract = contract_pda();
+        let owner = Pubkey::new_unique();
+        bank.deploy(&owner);
+        bank.airdrop(owner, 10_000_000_000);
+        assert_eq!(
+            bank.process(&unsigned(initialize_ix(&contract, &owner), 2)),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        assert!(!bank.accounts.contains_key(&contract));
+
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let user = Pubkey::new_unique();
+        let receiver = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        // the owner's key alone, passed read-only, grants nothing
+        let mut ix = grant_mint_ix(&contract, &admin, &user, "arena", "ipfs://arena");
+        ix.accounts[1] = AccountMeta::new_readonly(admin, false);
+        assert_eq!(
+            t.bank.process(&ix),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        assert!(!t
+            .bank
+            .accounts
+            .contains_key(&permission_pda(&user, "arena")));
+
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        let (mint, user_token) = t.mint_to_user(&user, "arena").unwrap();
+        let receiver_token = t.bank.create_token_account(&mint, &receiver);
+        let ix = transfer_ix(
+            &contract,
+            1,
+            "arena",
+            &user_token,
+            &receiver_token,
+            &user,
+            &receiver,
+        );
+        assert_eq!(
+            t.bank.process(&unsigned(ix, 4)),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        assert_eq!(t.bank.state::<TokenRecord>(&record_pda(1)).owner, user);
+
+        let ix = burn_ix(&contract, 1, "arena", &user_token, &user);
+        assert_eq!(
+            t.bank.process(&unsigned(ix, 0)),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+    }
+
+    #[test]
+    fn test_reserved_supply_held_back_until_released() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        t.configure(
+            "arena",
+            &GameConfig {
+                max_supply: 5,
+                reserved_supply: 2,
+                ..GameConfig::default()
+            },
+        )
+        .unwrap();
+
+        for _ in 0..3 {
+            t.mint_to_user(&user, "arena").unwrap();
+        }
+        assert_eq!(
+            t.mint_to_user(&user, "arena"),
+            Err(CustomError::SupplyExhausted.into())
+        );
+        assert_eq!(t.game("arena").unwrap().minted, 3);
+
+        assert_eq!(
+            t.bank
+                .process(&release_reserved_ix(&contract, &admin, "arena", 3)),
+            Err(CustomError::InvalidInstruction.into())
+        );
+        t.bank
+            .process(&release_reserved_ix(&contract, &admin, "arena", 2))
+            .unwrap();
+        assert_eq!(t.game("arena").unwrap().config.reserved_supply, 0);
+        for _ in 0..2 {
+            t.mint_to_user(&user, "arena").unwrap();
+        }
+        assert_eq!(
+            t.mint_to_user(&user, "arena"),
+            Err(CustomError::SupplyExhausted.into())
+        );
+        assert_eq!(t.game("arena").unwrap().minted, 5);
+    }
+
+    #[test]
+    fn test_transfer_moves_record_ownership() {
+        let mut t = TestContract::new();
+        let contract = t.contract;
+        let user = Pubkey::new_unique();
+        let receiver = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.bank.airdrop(receiver, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        let (mint, user_token) = t.mint_to_user(&user, "arena").unwrap();
+        let receiver_token = t.bank.create_token_account(&mint, &receiver);
+        let send = |from: &Pubkey, to: &Pubkey, from_token, to_token| {
+            transfer_ix(&contract, 1, "arena", from_token, to_token, from, to)
+        };
+
+        t.bank
+            .process(&send(&user, &receiver, &user_token, &receiver_token))
+            .unwrap();
+        let record: TokenRecord = t.bank.state(&record_pda(1));
+        assert_eq!(record.owner, receiver);
+        assert_eq!(record.token_id, 1);
+        assert_eq!(record.mint, mint);
+
+        // the record, not the caller's claim, decides who may move the token
+        assert_eq!(
+            t.bank
+                .process(&send(&user, &receiver, &user_token, &receiver_token)),
+            Err(CustomError::NotTokenOwner.into())
+        );
+        t.bank
+            .process(&send(&receiver, &user, &receiver_token, &user_token))
+            .unwrap();
+        assert_eq!(t.bank.state::<TokenRecord>(&record_pda(1)).owner, user);
+        assert_eq!(t.bank.token_balance(&user_token), 1);
+    }
+
+    #[test]
+    fn test_revoked_permission_can_no_longer_mint() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        t.mint_to_user(&user, "arena").unwrap();
+
+        let revoke = revoke_mint_ix(&contract, &admin, &user, "arena");
+        assert_eq!(
+            t.bank.process(&unsigned(revoke.clone(), 1)),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        let admin_lamports = t.bank.lamports(&admin);
+        let rent = t.bank.lamports(&permission_pda(&user, "arena"));
+        t.bank.process(&revoke).unwrap();
+        assert_eq!(t.bank.lamports(&admin), admin_lamports + rent);
+
+        assert_eq!(
+            t.mint_to_user(&user, "arena"),
+            Err(CustomError::MintNotPermitted.into())
+        );
+        assert_eq!(
+            t.bank.process(&revoke),
+            Err(CustomError::MintNotPermitted.into())
+        );
+        assert_eq!(t.contract_state().last_token_id, 1);
+
+        // a fresh grant restores the right to mint
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        t.mint_to_user(&user, "arena").unwrap();
+        assert_eq!(t.contract_state().last_token_id, 2);
+    }
+
+    #[test]
+    fn test_usd_price_converted_at_the_feed_rate() {
+        let mut t = TestContract::new();
+        let treasury = t.admin;
+        let user = Pubkey::new_unique();
+        let feed = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        let config = GameConfig {
+            price_usd_cents: 300,
+            price_feed: feed,
+            ..GameConfig::default()
+        };
+        t.configure("arena", &config).unwrap();
+        assert_eq!(
+            t.configure(
+                "arena",
+                &GameConfig {
+                    price_mint: Pubkey::new_unique(),
+                    ..config.clone()
+                }
+            ),
+            Err(CustomError::InvalidPriceFeed.into())
+        );
+
+        // SOL at $150.00: $3 buys 0.02 SOL
+        t.bank.warp_to_slot(1_000);
+        let quote = PriceFeed {
+            price: 15_000_000_000,
+            expo: -8,
+            publish_slot: 990,
+        };
+        t.bank
+            .set_account(feed, Pubkey::new_unique(), quote.try_to_vec().unwrap());
+        let fee_accounts = |feed| {
+            vec![
+                AccountMeta::new_readonly(feed, false),
+                AccountMeta::new(treasury, false),
+            ]
+        };
+        let before = t.bank.lamports(&treasury);
+        t.mint_to_user_with(&user, "arena", fee_accounts(feed))
+            .unwrap();
+        assert_eq!(t.bank.lamports(&treasury), before + 20_000_000);
+        assert_eq!(
+            t.bank.state::<TokenRecord>(&record_pda(1)).value,
+            20_000_000
+        );
+
+        let impostor = Pubkey::new_unique();
+        t.bank
+            .set_account(impostor, Pubkey::new_unique(), quote.try_to_vec().unwrap());
+        assert_eq!(
+            t.mint_to_user_with(&user, "arena", fee_accounts(impostor)),
+            Err(CustomError::InvalidPriceFeed.into())
+        );
+
+        t.bank.warp_to_slot(990 + MAX_PRICE_FEED_AGE_SLOTS + 1);
+        assert_eq!(
+            t.mint_to_user_with(&user, "arena", fee_accounts(feed)),
+            Err(CustomError::StalePriceFeed.into())
+        );
+        assert_eq!(t.contract_state().last_token_id, 1);
+    }
+
+    #[test]
+    fn test_burn_callback_notified_and_can_veto() {
+        let mut t = TestContract::new();
+        let contract = t.contract;
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        for (game_id, callback) in [("arena", BURN_CALLBACK), ("relic", REJECTING_VALIDATOR)] {
+            t.grant(&user, game_id, "ipfs://game").unwrap();
+            t.configure(
+                game_id,
+                &GameConfig {
+                    burn_callback_program: callback,
+                    ..GameConfig::default()
+                },
+            )
+            .unwrap();
+        }
+        let mut tokens = vec![];
+        for (token_id, game_id) in [(1, "arena"), (2, "relic")] {
+            let (_, token_account) = t.mint_to_user(&user, game_id).unwrap();
+            t.bank
+                .process(&enqueue_burn_ix(&contract, token_id, &token_account, &user))
+                .unwrap();
+            tokens.push((token_id, token_account));
+        }
+        BURN_NOTIFICATIONS.with(|n| n.borrow_mut().clear());
+
+        let burn = |game_id, queued, callback| {
+            let mut ix = process_burn_queue_ix(&contract, game_id, &user, &[queued]);
+            ix.accounts.push(AccountMeta::new_readonly(callback, false));
+            ix
+        };
+        assert_eq!(
+            t.bank.process(&process_burn_queue_ix(
+                &contract,
+                "arena",
+                &user,
+                &tokens[..1]
+            )),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+        assert_eq!(
+            t.bank
+                .process(&burn("arena", tokens[0], REJECTING_VALIDATOR)),
+            Err(ProgramError::IncorrectProgramId)
+        );
+        t.bank
+            .process(&burn("arena", tokens[0], BURN_CALLBACK))
+            .unwrap();
+        assert_eq!(
+            BURN_NOTIFICATIONS.with(|n| n.take()),
+            vec![BurnNotification {
+                game_id: "arena".to_string(),
+                token_id: 1,
+                former_owner: user,
+            }]
+        );
+        assert!(t.bank.state::<TokenRecord>(&record_pda(1)).burned);
+
+        // A failing callback rolls the burn back
+        assert_eq!(
+            t.bank
+                .process(&burn("relic", tokens[1], REJECTING_VALIDATOR)),
+            Err(ProgramError::Custom(0xdead))
+        );
+        let record: TokenRecord = t.bank.state(&record_pda(2));
+        assert!(record.burn_queued && !record.burned);
+        assert_eq!(t.bank.token_balance(&tokens[1].1), 1);
+        assert_eq!(t.contract_state().queued_burns, 1);
+    }
+
+    #[test]
+    fn test_burn_destroys_token_and_keeps_a_burned_record() {
+        let mut t = TestContract::new();
+        let contract = t.contract;
+        let user = Pubkey::new_unique();
+        let stranger = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.bank.airdrop(stranger, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        t.mint_to_user(&user, "arena").unwrap();
+        let (mint, token_account) = t.mint_to_user(&user, "arena").unwrap();
+        assert_eq!(t.game("arena").unwrap().holder_count, 1);
+
+        assert_eq!(
+            t.bank
+                .process(&burn_ix(&contract, 2, "arena", &token_account, &stranger)),
+            Err(CustomError::NotTokenOwner.into())
+        );
+
+        let before = t.bank.lamports(&user);
+        let reclaimed: u64 = [metadata_pda(&mint), uri_pda(2)]
+            .iter()
+            .map(|key| t.bank.lamports(key))
+            .sum();
+        t.bank
+            .process(&burn_ix(&contract, 2, "arena", &token_account, &user))
+            .unwrap();
+        assert_eq!(t.bank.token_balance(&token_account), 0);
+        assert_eq!(Mint::unpack(t.bank.data(&mint)).unwrap().supply, 0);
+        let record: TokenRecord = t.bank.state(&record_pda(2));
+        assert!(record.burned);
+        assert_eq!(t.bank.lamports(&user), before + reclaimed);
+        assert_eq!(t.game("arena").unwrap().holder_count, 1);
+        assert_eq!(t.contract_state().active_tokens, 1);
+
+        t.bank.process(&is_burned_ix(&contract, 2)).unwrap();
+        assert_eq!(t.bank.return_data(), vec![1]);
+        t.bank.process(&is_burned_ix(&contract, 1)).unwrap();
+        assert_eq!(t.bank.return_data(), vec![0]);
+        assert_eq!(
+            t.bank
+                .process(&burn_ix(&contract, 2, "arena", &token_account, &user)),
+            Err(CustomError::TokenBurned.into())
+        );
+
+        // The burned record still lets the game's token list drop the id
+        t.bank
+            .process(&compact_game_tokens_ix(
+                &contract,
+                &t.admin,
+                "arena",
+                1,
+                &[2],
+            ))
+            .unwrap();
+        assert_eq!(t.game("arena").unwrap().token_count, 1);
+    }
+
+    #[test]
+    fn test_transfer_ownership_moves_admin_rights() {
+        let mut t = TestContract::new();
+        let (contract, old_owner) = (t.contract, t.admin);
+        let new_owner = Pubkey::new_unique();
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(new_owner, 10_000_000_000);
+
+        assert_eq!(
+            t.bank
+                .process(&transfer_ownership_ix(&contract, &new_owner, &new_owner)),
+            Err(CustomError::NotAdmin.into())
+        );
+        t.bank
+            .process(&transfer_ownership_ix(&contract, &old_owner, &new_owner))
+            .unwrap();
+        let state = t.contract_state();
+        assert_eq!(state.contract_owner, new_owner);
+        assert_eq!(state.treasury, old_owner);
+
+        assert_eq!(
+            t.bank.process(&grant_mint_ix(
+                &contract,
+                &old_owner,
+                &user,
+                "arena",
+                "ipfs://arena"
+            )),
+            Err(CustomError::NotAdmin.into())
+        );
+        t.bank
+            .process(&grant_mint_ix(
+                &contract,
+                &new_owner,
+                &user,
+                "arena",
+                "ipfs://arena",
+            ))
+            .unwrap();
+        let permission: MintPermission = t.bank.state(&permission_pda(&user, "arena"));
+        assert_eq!(permission.user, user);
+    }
+
+    #[test]
+    fn test_get_token_records_mixes_found_and_missing() {
+        let mut t = TestContract::new();
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        t.mint_to_user(&user, "arena").unwrap();
+        t.mint_to_user(&user, "arena").unwrap();
+
+        t.bank.process(&get_token_records_ix(&[2, 9, 1])).unwrap();
+        let records = Vec::<Option<TokenRecord>>::try_from_slice(&t.bank.return_data()).unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0], Some(t.bank.state(&record_pda(2))));
+        assert_eq!(records[1], None);
+        let first = records[2].as_ref().unwrap();
+        assert_eq!((first.token_id, first.owner), (1, user));
+
+        let too_many: Vec<u64> = (1..=MAX_TOKEN_RECORDS_PAGE as u64 + 1).collect();
+        assert_eq!(
+            t.bank.process(&get_token_records_ix(&too_many)),
+            Err(CustomError::InvalidInstruction.into())
+        );
+        let mut mismatched = get_token_records_ix(&[1, 2]);
+        mismatched.accounts.swap(0, 1);
+        assert_eq!(t.bank.process(&mismatched), Err(ProgramError::InvalidSeeds));
+    }
+
+    #[test]
+    fn test_timelocked_ownership_transfer() {
+        let mut t = TestContract::new();
+        let (contract, owner) = (t.contract, t.admin);
+        let new_owner = Pubkey::new_unique();
+        t.bank.airdrop(new_owner, 10_000_000_000);
+        assert_eq!(
+            t.bank.process(&execute_change_ix(&contract, &owner)),
+            Err(CustomError::NoPendingChange.into())
+        );
+
+        // Without a timelock a proposal can be executed straight away
+        t.bank
+            .process(&propose_change_ix(
+                &contract,
+                &owner,
+                &ProposedChange::SetTimelock { slots: 100 },
+            ))
+            .unwrap();
+        t.bank
+            .process(&execute_change_ix(&contract, &owner))
+            .unwrap();
+        assert_eq!(t.contract_state().timelock_slots, 100);
+        assert!(!t.bank.accounts.contains_key(&pending_change_pda()));
+
+        assert_eq!(
+            t.bank
+                .process(&transfer_ownership_ix(&contract, &owner, &new_owner)),
+            Err(CustomError::TimelockRequired.into())
+        );
+        t.bank.warp_to_slot(50);
+        let change = ProposedChange::TransferOwnership { new_owner };
+        t.bank
+            .process(&propose_change_ix(&contract, &owner, &change))
+            .unwrap();
+        let pending: PendingChange = t.bank.state(&pending_change_pda());
+        assert_eq!(
+            pending,
+            PendingChange {
+                change,
+                execute_after_slot: 150,
+            }
+        );
+
+        t.bank.warp_to_slot(149);
+        assert_eq!(
+            t.bank.process(&execute_change_ix(&contract, &owner)),
+            Err(CustomError::TimelockActive.into())
+        );
+        assert_eq!(t.contract_state().contract_owner, owner);
+        t.bank.warp_to_slot(150);
+        t.bank
+            .process(&execute_change_ix(&contract, &owner))
+            .unwrap();
+        assert_eq!(t.contract_state().contract_owner, new_owner);
+    }
+
+    #[test]
+    fn test_timelock_gates_treasury_and_kyc_changes() {
+        let mut t = TestContract::new();
+        let (contract, owner) = (t.contract, t.admin);
+        assert_eq!(
+            pending_change_address(&PROGRAM_ID).0,
+            pda(&[PENDING_CHANGE_SEED, contract.as_ref()])
+        );
+        t.bank
+            .process(&propose_change_ix(
+                &contract,
+                &owner,
+                &ProposedChange::SetTimelock { slots: 100 },
+            ))
+            .unwrap();
+        t.bank
+            .process(&execute_change_ix(&contract, &owner))
+            .unwrap();
+
+        let recipient = Pubkey::new_unique();
+        let split = vec![TreasuryShare {
+            recipient,
+            bps: MAX_BPS,
+        }];
+        assert_eq!(
+            t.bank
+                .process(&set_treasury_split_ix(&contract, &owner, &split)),
+            Err(CustomError::TimelockRequired.into())
+        );
+        let authority = Pubkey::new_unique();
+        assert_eq!(
+            t.bank
+                .process(&set_kyc_gate_ix(&contract, &owner, true, &authority)),
+            Err(CustomError::TimelockRequired.into())
+        );
+
+        // An invalid split is refused when proposed, not when executed
+        assert_eq!(
+            t.bank.process(&propose_change_ix(
+                &contract,
+                &owner,
+                &ProposedChange::SetTreasurySplit {
+                    recipients: vec![TreasuryShare { recipient, bps: 1 }],
+                },
+            )),
+            Err(CustomError::InvalidTreasurySplit.into())
+        );
+        t.bank
+            .process(&propose_change_ix(
+                &contract,
+                &owner,
+                &ProposedChange::SetTreasurySplit {
+                    recipients: split.clone(),
+                },
+            ))
+            .unwrap();
+        t.bank.warp_to_slot(100);
+        t.bank
+            .process(&execute_change_ix(&contract, &owner))
+            .unwrap();
+        assert_eq!(t.contract_state().treasury_split, split);
+
+        t.bank
+            .process(&propose_change_ix(
+                &contract,
+                &owner,
+                &ProposedChange::SetKycGate {
+                    required: true,
+                    authority,
+                },
+            ))
+            .unwrap();
+        assert!(!t.contract_state().kyc_required);
+        t.bank.warp_to_slot(200);
+        t.bank
+            .process(&execute_change_ix(&contract, &owner))
+            .unwrap();
+        let state = t.contract_state();
+        assert!(state.kyc_required);
+        assert_eq!(state.kyc_authority, authority);
+    }
+
+    #[test]
+    fn test_mint_transfer_and_burn_log_events() {
+        let mut t = TestContract::new();
+        let contract = t.contract;
+        let user = Pubkey::new_unique();
+        let receiver = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.bank.airdrop(receiver, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+
+        let (mint, user_token) = t.mint_to_user(&user, "arena").unwrap();
+        let expected = format!("COC_EVENT:MINT:1:{}:arena", user);
+        assert!(t.bank.logs().contains(&expected), "{:?}", t.bank.logs());
+
+        let receiver_token = t.bank.create_token_account(&mint, &receiver);
+        t.bank
+            .process(&transfer_ix(
+                &contract,
+                1,
+                "arena",
+                &user_token,
+                &receiver_token,
+                &user,
+                &receiver,
+            ))
+            .unwrap();
+        let expected = format!("COC_EVENT:TRANSFER:1:{}:{}", user, receiver);
+        assert!(t.bank.logs().contains(&expected));
+
+        t.bank
+            .process(&burn_ix(&contract, 1, "arena", &receiver_token, &receiver))
+            .unwrap();
+        assert!(t.bank.logs().contains(&"COC_EVENT:BURN:1".to_string()));
+    }
+
+    #[test]
+    fn test_dashboard_bundles_contract_statistics() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let user = Pubkey::new_unique();
+        let dashboard_ix = build_get_dashboard(&PROGRAM_ID, &contract);
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        t.grant(&user, "relic", "ipfs://relic").unwrap();
+        t.configure(
+            "relic",
+            &GameConfig {
+                price_amount: 1_000,
+                ..GameConfig::default()
+            },
+        )
+        .unwrap();
+        t.mint_to_user(&user, "arena").unwrap();
+        let (_, token_account) = t.mint_to_user(&user, "arena").unwrap();
+        t.mint_to_user_with(&user, "relic", vec![AccountMeta::new(admin, false)])
+            .unwrap();
+        t.bank
+            .process(&burn_ix(&contract, 2, "arena", &token_account, &user))
+            .unwrap();
+        t.bank
+            .process(&freeze_transfers_until_ix(&contract, &admin, 100))
+            .unwrap();
+
+        t.bank.process(&dashboard_ix).unwrap();
+        let dashboard = Dashboard::try_from_slice(&t.bank.return_data()).unwrap();
+        let state = t.contract_state();
+        assert_eq!(
+            dashboard,
+            Dashboard {
+                last_token_id: state.last_token_id,
+                active_tokens: 2,
+                total_grants: 2,
+                total_fees_collected: state.total_fees_collected,
+                paused: state.paused,
+                minting_enabled: state.minting_enabled,
+                freeze_transfers_until_slot: state.freeze_transfers_until_slot,
+            }
+        );
+        assert_eq!(
+            (dashboard.last_token_id, dashboard.total_fees_collected),
+            (3, 1_000)
+        );
+        // A transfer freeze isn't a pause
+        assert_eq!(
+            (dashboard.paused, dashboard.freeze_transfers_until_slot),
+            (false, 100)
+        );
+
+        t.bank
+            .process(&set_paused_ix(&contract, &admin, true))
+            .unwrap();
+        t.bank.process(&dashboard_ix).unwrap();
+        assert!(
+            Dashboard::try_from_slice(&t.bank.return_data())
+                .unwrap()
+                .paused
+        );
+    }
+
+    // One instruction per variant, in discriminant order
+    fn one_of_each_instruction() -> Vec<ContractInstruction> {
+        let key = Pubkey::new_unique();
+        let other = Pubkey::new_unique();
+        let game_id = || "arena".to_string();
+        vec![
+            ContractInstruction::InitializeContract {
+                owner: key,
+                max_supply: 10,
+            },
+            ContractInstruction::GrantMint {
+                user: key,
+                game_id: game_id(),
+                token_uri: "ipfs://arena".to_string(),
+                name: "Arena Pass".to_string(),
+                overwrite: true,
+            },
+            ContractInstruction::Mint {
+                receiver: key,
+                game_id: game_id(),
+            },
+            ContractInstruction::Transfer {
+                token_id: 7,
+                owner: key,
+                receiver: other,
+            },
+            ContractInstruction::Burn { token_id: 7 },
+            ContractInstruction::GetGameTokens {
+                game_id: game_id(),
+                start: 3,
+                limit: 10,
+            },
+            ContractInstruction::SetGameConfig {
+                game_id: game_id(),
+                config: Box::new(GameConfig {
+                    price_amount: 1_000,
+                    max_supply: 100,
+                    price_feed: other,
+                    ..GameConfig::default()
+                }),
+            },
+            ContractInstruction::VerifyAuthentic {
+                token_id: 7,
+                mint: key,
+            },
+            ContractInstruction::SetAllowedUriSchemes { schemes: 3 },
+            ContractInstruction::UpdateTokenUri {
+                token_id: 7,
+                token_uri: "ar://new".to_string(),
+            },
+            ContractInstruction::EnqueueBurn { token_id: 7 },
+            ContractInstruction::ProcessBurnQueue,
+            ContractInstruction::TransferWithPayment {
+                token_id: 7,
+                price: 5_000,
+            },
+            ContractInstruction::BatchGrant {
+                grants: vec![MintPermission {
+                    user: key,
+                    game_id: game_id(),
+                    token_uri: String::new(),
+                    name: String::new(),
+                    minted: 0,
+                }],
+            },
+            ContractInstruction::SetMintingEnabled { enabled: true },
+            ContractInstruction::IsBurned { token_id: 7 },
+            ContractInstruction::SetTransferApprovalThreshold { threshold: 9 },
+            ContractInstruction::RecordSnapshot { snapshot_slot: 12 },
+            ContractInstruction::ClaimSnapshotReward { token_id: 7 },
+            ContractInstruction::SetGameMetadataTemplate {
+                game_id: game_id(),
+                template: GameMetadataTemplate {
+                    name_template: "Arena".to_string(),
+                    ..GameMetadataTemplate::default()
+                },
+            },
+            ContractInstruction::GetContractPda,
+            ContractInstruction::SetTreasurySplit {
+                recipients: vec![TreasuryShare {
+                    recipient: key,
+                    bps: 10_000,
+                }],
+            },
+            ContractInstruction::DisableMinting { token_id: 7 },
+            ContractInstruction::SetKycGate {
+                required: true,
+                authority: key,
+            },
+            ContractInstruction::AttestKyc {
+                subject: key,
+                valid: true,
+            },
+            ContractInstruction::CompactGameTokens { game_id: game_id() },
+            ContractInstruction::ExportState { page: 2 },
+            ContractInstruction::ActivateGame { game_id: game_id() },
+            ContractInstruction::SetDefaultRoyalty {
+                bps: 500,
+                recipient: key,
+            },
+            ContractInstruction::SetGrantCooldown { slots: 10 },
+            ContractInstruction::GetHolderCount { game_id: game_id() },
+            ContractInstruction::SetMaxMetadataRent { lamports: 1_000 },
+            ContractInstruction::ReassignPermission {
+                game_id: game_id(),
+                old_user: key,
+                new_user: other,
+            },
+            ContractInstruction::PeekBatchIds { count: 4 },
+            ContractInstruction::BatchUpdateUris {
+                updates: vec![(7, "ar://seven".to_string()), (8, String::new())],
+            },
+            ContractInstruction::SetAllowedTokenPrograms {
+                programs: vec![spl_token::id()],
+            },
+            ContractInstruction::GetTotalFees,
+            ContractInstruction::VerifyBundleOwnership {
+                token_ids: vec![1, 2, 3],
+                owner: key,
+            },
+            ContractInstruction::BurnExpired { token_id: 7 },
+            ContractInstruction::FreezeTransfersUntil { slot: 100 },
+            ContractInstruction::GetGameConfig { game_id: game_id() },
+            ContractInstruction::RenameGame {
+                old_game_id: game_id(),
+                new_game_id: "colosseum".to_string(),
+            },
+            ContractInstruction::CanMint {
+                user: key,
+                game_id: game_id(),
+                receiver: other,
+            },
+            ContractInstruction::SetMerkleRoot {
+                game_id: game_id(),
+                root: [7; 32],
+            },
+            ContractInstruction::GetMerkleRoot { game_id: game_id() },
+            ContractInstruction::GetTokenHistory {
+                token_id: 7,
+                start: 1,
+                limit: 5,
+            },
+            ContractInstruction::Bootstrap {
+                owner: key,
+                game_id: game_id(),
+                token_uri: "ipfs://arena".to_string(),
+            },
+            ContractInstruction::ReleaseReserved {
+                game_id: game_id(),
+                amount: 2,
+            },
+            ContractInstruction::RevokeMint {
+                user: key,
+                game_id: game_id(),
+            },
+            ContractInstruction::TransferOwnership { new_owner: key },
+            ContractInstruction::GetTokenRecords {
+                token_ids: vec![4, 5],
+            },
+            ContractInstruction::ProposeChange {
+                change: ProposedChange::SetTimelock { slots: 30 },
+            },
+            ContractInstruction::ExecuteChange,
+            ContractInstruction::GetDashboard,
+            ContractInstruction::BatchMint {
+                receiver: key,
+                game_id: game_id(),
+                count: 12,
+            },
+            ContractInstruction::CloseContract {
+                force: true,
+                pdas: vec![
+                    ProgramPda::TokenRecord { token_id: 3 },
+                    ProgramPda::Permission {
+                        user: key,
+                        game_id: game_id(),
+                    },
+                ],
+            },
+            ContractInstruction::SetPaused { paused: true },
+        ]
+    }
+
+    #[test]
+    fn test_packed_instructions_round_trip_through_unpack() {
+        for (variant, instruction) in one_of_each_instruction().into_iter().enumerate() {
+            let data = instruction.pack();
+            assert_eq!(data[0] as usize, variant, "{:?}", instruction);
+            assert_eq!(ContractInstruction::unpack(&data).unwrap(), instruction);
+        }
+    }
+
+    #[test]
+    fn test_unpack_rejects_trailing_bytes_and_bad_flags() {
+        for instruction in one_of_each_instruction() {
+            let mut data = instruction.pack();
+            data.push(0);
+            assert_eq!(
+                ContractInstruction::unpack(&data).err(),
+                Some(CustomError::InvalidInstruction.into()),
+                "{:?}",
+                instruction
+            );
+        }
+
+        // Flags only take 0 or 1, given with the offset of each flag
+        let flagged = [
+            (ContractInstruction::SetMintingEnabled { enabled: true }, 1),
+            (
+                ContractInstruction::SetKycGate {
+                    required: true,
+                    authority: Pubkey::new_unique(),
+                },
+                1,
+            ),
+            (
+                ContractInstruction::AttestKyc {
+                    subject: Pubkey::new_unique(),
+                    valid: true,
+                },
+                33,
+            ),
+            (
+                ContractInstruction::CloseContract {
+                    force: true,
+                    pdas: vec![],
+                },
+                1,
+            ),
+            (ContractInstruction::SetPaused { paused: true }, 1),
+        ];
+        for (instruction, flag_at) in flagged {
+            let mut data = instruction.pack();
+            assert_eq!(data[flag_at], 1);
+            data[flag_at] = 2;
+            assert_eq!(
+                ContractInstruction::unpack(&data).err(),
+                Some(CustomError::InvalidInstruction.into()),
+                "{:?}",
+                instruction
+            );
+        }
+        let mut grant = ContractInstruction::GrantMint {
+            user: Pubkey::new_unique(),
+            game_id: "arena".to_string(),
+            token_uri: String::new(),
+            name: String::new(),
+            overwrite: true,
+        }
+        .pack();
+        *grant.last_mut().unwrap() = 2;
+        assert_eq!(
+            ContractInstruction::unpack(&grant).err(),
+            Some(CustomError::InvalidInstruction.into())
+        );
+    }
+
+    #[test]
+    fn test_builders_match_hand_encoded_layout() {
+        let (contract, user) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let ix = build_grant_mint(
+            &PROGRAM_ID,
+            &contract,
+            &user,
+            &user,
+            "arena",
+            "ipfs://a",
+            "",
+            false,
+        );
+        let mut data = vec![1];
+        data.extend_from_slice(user.as_ref());
+        data.extend_from_slice(&5u32.to_le_bytes());
+        data.extend_from_slice(b"arena");
+        data.extend_from_slice(&8u32.to_le_bytes());
+        data.extend_from_slice(b"ipfs://a");
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.push(0);
+        assert_eq!(ix.data, data);
+        assert_eq!(ix.accounts[2].pubkey, permission_pda(&user, "arena"));
+        assert_eq!(ix.accounts[3].pubkey, game_pda("arena"));
+
+        let ix = build_burn(&PROGRAM_ID, &contract, 7, "arena", &user, &user, 0);
+        assert_eq!(ix.data, [&[4][..], &7u64.to_le_bytes()].concat());
+        assert_eq!(ix.accounts[1].pubkey, mint_pda(7));
+        assert_eq!(ix.accounts[6].pubkey, holding_pda("arena", &user));
+    }
+
+    #[test]
+    fn test_builders_cover_every_instruction() {
+        let (contract, user) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let config = GameConfig::default();
+        let template = GameMetadataTemplate::default();
+        let change = ProposedChange::SetTimelock { slots: 10 };
+        let pdas = [ProgramPda::TokenRecord { token_id: 1 }];
+        let ixs = [
+            build_initialize_contract(&PROGRAM_ID, &contract, &user, 0),
+            build_grant_mint(&PROGRAM_ID, &contract, &user, &user, "arena", "", "", false),
+            build_mint(&PROGRAM_ID, &contract, &user, &user, &user, "arena", 1, 0),
+            build_transfer(
+                &PROGRAM_ID,
+                &contract,
+                1,
+                "arena",
+                &user,
+                &user,
+                &user,
+                &user,
+                0,
+            ),
+            build_burn(&PROGRAM_ID, &contract, 1, "arena", &user, &user, 0),
+            build_get_game_tokens(&PROGRAM_ID, "arena", 0, 10),
+            build_set_game_config(&PROGRAM_ID, &contract, &user, "arena", &config),
+            build_verify_authentic(&PROGRAM_ID, 1, &mint_pda(1)),
+            build_set_allowed_uri_schemes(&PROGRAM_ID, &contract, &user, 0),
+            build_update_token_uri(&PROGRAM_ID, &contract, &user, 1, ""),
+            build_enqueue_burn(&PROGRAM_ID, &contract, 1, &user, &user),
+            build_process_burn_queue(&PROGRAM_ID, &contract, &user, &[]),
+            build_transfer_with_payment(
+                &PROGRAM_ID,
+                &contract,
+                1,
+                0,
+                "arena",
+                &user,
+                &user,
+                &user,
+                &user,
+                0,
+            ),
+            build_batch_grant(&PROGRAM_ID, &contract, &user, &[]),
+            build_set_minting_enabled(&PROGRAM_ID, &contract, &user, true),
+            build_is_burned(&PROGRAM_ID, &contract, 1),
+            build_set_transfer_approval_threshold(&PROGRAM_ID, &contract, &user, 0),
+            build_record_snapshot(&PROGRAM_ID, &contract, &user, 5, &[]),
+            build_claim_snapshot_reward(&PROGRAM_ID, 5, 1, &user),
+            build_set_game_metadata_template(&PROGRAM_ID, &contract, &user, "arena", &template),
+            build_get_contract_pda(&PROGRAM_ID),
+            build_set_treasury_split(&PROGRAM_ID, &contract, &user, &[]),
+            build_disable_minting(&PROGRAM_ID, &contract, &user, 1),
+            build_set_kyc_gate(&PROGRAM_ID, &contract, &user, true, &user),
+            build_attest_kyc(&PROGRAM_ID, &contract, &user, &user, true),
+            build_compact_game_tokens(&PROGRAM_ID, &contract, &user, "arena", 1, &[]),
+            build_export_state(&PROGRAM_ID, &contract, 0),
+            build_activate_game(&PROGRAM_ID, &contract, &user, "arena"),
+            build_set_default_royalty(&PROGRAM_ID, &contract, &user, 0, &user),
+            build_set_grant_cooldown(&PROGRAM_ID, &contract, &user, 0),
+            build_get_holder_count(&PROGRAM_ID, "arena"),
+            build_set_max_metadata_rent(&PROGRAM_ID, &contract, &user, 0),
+            build_reassign_permission(&PROGRAM_ID, &contract, &user, "arena", &user, &contract),
+            build_peek_batch_ids(&PROGRAM_ID, &contract, 1),
+            build_batch_update_uris(&PROGRAM_ID, &contract, &user, &[]),
+            build_set_allowed_token_programs(&PROGRAM_ID, &contract, &user, &[]),
+            build_get_total_fees(&PROGRAM_ID, &contract),
+            build_verify_bundle_ownership(&PROGRAM_ID, &[1], &user),
+            build_burn_expired(&PROGRAM_ID, &contract, 1, "arena", &user, &user, 0, &user),
+            build_freeze_transfers_until(&PROGRAM_ID, &contract, &user, 0),
+            build_get_game_config(&PROGRAM_ID, "arena"),
+            build_rename_game(&PROGRAM_ID, &contract, &user, "arena", "racer", &[user]),
+            build_can_mint(&PROGRAM_ID, &contract, &user, "arena", &user),
+            build_set_merkle_root(&PROGRAM_ID, &contract, &user, "arena", &[0; 32]),
+            build_get_merkle_root(&PROGRAM_ID, "arena"),
+            build_get_token_history(&PROGRAM_ID, 1, 0, 10),
+            build_bootstrap(&PROGRAM_ID, &contract, &user, "arena", ""),
+            build_release_reserved(&PROGRAM_ID, &contract, &user, "arena", 1),
+            build_revoke_mint(&PROGRAM_ID, &contract, &user, &user, "arena"),
+            build_transfer_ownership(&PROGRAM_ID, &contract, &user, &user),
+            build_get_token_records(&PROGRAM_ID, &[1]),
+            build_propose_change(&PROGRAM_ID, &contract, &user, &change),
+            build_execute_change(&PROGRAM_ID, &contract, &user),
+            build_get_dashboard(&PROGRAM_ID, &contract),
+            build_batch_mint(&PROGRAM_ID, &contract, &user, &user, "arena", 1, &[]),
+            build_close_contract(&PROGRAM_ID, &contract, &user, &user, false, &pdas),
+            build_set_paused(&PROGRAM_ID, &contract, &user, true),
+        ];
+        for (discriminant, ix) in ixs.iter().enumerate() {
+            assert_eq!(ix.data[0] as usize, discriminant);
+            ContractInstruction::unpack(&ix.data).unwrap();
+        }
+
+        // Paged reads pass exactly the chunks covering the page
+        assert_eq!(
+            build_get_game_tokens(&PROGRAM_ID, "arena", 25, 10),
+            get_game_tokens_ix("arena", 25, 10, &[0, 1])
+        );
+        assert_eq!(
+            build_get_game_tokens(&PROGRAM_ID, "arena", 0, 0),
+            get_game_tokens_ix("arena", 0, 0, &[])
+        );
+        assert_eq!(
+            build_get_token_history(&PROGRAM_ID, 1, 6, 4),
+            get_token_history_ix(1, 6, 4, &[0, 1])
+        );
+        assert_eq!(ixs[17].accounts[2].pubkey, snapshot_pda(5));
+        assert_eq!(ixs[19].accounts[2].pubkey, template_pda("arena"));
+        assert_eq!(ixs[24].accounts[2].pubkey, kyc_pda(&user));
+        assert_eq!(ixs[55].accounts[3].pubkey, record_pda(1));
+    }
+
+    #[test]
+    fn test_contract_max_supply_caps_token_ids() {
+        let mut t = TestContract::with_max_supply(2);
+        let user = Pubkey::new_unique();
+        let contract = t.contract;
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        assert_eq!(t.contract_state().max_supply, 2);
+
+        t.mint_to_user(&user, "arena").unwrap();
+        let peek = Instruction::new_with_bytes(
+            PROGRAM_ID,
+            &ContractInstruction::PeekBatchIds { count: 5 }.pack(),
+            vec![AccountMeta::new_readonly(contract, false)],
+        );
+        t.bank.process(&peek).unwrap();
+        assert_eq!(
+            Vec::<u64>::try_from_slice(&t.bank.return_data()).unwrap(),
+            vec![2]
+        );
+        t.mint_to_user(&user, "arena").unwrap();
+        assert_eq!(t.contract_state().last_token_id, 2);
+
+        assert_eq!(
+            t.mint_to_user(&user, "arena"),
+            Err(CustomError::SupplyExhausted.into())
+        );
+        t.bank
+            .process(&can_mint_ix(&contract, &user, "arena", &user, vec![]))
+            .unwrap();
+        assert_eq!(
+            Option::<u32>::try_from_slice(&t.bank.return_data()).unwrap(),
+            Some(CustomError::SupplyExhausted as u32)
+        );
+        t.bank.process(&peek).unwrap();
+        assert!(Vec::<u64>::try_from_slice(&t.bank.return_data())
+            .unwrap()
+            .is_empty());
+        assert_eq!(t.contract_state().last_token_id, 2);
+    }
+
+    #[test]
+    fn test_contract_max_supply_defaults_to_unlimited() {
+        let owner = Pubkey::new_unique();
+        let mut data = vec![0];
+        data.extend_from_slice(owner.as_ref());
+        assert_eq!(
+            ContractInstruction::unpack(&data).unwrap(),
+            ContractInstruction::InitializeContract {
+                owner,
+                max_supply: 0
+            }
+        );
+
+        let mut t = TestContract::new();
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        for _ in 0..5 {
+            t.mint_to_user(&user, "arena").unwrap();
+        }
+        assert_eq!(t.contract_state().max_supply, 0);
+        assert_eq!(t.contract_state().last_token_id, 5);
+    }
+
+    #[test]
+    fn test_refill_on_burn_frees_capped_supply() {
+        for refill_on_burn in [true, false] {
+            let mut t = TestContract::new();
+            let user = Pubkey::new_unique();
+            let contract = t.contract;
+            t.bank.airdrop(user, 10_000_000_000);
+            t.grant(&user, "arena", "ipfs://arena").unwrap();
+            t.configure(
+                "arena",
+                &GameConfig {
+                    max_supply: 1,
+                    refill_on_burn,
+                    ..GameConfig::default()
+                },
+            )
+            .unwrap();
+            let (_, token_account) = t.mint_to_user(&user, "arena").unwrap();
+            assert_eq!(
+                t.mint_to_user(&user, "arena"),
+                Err(CustomError::SupplyExhausted.into())
+            );
+
+            t.bank
+                .process(&burn_ix(&contract, 1, "arena", &token_account, &user))
+                .unwrap();
+            assert_eq!(
+                t.game("arena").unwrap().minted,
+                if refill_on_burn { 0 } else { 1 }
+            );
+            let remint = t.mint_to_user(&user, "arena");
+            if refill_on_burn {
+                remint.unwrap();
+                assert_eq!(t.game("arena").unwrap().minted, 1);
+            } else {
+                assert_eq!(remint, Err(CustomError::SupplyExhausted.into()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_mint_signs_as_the_mint_authority_pda() {
+        let mut t = TestContract::new();
+        let contract = t.contract;
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+
+        // The contract account can't sign, so it can't stand in for the authority
+        let token_account = t.bank.allocate_token_account();
+        let mut ix = mint_ix(&contract, 1, &token_account, &user, &user, "arena", 0);
+        ix.accounts[5] = AccountMeta::new_readonly(contract, false);
+        assert_eq!(t.bank.process(&ix), Err(ProgramError::InvalidSeeds));
+
+        let (mint, token_account) = t.mint_to_user(&user, "arena").unwrap();
+        let state = Mint::unpack(t.bank.data(&mint)).unwrap();
+        assert_eq!(
+            state.mint_authority,
+            COption::Some(pda(&[MINT_AUTHORITY_SEED, mint.as_ref()]))
+        );
+        assert_eq!((state.supply, state.decimals), (1, 0));
+        assert_eq!(t.bank.token_balance(&token_account), 1);
+        let account = Account::unpack(t.bank.data(&token_account)).unwrap();
+        assert_eq!((account.mint, account.owner), (mint, user));
+    }
+
+    fn batch_mint_ix(
+        t: &mut TestContract,
+        user: &Pubkey,
+        game_id: &str,
+        count: u64,
+    ) -> Instruction {
+        let first_token_id = t.contract_state().last_token_id + 1;
+        let token_count = t.game(game_id).map_or(0, |g| g.token_count);
+        let tokens: Vec<(Pubkey, u32)> = (0..count)
+            .map(|offset| {
+                (
+                    t.bank.allocate_token_account(),
+                    ((token_count + offset) / GAME_TOKENS_PER_CHUNK) as u32,
+                )
+            })
+            .collect();
+        build_batch_mint(
+            &PROGRAM_ID,
+            &t.contract,
+            user,
+            user,
+            game_id,
+            first_token_id,
+            &tokens,
+        )
     }
-}
 
-// Entry point
-entrypoint!(process_instruction);
-fn process_instruction(
-    program_id: &Pubkey,
-    accounts: &[AccountInfo],
-    instruction_data: &[u8],
-) -> ProgramResult {
-    let instruction = ContractInstruction::unpack(instruction_data)?;
+    #[test]
+    fn test_batch_mint_mints_consecutive_tokens() {
+        let mut t = TestContract::new();
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        t.mint_to_user(&user, "arena").unwrap();
 
-    match instruction {
-        ContractInstruction::InitializeContract { owner } => {
-            initialize_contract(program_id, accounts, owner)
+        let ix = batch_mint_ix(&mut t, &user, "arena", 3);
+        t.bank.process(&ix).unwrap();
+        assert_eq!(t.contract_state().last_token_id, 4);
+        assert_eq!(t.game("arena").unwrap().token_count, 4);
+        for token_id in 2..=4 {
+            let record: TokenRecord = t.bank.state(&record_pda(token_id));
+            assert_eq!((record.owner, record.game_id.as_str()), (user, "arena"));
         }
-        ContractInstruction::GrantMint {
+        let mints = t
+            .bank
+            .logs()
+            .iter()
+            .filter(|line| line.starts_with("COC_EVENT:MINT:"))
+            .count();
+        assert_eq!(mints, 3);
+
+        let mut too_many = batch_mint_ix(&mut t, &user, "arena", 1);
+        too_many.data = ContractInstruction::BatchMint {
+            receiver: user,
+            game_id: "arena".to_string(),
+            count: MAX_BATCH_MINT + 1,
+        }
+        .pack();
+        assert_eq!(
+            t.bank.process(&too_many),
+            Err(CustomError::InvalidInstruction.into())
+        );
+    }
+
+    #[test]
+    fn test_batch_mint_past_supply_cap_mints_nothing() {
+        let mut t = TestContract::with_max_supply(3);
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        t.mint_to_user(&user, "arena").unwrap();
+
+        let ix = batch_mint_ix(&mut t, &user, "arena", 3);
+        assert_eq!(
+            t.bank.process(&ix),
+            Err(CustomError::SupplyExhausted.into())
+        );
+        assert_eq!(t.contract_state().last_token_id, 1);
+        assert_eq!(t.game("arena").unwrap().token_count, 1);
+
+        let ix = batch_mint_ix(&mut t, &user, "arena", 2);
+        t.bank.process(&ix).unwrap();
+        assert_eq!(t.contract_state().last_token_id, 3);
+    }
+
+    #[test]
+    fn test_custom_error_codes_decode_and_display() {
+        let mut messages = vec![];
+        for code in 0.. {
+            let Some(error) = CustomError::from_u32(code) else {
+                break;
+            };
+            messages.push(error.to_string());
+            assert_eq!(ProgramError::from(error), ProgramError::Custom(code));
+        }
+        assert_eq!(messages.len(), CustomError::ContractPaused as usize + 1);
+        assert_eq!(CustomError::from_u32(messages.len() as u32), None);
+        assert_eq!(
+            messages[CustomError::NotAdmin as usize],
+            "caller is not the contract admin"
+        );
+        assert_eq!(
+            messages[CustomError::SupplyExhausted as usize],
+            "supply is exhausted"
+        );
+        assert!(messages.iter().all(|message| !message.is_empty()));
+        let mut distinct = messages.clone();
+        distinct.sort();
+        distinct.dedup();
+        assert_eq!(distinct.len(), messages.len());
+    }
+
+    fn close_contract_ix(
+        contract: &Pubkey,
+        admin: &Pubkey,
+        destination: &Pubkey,
+        force: bool,
+        pdas: &[ProgramPda],
+    ) -> Instruction {
+        build_close_contract(&PROGRAM_ID, contract, admin, destination, force, pdas)
+    }
+
+    #[test]
+    fn test_close_contract_reclaims_rent() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let user = Pubkey::new_unique();
+        let destination = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.bank.airdrop(destination, 1);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        let (_, token_account) = t.mint_to_user(&user, "arena").unwrap();
+        let permission = permission_pda(&user, "arena");
+        let permission_pdas = [ProgramPda::Permission {
             user,
-            game_id,
-            token_uri,
-        } => grant_mint(program_id, accounts, user, game_id, token_uri),
-        ContractInstruction::Mint { receiver, game_id } => {
-            mint(program_id, accounts, receiver, game_id)
+            game_id: "arena".to_string(),
+        }];
+
+        assert_eq!(
+            t.bank.process(&close_contract_ix(
+                &contract,
+                &user,
+                &destination,
+                true,
+                &[]
+            )),
+            Err(CustomError::NotAdmin.into())
+        );
+        assert_eq!(
+            t.bank
+                .process(&close_contract_ix(&contract, &admin, &contract, true, &[])),
+            Err(CustomError::InvalidInstruction.into())
+        );
+        assert_eq!(
+            t.bank.process(&close_contract_ix(
+                &contract,
+                &admin,
+                &destination,
+                false,
+                &permission_pdas
+            )),
+            Err(CustomError::TokensOutstanding.into())
+        );
+        t.bank
+            .process(&burn_ix(&contract, 1, "arena", &token_account, &user))
+            .unwrap();
+
+        let reclaimed = t.bank.lamports(&contract) + t.bank.lamports(&permission);
+        t.bank
+            .process(&close_contract_ix(
+                &contract,
+                &admin,
+                &destination,
+                false,
+                &permission_pdas,
+            ))
+            .unwrap();
+        assert_eq!(t.bank.lamports(&destination), 1 + reclaimed);
+        for closed in [contract, permission] {
+            assert_eq!(t.bank.lamports(&closed), 0);
+            assert!(t.bank.data(&closed).is_empty());
         }
-        ContractInstruction::Transfer {
-            token_id,
-            owner,
-            receiver,
-        } => transfer(program_id, accounts, token_id, owner, receiver),
-        ContractInstruction::Burn { token_id } => burn(program_id, accounts, token_id),
     }
-}
 
-fn initialize_contract(
-    program_id: &Pubkey,
-    accounts: &[AccountInfo],
-    owner: Pubkey,
-) -> ProgramResult {
-    let account_info_iter = &mut accounts.iter();
-    let contract_account = next_account_info(account_info_iter)?;
+    #[test]
+    fn test_force_close_contract_with_live_tokens() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        t.mint_to_user(&user, "arena").unwrap();
 
-    let rent = &Rent::from_account_info(next_account_info(account_info_iter)?)?;
-    if !rent.is_exempt(contract_account.lamports(), contract_account.data_len()) {
-        return Err(ProgramError::AccountNotRentExempt);
+        let rent = t.bank.lamports(&contract);
+        let before = t.bank.lamports(&admin);
+        t.bank
+            .process(&close_contract_ix(&contract, &admin, &admin, true, &[]))
+            .unwrap();
+        assert_eq!(t.bank.lamports(&admin), before + rent);
+        assert!(t.bank.data(&contract).is_empty());
     }
 
-    let mut contract_state = ContractState::try_from_slice(&contract_account.data.borrow())?;
-    contract_state.contract_owner = owner;
-    contract_state.last_token_id = 0;
-    contract_state.serialize(&mut &mut contract_account.data.borrow_mut()[..])?;
+    #[test]
+    fn test_only_the_permitted_minter_can_mint() {
+        let mut t = TestContract::new();
+        let contract = t.contract;
+        let user = Pubkey::new_unique();
+        let intruder = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.bank.airdrop(intruder, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
 
-    Ok(())
-}
+        // Another signer can't mint under the user's permission
+        let token_account = t.bank.allocate_token_account();
+        let mut ix = mint_ix(
+            &contract,
+            1,
+            &token_account,
+            &intruder,
+            &intruder,
+            "arena",
+            0,
+        );
+        ix.accounts[3] = AccountMeta::new(permission_pda(&user, "arena"), false);
+        assert_eq!(
+            t.bank.process(&ix),
+            Err(CustomError::MintNotPermitted.into())
+        );
 
-fn grant_mint(
-    program_id: &Pubkey,
-    accounts: &[AccountInfo],
-    user: Pubkey,
-    game_id: String,
-    token_uri: String,
-) -> ProgramResult {
-    let account_info_iter = &mut accounts.iter();
-    let contract_account = next_account_info(account_info_iter)?;
-    let admin_account = next_account_info(account_info_iter)?;
+        // nor can the user's key without its signature
+        let ix = mint_ix(&contract, 1, &token_account, &user, &user, "arena", 0);
+        assert_eq!(
+            t.bank.process(&unsigned(ix, 4)),
+            Err(ProgramError::MissingRequiredSignature)
+        );
 
-    let mut contract_state = ContractState::try_from_slice(&contract_account.data.borrow())?;
-    if contract_state.contract_owner != *admin_account.key {
-        return Err(CustomError::NotAdmin.into());
+        t.mint_to_user(&user, "arena").unwrap();
+        let record: TokenRecord = t.bank.state(&record_pda(1));
+        assert_eq!(record.owner, user);
     }
 
-    let mint_permission = MintPermission {
-        user,
-        game_id,
-        token_uri,
-    };
-    mint_permission.serialize(&mut &mut contract_account.data.borrow_mut()[..])?;
+    // Points every classic SPL Token account and program reference of `ix`
+    // at Token-2022
+    fn into_token_2022(t: &mut TestContract, mut ix: Instruction) -> Instruction {
+        for meta in ix.accounts.iter_mut() {
+            if meta.pubkey == spl_token::id() {
+                meta.pubkey = TOKEN_2022_PROGRAM_ID;
+            } else if let Some(account) = t.bank.accounts.get_mut(&meta.pubkey) {
+                if account.owner == spl_token::id() {
+                    account.owner = TOKEN_2022_PROGRAM_ID;
+                }
+            }
+        }
+        ix
+    }
 
-    Ok(())
-}
+    #[test]
+    fn test_mint_transfer_and_burn_on_either_token_program() {
+        for token_program in [spl_token::id(), TOKEN_2022_PROGRAM_ID] {
+            let mut t = TestContract::new();
+            let (contract, admin) = (t.contract, t.admin);
+            let user = Pubkey::new_unique();
+            let receiver = Pubkey::new_unique();
+            t.bank.airdrop(user, 10_000_000_000);
+            t.bank.airdrop(receiver, 10_000_000_000);
+            t.grant(&user, "arena", "ipfs://arena").unwrap();
+            t.bank
+                .process(&set_allowed_token_programs_ix(
+                    &contract,
+                    &admin,
+                    &[spl_token::id(), TOKEN_2022_PROGRAM_ID],
+                ))
+                .unwrap();
+            let on_program = |t: &mut TestContract, ix: Instruction| {
+                if token_program == TOKEN_2022_PROGRAM_ID {
+                    into_token_2022(t, ix)
+                } else {
+                    ix
+                }
+            };
 
-fn mint(
-    program_id: &Pubkey,
-    accounts: &[AccountInfo],
-    receiver: Pubkey,
-    game_id: String,
-) -> ProgramResult {
-    let account_info_iter = &mut accounts.iter();
-    let contract_account = next_account_info(account_info_iter)?;
-    let mint_account = next_account_info(account_info_iter)?;
-    let receiver_account = next_account_info(account_info_iter)?;
+            let token = t.bank.allocate_token_account();
+            let ix = mint_ix(&contract, 1, &token, &user, &user, "arena", 0);
+            let ix = on_program(&mut t, ix);
+            t.bank.process(&ix).unwrap();
+            let mint = mint_pda(1);
+            assert_eq!(t.bank.accounts[&mint].owner, token_program);
+            assert_eq!(t.bank.accounts[&token].owner, token_program);
+            assert_eq!(t.bank.token_balance(&token), 1);
 
-    let mut contract_state = ContractState::try_from_slice(&contract_account.data.borrow())?;
-    let mint_permission = MintPermission::try_from_slice(&contract_account.data.borrow())?;
+            let receiver_token = t.bank.create_token_account(&mint, &receiver);
+            let ix = transfer_ix(
+                &contract,
+                1,
+                "arena",
+                &token,
+                &receiver_token,
+                &user,
+                &receiver,
+            );
+            let ix = on_program(&mut t, ix);
+            t.bank.process(&ix).unwrap();
+            assert_eq!(t.bank.token_balance(&receiver_token), 1);
 
-    if mint_permission.user != *contract_account.key || mint_permission.game_id != game_id {
-        return Err(CustomError::MintNotPermitted.into());
+            let ix = burn_ix(&contract, 1, "arena", &receiver_token, &receiver);
+            let ix = on_program(&mut t, ix);
+            t.bank.process(&ix).unwrap();
+            assert_eq!(t.bank.token_balance(&receiver_token), 0);
+            assert_eq!(Mint::unpack(t.bank.data(&mint)).unwrap().supply, 0);
+        }
     }
 
-    let token_id = contract_state.last_token_id + 1;
-    contract_state.last_token_id = token_id;
-    contract_state.serialize(&mut &mut contract_account.data.borrow_mut()[..])?;
+    #[test]
+    fn test_one_user_holds_permissions_for_several_games() {
+        let mut t = TestContract::new();
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        t.grant(&user, "relic", "ipfs://relic").unwrap();
 
-    invoke(
-        &mint_to(
-            program_id,
-            &mint_account.key,
-            &receiver_account.key,
-            &contract_account.key,
-            &[],
+        // Each grant lives in its own (user, game_id) PDA, so neither replaces the other
+        for (game_id, token_uri) in [("arena", "ipfs://arena"), ("relic", "ipfs://relic")] {
+            let permission: MintPermission = t.bank.state(&permission_pda(&user, game_id));
+            assert_eq!(
+                (permission.game_id.as_str(), permission.token_uri.as_str()),
+                (game_id, token_uri)
+            );
+        }
+
+        t.mint_to_user(&user, "arena").unwrap();
+        t.mint_to_user(&user, "relic").unwrap();
+        t.mint_to_user(&user, "arena").unwrap();
+        let games: Vec<String> = (1..=3)
+            .map(|token_id| t.bank.state::<TokenRecord>(&record_pda(token_id)).game_id)
+            .collect();
+        assert_eq!(games, ["arena", "relic", "arena"]);
+        for (game_id, minted) in [("arena", 2), ("relic", 1)] {
+            let permission: MintPermission = t.bank.state(&permission_pda(&user, game_id));
+            assert_eq!(permission.minted, minted);
+        }
+    }
+
+    #[test]
+    fn test_token_record_resolves_to_the_granted_uri() {
+        let mut t = TestContract::new();
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://bafy/arena.json").unwrap();
+        t.mint_to_user(&user, "arena").unwrap();
+
+        // A client only needs the token id
+        let (record_address, _) = token_record_address(&PROGRAM_ID, 1);
+        let data = t.bank.data(&record_address);
+        assert_eq!(data[0], TOKEN_RECORD_VERSION);
+        let record = TokenRecord::deserialize(&mut &data[..]).unwrap();
+        assert_eq!(record.token_id, 1);
+        assert_eq!(record.uri_account, token_uri_address(&PROGRAM_ID, 1).0);
+        let uri: TokenUri = t.bank.state(&record.uri_account);
+        assert_eq!(uri.uri, "ipfs://bafy/arena.json");
+    }
+
+    #[test]
+    fn test_initialize_contract_runs_once() {
+        let mut bank = TestBank::new();
+        let contract = contract_pda();
+        let (owner, squatter) = (Pubkey::new_unique(), Pubkey::new_unique());
+        bank.deploy(&owner);
+        bank.airdrop(owner, 10_000_000_000);
+        bank.airdrop(squatter, 10_000_000_000);
+        // only the upgrade authority can claim the deployment
+        assert_eq!(
+            bank.process(&initialize_ix(&contract, &squatter)),
+            Err(CustomError::NotAdmin.into())
+        );
+        // and only at the contract PDA
+        let elsewhere = Pubkey::new_unique();
+        bank.set_account(elsewhere, PROGRAM_ID, vec![0; ContractState::LEN]);
+        assert_eq!(
+            bank.process(&initialize_ix(&elsewhere, &owner)),
+            Err(ProgramError::InvalidSeeds)
+        );
+        assert!(!bank.accounts.contains_key(&contract));
+        bank.process(&initialize_ix(&contract, &owner)).unwrap();
+        let state: ContractState = bank.state(&contract);
+        assert!(state.is_initialized());
+        assert_eq!(state.contract_owner, owner);
+
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        t.mint_to_user(&user, "arena").unwrap();
+
+        // Neither an attacker nor the owner can reset the contract
+        for caller in [user, admin] {
+            assert_eq!(
+                t.bank.process(&initialize_ix(&contract, &caller)),
+                Err(CustomError::AlreadyInitialized.into())
+            );
+        }
+        let state = t.contract_state();
+        assert_eq!((state.contract_owner, state.last_token_id), (admin, 1));
+    }
+
+    #[test]
+    fn test_mint_initializes_the_receiver_account_only_when_needed() {
+        let mut t = TestContract::new();
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        t.grant(&user, "dojo", "ipfs://dojo").unwrap();
+
+        // an allocated account is initialized for the new mint
+        let (mint, token_account) = t.mint_to_user(&user, "arena").unwrap();
+        let holding = Account::unpack(t.bank.data(&token_account)).unwrap();
+        assert_eq!(
+            (holding.mint, holding.owner, holding.amount),
+            (mint, user, 1)
+        );
+
+        // one already initialized for the mint goes straight to mint_to
+        let mint = mint_pda(2);
+        let token_account = t.bank.create_token_account(&mint, &user);
+        let ix = mint_ix(&t.contract, 2, &token_account, &user, &user, "dojo", 0);
+        t.bank.process(&ix).unwrap();
+        assert_eq!(t.bank.token_balance(&token_account), 1);
+
+        // one initialized for another mint or owner is rejected
+        for (mint, owner) in [(Pubkey::new_unique(), user), (mint_pda(3), t.admin)] {
+            let token_account = t.bank.create_token_account(&mint, &owner);
+            let ix = mint_ix(&t.contract, 3, &token_account, &user, &user, "dojo", 0);
+            assert_eq!(t.bank.process(&ix), Err(ProgramError::InvalidAccountData));
+        }
+        assert_eq!(t.contract_state().last_token_id, 2);
+    }
+
+    fn set_paused_ix(contract: &Pubkey, admin: &Pubkey, paused: bool) -> Instruction {
+        build_set_paused(&PROGRAM_ID, contract, admin, paused)
+    }
+
+    #[test]
+    fn test_pause_blocks_mints_and_transfers_until_lifted() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let user = Pubkey::new_unique();
+        let receiver = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        let (mint, token_account) = t.mint_to_user(&user, "arena").unwrap();
+        let (_, burnable) = t.mint_to_user(&user, "arena").unwrap();
+        let receiver_token = t.bank.create_token_account(&mint, &receiver);
+        let transfer = transfer_ix(
+            &contract,
             1,
-        )?,
-        &[
-            mint_account.clone(),
-            receiver_account.clone(),
-            contract_account.clone(),
-        ],
-    )?;
+            "arena",
+            &token_account,
+            &receiver_token,
+            &user,
+            &receiver,
+        );
 
-    Ok(())
-}
+        assert_eq!(
+            t.bank.process(&set_paused_ix(&contract, &user, true)),
+            Err(CustomError::NotAdmin.into())
+        );
+        t.bank
+            .process(&set_paused_ix(&contract, &admin, true))
+            .unwrap();
+        assert!(t.contract_state().paused);
 
-fn transfer(
-    program_id: &Pubkey,
-    accounts: &[AccountInfo],
-    token_id: u64,
-    owner: Pubkey,
-    receiver: Pubkey,
-) -> ProgramResult {
-    let account_info_iter = &mut accounts.iter();
-    let owner_account = next_account_info(account_info_iter)?;
-    let receiver_account = next_account_info(account_info_iter)?;
+        assert_eq!(
+            t.mint_to_user(&user, "arena"),
+            Err(CustomError::ContractPaused.into())
+        );
+        let batch = batch_mint_ix(&mut t, &user, "arena", 2);
+        assert_eq!(
+            t.bank.process(&batch),
+            Err(CustomError::ContractPaused.into())
+        );
+        assert_eq!(
+            t.bank.process(&transfer),
+            Err(CustomError::ContractPaused.into())
+        );
+        t.bank
+            .process(&can_mint_ix(&contract, &user, "arena", &user, vec![]))
+            .unwrap();
+        assert_eq!(
+            Option::<u32>::try_from_slice(&t.bank.return_data()).unwrap(),
+            Some(CustomError::ContractPaused as u32)
+        );
+        t.bank
+            .process(&build_get_dashboard(&PROGRAM_ID, &contract))
+            .unwrap();
+        assert!(
+            Dashboard::try_from_slice(&t.bank.return_data())
+                .unwrap()
+                .paused
+        );
 
-    if *owner_account.key != owner {
-        return Err(CustomError::NotTokenOwner.into());
+        // burns and admin instructions still go through
+        t.bank
+            .process(&burn_ix(&contract, 2, "arena", &burnable, &user))
+            .unwrap();
+        t.configure("arena", &GameConfig::default()).unwrap();
+
+        t.bank
+            .process(&set_paused_ix(&contract, &admin, false))
+            .unwrap();
+        t.bank.process(&transfer).unwrap();
+        assert_eq!(t.bank.token_balance(&receiver_token), 1);
+        t.mint_to_user(&user, "arena").unwrap();
+        assert_eq!(t.contract_state().last_token_id, 3);
     }
 
-    invoke(
-        &spl_token::instruction::transfer(
-            program_id,
-            &owner_account.key,
-            &receiver_account.key,
-            &owner_account.key,
-            &[],
+    #[test]
+    fn test_underfunded_pdas_are_not_written() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let user = Pubkey::new_unique();
+        let receiver = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        t.grant(&user, "dojo", "ipfs://dojo").unwrap();
+        let (mint, token_account) = t.mint_to_user(&user, "arena").unwrap();
+        let receiver_token = t.bank.create_token_account(&mint, &receiver);
+        let drain = |bank: &mut TestBank, key: &Pubkey| {
+            let account = bank.accounts.get_mut(key).unwrap();
+            let refund = account.lamports - 1;
+            account.lamports = 1;
+            bank.airdrop(Pubkey::new_unique(), refund);
+        };
+
+        drain(&mut t.bank, &permission_pda(&user, "arena"));
+        let ix = regrant_ix(&contract, &admin, &user, "arena", "ipfs://new", true);
+        assert_eq!(t.bank.process(&ix), Err(ProgramError::AccountNotRentExempt));
+        assert_eq!(
+            t.mint_to_user(&user, "arena"),
+            Err(ProgramError::AccountNotRentExempt)
+        );
+
+        drain(&mut t.bank, &record_pda(1));
+        let ix = transfer_ix(
+            &contract,
             1,
-        )?,
-        &[
-            owner_account.clone(),
-            receiver_account.clone(),
-            owner_account.clone(),
-        ],
-    )?;
+            "arena",
+            &token_account,
+            &receiver_token,
+            &user,
+            &receiver,
+        );
+        assert_eq!(t.bank.process(&ix), Err(ProgramError::AccountNotRentExempt));
+        assert_eq!(t.bank.token_balance(&token_account), 1);
 
-    Ok(())
-}
+        // PDAs that kept their rent are unaffected
+        t.mint_to_user(&user, "dojo").unwrap();
+        assert_eq!(t.contract_state().last_token_id, 2);
+    }
 
-fn burn(program_id: &Pubkey, accounts: &[AccountInfo], token_id: u64) -> ProgramResult {
-    let account_info_iter = &mut accounts.iter();
-    let owner_account = next_account_info(account_info_iter)?;
-    let mint_account = next_account_info(account_info_iter)?;
+    fn assert_invalid_or_decoded(data: &[u8]) {
+        if let Err(err) = ContractInstruction::unpack(data) {
+            assert_eq!(err, CustomError::InvalidInstruction.into(), "{:?}", data);
+        }
+    }
 
-    invoke(
-        &spl_burn(
-            program_id,
-            &mint_account.key,
-            &owner_account.key,
-            &owner_account.key,
-            &[],
-            token_id,
-        )?,
-        &[
-            mint_account.clone(),
-            owner_account.clone(),
-            owner_account.clone(),
-        ],
-    )?;
+    #[test]
+    fn test_malformed_instruction_data_is_rejected_without_panicking() {
+        let invalid = Err(CustomError::InvalidInstruction.into());
+        assert_eq!(ContractInstruction::unpack(&[]), invalid);
+        let variants = one_of_each_instruction().len();
+        for variant in variants..=u8::MAX as usize {
+            assert_eq!(ContractInstruction::unpack(&[variant as u8, 0, 0]), invalid);
+        }
 
-    Ok(())
-}
+        // every truncation of a valid encoding either decodes (trailing fields
+        // can be optional) or is rejected
+        for instruction in one_of_each_instruction() {
+            let data = instruction.pack();
+            for end in 0..data.len() {
+                assert_invalid_or_decoded(&data[..end]);
+            }
+        }
+        // a string length running past the end of the data
+        let mut data = vec![1];
+        data.extend_from_slice(Pubkey::new_unique().as_ref());
+        data.extend_from_slice(&u32::MAX.to_le_bytes());
+        data.extend_from_slice(b"arena");
+        assert_eq!(ContractInstruction::unpack(&data), invalid);
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use solana_program::{
-        instruction::{AccountMeta, Instruction},
-        program_pack::Pack,
-        rent::Rent,
-        sysvar,
-    };
-    use std::convert::TryInto;
+        // xorshift, so failures reproduce
+        let mut seed = 0x9e37_79b9_7f4a_7c15_u64;
+        let mut next = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+        for _ in 0..5_000 {
+            let len = (next() % 80) as usize;
+            let mut data: Vec<u8> = (0..len).map(|_| next() as u8).collect();
+            if let Some(variant) = data.first_mut() {
+                *variant %= variants as u8 + 2;
+            }
+            assert_invalid_or_decoded(&data);
+        }
+
+        // and through the entrypoint, leaving the contract untouched
+        let mut t = TestContract::new();
+        let before = t.bank.data(&t.contract).to_vec();
+        for data in [vec![], vec![2], vec![0, 1, 2], vec![u8::MAX]] {
+            let ix = Instruction::new_with_bytes(
+                PROGRAM_ID,
+                &data,
+                vec![AccountMeta::new(t.contract, false)],
+            );
+            assert_eq!(
+                t.bank.process(&ix),
+                Err(CustomError::InvalidInstruction.into())
+            );
+        }
+        assert_eq!(t.bank.data(&t.contract), before);
+    }
+
+    #[test]
+    fn test_initialize_grant_mint_transfer_and_burn_end_to_end() {
+        let mut bank = TestBank::new();
+        let contract = contract_pda();
+        let (owner, player, friend) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        bank.airdrop(owner, 10_000_000_000);
+        bank.airdrop(player, 10_000_000_000);
+        bank.airdrop(friend, 10_000_000_000);
+        bank.deploy(&owner);
+
+        bank.process(&build_initialize_contract(
+            &PROGRAM_ID,
+            &contract,
+            &owner,
+            0,
+        ))
+        .unwrap();
+        let state: ContractState = bank.state(&contract);
+        assert!(state.is_initialized());
+        assert_eq!((state.contract_owner, state.last_token_id), (owner, 0));
+
+        bank.process(&build_grant_mint(
+            &PROGRAM_ID,
+            &contract,
+            &owner,
+            &player,
+            "arena",
+            "ipfs://arena",
+            "",
+            false,
+        ))
+        .unwrap();
+        bank.process(&activate_game_ix(&contract, &owner, "arena"))
+            .unwrap();
+        let permission: MintPermission = bank.state(&permission_pda(&player, "arena"));
+        assert_eq!((permission.user, permission.minted), (player, 0));
+
+        let player_token = bank.allocate_token_account();
+        bank.process(&build_mint(
+            &PROGRAM_ID,
+            &contract,
+            &player,
+            &player,
+            &player_token,
+            "arena",
+            1,
+            0,
+        ))
+        .unwrap();
+        let mint = mint_pda(1);
+        assert_eq!(bank.token_balance(&player_token), 1);
+        assert_eq!(bank.state::<TokenRecord>(&record_pda(1)).owner, player);
+        assert_eq!(
+            bank.state::<MintPermission>(&permission_pda(&player, "arena"))
+                .minted,
+            1
+        );
+        let state: ContractState = bank.state(&contract);
+        assert_eq!((state.last_token_id, state.active_tokens), (1, 1));
+
+        let friend_token = bank.create_token_account(&mint, &friend);
+        bank.process(&build_transfer(
+            &PROGRAM_ID,
+            &contract,
+            1,
+            "arena",
+            &player,
+            &player_token,
+            &friend,
+            &friend_token,
+            0,
+        ))
+        .unwrap();
+        assert_eq!(bank.token_balance(&player_token), 0);
+        assert_eq!(bank.token_balance(&friend_token), 1);
+        assert_eq!(bank.state::<TokenRecord>(&record_pda(1)).owner, friend);
+
+        // only the new owner can burn it
+        let burn = |owner: &Pubkey, token: &Pubkey| {
+            build_burn(&PROGRAM_ID, &contract, 1, "arena", owner, token, 0)
+        };
+        assert!(bank.process(&burn(&player, &player_token)).is_err());
+        bank.process(&burn(&friend, &friend_token)).unwrap();
+        assert!(bank.state::<TokenRecord>(&record_pda(1)).burned);
+        let state: ContractState = bank.state(&contract);
+        assert_eq!((state.last_token_id, state.active_tokens), (1, 0));
+        assert_eq!(bank.state::<GameRecord>(&game_pda("arena")).holder_count, 0);
+    }
+
+    // A program-owned account holding a copy of the contract's state with
+    // `owner` as its owner, as anyone can create one
+    fn forge_contract(t: &mut TestContract, owner: &Pubkey) -> Pubkey {
+        let mut state = t.contract_state();
+        state.contract_owner = *owner;
+        let forged = Pubkey::new_unique();
+        let mut data = vec![0; ContractState::LEN];
+        state.serialize(&mut &mut data[..]).unwrap();
+        t.bank.set_account(forged, PROGRAM_ID, data);
+        t.bank.airdrop(*owner, 10_000_000_000);
+        forged
+    }
+
+    #[test]
+    fn test_a_forged_contract_grants_nothing() {
+        let mut t = TestContract::new();
+        let attacker = Pubkey::new_unique();
+        t.grant(&Pubkey::new_unique(), "arena", "ipfs://arena")
+            .unwrap();
+        let forged = forge_contract(&mut t, &attacker);
+
+        let ix = grant_mint_ix(&forged, &attacker, &attacker, "arena", "ipfs://evil");
+        assert_eq!(t.bank.process(&ix), Err(ProgramError::InvalidSeeds));
+        assert!(!t
+            .bank
+            .accounts
+            .contains_key(&permission_pda(&attacker, "arena")));
+        assert_eq!(
+            t.mint_to_user(&attacker, "arena"),
+            Err(CustomError::MintNotPermitted.into())
+        );
+
+        // nor can state be read from or written through a forged contract
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        let token_account = t.bank.allocate_token_account();
+        let ix = mint_ix(&forged, 1, &token_account, &user, &user, "arena", 0);
+        assert_eq!(t.bank.process(&ix), Err(ProgramError::InvalidSeeds));
+        let ix = build_get_dashboard(&PROGRAM_ID, &forged);
+        assert_eq!(t.bank.process(&ix), Err(ProgramError::InvalidSeeds));
+        let outsider = Pubkey::new_unique();
+        t.bank.set_account(
+            outsider,
+            Pubkey::new_unique(),
+            t.bank.data(&forged).to_vec(),
+        );
+        let ix = build_get_dashboard(&PROGRAM_ID, &outsider);
+        assert_eq!(t.bank.process(&ix), Err(ProgramError::IncorrectProgramId));
+    }
 
+    #[test]
+    fn test_admin_instructions_through_a_forged_contract_are_rejected() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        t.configure(
+            "arena",
+            &GameConfig {
+                price_amount: 5_000_000,
+                ..GameConfig::default()
+            },
+        )
+        .unwrap();
+        let attacker = Pubkey::new_unique();
+        let forged = forge_contract(&mut t, &attacker);
 
+        for ix in [
+            set_game_config_ix(&forged, &attacker, "arena", &GameConfig::default()),
+            revoke_mint_ix(&forged, &attacker, &user, "arena"),
+            set_paused_ix(&forged, &attacker, true),
+            transfer_ownership_ix(&forged, &attacker, &attacker),
+            freeze_transfers_until_ix(&forged, &attacker, 1_000),
+        ] {
+            assert_eq!(t.bank.process(&ix), Err(ProgramError::InvalidSeeds));
+        }
+        assert_eq!(t.game("arena").unwrap().config.price_amount, 5_000_000);
+        assert!(t
+            .bank
+            .accounts
+            .contains_key(&permission_pda(&user, "arena")));
+        let state = t.contract_state();
+        assert_eq!((state.contract_owner, state.paused), (admin, false));
+
+        // the real owner still administers the real contract
+        t.bank
+            .process(&set_paused_ix(&contract, &admin, true))
+            .unwrap();
+        assert!(t.contract_state().paused);
+    }
+
+    // Mints token 1 of `game_id` to a fresh user and opens a token account for
+    // it for a fresh receiver, returning (user, user token, receiver, receiver
+    // token)
+    fn ready_transfer(t: &mut TestContract, game_id: &str) -> (Pubkey, Pubkey, Pubkey, Pubkey) {
+        let (user, receiver) = (Pubkey::new_unique(), Pubkey::new_unique());
+        t.bank.airdrop(user, 10_000_000_000);
+        t.bank.airdrop(receiver, 10_000_000_000);
+        t.grant(&user, game_id, "ipfs://arena").unwrap();
+        let (mint, user_token) = t.mint_to_user(&user, game_id).unwrap();
+        let receiver_token = t.bank.create_token_account(&mint, &receiver);
+        (user, user_token, receiver, receiver_token)
+    }
+
+    #[test]
+    fn test_a_forged_contract_does_not_lift_the_pause() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let (user, user_token, receiver, receiver_token) = ready_transfer(&mut t, "arena");
+        let forged = forge_contract(&mut t, &Pubkey::new_unique());
+        t.bank
+            .process(&set_paused_ix(&contract, &admin, true))
+            .unwrap();
+
+        for (contract, error) in [
+            (forged, ProgramError::InvalidSeeds),
+            (contract, CustomError::ContractPaused.into()),
+        ] {
+            let transfer = transfer_ix(
+                &contract,
+                1,
+                "arena",
+                &user_token,
+                &receiver_token,
+                &user,
+                &receiver,
+            );
+            assert_eq!(t.bank.process(&transfer), Err(error.clone()));
+            let sale = transfer_with_payment_ix(
+                &contract,
+                1,
+                0,
+                "arena",
+                &user_token,
+                &receiver_token,
+                &user,
+                &receiver,
+            );
+            assert_eq!(t.bank.process(&sale), Err(error));
+        }
+        assert_eq!(t.bank.token_balance(&user_token), 1);
+    }
+
+    // Transfers token 1 as set up by `ready_transfer`, with and without a
+    // payment, expecting the forged contract to be refused and the real one's
+    // gate to fail with `error`
+    fn assert_transfer_gated_by_the_real_contract(
+        t: &mut TestContract,
+        forged: &Pubkey,
+        (user, user_token, receiver, receiver_token): (Pubkey, Pubkey, Pubkey, Pubkey),
+        error: ProgramError,
+    ) {
+        let contract = t.contract;
+        for (contract, error) in [(*forged, ProgramError::InvalidSeeds), (contract, error)] {
+            let transfer = transfer_ix(
+                &contract,
+                1,
+                "arena",
+                &user_token,
+                &receiver_token,
+                &user,
+                &receiver,
+            );
+            assert_eq!(t.bank.process(&transfer), Err(error.clone()));
+            let sale = transfer_with_payment_ix(
+                &contract,
+                1,
+                0,
+                "arena",
+                &user_token,
+                &receiver_token,
+                &user,
+                &receiver,
+            );
+            assert_eq!(t.bank.process(&sale), Err(error));
+        }
+        assert_eq!(t.bank.token_balance(&user_token), 1);
+    }
+
+    #[test]
+    fn test_a_forged_contract_does_not_lift_a_transfer_freeze() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let transfer = ready_transfer(&mut t, "arena");
+        let forged = forge_contract(&mut t, &Pubkey::new_unique());
+        t.bank
+            .process(&freeze_transfers_until_ix(&contract, &admin, 1_000))
+            .unwrap();
+        assert_transfer_gated_by_the_real_contract(
+            &mut t,
+            &forged,
+            transfer,
+            CustomError::TransfersFrozen.into(),
+        );
+    }
+
+    #[test]
+    fn test_a_forged_contract_does_not_skip_kyc() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let transfer = ready_transfer(&mut t, "arena");
+        let forged = forge_contract(&mut t, &Pubkey::new_unique());
+        t.bank
+            .process(&set_kyc_gate_ix(
+                &contract,
+                &admin,
+                true,
+                &Pubkey::new_unique(),
+            ))
+            .unwrap();
+        assert_transfer_gated_by_the_real_contract(
+            &mut t,
+            &forged,
+            transfer,
+            CustomError::KycRequired.into(),
+        );
+    }
+
+    #[test]
+    fn test_a_forged_contract_does_not_skip_transfer_approval() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let transfer = ready_transfer(&mut t, "arena");
+        // as if it had been minted for a price above the threshold
+        let mut record: TokenRecord = t.bank.state(&record_pda(1));
+        record.value = 5_000_000;
+        let data = &mut t.bank.accounts.get_mut(&record_pda(1)).unwrap().data;
+        record.serialize(&mut &mut data[..]).unwrap();
+        let forged = forge_contract(&mut t, &Pubkey::new_unique());
+        t.bank
+            .process(&set_transfer_approval_threshold_ix(
+                &contract, &admin, 1_000_000,
+            ))
+            .unwrap();
+        assert_transfer_gated_by_the_real_contract(
+            &mut t,
+            &forged,
+            transfer,
+            CustomError::TransferApprovalRequired.into(),
+        );
+    }
+
+    #[test]
+    fn test_a_forged_contract_does_not_widen_the_token_program_allowlist() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let transfer = ready_transfer(&mut t, "arena");
+        let forged = forge_contract(&mut t, &Pubkey::new_unique());
+        t.bank
+            .process(&set_allowed_token_programs_ix(
+                &contract,
+                &admin,
+                &[TOKEN_2022_PROGRAM_ID],
+            ))
+            .unwrap();
+        assert_transfer_gated_by_the_real_contract(
+            &mut t,
+            &forged,
+            transfer,
+            CustomError::TokenProgramNotAllowed.into(),
+        );
+    }
+
+    #[test]
+    fn test_close_contract_only_closes_the_contracts_own_pdas() {
+        let mut t = TestContract::new();
+        let (contract, admin) = (t.contract, t.admin);
+        let user = Pubkey::new_unique();
+        t.bank.airdrop(user, 10_000_000_000);
+        t.grant(&user, "arena", "ipfs://arena").unwrap();
+        t.mint_to_user(&user, "arena").unwrap();
+        let game = ProgramPda::Game {
+            game_id: "arena".to_string(),
+        };
+        let record = ProgramPda::TokenRecord { token_id: 1 };
+        let game_only = [game.clone()];
+
+        // a forged contract can't reach the real contract's PDAs
+        let attacker = Pubkey::new_unique();
+        let forged = forge_contract(&mut t, &attacker);
+        let ix = close_contract_ix(&forged, &attacker, &attacker, true, &game_only);
+        assert_eq!(t.bank.process(&ix), Err(ProgramError::InvalidSeeds));
+
+        // every account must be the PDA its seeds derive
+        let mut ix = close_contract_ix(&contract, &admin, &admin, true, &game_only);
+        ix.accounts[3].pubkey = record.address(&PROGRAM_ID);
+        assert_eq!(t.bank.process(&ix), Err(ProgramError::InvalidSeeds));
+        let mut ix = close_contract_ix(&contract, &admin, &admin, true, &game_only);
+        ix.accounts
+            .push(AccountMeta::new(record.address(&PROGRAM_ID), false));
+        assert_eq!(
+            t.bank.process(&ix),
+            Err(CustomError::InvalidInstruction.into())
+        );
+        for pda in [&game, &record] {
+            assert!(t.bank.accounts.contains_key(&pda.address(&PROGRAM_ID)));
+        }
+
+        t.bank
+            .process(&close_contract_ix(
+                &contract,
+                &admin,
+                &admin,
+                true,
+                &[game.clone(), record.clone()],
+            ))
+            .unwrap();
+        for closed in [contract, game.address(&PROGRAM_ID), record_pda(1)] {
+            assert!(!t.bank.accounts.contains_key(&closed));
+        }
+    }
 }